@@ -0,0 +1,539 @@
+//! The admin API, serving the live state of the broker over HTTP in JSON, for the operators to
+//! inspect it. The queues can also be purged and deleted through it, once its mutations are
+//! allowed by `admin.allow_mutations`.
+//!
+//! It also serves `/health` for the liveness and readiness probes of the orchestrators, which is
+//! answered by `200 OK` and `{"status":"ok"}` while the broker accepts connections, or by
+//! `503 Service Unavailable` and `{"status":"shutting_down"}` once its shutdown is requested.
+
+use crate::{
+    broker::{Broker, InUse, Lag},
+    config::{Config, DEFAULT_VHOST},
+    http::{self, Request, Response},
+    shutdown::Shutdown,
+    stream::Peer,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Instant,
+};
+
+/// The connections open by the clients, whatever their protocol.
+#[derive(Default)]
+pub struct Connections {
+    next_id: AtomicU64,
+    /// The client of each connection and when it was opened, in the order they were opened.
+    open: Mutex<BTreeMap<u64, (Peer, Instant)>>,
+}
+
+impl Connections {
+    /// Adds the connection of `peer`, returning the identifier to remove it with.
+    pub fn add(&self, peer: Peer) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.open.lock().unwrap().insert(id, (peer, Instant::now()));
+        id
+    }
+
+    /// Removes the connection added under `id`, once it's closed.
+    pub fn remove(&self, id: u64) {
+        self.open.lock().unwrap().remove(&id);
+    }
+}
+
+/// Binds the HTTP listener serving the admin API of `broker` on `address`, then serves the
+/// requests from a thread of its own. The health of the broker depends on `shutdown`, and the
+/// mutations are allowed, and their users authenticated, by the current `config`.
+pub fn serve(
+    address: SocketAddr,
+    broker: Arc<Broker>,
+    shutdown: Arc<Shutdown>,
+    config: Arc<RwLock<Config>>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    log::info!("Serving the admin API on http://{}", address);
+
+    http::spawn("admin", listener, move |request| {
+        let path = request.path.as_str();
+        if let Some(queue) = path.strip_prefix("/queues/") {
+            return mutate(request, queue, &broker, &config);
+        }
+
+        let (status, body) = match (request.method.as_str(), path) {
+            ("GET", "/health") if shutdown.is_requested() => (
+                "503 Service Unavailable",
+                "{\"status\":\"shutting_down\"}\n".into(),
+            ),
+            ("GET", "/health") => ("200 OK", "{\"status\":\"ok\"}\n".into()),
+            ("GET", "/queues") => ("200 OK", queues(&broker)),
+            ("GET", "/connections") => ("200 OK", connections(&broker)),
+            (_, "/health" | "/queues" | "/connections") => return Some(http::method_not_allowed()),
+            _ => return None,
+        };
+
+        Some(json(status, body))
+    })
+}
+
+/// Answers a request on `/queues/<name>/purge` or `/queues/<name>`, `path` being the part of its
+/// path following `/queues/`.
+fn mutate(
+    request: &Request,
+    path: &str,
+    broker: &Broker,
+    config: &RwLock<Config>,
+) -> Option<Response> {
+    let (name, purge) = match path.strip_suffix("/purge") {
+        Some(name) => (name, true),
+        None => (path, false),
+    };
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    if request.method != if purge { "POST" } else { "DELETE" } {
+        return Some(http::method_not_allowed());
+    }
+
+    let username = {
+        let config = config.read().unwrap();
+        if !config.admin.allow_mutations {
+            return Some(error(
+                "403 Forbidden",
+                "the mutations of the admin API are not allowed",
+            ));
+        }
+
+        match &request.credentials {
+            Some((username, password)) if config.auth.verify(username, password) => {
+                username.as_str()
+            }
+            _ => {
+                let mut response = error("401 Unauthorized", "the credentials are invalid");
+                response
+                    .headers
+                    .push(("WWW-Authenticate", "Basic realm=\"another-mq\"".into()));
+                return Some(response);
+            }
+        }
+    };
+
+    let name = http::decode(name);
+    let vhost = request.param("vhost").unwrap_or(DEFAULT_VHOST);
+    let queue = match broker.queue(vhost, &name) {
+        Some(queue) => queue,
+        None => {
+            return Some(error(
+                "404 Not Found",
+                &format!("no queue named {} in virtual host {}", name, vhost),
+            ))
+        }
+    };
+
+    if purge {
+        let purged = broker.purge(&queue);
+        log::info!(
+            "Purged {} messages of queue {} of virtual host {}, as requested by {} through the admin API",
+            purged,
+            name,
+            vhost,
+            username
+        );
+
+        return Some(json(
+            "200 OK",
+            format!("{{\"messages_purged\":{}}}\n", purged),
+        ));
+    }
+
+    match broker.delete(&queue, request.param("force") == Some("true")) {
+        Ok(deleted) => {
+            log::info!(
+                "Deleted queue {} of virtual host {} and its {} messages, as requested by {} through the admin API",
+                name,
+                vhost,
+                deleted,
+                username
+            );
+
+            Some(json(
+                "200 OK",
+                format!("{{\"messages_deleted\":{}}}\n", deleted),
+            ))
+        }
+        Err(InUse(consumers)) => Some(error(
+            "409 Conflict",
+            &format!(
+                "the queue has {} consumers, which force=true cancels",
+                consumers
+            ),
+        )),
+    }
+}
+
+fn json(status: &'static str, body: String) -> Response {
+    Response {
+        status,
+        content_type: "application/json",
+        headers: Vec::new(),
+        body,
+    }
+}
+
+/// Returns a response whose body is `{"error":<reason>}`.
+fn error(status: &'static str, reason: &str) -> Response {
+    json(status, format!("{{\"error\":{}}}\n", string(reason)))
+}
+
+/// Renders the queues of every virtual host, along with the number of their messages and
+/// consumers, and the lag of their consumers: the number of messages ready to be delivered, the
+/// number of messages left unacknowledged, and the age in seconds of the oldest of them all, which
+/// is `null` if there's none.
+fn queues(broker: &Broker) -> String {
+    let queues: Vec<_> = broker
+        .queues()
+        .iter()
+        .map(|queue| {
+            format!(
+                "{{\"vhost\":{},\"name\":{},\"messages\":{},\"consumers\":{},\"lag\":{}}}",
+                string(queue.vhost()),
+                string(queue.name()),
+                queue.message_count(),
+                queue.consumer_count(),
+                lag(&queue.lag())
+            )
+        })
+        .collect();
+
+    format!("[{}]\n", queues.join(","))
+}
+
+/// Renders the lag of the consumers of a queue.
+fn lag(lag: &Lag) -> String {
+    let oldest_age = match lag.oldest_age {
+        Some(age) => format!("{:.3}", age.as_secs_f64()),
+        None => "null".into(),
+    };
+
+    format!(
+        "{{\"ready\":{},\"unacked\":{},\"oldest_message_age_seconds\":{}}}",
+        lag.ready, lag.unacked, oldest_age
+    )
+}
+
+/// Renders the open connections, along with the address of their client and how long they have
+/// been open, in seconds.
+fn connections(broker: &Broker) -> String {
+    let connections: Vec<_> = broker
+        .connections()
+        .open
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(peer, opened_at)| {
+            format!(
+                "{{\"peer\":{},\"uptime_seconds\":{}}}",
+                string(&peer.to_string()),
+                opened_at.elapsed().as_secs()
+            )
+        })
+        .collect();
+
+    format!("[{}]\n", connections.join(","))
+}
+
+/// Writes `value` as a JSON string, escaping the quotes, the backslashes and the control
+/// characters.
+pub fn string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{broker::Message, config::QueueDefinition, protocol::Properties};
+    use std::{thread, time::Duration};
+    #[cfg(all(unix, feature = "bcrypt"))]
+    use {
+        crate::broker::{Consumer, Queue, Selector},
+        std::sync::atomic::AtomicBool,
+    };
+
+    /// A consumer which takes no message, recording whether it was cancelled.
+    #[cfg(all(unix, feature = "bcrypt"))]
+    #[derive(Default)]
+    struct Idle {
+        cancelled: AtomicBool,
+    }
+
+    #[cfg(all(unix, feature = "bcrypt"))]
+    impl Consumer for Idle {
+        fn deliver(&self, _queue: &Arc<Queue>, message: Message) -> Option<Message> {
+            Some(message)
+        }
+
+        fn requires_ack(&self) -> bool {
+            false
+        }
+
+        fn unacked(&self, _queue: &Arc<Queue>) -> (usize, Option<Instant>) {
+            (0, None)
+        }
+
+        fn take_unacked(&self, _queue: &Arc<Queue>, _deadline: Instant) -> Vec<Message> {
+            Vec::new()
+        }
+
+        fn selector(&self) -> Option<&Selector> {
+            None
+        }
+
+        fn cancel(&self) {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns a configuration declaring the queue `tasks`, whose mutations are allowed to the
+    /// user `guest`, whose password is `guest`.
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.admin.allow_mutations = true;
+        config.queue.definitions.push(QueueDefinition::new("tasks"));
+        config.auth.users.push(crate::config::User {
+            username: "guest".into(),
+            password_hash: "$2b$04$0acWwhKGPAxXGZi.b9EZ0OjzxXakOS9KOlYB.DJRnN5UGbm.MAH3S".into(),
+            max_connections: None,
+            max_channels: None,
+        });
+        config
+    }
+
+    /// Returns the broker of `config`, with `messages` published to its queue `tasks`.
+    fn broker(config: &Config, messages: usize) -> Broker {
+        let broker = Broker::new(&config.queue, &config.vhosts, &config.metrics).unwrap();
+        let queue = broker.queue(DEFAULT_VHOST, "tasks").unwrap();
+
+        for _ in 0..messages {
+            let message = Message::new(
+                String::new(),
+                "tasks".into(),
+                Properties::default(),
+                b"task".to_vec(),
+            );
+            assert!(broker.publish(&queue, message));
+        }
+        broker
+    }
+
+    fn request(method: &str, target: &str, query: &[(&str, &str)], password: &str) -> Request {
+        Request {
+            method: method.into(),
+            path: format!("/queues/{}", target),
+            query: query
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            credentials: Some(("guest".into(), password.into())),
+        }
+    }
+
+    /// Answers `request` with the broker and the configuration given.
+    fn answer(request: &Request, broker: &Broker, config: Config) -> Response {
+        let path = request.path.strip_prefix("/queues/").unwrap();
+
+        mutate(request, path, broker, &RwLock::new(config)).unwrap()
+    }
+
+    #[test]
+    fn mutations_are_forbidden_unless_allowed() {
+        let mut config = config();
+        config.admin.allow_mutations = false;
+        let broker = broker(&config, 2);
+
+        let response = answer(
+            &request("POST", "tasks/purge", &[], "guest"),
+            &broker,
+            config,
+        );
+
+        assert_eq!(response.status, "403 Forbidden");
+        assert_eq!(
+            broker
+                .queue(DEFAULT_VHOST, "tasks")
+                .unwrap()
+                .message_count(),
+            2
+        );
+    }
+
+    #[test]
+    fn mutations_require_valid_credentials() {
+        let config = config();
+        let broker = broker(&config, 2);
+
+        let response = answer(&request("DELETE", "tasks", &[], "wrong"), &broker, config);
+
+        assert_eq!(response.status, "401 Unauthorized");
+        assert!(broker.queue(DEFAULT_VHOST, "tasks").is_some());
+    }
+
+    #[test]
+    fn mutations_only_answer_their_method() {
+        let config = config();
+        let broker = broker(&config, 0);
+
+        let purge = answer(
+            &request("GET", "tasks/purge", &[], "guest"),
+            &broker,
+            config.clone(),
+        );
+        let delete = answer(&request("POST", "tasks", &[], "guest"), &broker, config);
+
+        assert_eq!(purge.status, http::method_not_allowed().status);
+        assert_eq!(delete.status, http::method_not_allowed().status);
+    }
+
+    #[cfg(all(unix, feature = "bcrypt"))]
+    #[test]
+    fn purge_reports_the_messages_removed() {
+        let config = config();
+        let broker = broker(&config, 3);
+
+        let response = answer(
+            &request("POST", "tasks/purge", &[], "guest"),
+            &broker,
+            config,
+        );
+
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.body, "{\"messages_purged\":3}\n");
+        assert_eq!(
+            broker
+                .queue(DEFAULT_VHOST, "tasks")
+                .unwrap()
+                .message_count(),
+            0
+        );
+    }
+
+    #[cfg(all(unix, feature = "bcrypt"))]
+    #[test]
+    fn unknown_queue_is_not_found() {
+        let config = config();
+        let broker = broker(&config, 0);
+
+        let response = answer(
+            &request("POST", "jobs/purge", &[], "guest"),
+            &broker,
+            config,
+        );
+
+        assert_eq!(response.status, "404 Not Found");
+    }
+
+    #[cfg(all(unix, feature = "bcrypt"))]
+    #[test]
+    fn delete_removes_the_queue_and_its_messages() {
+        let config = config();
+        let broker = broker(&config, 2);
+
+        let response = answer(&request("DELETE", "tasks", &[], "guest"), &broker, config);
+
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.body, "{\"messages_deleted\":2}\n");
+        assert!(broker.queue(DEFAULT_VHOST, "tasks").is_none());
+    }
+
+    #[cfg(all(unix, feature = "bcrypt"))]
+    #[test]
+    fn delete_of_a_consumed_queue_conflicts_unless_forced() {
+        let config = config();
+        let broker = broker(&config, 2);
+        let queue = broker.queue(DEFAULT_VHOST, "tasks").unwrap();
+        let consumer = Arc::new(Idle::default());
+        assert!(queue.add_consumer(consumer.clone(), || ()));
+
+        let conflict = answer(
+            &request("DELETE", "tasks", &[], "guest"),
+            &broker,
+            config.clone(),
+        );
+        assert_eq!(conflict.status, "409 Conflict");
+        assert!(broker.queue(DEFAULT_VHOST, "tasks").is_some());
+        assert!(!consumer.cancelled.load(Ordering::SeqCst));
+
+        let forced = answer(
+            &request("DELETE", "tasks", &[("force", "true")], "guest"),
+            &broker,
+            config,
+        );
+        assert_eq!(forced.status, "200 OK");
+        assert_eq!(forced.body, "{\"messages_deleted\":2}\n");
+        assert!(broker.queue(DEFAULT_VHOST, "tasks").is_none());
+        assert!(consumer.cancelled.load(Ordering::SeqCst));
+    }
+
+    /// Returns the age of the oldest message rendered in the lag of `queues`, in seconds.
+    fn oldest_age(queues: &str) -> Option<f64> {
+        let (_, age) = queues
+            .split_once("\"oldest_message_age_seconds\":")
+            .unwrap();
+        let age = age.split('}').next().unwrap();
+
+        age.parse().ok()
+    }
+
+    #[test]
+    fn lag_of_an_empty_queue_has_no_age() {
+        let broker = broker(&config(), 0);
+
+        assert_eq!(
+            queues(&broker),
+            "[{\"vhost\":\"/\",\"name\":\"tasks\",\"messages\":0,\"consumers\":0,\"lag\":\
+             {\"ready\":0,\"unacked\":0,\"oldest_message_age_seconds\":null}}]\n"
+        );
+    }
+
+    #[test]
+    fn lag_reflects_the_oldest_message() {
+        let broker = broker(&config(), 1);
+        thread::sleep(Duration::from_millis(300));
+        let queue = broker.queue(DEFAULT_VHOST, "tasks").unwrap();
+        let message = Message::new(
+            String::new(),
+            "tasks".into(),
+            Properties::default(),
+            b"task".to_vec(),
+        );
+        assert!(broker.publish(&queue, message));
+
+        let queues = queues(&broker);
+        assert!(queues.contains("\"lag\":{\"ready\":2,\"unacked\":0,"));
+        let age = oldest_age(&queues).unwrap();
+        assert!((0.3..5.0).contains(&age), "{}", age);
+    }
+}
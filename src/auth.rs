@@ -0,0 +1,366 @@
+//! The authentication of the clients, against the users of the auth namespace, and the limits of
+//! the connections and the channels of the users.
+//!
+//! The passwords are stored as bcrypt hashes, verified with the `crypt_ra` function of the
+//! libxcrypt library of the system when the `bcrypt` feature is enabled, or as argon2 hashes,
+//! verified with the libargon2 library of the system when the `argon2` feature is enabled.
+
+use crate::config::Auth;
+use std::{collections::HashMap, error, fmt, sync::Mutex};
+
+/// The reasons for a client to be refused by the broker.
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// The client chose a mechanism which the broker does not support.
+    UnsupportedMechanism(String),
+
+    /// The response of the client is not a valid response of its mechanism.
+    MalformedResponse,
+
+    /// The user is unknown, or its password is wrong.
+    InvalidCredentials(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedMechanism(mechanism) => {
+                write!(f, "unsupported mechanism {}", mechanism)
+            }
+            Self::MalformedResponse => f.write_str("malformed authentication response"),
+            Self::InvalidCredentials(username) => write!(f, "login refused for user {}", username),
+        }
+    }
+}
+
+impl error::Error for AuthError {}
+
+/// The connections and the channels open by each user, across all the listeners, so they're
+/// bounded by the limits of the user. The anonymous clients, whose username is empty, are never
+/// counted nor refused.
+#[derive(Default)]
+pub struct Usage {
+    /// The number of connections and of channels of each user with a connection open.
+    users: Mutex<HashMap<String, (usize, usize)>>,
+}
+
+impl Usage {
+    /// Counts a connection of `username`, unless the user already has `max_connections` open, in
+    /// which case `false` is returned.
+    pub fn connect(&self, username: &str, max_connections: Option<usize>) -> bool {
+        if username.is_empty() {
+            return true;
+        }
+        let mut users = self.users.lock().unwrap();
+        let (connections, _) = users.entry(username.into()).or_default();
+
+        if max_connections.is_some_and(|max| *connections >= max) {
+            return false;
+        }
+        *connections += 1;
+
+        true
+    }
+
+    /// Forgets a connection of `username` once it's closed, its channels being closed beforehand.
+    pub fn disconnect(&self, username: &str) {
+        let mut users = self.users.lock().unwrap();
+
+        if let Some((connections, channels)) = users.get_mut(username) {
+            *connections -= 1;
+            if *connections == 0 && *channels == 0 {
+                users.remove(username);
+            }
+        }
+    }
+
+    /// Counts a channel of `username`, unless the user already has `max_channels` open, in which
+    /// case `false` is returned.
+    pub fn open_channel(&self, username: &str, max_channels: Option<usize>) -> bool {
+        if username.is_empty() {
+            return true;
+        }
+        let mut users = self.users.lock().unwrap();
+        let (_, channels) = users.entry(username.into()).or_default();
+
+        if max_channels.is_some_and(|max| *channels >= max) {
+            return false;
+        }
+        *channels += 1;
+
+        true
+    }
+
+    /// Forgets a channel of `username` once it's closed.
+    pub fn close_channel(&self, username: &str) {
+        if let Some((_, channels)) = self.users.lock().unwrap().get_mut(username) {
+            *channels -= 1;
+        }
+    }
+}
+
+/// Authenticates a client with the response of the `PLAIN` mechanism, holding its username and
+/// its password. The name of the authenticated user is returned, which is empty when the
+/// anonymous access is allowed.
+pub fn authenticate(auth: &Auth, mechanism: &str, response: &[u8]) -> Result<String, AuthError> {
+    if mechanism != "PLAIN" {
+        return Err(AuthError::UnsupportedMechanism(mechanism.into()));
+    }
+    if auth.allows_anonymous() {
+        return Ok(String::new());
+    }
+
+    let (username, password) = parse_plain(response)?;
+
+    if auth.verify(&username, &password) {
+        Ok(username)
+    } else {
+        Err(AuthError::InvalidCredentials(username))
+    }
+}
+
+/// Parses the response of the `PLAIN` mechanism (RFC 4616): an optional authorization identity,
+/// the username and the password, separated by NUL bytes.
+fn parse_plain(response: &[u8]) -> Result<(String, String), AuthError> {
+    let mut parts = response.split(|&byte| byte == 0);
+
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(username), Some(password), None) => {
+            let username = String::from_utf8(username.to_vec());
+            let password = String::from_utf8(password.to_vec());
+
+            match (username, password) {
+                (Ok(username), Ok(password)) => Ok((username, password)),
+                _ => Err(AuthError::MalformedResponse),
+            }
+        }
+        _ => Err(AuthError::MalformedResponse),
+    }
+}
+
+/// Returns `true` if `hash` has the form of a bcrypt hash: `$2b$`, a two digits cost, `$` and
+/// 53 characters of salt and checksum.
+pub fn is_bcrypt_hash(hash: &str) -> bool {
+    let bytes = hash.as_bytes();
+
+    bytes.len() == 60
+        && (hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$"))
+        && bytes[4].is_ascii_digit()
+        && bytes[5].is_ascii_digit()
+        && bytes[6] == b'$'
+        && bytes[7..]
+            .iter()
+            .all(|&c| c.is_ascii_alphanumeric() || c == b'.' || c == b'/')
+}
+
+/// Checks `password` against a bcrypt hash.
+#[cfg(all(unix, feature = "bcrypt"))]
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    use std::{
+        ffi::{CStr, CString},
+        os::raw::{c_char, c_int, c_void},
+        ptr,
+    };
+
+    #[link(name = "crypt")]
+    extern "C" {
+        fn crypt_ra(
+            phrase: *const c_char,
+            setting: *const c_char,
+            data: *mut *mut c_void,
+            size: *mut c_int,
+        ) -> *mut c_char;
+    }
+
+    let (password, setting) = match (CString::new(password), CString::new(hash)) {
+        (Ok(password), Ok(setting)) => (password, setting),
+        _ => return false,
+    };
+    let mut data = ptr::null_mut();
+    let mut size = 0;
+
+    // The hash is computed with the salt and the cost of the stored hash, then compared to it.
+    let matches = unsafe {
+        let computed = crypt_ra(password.as_ptr(), setting.as_ptr(), &mut data, &mut size);
+
+        !computed.is_null()
+            && constant_time_eq(CStr::from_ptr(computed).to_bytes(), hash.as_bytes())
+    };
+    unsafe { libc::free(data) };
+
+    matches
+}
+
+/// Checks `password` against a bcrypt hash. Without the `bcrypt` feature, or outside of Unix
+/// where the system offers no bcrypt implementation, every password is refused.
+#[cfg(not(all(unix, feature = "bcrypt")))]
+pub fn verify_password(_: &str, _: &str) -> bool {
+    false
+}
+
+/// Returns `true` if `hash` has the form of an argon2 hash in the PHC format: `$argon2id$`,
+/// `$argon2i$` or `$argon2d$`, then the version, the parameters, the salt and the hash separated
+/// by `$`.
+pub fn is_argon2_hash(hash: &str) -> bool {
+    let rest = match ["$argon2id$", "$argon2i$", "$argon2d$"]
+        .iter()
+        .find_map(|prefix| hash.strip_prefix(prefix))
+    {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let fields: Vec<&str> = rest.split('$').collect();
+
+    fields.len() == 4
+        && fields[0].starts_with("v=")
+        && fields[1].starts_with("m=")
+        && fields[2..].iter().all(|field| {
+            !field.is_empty()
+                && field
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'+' || c == b'/')
+        })
+}
+
+/// Checks `password` against an argon2 hash, whose variant is read from the hash.
+#[cfg(feature = "argon2")]
+pub fn verify_argon2_password(password: &str, hash: &str) -> bool {
+    use std::{
+        ffi::CString,
+        os::raw::{c_char, c_int, c_void},
+    };
+
+    const ARGON2_OK: c_int = 0;
+
+    #[link(name = "argon2")]
+    extern "C" {
+        fn argon2_verify(
+            encoded: *const c_char,
+            pwd: *const c_void,
+            pwdlen: usize,
+            argon2_type: c_int,
+        ) -> c_int;
+    }
+
+    let argon2_type = if hash.starts_with("$argon2id$") {
+        2
+    } else if hash.starts_with("$argon2i$") {
+        1
+    } else {
+        0
+    };
+    let encoded = match CString::new(hash) {
+        Ok(encoded) => encoded,
+        Err(_) => return false,
+    };
+
+    unsafe {
+        argon2_verify(
+            encoded.as_ptr(),
+            password.as_ptr() as *const c_void,
+            password.len(),
+            argon2_type,
+        ) == ARGON2_OK
+    }
+}
+
+/// Checks `password` against an argon2 hash. Without the `argon2` feature, every password is
+/// refused, though the configuration is rejected before it comes to that.
+#[cfg(not(feature = "argon2"))]
+pub fn verify_argon2_password(_: &str, _: &str) -> bool {
+    false
+}
+
+/// Compares two byte strings in a time which does not depend on their content.
+#[cfg(all(unix, feature = "bcrypt"))]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_counted_up_to_the_maximum() {
+        let usage = Usage::default();
+
+        assert!(usage.connect("guest", Some(2)));
+        assert!(usage.connect("guest", Some(2)));
+        assert!(!usage.connect("guest", Some(2)));
+        assert!(usage.connect("admin", Some(2)));
+
+        usage.disconnect("guest");
+        assert!(usage.connect("guest", Some(2)));
+        assert!(!usage.connect("guest", Some(2)));
+    }
+
+    #[test]
+    fn channels_are_counted_across_the_connections() {
+        let usage = Usage::default();
+        usage.connect("guest", None);
+        usage.connect("guest", None);
+
+        assert!(usage.open_channel("guest", Some(2)));
+        assert!(usage.open_channel("guest", Some(2)));
+        assert!(!usage.open_channel("guest", Some(2)));
+
+        usage.close_channel("guest");
+        assert!(usage.open_channel("guest", Some(2)));
+    }
+
+    #[test]
+    fn refused_connection_is_not_counted() {
+        let usage = Usage::default();
+
+        assert!(usage.connect("guest", Some(1)));
+        assert!(!usage.connect("guest", Some(1)));
+        assert!(!usage.connect("guest", Some(1)));
+
+        // The connection open is the only one released.
+        usage.disconnect("guest");
+        assert!(usage.connect("guest", Some(1)));
+    }
+
+    #[test]
+    fn closed_connection_releases_its_channels_and_its_slot() {
+        let usage = Usage::default();
+        assert!(usage.connect("guest", Some(1)));
+        assert!(usage.open_channel("guest", Some(1)));
+        assert!(!usage.open_channel("guest", Some(1)));
+        assert!(usage.open_channel("admin", Some(1)));
+
+        // A connection closes its channels, then goes away.
+        usage.close_channel("guest");
+        usage.disconnect("guest");
+        assert!(!usage.users.lock().unwrap().contains_key("guest"));
+
+        assert!(usage.connect("guest", Some(1)));
+        assert!(usage.open_channel("guest", Some(1)));
+        assert!(!usage.open_channel("admin", Some(1)));
+    }
+
+    #[test]
+    fn usage_is_unbounded_without_maximum_or_user() {
+        let usage = Usage::default();
+
+        for _ in 0..100 {
+            assert!(usage.connect("guest", None));
+            assert!(usage.open_channel("guest", None));
+            assert!(usage.connect("", Some(0)));
+            assert!(usage.open_channel("", Some(0)));
+        }
+    }
+
+    #[test]
+    fn plain_response_holds_the_username_and_the_password() {
+        assert_eq!(
+            parse_plain(b"\0guest\0secret").unwrap(),
+            ("guest".to_string(), "secret".to_string())
+        );
+        assert!(matches!(
+            parse_plain(b"guest\0secret"),
+            Err(AuthError::MalformedResponse)
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+//! The alerts of the queues, posted to their webhooks as the number of messages of the queues
+//! crosses their thresholds (see [`config::Alert`]).
+
+use super::Queue;
+use crate::{admin, config, http};
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+/// The states of the alerts of the queues, by virtual host, queue name and index of the alert
+/// among the ones of its queue.
+#[derive(Default)]
+pub struct Alerts {
+    states: HashMap<(String, String, usize), State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Whether the queue holds more messages than the threshold of the alert.
+    firing: bool,
+    /// The state last posted to the webhook, the alert being resolved until it's first posted.
+    posted: bool,
+    last_post: Option<Instant>,
+}
+
+impl Alerts {
+    /// Checks the depth of `queues` against the thresholds of their alerts, posting the alerts
+    /// whose state changed. The states of the queues which are gone are forgotten.
+    pub fn check(&mut self, queues: &[Arc<Queue>]) {
+        self.states.retain(|(vhost, name, _), _| {
+            queues
+                .iter()
+                .any(|queue| queue.vhost() == vhost && queue.name() == name)
+        });
+
+        for queue in queues {
+            let depth = queue.message_count() as u64;
+
+            for (i, alert) in queue.definition().alerts.iter().enumerate() {
+                let key = (queue.vhost().to_string(), queue.name().to_string(), i);
+                let state = self.states.entry(key).or_default();
+
+                if !state.firing && depth >= alert.depth_threshold {
+                    state.firing = true;
+                } else if state.firing && depth <= alert.effective_clear_threshold() {
+                    state.firing = false;
+                }
+
+                let is_due = state
+                    .last_post
+                    .is_none_or(|last_post| last_post.elapsed() >= alert.min_interval);
+                if state.firing != state.posted && is_due {
+                    state.last_post = Some(Instant::now());
+                    if post(queue, alert, state.firing, depth) {
+                        state.posted = state.firing;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Posts the state of the alert of `queue` to its webhook, returning `true` if it's received.
+fn post(queue: &Queue, alert: &config::Alert, firing: bool, depth: u64) -> bool {
+    let state = if firing { "firing" } else { "resolved" };
+    let body = format!(
+        "{{\"vhost\":{},\"queue\":{},\"state\":\"{}\",\"depth\":{},\"depth_threshold\":{}}}",
+        admin::string(queue.vhost()),
+        admin::string(queue.name()),
+        state,
+        depth,
+        alert.depth_threshold
+    );
+
+    let result = http::Url::parse(&alert.webhook_url)
+        .and_then(|url| http::post(&url, &body).map_err(|err| err.to_string()));
+
+    match result {
+        Ok(()) => {
+            log::info!(
+                "Posted the {} alert of queue {} holding {} messages to {}",
+                state,
+                queue.name(),
+                depth,
+                alert.webhook_url
+            );
+            true
+        }
+        Err(err) => {
+            log::warn!(
+                "Could not post the {} alert of queue {} to {}: {}",
+                state,
+                queue.name(),
+                alert.webhook_url,
+                err
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! The compression of the bodies of the messages while they're held by the broker, built on top of
+//! the zlib and the libzstd libraries of the system.
+//!
+//! The gzip compression is only available with the `gzip` feature, and the zstd compression with
+//! the `zstd` feature. The configuration using a compression which is not built in is rejected, so
+//! the broker never meets the bodies it can't decompress, except in the storage.
+
+use crate::config::Compression;
+use std::io;
+
+/// Compresses a body with `compression`.
+pub fn compress(compression: Compression, body: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(body.to_vec()),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => gzip::compress(body),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::compress(body),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(compression)),
+    }
+}
+
+/// Decompresses a body compressed with `compression`.
+pub fn decompress(compression: Compression, body: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(body.to_vec()),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => gzip::decompress(body),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::decompress(body),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(compression)),
+    }
+}
+
+#[allow(dead_code)]
+fn unsupported(compression: Compression) -> io::Error {
+    io::Error::other(format!(
+        "another-mq is built without the {:?} compression",
+        compression
+    ))
+}
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    use std::{
+        io, mem,
+        os::raw::{c_char, c_int, c_uint, c_ulong, c_void},
+        ptr,
+    };
+
+    const Z_OK: c_int = 0;
+    const Z_STREAM_END: c_int = 1;
+    const Z_BUF_ERROR: c_int = -5;
+    const Z_NO_FLUSH: c_int = 0;
+    const Z_FINISH: c_int = 4;
+    const Z_DEFAULT_COMPRESSION: c_int = -1;
+    const Z_DEFLATED: c_int = 8;
+    const Z_DEFAULT_STRATEGY: c_int = 0;
+
+    /// The window bits of a gzip stream: the largest window, plus 16 for the gzip wrapper.
+    const GZIP_WINDOW_BITS: c_int = 15 + 16;
+
+    /// The size of the chunks the output grows by.
+    const CHUNK_BYTES: usize = 16 * 1024;
+
+    #[repr(C)]
+    struct z_stream {
+        next_in: *const u8,
+        avail_in: c_uint,
+        total_in: c_ulong,
+        next_out: *mut u8,
+        avail_out: c_uint,
+        total_out: c_ulong,
+        msg: *const c_char,
+        state: *mut c_void,
+        zalloc: *const c_void,
+        zfree: *const c_void,
+        opaque: *mut c_void,
+        data_type: c_int,
+        adler: c_ulong,
+        reserved: c_ulong,
+    }
+
+    #[link(name = "z")]
+    extern "C" {
+        fn zlibVersion() -> *const c_char;
+        fn deflateInit2_(
+            strm: *mut z_stream,
+            level: c_int,
+            method: c_int,
+            window_bits: c_int,
+            mem_level: c_int,
+            strategy: c_int,
+            version: *const c_char,
+            stream_size: c_int,
+        ) -> c_int;
+        fn deflate(strm: *mut z_stream, flush: c_int) -> c_int;
+        fn deflateEnd(strm: *mut z_stream) -> c_int;
+        fn inflateInit2_(
+            strm: *mut z_stream,
+            window_bits: c_int,
+            version: *const c_char,
+            stream_size: c_int,
+        ) -> c_int;
+        fn inflate(strm: *mut z_stream, flush: c_int) -> c_int;
+        fn inflateEnd(strm: *mut z_stream) -> c_int;
+    }
+
+    pub fn compress(body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut stream = new_stream(body)?;
+        let initialized = unsafe {
+            deflateInit2_(
+                &mut stream,
+                Z_DEFAULT_COMPRESSION,
+                Z_DEFLATED,
+                GZIP_WINDOW_BITS,
+                8,
+                Z_DEFAULT_STRATEGY,
+                zlibVersion(),
+                mem::size_of::<z_stream>() as c_int,
+            )
+        };
+        if initialized != Z_OK {
+            return Err(error("could not start the gzip compression"));
+        }
+
+        let result = run(&mut stream, |stream| unsafe { deflate(stream, Z_FINISH) });
+        unsafe { deflateEnd(&mut stream) };
+
+        result
+    }
+
+    pub fn decompress(body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut stream = new_stream(body)?;
+        let initialized = unsafe {
+            inflateInit2_(
+                &mut stream,
+                GZIP_WINDOW_BITS,
+                zlibVersion(),
+                mem::size_of::<z_stream>() as c_int,
+            )
+        };
+        if initialized != Z_OK {
+            return Err(error("could not start the gzip decompression"));
+        }
+
+        let result = run(&mut stream, |stream| unsafe { inflate(stream, Z_NO_FLUSH) });
+        unsafe { inflateEnd(&mut stream) };
+
+        result
+    }
+
+    fn new_stream(input: &[u8]) -> io::Result<z_stream> {
+        if input.len() > c_uint::MAX as usize {
+            return Err(error("the body is too large to be compressed"));
+        }
+
+        Ok(z_stream {
+            next_in: input.as_ptr(),
+            avail_in: input.len() as c_uint,
+            total_in: 0,
+            next_out: ptr::null_mut(),
+            avail_out: 0,
+            total_out: 0,
+            msg: ptr::null(),
+            state: ptr::null_mut(),
+            zalloc: ptr::null(),
+            zfree: ptr::null(),
+            opaque: ptr::null_mut(),
+            data_type: 0,
+            adler: 0,
+            reserved: 0,
+        })
+    }
+
+    /// Runs `step` over the input of the stream, growing the output as long as the stream needs
+    /// more room, until the end of the stream is reached.
+    fn run<F>(stream: &mut z_stream, mut step: F) -> io::Result<Vec<u8>>
+    where
+        F: FnMut(&mut z_stream) -> c_int,
+    {
+        let mut output: Vec<u8> = Vec::new();
+
+        loop {
+            let written = output.len();
+            output.resize(written + CHUNK_BYTES, 0);
+            stream.next_out = unsafe { output.as_mut_ptr().add(written) };
+            stream.avail_out = CHUNK_BYTES as c_uint;
+
+            let status = step(stream);
+            output.truncate(written + CHUNK_BYTES - stream.avail_out as usize);
+
+            match status {
+                Z_STREAM_END => return Ok(output),
+                Z_OK => {}
+                // No progress could be made with the room left, which can't happen once the
+                // whole input is available unless it's truncated.
+                Z_BUF_ERROR if stream.avail_out == 0 => {}
+                _ => return Err(error("the gzip stream is corrupted")),
+            }
+        }
+    }
+
+    fn error(reason: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, reason)
+    }
+}
+
+#[cfg(feature = "zstd")]
+mod zstd {
+    use std::{
+        io,
+        os::raw::{c_int, c_uint, c_ulonglong, c_void},
+    };
+
+    /// The compression level of zstd by default.
+    const ZSTD_CLEVEL_DEFAULT: c_int = 3;
+
+    const ZSTD_CONTENTSIZE_UNKNOWN: c_ulonglong = c_ulonglong::MAX;
+    const ZSTD_CONTENTSIZE_ERROR: c_ulonglong = c_ulonglong::MAX - 1;
+
+    #[link(name = "zstd")]
+    extern "C" {
+        fn ZSTD_compressBound(src_size: usize) -> usize;
+        fn ZSTD_compress(
+            dst: *mut c_void,
+            dst_capacity: usize,
+            src: *const c_void,
+            src_size: usize,
+            compression_level: c_int,
+        ) -> usize;
+        fn ZSTD_getFrameContentSize(src: *const c_void, src_size: usize) -> c_ulonglong;
+        fn ZSTD_decompress(
+            dst: *mut c_void,
+            dst_capacity: usize,
+            src: *const c_void,
+            compressed_size: usize,
+        ) -> usize;
+        fn ZSTD_isError(code: usize) -> c_uint;
+    }
+
+    pub fn compress(body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = vec![0; unsafe { ZSTD_compressBound(body.len()) }];
+        let size = unsafe {
+            ZSTD_compress(
+                output.as_mut_ptr() as *mut c_void,
+                output.len(),
+                body.as_ptr() as *const c_void,
+                body.len(),
+                ZSTD_CLEVEL_DEFAULT,
+            )
+        };
+        if unsafe { ZSTD_isError(size) } != 0 {
+            return Err(error("could not compress the body with zstd"));
+        }
+        output.truncate(size);
+
+        Ok(output)
+    }
+
+    /// Decompresses a single zstd frame, which holds the size of its content as it's written by
+    /// [`compress`].
+    pub fn decompress(body: &[u8]) -> io::Result<Vec<u8>> {
+        let content_size =
+            unsafe { ZSTD_getFrameContentSize(body.as_ptr() as *const c_void, body.len()) };
+        if content_size == ZSTD_CONTENTSIZE_UNKNOWN
+            || content_size == ZSTD_CONTENTSIZE_ERROR
+            || content_size > usize::MAX as c_ulonglong
+        {
+            return Err(error("the zstd frame is corrupted"));
+        }
+
+        let mut output = vec![0; content_size as usize];
+        let size = unsafe {
+            ZSTD_decompress(
+                output.as_mut_ptr() as *mut c_void,
+                output.len(),
+                body.as_ptr() as *const c_void,
+                body.len(),
+            )
+        };
+        if unsafe { ZSTD_isError(size) } != 0 || size != output.len() {
+            return Err(error("the zstd frame is corrupted"));
+        }
+
+        Ok(output)
+    }
+
+    fn error(reason: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A body compressing well, spanning several chunks of the output once decompressed.
+    fn body() -> Vec<u8> {
+        (0..100_000u32)
+            .flat_map(|i| format!("{{\"id\":{}}}", i % 1000).into_bytes())
+            .collect()
+    }
+
+    fn round_trip(compression: Compression) {
+        for body in [Vec::new(), b"{}".to_vec(), body()] {
+            let compressed = compress(compression, &body).unwrap();
+
+            assert_eq!(decompress(compression, &compressed).unwrap(), body);
+        }
+    }
+
+    #[test]
+    fn none_round_trips() {
+        round_trip(Compression::None);
+        assert_eq!(compress(Compression::None, b"body").unwrap(), b"body");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trips() {
+        round_trip(Compression::Gzip);
+        assert!(compress(Compression::Gzip, &body()).unwrap().len() < body().len() / 10);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_rejects_a_corrupted_body() {
+        let mut compressed = compress(Compression::Gzip, &body()).unwrap();
+        compressed.truncate(compressed.len() / 2);
+
+        assert!(decompress(Compression::Gzip, &compressed).is_err());
+        assert!(decompress(Compression::Gzip, b"not gzip").is_err());
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn gzip_is_unsupported() {
+        assert!(compress(Compression::Gzip, b"body").is_err());
+        assert!(decompress(Compression::Gzip, b"body").is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trips() {
+        round_trip(Compression::Zstd);
+        assert!(compress(Compression::Zstd, &body()).unwrap().len() < body().len() / 10);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_rejects_a_corrupted_body() {
+        let mut compressed = compress(Compression::Zstd, &body()).unwrap();
+        compressed.truncate(compressed.len() / 2);
+
+        assert!(decompress(Compression::Zstd, &compressed).is_err());
+        assert!(decompress(Compression::Zstd, b"not zstd").is_err());
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_is_unsupported() {
+        assert!(compress(Compression::Zstd, b"body").is_err());
+        assert!(decompress(Compression::Zstd, b"body").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use super::compression;
+use crate::{
+    config::Compression,
+    protocol::{FieldTable, FieldValue, Properties},
+};
+use std::time::{Duration, Instant};
+
+/// A message published to the broker.
+#[derive(Clone, Debug)]
+pub struct Message {
+    /// The exchange the message was published to.
+    pub exchange: String,
+    pub routing_key: String,
+    pub properties: Properties,
+    pub body: Vec<u8>,
+    /// Whether the message was delivered to a consumer before being requeued.
+    pub redelivered: bool,
+    /// The number of times the message was requeued after being delivered.
+    pub(super) redeliveries: u32,
+    /// When the message was pushed to the queue holding it, which its requeues don't change.
+    pub(super) enqueued_at: Instant,
+    /// When the message expires in the queue holding it.
+    pub(super) expires_at: Option<Instant>,
+    /// The queues the message was dead-lettered from, used to detect the cycles.
+    pub(super) deaths: Vec<String>,
+    /// The identifier of the message in the storage, if it's stored.
+    pub(super) stored: Option<u64>,
+    /// How the body is compressed while the message is held by the broker.
+    pub(super) compression: Compression,
+    /// Whether the body is left in the storage, `body` being empty until it's read back.
+    pub(super) paged_out: bool,
+    /// The size of the body as it was published, whether it's compressed or paged out since.
+    pub(super) body_size: u64,
+}
+
+impl Message {
+    pub fn new(
+        exchange: String,
+        routing_key: String,
+        properties: Properties,
+        body: Vec<u8>,
+    ) -> Self {
+        Self {
+            body_size: body.len() as u64,
+            exchange,
+            routing_key,
+            properties,
+            body,
+            redelivered: false,
+            redeliveries: 0,
+            enqueued_at: Instant::now(),
+            expires_at: None,
+            deaths: Vec::new(),
+            stored: None,
+            compression: Compression::None,
+            paged_out: false,
+        }
+    }
+
+    /// Drops the body while the message waits in a lazy queue, if it's stored so it can be read
+    /// back from the storage.
+    pub(super) fn page_out(&mut self) {
+        if self.stored.is_some() {
+            self.body = Vec::new();
+            self.paged_out = true;
+        }
+    }
+
+    /// Compresses the body with `compression` if it's not compressed yet and it's at least
+    /// `threshold_bytes` long. The body is kept as it is if compressing it saves nothing.
+    pub(super) fn compress(&mut self, compression: Compression, threshold_bytes: u64) {
+        if self.compression != Compression::None
+            || compression == Compression::None
+            || (self.body.len() as u64) < threshold_bytes
+        {
+            return;
+        }
+
+        match compression::compress(compression, &self.body) {
+            Ok(body) if body.len() < self.body.len() => {
+                self.body = body;
+                self.compression = compression;
+            }
+            Ok(_) => {}
+            Err(err) => log::error!("Could not compress the body of a message: {}", err),
+        }
+    }
+
+    /// Decompresses the body before the message is delivered. If it can't be decompressed, the
+    /// body is delivered as it's held.
+    pub(super) fn decompress(&mut self) {
+        if self.compression == Compression::None {
+            return;
+        }
+
+        match compression::decompress(self.compression, &self.body) {
+            Ok(body) => self.body = body,
+            Err(err) => log::error!("Could not decompress the body of a message: {}", err),
+        }
+        self.compression = Compression::None;
+    }
+
+    /// Returns the delay before the message can be delivered, from its `x-delay` header holding
+    /// a number of milliseconds. The messages without a positive delay are delivered right away.
+    pub(super) fn delay(&self) -> Option<Duration> {
+        let delay = match self.properties.headers.as_ref()?.get("x-delay")? {
+            FieldValue::I8(delay) => *delay as i64,
+            FieldValue::U8(delay) => *delay as i64,
+            FieldValue::I16(delay) => *delay as i64,
+            FieldValue::U16(delay) => *delay as i64,
+            FieldValue::I32(delay) => *delay as i64,
+            FieldValue::U32(delay) => *delay as i64,
+            FieldValue::I64(delay) => *delay,
+            _ => return None,
+        };
+
+        if delay > 0 {
+            Some(Duration::from_millis(delay as u64))
+        } else {
+            None
+        }
+    }
+
+    /// Returns when the message was pushed to the queue holding it.
+    pub fn enqueued_at(&self) -> Instant {
+        self.enqueued_at
+    }
+
+    /// Returns `true` if the message expired in the queue holding it.
+    pub(super) fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Records that the message was dead-lettered from `queue`. The first death is exposed to the
+    /// consumers in the `x-first-death-queue`, `x-first-death-reason` and `x-first-death-exchange` headers.
+    pub(super) fn record_death(&mut self, queue: &str, reason: DeathReason) {
+        let headers = self.properties.headers.get_or_insert_with(Default::default);
+
+        if !headers.contains_key("x-first-death-queue") {
+            headers.insert("x-first-death-queue".into(), queue.into());
+            headers.insert("x-first-death-reason".into(), reason.as_str().into());
+            headers.insert(
+                "x-first-death-exchange".into(),
+                self.exchange.as_str().into(),
+            );
+        }
+
+        self.deaths.push(queue.into());
+        self.redelivered = false;
+        self.redeliveries = 0;
+    }
+}
+
+/// Why a message is dead-lettered.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DeathReason {
+    /// The message was negatively acknowledged by a consumer, without being requeued.
+    Rejected,
+    /// The message stayed in its queue longer than the time-to-live of the queue.
+    Expired,
+    /// The message was requeued more times than the maximum of redeliveries of the queue.
+    DeliveryLimit,
+    /// The message was taken from the head of its queue to make room for a message published to
+    /// it, as the queue held its maximum length.
+    MaxLength,
+}
+
+impl DeathReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Rejected => "rejected",
+            Self::Expired => "expired",
+            Self::DeliveryLimit => "delivery_limit",
+            Self::MaxLength => "maxlen",
+        }
+    }
+}
+
+/// The headers a consumer selects the messages delivered to it by: a message is selected if it
+/// holds each of them, with the same string value.
+#[derive(Clone, Debug)]
+pub struct Selector(Vec<(String, Vec<u8>)>);
+
+impl Selector {
+    /// Reads a selector from the table of an `x-selector` argument, whose values must be strings.
+    /// `None` is returned if the table holds one which is not a string.
+    pub fn from_table(table: &FieldTable) -> Option<Self> {
+        let mut headers = Vec::new();
+
+        for (name, value) in table {
+            match value {
+                FieldValue::LongStr(value) => headers.push((name.clone(), value.clone())),
+                _ => return None,
+            }
+        }
+
+        Some(Self(headers))
+    }
+
+    /// Returns `true` if `message` holds each header of the selector.
+    pub fn matches(&self, message: &Message) -> bool {
+        let headers = match &message.properties.headers {
+            Some(headers) => headers,
+            None => return self.0.is_empty(),
+        };
+
+        self.0.iter().all(|(name, expected)| {
+            matches!(headers.get(name), Some(FieldValue::LongStr(value)) if value == expected)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(pairs: &[(&str, FieldValue)]) -> FieldTable {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn string(value: &str) -> FieldValue {
+        FieldValue::LongStr(value.as_bytes().to_vec())
+    }
+
+    fn message(headers: Option<FieldTable>) -> Message {
+        Message::new(
+            String::new(),
+            "tasks".into(),
+            Properties {
+                headers,
+                ..Properties::default()
+            },
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn selector_matches_the_messages_holding_each_header() {
+        let selector = Selector::from_table(&table(&[
+            ("type", string("invoice")),
+            ("region", string("eu")),
+        ]))
+        .unwrap();
+
+        assert!(selector.matches(&message(Some(table(&[
+            ("type", string("invoice")),
+            ("region", string("eu")),
+            ("priority", string("high")),
+        ])))));
+        assert!(!selector.matches(&message(Some(table(&[("type", string("invoice"))])))));
+        assert!(!selector.matches(&message(Some(table(&[
+            ("type", string("receipt")),
+            ("region", string("eu")),
+        ])))));
+        assert!(!selector.matches(&message(None)));
+    }
+
+    #[test]
+    fn selector_only_matches_string_headers() {
+        let selector = Selector::from_table(&table(&[("count", string("1"))])).unwrap();
+
+        assert!(!selector.matches(&message(Some(table(&[("count", FieldValue::I32(1))])))));
+    }
+
+    #[test]
+    fn selector_of_values_other_than_strings_is_refused() {
+        assert!(Selector::from_table(&table(&[("count", FieldValue::I32(1))])).is_none());
+    }
+}
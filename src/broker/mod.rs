@@ -0,0 +1,1426 @@
+//! The broker, holding the queues shared by the connections of the clients, each queue belonging
+//! to a virtual host.
+
+mod alert;
+mod compression;
+mod message;
+mod queue;
+mod storage;
+
+pub use message::{DeathReason, Message, Selector};
+pub use queue::{Consumer, InUse, Lag, Queue};
+
+use crate::{
+    admin::Connections,
+    auth::Usage,
+    config::{self, Compression, QueueDefinition, VirtualHost, DEFAULT_VHOST},
+    metrics::Registry,
+};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The exchange routing the messages to every queue bound to it by a pattern matching their
+/// routing key, alongside the default exchange routing them to the queue named by their routing
+/// key.
+pub const TOPIC_EXCHANGE: &str = "amq.topic";
+
+/// How often the depth of the queues is checked against the thresholds of their alerts.
+const ALERT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The state of the broker, shared by every connection.
+pub struct Broker {
+    /// The queues, by virtual host and name.
+    queues: Mutex<HashMap<(String, String), Arc<Queue>>>,
+    generated_names: AtomicU64,
+    max_message_bytes: RwLock<Option<u64>>,
+    default_queue: RwLock<Option<String>>,
+    expiry_scan_interval: RwLock<Duration>,
+    /// The time-to-live of the messages of the queues declared without one.
+    default_message_ttl: RwLock<Option<Duration>>,
+    /// The compression of the bodies of the messages, along with the size from which they're
+    /// compressed.
+    compression: RwLock<(Compression, u64)>,
+    /// Whether the messages published with a delay are held until they're due, rather than
+    /// delivered right away.
+    enable_delayed: RwLock<bool>,
+    /// Whether the consumers can select the messages delivered to them by their headers.
+    enable_selectors: RwLock<bool>,
+    storage: Option<storage::Storage>,
+    /// Whether every queue is declared as a non-durable queue, as the broker never writes the
+    /// messages to the disk.
+    memory_only: bool,
+    metrics: Registry,
+    connections: Connections,
+    usage: Usage,
+    unacked: UnackedCount,
+}
+
+/// The number of messages left unacknowledged across every queue, along with its maximum.
+#[derive(Default)]
+struct UnackedCount {
+    count: AtomicU64,
+    max: RwLock<Option<u64>>,
+    /// Whether a delivery was held back by the maximum since the messages were last settled.
+    held_back: AtomicBool,
+    /// Whether the maximum was ever reached, so it's only warned about once.
+    warned: AtomicBool,
+}
+
+impl UnackedCount {
+    /// Counts a message about to be left unacknowledged, returning `false` if the maximum is
+    /// reached, in which case the message must not be delivered.
+    fn acquire(&self) -> bool {
+        let max = match *self.max.read().unwrap() {
+            Some(max) => max,
+            None => {
+                self.count.fetch_add(1, Ordering::SeqCst);
+                return true;
+            }
+        };
+        let increment = |count: u64| (count < max).then(|| count + 1);
+
+        if self
+            .count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, increment)
+            .is_ok()
+        {
+            return true;
+        }
+
+        // The messages may be settled between the attempts, so it's retried once the delivery is
+        // known to be held back, rather than waiting for another message to be settled.
+        self.held_back.store(true, Ordering::SeqCst);
+        if self
+            .count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, increment)
+            .is_ok()
+        {
+            return true;
+        }
+
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            log::warn!(
+                "The broker holds {} unacknowledged messages, the maximum of \
+                 queue.max_unacked_total, so it stops delivering messages until some are settled",
+                max
+            );
+        }
+        false
+    }
+
+    /// Stops counting `settled` messages, returning `true` if a delivery was held back by the
+    /// maximum since the messages were last settled.
+    fn release(&self, settled: u64) -> bool {
+        let _ = self
+            .count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                Some(count.saturating_sub(settled))
+            });
+
+        settled > 0 && self.held_back.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Broker {
+    /// Creates the broker, configured by the queue namespace and the virtual hosts, its metrics
+    /// being configured by the metrics namespace. If the queue namespace defines a storage, it's
+    /// opened and the messages it holds are put back in their queues. The queues which are not
+    /// defined anymore are declared as durable queues.
+    pub fn new(
+        config: &config::Queue,
+        vhosts: &[VirtualHost],
+        metrics: &config::Metrics,
+    ) -> io::Result<Self> {
+        let is_lazy = |vhost: &str, name: &str| {
+            let definitions = if vhost == DEFAULT_VHOST {
+                &config.definitions
+            } else {
+                match vhosts.iter().find(|v| v.name == vhost) {
+                    Some(vhost) => &vhost.definitions,
+                    None => return false,
+                }
+            };
+
+            definitions.iter().any(|d| d.name == name && d.lazy)
+        };
+        let (storage, recovered) = match &config.storage {
+            Some(storage) => {
+                let (storage, recovered) = storage::Storage::open(storage, is_lazy)?;
+                (Some(storage), recovered)
+            }
+            None => (None, Vec::new()),
+        };
+        let broker = Self {
+            queues: Mutex::new(HashMap::new()),
+            generated_names: AtomicU64::new(0),
+            max_message_bytes: RwLock::new(None),
+            default_queue: RwLock::new(None),
+            expiry_scan_interval: RwLock::new(config.expiry_scan_interval),
+            default_message_ttl: RwLock::new(None),
+            compression: RwLock::new((Compression::None, 0)),
+            enable_delayed: RwLock::new(false),
+            enable_selectors: RwLock::new(false),
+            storage,
+            memory_only: config.memory_only,
+            metrics: Registry::new(metrics.effective_size_buckets()),
+            connections: Connections::default(),
+            usage: Usage::default(),
+            unacked: UnackedCount::default(),
+        };
+
+        if broker.memory_only {
+            log::warn!(
+                "The broker runs in memory only: the queues are not durable, and their messages \
+                 are lost once it stops"
+            );
+        }
+        broker.configure(config, vhosts);
+
+        if !recovered.is_empty() {
+            log::info!("Recovered {} messages from the storage", recovered.len());
+        }
+        let mut dropped = Vec::new();
+        for (vhost, name, message) in recovered {
+            let queue = broker.queue(&vhost, &name).unwrap_or_else(|| {
+                log::info!("Declared queue {} of virtual host {}", name, vhost);
+                broker.declare(
+                    &vhost,
+                    QueueDefinition {
+                        durable: true,
+                        ..QueueDefinition::new(name)
+                    },
+                )
+            });
+
+            let stored = message.stored;
+            match queue.push(message) {
+                Ok(heads) => dropped.extend(heads.iter().filter_map(|message| message.stored)),
+                Err(_) => dropped.extend(stored),
+            }
+        }
+        if !dropped.is_empty() {
+            log::warn!(
+                "Dropped {} messages recovered from the storage, as their queue overflowed",
+                dropped.len()
+            );
+            broker.forget(dropped);
+        }
+
+        Ok(broker)
+    }
+
+    /// Applies the queue namespace and the virtual hosts to the broker, declaring the queues they
+    /// define which are not declared yet. The queues which are already declared are kept as they
+    /// are.
+    pub fn configure(&self, config: &config::Queue, vhosts: &[VirtualHost]) {
+        *self.max_message_bytes.write().unwrap() = config.max_message_bytes;
+        *self.default_queue.write().unwrap() = config.default_queue.clone();
+        *self.expiry_scan_interval.write().unwrap() = config.expiry_scan_interval;
+        *self.default_message_ttl.write().unwrap() = config.default_message_ttl;
+        *self.compression.write().unwrap() = (
+            config.compression.unwrap_or_default(),
+            config.compression_threshold_bytes,
+        );
+        *self.enable_delayed.write().unwrap() = config.enable_delayed;
+        *self.enable_selectors.write().unwrap() = config.enable_selectors;
+        *self.unacked.max.write().unwrap() = config.max_unacked_total;
+
+        let definitions = vhosts
+            .iter()
+            .map(|vhost| (vhost.name.as_str(), &vhost.definitions));
+        for (vhost, definitions) in Some((DEFAULT_VHOST, &config.definitions))
+            .into_iter()
+            .chain(definitions)
+        {
+            for definition in definitions {
+                if self.queue(vhost, &definition.name).is_none() {
+                    self.declare(vhost, definition.clone());
+                    log::info!(
+                        "Declared queue {} of virtual host {}",
+                        definition.name,
+                        vhost
+                    );
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the broker never writes the messages to the disk, every queue being
+    /// non-durable.
+    pub fn is_memory_only(&self) -> bool {
+        self.memory_only
+    }
+
+    /// Returns the maximum size of the body of a message, if any.
+    pub fn max_message_bytes(&self) -> Option<u64> {
+        *self.max_message_bytes.read().unwrap()
+    }
+
+    /// Returns `true` if the consumers can select the messages delivered to them by their
+    /// headers.
+    pub fn selectors_enabled(&self) -> bool {
+        *self.enable_selectors.read().unwrap()
+    }
+
+    /// Starts the thread sweeping the queues for expired messages, every expiry scan interval of
+    /// the queue namespace.
+    pub fn start_expiry(self: &Arc<Self>) -> io::Result<()> {
+        let broker = self.clone();
+
+        thread::Builder::new()
+            .name("expiry".into())
+            .spawn(move || loop {
+                let interval = *broker.expiry_scan_interval.read().unwrap();
+
+                thread::sleep(interval);
+                broker.expire();
+            })?;
+
+        Ok(())
+    }
+
+    /// Starts the thread checking the depth of the queues against the thresholds of their alerts,
+    /// which posts them to their webhooks.
+    pub fn start_alerts(self: &Arc<Self>) -> io::Result<()> {
+        let broker = self.clone();
+
+        thread::Builder::new()
+            .name("alerts".into())
+            .spawn(move || {
+                let mut alerts = alert::Alerts::default();
+
+                loop {
+                    thread::sleep(ALERT_CHECK_INTERVAL);
+                    alerts.check(&broker.queues());
+                }
+            })?;
+
+        Ok(())
+    }
+
+    /// Pushes the delayed messages which are due to their queue, dead-letters the expired messages
+    /// of the queues with a time-to-live, then requeues the messages left unacknowledged for too
+    /// long in the queues with an acknowledgement timeout.
+    fn expire(&self) {
+        for queue in self.queues() {
+            let due = queue.take_due(Instant::now());
+
+            if !due.is_empty() {
+                let mut rejected = 0;
+                for message in due {
+                    if !self.enqueue(&queue, message) {
+                        rejected += 1;
+                    }
+                }
+                if rejected > 0 {
+                    log::warn!(
+                        "Dropped {} delayed messages of queue {}, as the queue is full",
+                        rejected,
+                        queue.name()
+                    );
+                }
+                self.dispatch(&queue);
+            }
+
+            if queue.definition().message_ttl.is_some() {
+                let expired = queue.expire();
+
+                self.dead_letter(&queue, expired, DeathReason::Expired);
+            }
+
+            if let Some(ack_timeout) = queue.definition().ack_timeout {
+                let timed_out = queue.take_timed_out();
+
+                if !timed_out.is_empty() {
+                    log::warn!(
+                        "Took back {} messages of queue {} left unacknowledged for longer than {}",
+                        timed_out.len(),
+                        queue.name(),
+                        humantime::format_duration(ack_timeout)
+                    );
+                    self.settle(timed_out.len());
+                    self.requeue(&queue, timed_out);
+                }
+            }
+        }
+    }
+
+    /// Returns the counters and the gauges of the broker.
+    pub fn metrics(&self) -> &Registry {
+        &self.metrics
+    }
+
+    /// Returns the connections open by the clients.
+    pub fn connections(&self) -> &Connections {
+        &self.connections
+    }
+
+    /// Returns the connections and the channels open by each user.
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    /// Returns every queue declared, sorted by virtual host and name.
+    pub fn queues(&self) -> Vec<Arc<Queue>> {
+        let mut queues: Vec<_> = self.queues.lock().unwrap().values().cloned().collect();
+
+        queues.sort_by(|a, b| (a.vhost(), a.name()).cmp(&(b.vhost(), b.name())));
+        queues
+    }
+
+    /// Returns the queue named `name` of the virtual host `vhost`, if it's declared.
+    pub fn queue(&self, vhost: &str, name: &str) -> Option<Arc<Queue>> {
+        self.queues
+            .lock()
+            .unwrap()
+            .get(&(vhost.to_string(), name.to_string()))
+            .cloned()
+    }
+
+    /// Returns the queues of the virtual host `vhost` which a message published to the topic
+    /// exchange with `routing_key` is routed to, sorted by name.
+    pub fn route(&self, vhost: &str, routing_key: &str) -> Vec<Arc<Queue>> {
+        self.queues()
+            .into_iter()
+            .filter(|queue| {
+                queue.vhost() == vhost
+                    && queue
+                        .definition()
+                        .bindings
+                        .iter()
+                        .any(|binding| binding.matches(routing_key))
+            })
+            .collect()
+    }
+
+    /// Returns the queue of the virtual host `vhost` receiving the messages whose routing key
+    /// matches no queue, if there is one. Only the queue namespace defines one, for the default
+    /// virtual host.
+    pub fn default_queue(&self, vhost: &str) -> Option<Arc<Queue>> {
+        if vhost != DEFAULT_VHOST {
+            return None;
+        }
+        let name = self.default_queue.read().unwrap().clone()?;
+
+        self.queue(vhost, &name)
+    }
+
+    /// Declares the queue defined by `definition` in the virtual host `vhost`, as a non-durable
+    /// queue if the broker runs in memory only, and with the default time-to-live of the messages
+    /// if it defines none. If a queue with the same name is already declared there, it's returned
+    /// instead.
+    pub fn declare(&self, vhost: &str, mut definition: QueueDefinition) -> Arc<Queue> {
+        if self.memory_only {
+            definition.durable = false;
+        }
+        if definition.message_ttl.is_none() {
+            definition.message_ttl = *self.default_message_ttl.read().unwrap();
+        }
+
+        self.queues
+            .lock()
+            .unwrap()
+            .entry((vhost.to_string(), definition.name.clone()))
+            .or_insert_with(|| {
+                let counters = self.metrics.queue_counters();
+                Arc::new(Queue::new(vhost.to_string(), definition, counters))
+            })
+            .clone()
+    }
+
+    /// Drops the messages waiting in `queue`, the delayed ones included, returning their number.
+    /// The messages delivered to the consumers are left to them.
+    pub fn purge(&self, queue: &Queue) -> usize {
+        let purged = queue.purge();
+
+        self.forget(purged.iter().filter_map(|message| message.stored));
+        purged.len()
+    }
+
+    /// Deletes `queue` along with its messages, returning their number. A queue with consumers is
+    /// only deleted if `force` is set, its consumers being cancelled. The messages the consumers
+    /// left unacknowledged are dropped once they're handed back.
+    pub fn delete(&self, queue: &Arc<Queue>, force: bool) -> Result<usize, InUse> {
+        {
+            let mut queues = self.queues.lock().unwrap();
+            let consumers = queue.consumer_count();
+
+            if consumers > 0 && !force {
+                return Err(InUse(consumers));
+            }
+
+            let key = (queue.vhost().to_string(), queue.name().to_string());
+            if queues.get(&key).is_some_and(|q| Arc::ptr_eq(q, queue)) {
+                queues.remove(&key);
+            }
+        }
+
+        let (messages, consumers) = queue.delete();
+        for consumer in consumers {
+            consumer.cancel();
+        }
+        self.forget(messages.iter().filter_map(|message| message.stored));
+
+        Ok(messages.len())
+    }
+
+    /// Publishes a message to `queue`, then pushes the messages of the queue to its consumers.
+    /// `false` is returned if the queue holds its maximum length and rejects the message.
+    ///
+    /// A message whose identifier was already published within the deduplication window of the
+    /// queue is discarded, as if it was published.
+    ///
+    /// When the delayed messages are enabled, a message published with a delay is stored right
+    /// away, but it's only pushed to the queue once it's due, by the expiry thread.
+    pub fn publish(&self, queue: &Arc<Queue>, mut message: Message) -> bool {
+        if queue.is_duplicate(&message) {
+            log::debug!(
+                "Discarded a message of queue {} already published with the identifier {}",
+                queue.name(),
+                message.properties.message_id.as_deref().unwrap_or_default()
+            );
+            return true;
+        }
+
+        // The size is the one of the body published, before it's compressed.
+        let size = message.body.len() as u64;
+        if *self.enable_delayed.read().unwrap() {
+            if let Some(delay) = message.delay() {
+                self.store(queue, &mut message);
+                queue.delay(message, Instant::now() + delay);
+                self.metrics.published.inc();
+                queue.counters().published.inc();
+                queue.counters().message_sizes.observe(size);
+
+                return true;
+            }
+        }
+
+        let message_id = message.properties.message_id.clone();
+        if !self.push(queue, message) {
+            if let Some(message_id) = message_id {
+                queue.forget_id(&message_id);
+            }
+            return false;
+        }
+        self.metrics.published.inc();
+        queue.counters().published.inc();
+        queue.counters().message_sizes.observe(size);
+        self.dispatch(queue);
+
+        true
+    }
+
+    /// Pushes the messages of `queue` to its consumers. The expired messages are dead-lettered.
+    pub fn dispatch(&self, queue: &Arc<Queue>) {
+        let dispatched = queue.dispatch(|message| self.load(queue, message), &self.unacked);
+
+        self.metrics.delivered.add(dispatched.delivered);
+        queue.counters().consumed.add(dispatched.delivered);
+        self.forget(dispatched.settled);
+        self.dead_letter(queue, dispatched.expired, DeathReason::Expired);
+    }
+
+    /// Takes the message at the head of `queue`, for a client getting it without consuming the
+    /// queue. The message is settled right away if `no_ack` is set. The expired messages are
+    /// dead-lettered.
+    ///
+    /// Unless `no_ack` is set, no message is taken once the broker holds the maximum number of
+    /// unacknowledged messages.
+    pub fn get(&self, queue: &Arc<Queue>, no_ack: bool) -> Option<Message> {
+        if !no_ack && !self.unacked.acquire() {
+            return None;
+        }
+        let (mut message, expired) = queue.pop();
+
+        if let Some(popped) = &mut message {
+            if !self.load(queue, popped) {
+                message = None;
+            }
+        }
+        if message.is_none() && !no_ack {
+            self.unacked.release(1);
+        }
+        if message.is_some() {
+            self.metrics.delivered.inc();
+            queue.counters().consumed.inc();
+        }
+        if no_ack {
+            self.forget(message.iter().filter_map(|message| message.stored));
+        }
+        self.dead_letter(queue, expired, DeathReason::Expired);
+
+        if let Some(message) = &mut message {
+            message.decompress();
+        }
+        message
+    }
+
+    /// Settles messages acknowledged by a consumer.
+    pub fn ack(&self, messages: Vec<Message>) {
+        self.settle(messages.len());
+        self.metrics.acknowledged.add(messages.len() as u64);
+        self.forget(messages.into_iter().filter_map(|message| message.stored));
+    }
+
+    /// Hands back messages of `queue` left unacknowledged by a consumer. They are put back in the
+    /// queue if `requeue` is set, and dead-lettered otherwise.
+    pub fn reject(&self, queue: &Arc<Queue>, messages: Vec<Message>, requeue: bool) {
+        self.settle(messages.len());
+        if requeue {
+            self.requeue(queue, messages);
+        } else {
+            self.dead_letter(queue, messages, DeathReason::Rejected);
+        }
+    }
+
+    /// Stops counting `settled` messages as unacknowledged. If the maximum number of
+    /// unacknowledged messages held back a delivery meanwhile, every queue is pushed to its
+    /// consumers again.
+    fn settle(&self, settled: usize) {
+        if self.unacked.release(settled as u64) {
+            for queue in self.queues() {
+                self.dispatch(&queue);
+            }
+        }
+    }
+
+    /// Puts messages back in `queue` for another delivery, compressing their body back. The
+    /// messages which were already redelivered the maximum number of times of the queue are
+    /// dead-lettered instead, and the messages of a deleted queue are dropped.
+    fn requeue(&self, queue: &Arc<Queue>, messages: Vec<Message>) {
+        if queue.is_deleted() {
+            self.forget(messages.iter().filter_map(|message| message.stored));
+            return;
+        }
+
+        let (exceeded, mut requeued): (_, Vec<_>) = match queue.definition().max_redeliveries {
+            Some(max) => messages
+                .into_iter()
+                .partition(|message| message.redeliveries >= max),
+            None => (Vec::new(), messages),
+        };
+        let (compression, threshold_bytes) = *self.compression.read().unwrap();
+        for message in &mut requeued {
+            message.compress(compression, threshold_bytes);
+        }
+
+        queue.requeue(requeued);
+        self.dispatch(queue);
+        self.dead_letter(queue, exceeded, DeathReason::DeliveryLimit);
+    }
+
+    /// Republishes messages of `queue` to its dead-letter queue. They are dropped if the queue has
+    /// no dead-letter queue, or if they expire or overflow towards a queue they were already
+    /// dead-lettered from, as they would go around a cycle of queues forever.
+    fn dead_letter(&self, queue: &Queue, mut messages: Vec<Message>, reason: DeathReason) {
+        // The bodies are read back before the messages are removed from the storage.
+        if queue.definition().dead_letter.is_some() {
+            messages.retain_mut(|message| self.load(queue, message));
+        }
+        if messages.is_empty() {
+            return;
+        }
+        self.metrics.dead_lettered.add(messages.len() as u64);
+        self.forget(messages.iter().filter_map(|message| message.stored));
+
+        let config = match &queue.definition().dead_letter {
+            Some(config) => config,
+            None => return,
+        };
+        let target = match self.queue(queue.vhost(), &config.queue) {
+            Some(target) => target,
+            None => {
+                log::warn!(
+                    "Dropped {} messages of queue {}, as its dead-letter queue {} is not declared",
+                    messages.len(),
+                    queue.name(),
+                    config.queue
+                );
+                return;
+            }
+        };
+
+        let mut rejected = 0;
+        for mut message in messages {
+            if matches!(reason, DeathReason::Expired | DeathReason::MaxLength)
+                && message.deaths.iter().any(|death| death == target.name())
+            {
+                log::warn!(
+                    "Dropped a message of queue {} going around a cycle of dead-letter queues",
+                    queue.name()
+                );
+                continue;
+            }
+
+            message.record_death(queue.name(), reason);
+            if let Some(routing_key) = &config.routing_key {
+                message.routing_key = routing_key.clone();
+            }
+            if !self.push(&target, message) {
+                rejected += 1;
+            }
+        }
+        if rejected > 0 {
+            log::warn!(
+                "Dropped {} messages of queue {}, as its dead-letter queue {} is full",
+                rejected,
+                queue.name(),
+                target.name()
+            );
+        }
+
+        self.dispatch(&target);
+    }
+
+    /// Appends a message to `queue`, storing it first if the queue is durable. If it can't be
+    /// stored, the message is only held in memory. Its body is compressed beforehand when it
+    /// reaches the compression threshold of the queue namespace.
+    ///
+    /// `false` is returned if the queue holds its maximum length and rejects the message. The
+    /// message taken from the head of a queue dropping it instead is dead-lettered.
+    fn push(&self, queue: &Queue, mut message: Message) -> bool {
+        self.store(queue, &mut message);
+        self.enqueue(queue, message)
+    }
+
+    /// Compresses the body of a message pushed to `queue` if it reaches the compression threshold,
+    /// then stores the message if the queue is durable.
+    fn store(&self, queue: &Queue, message: &mut Message) {
+        message.stored = None;
+
+        let (compression, threshold_bytes) = *self.compression.read().unwrap();
+        message.compress(compression, threshold_bytes);
+
+        if let Some(storage) = &self.storage {
+            if queue.definition().durable {
+                let sync = queue.definition().sync;
+                match storage.append(queue.vhost(), queue.name(), message, sync) {
+                    Ok(id) => message.stored = Some(id),
+                    Err(err) => log::error!(
+                        "Could not store a message of queue {}: {}",
+                        queue.name(),
+                        err
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Appends a message already stored by [`Broker::store`] to `queue`, returning `false` if the
+    /// queue rejects it.
+    fn enqueue(&self, queue: &Queue, message: Message) -> bool {
+        let stored = message.stored;
+        match queue.push(message) {
+            Ok(dropped) => {
+                self.dead_letter(queue, dropped, DeathReason::MaxLength);
+                true
+            }
+            Err(_) => {
+                self.forget(stored);
+                false
+            }
+        }
+    }
+
+    /// Reads back the body of a message of `queue` left in the storage, once it leaves the lazy
+    /// queue. A message whose body can't be read is dropped, `false` being returned.
+    fn load(&self, queue: &Queue, message: &mut Message) -> bool {
+        let (storage, id) = match (&self.storage, message.stored) {
+            (Some(storage), Some(id)) if message.paged_out => (storage, id),
+            _ => return true,
+        };
+
+        match storage.read_body(id) {
+            Ok((body, compression)) => {
+                message.body = body;
+                message.compression = compression;
+                message.paged_out = false;
+                true
+            }
+            Err(err) => {
+                log::error!(
+                    "Dropped a message of queue {}, as its body could not be read from the \
+                     storage: {}",
+                    queue.name(),
+                    err
+                );
+                self.forget(Some(id));
+                false
+            }
+        }
+    }
+
+    /// Removes the messages stored under `ids` from the storage, once they're settled.
+    fn forget<I: IntoIterator<Item = u64>>(&self, ids: I) {
+        let storage = match &self.storage {
+            Some(storage) => storage,
+            None => return,
+        };
+        let ids: Vec<u64> = ids.into_iter().collect();
+
+        if let Err(err) = storage.remove(&ids) {
+            log::error!(
+                "Could not remove {} messages from the storage: {}",
+                ids.len(),
+                err
+            );
+        }
+    }
+
+    /// Generates a unique name for a queue declared without a name.
+    pub fn generate_queue_name(&self) -> String {
+        let id = self.generated_names.fetch_add(1, Ordering::Relaxed);
+
+        format!("amq.gen-{}", id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DeadLetterConfig,
+        protocol::{FieldTable, FieldValue, Properties},
+    };
+
+    fn broker(config: config::Queue) -> Broker {
+        Broker::new(&config, &[], &config::Metrics::default()).unwrap()
+    }
+
+    fn message(body: &[u8]) -> Message {
+        Message::new(
+            String::new(),
+            "tasks".into(),
+            Properties::default(),
+            body.to_vec(),
+        )
+    }
+
+    fn queue(broker: &Broker, name: &str) -> Arc<Queue> {
+        broker.queue(DEFAULT_VHOST, name).unwrap()
+    }
+
+    /// A consumer acknowledging the messages, which holds them until they're taken back.
+    #[derive(Default)]
+    struct Holder {
+        held: Mutex<Vec<(Instant, Message)>>,
+        cancelled: AtomicBool,
+    }
+
+    impl Holder {
+        fn held(&self) -> Vec<Message> {
+            let held = self.held.lock().unwrap();
+
+            held.iter().map(|(_, message)| message.clone()).collect()
+        }
+    }
+
+    impl Consumer for Holder {
+        fn deliver(&self, _queue: &Arc<Queue>, message: Message) -> Option<Message> {
+            self.held.lock().unwrap().push((Instant::now(), message));
+            None
+        }
+
+        fn requires_ack(&self) -> bool {
+            true
+        }
+
+        fn unacked(&self, _queue: &Arc<Queue>) -> (usize, Option<Instant>) {
+            let held = self.held.lock().unwrap();
+
+            (held.len(), held.iter().map(|(_, m)| m.enqueued_at()).min())
+        }
+
+        fn take_unacked(&self, _queue: &Arc<Queue>, deadline: Instant) -> Vec<Message> {
+            let mut held = self.held.lock().unwrap();
+            let (taken, kept) = held
+                .drain(..)
+                .partition(|(delivered_at, _)| *delivered_at < deadline);
+
+            *held = kept;
+            taken.into_iter().map(|(_, message)| message).collect()
+        }
+
+        fn selector(&self) -> Option<&Selector> {
+            None
+        }
+
+        fn cancel(&self) {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn expired_messages_are_dead_lettered() {
+        let broker = broker(config::Queue {
+            definitions: vec![
+                QueueDefinition {
+                    message_ttl: Some(Duration::from_millis(50)),
+                    dead_letter: Some(DeadLetterConfig {
+                        queue: "expired".into(),
+                        routing_key: None,
+                    }),
+                    ..QueueDefinition::new("tasks")
+                },
+                QueueDefinition::new("expired"),
+            ],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message(b"first")));
+        assert!(broker.publish(&tasks, message(b"second")));
+        broker.expire();
+        assert_eq!(tasks.message_count(), 2);
+
+        thread::sleep(Duration::from_millis(100));
+        broker.expire();
+        assert_eq!(tasks.message_count(), 0);
+
+        let expired = queue(&broker, "expired");
+        let (first, _) = expired.pop();
+        let first = first.unwrap();
+        assert_eq!(first.body, b"first");
+        assert_eq!(first.deaths, vec!["tasks".to_string()]);
+        assert_eq!(expired.pop().0.unwrap().body, b"second");
+    }
+
+    #[test]
+    fn expired_messages_are_dropped_without_dead_letter_queue() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition {
+                message_ttl: Some(Duration::from_millis(50)),
+                ..QueueDefinition::new("tasks")
+            }],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message(b"first")));
+        thread::sleep(Duration::from_millis(100));
+        assert!(broker.publish(&tasks, message(b"second")));
+        broker.expire();
+
+        assert_eq!(tasks.message_count(), 1);
+        assert_eq!(tasks.pop().0.unwrap().body, b"second");
+        assert_eq!(broker.queues().len(), 1);
+    }
+
+    #[test]
+    fn messages_left_unacknowledged_are_redelivered_to_another_consumer() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition {
+                ack_timeout: Some(Duration::from_millis(50)),
+                ..QueueDefinition::new("tasks")
+            }],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        let stuck = Arc::new(Holder::default());
+        let other = Arc::new(Holder::default());
+        assert!(tasks.add_consumer(stuck.clone(), || ()));
+        assert!(tasks.add_consumer(other.clone(), || ()));
+
+        assert!(broker.publish(&tasks, message(b"task")));
+        assert_eq!(stuck.held().len(), 1);
+        broker.expire();
+        assert_eq!(stuck.held().len(), 1);
+
+        thread::sleep(Duration::from_millis(100));
+        broker.expire();
+        assert!(stuck.held().is_empty());
+        let redelivered = other.held();
+        assert_eq!(redelivered.len(), 1);
+        assert_eq!(redelivered[0].body, b"task");
+        assert!(redelivered[0].redelivered);
+        assert_eq!(redelivered[0].redeliveries, 1);
+    }
+
+    #[test]
+    fn messages_redelivered_too_many_times_are_dead_lettered() {
+        let broker = broker(config::Queue {
+            definitions: vec![
+                QueueDefinition {
+                    ack_timeout: Some(Duration::from_millis(20)),
+                    max_redeliveries: Some(1),
+                    dead_letter: Some(DeadLetterConfig {
+                        queue: "dead".into(),
+                        routing_key: None,
+                    }),
+                    ..QueueDefinition::new("tasks")
+                },
+                QueueDefinition::new("dead"),
+            ],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        let stuck = Arc::new(Holder::default());
+        assert!(tasks.add_consumer(stuck.clone(), || ()));
+
+        assert!(broker.publish(&tasks, message(b"task")));
+        thread::sleep(Duration::from_millis(50));
+        broker.expire();
+        assert_eq!(stuck.held()[0].redeliveries, 1);
+
+        thread::sleep(Duration::from_millis(50));
+        broker.expire();
+        assert!(stuck.held().is_empty());
+        let dead = queue(&broker, "dead").pop().0.unwrap();
+        assert_eq!(dead.body, b"task");
+        assert_eq!(dead.deaths, vec!["tasks".to_string()]);
+    }
+
+    #[test]
+    fn heads_dropped_by_a_full_queue_are_dead_lettered() {
+        let broker = broker(config::Queue {
+            definitions: vec![
+                QueueDefinition {
+                    max_length: Some(1),
+                    overflow: config::OverflowBehavior::DropHead,
+                    dead_letter: Some(DeadLetterConfig {
+                        queue: "dropped".into(),
+                        routing_key: Some("overflow".into()),
+                    }),
+                    ..QueueDefinition::new("tasks")
+                },
+                QueueDefinition::new("dropped"),
+            ],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message(b"first")));
+        assert!(broker.publish(&tasks, message(b"second")));
+
+        assert_eq!(tasks.pop().0.unwrap().body, b"second");
+        let dropped = queue(&broker, "dropped").pop().0.unwrap();
+        assert_eq!(dropped.body, b"first");
+        assert_eq!(dropped.routing_key, "overflow");
+    }
+
+    #[test]
+    fn full_queue_rejecting_the_publications_keeps_its_messages() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition {
+                max_length: Some(1),
+                ..QueueDefinition::new("tasks")
+            }],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message(b"first")));
+        assert!(!broker.publish(&tasks, message(b"second")));
+
+        assert_eq!(tasks.message_count(), 1);
+        assert_eq!(tasks.pop().0.unwrap().body, b"first");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_bodies_are_delivered_decompressed() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            compression: Some(Compression::Gzip),
+            compression_threshold_bytes: 100,
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        let large = vec![b'a'; 1000];
+
+        assert!(broker.publish(&tasks, message(&large)));
+        assert!(broker.publish(&tasks, message(b"small")));
+        let consumer = Arc::new(Holder::default());
+        assert!(tasks.add_consumer(consumer.clone(), || ()));
+        broker.dispatch(&tasks);
+
+        let delivered = consumer.held();
+        assert_eq!(delivered[0].body, large);
+        assert_eq!(delivered[0].compression, Compression::None);
+        assert_eq!(delivered[1].body, b"small");
+        assert!(broker.get(&tasks, true).is_none());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn bodies_are_held_compressed_past_the_threshold() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            compression: Some(Compression::Gzip),
+            compression_threshold_bytes: 100,
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        let large = vec![b'a'; 1000];
+
+        assert!(broker.publish(&tasks, message(&large)));
+        assert!(broker.publish(&tasks, message(b"small")));
+
+        let held = tasks.pop().0.unwrap();
+        assert_eq!(held.compression, Compression::Gzip);
+        assert!(held.body.len() < large.len());
+        assert_eq!(broker.get(&tasks, true).unwrap().body, b"small");
+    }
+
+    fn message_with_id(body: &[u8], message_id: &str) -> Message {
+        Message {
+            properties: Properties {
+                message_id: Some(message_id.into()),
+                ..Properties::default()
+            },
+            ..message(body)
+        }
+    }
+
+    #[test]
+    fn duplicates_are_discarded_within_the_window() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition {
+                dedup_window: Some(Duration::from_millis(100)),
+                ..QueueDefinition::new("tasks")
+            }],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message_with_id(b"first", "1")));
+        assert!(broker.publish(&tasks, message_with_id(b"again", "1")));
+        assert!(broker.publish(&tasks, message_with_id(b"second", "2")));
+        assert!(broker.publish(&tasks, message(b"no id")));
+        assert!(broker.publish(&tasks, message(b"no id")));
+
+        assert_eq!(tasks.message_count(), 4);
+        assert_eq!(tasks.pop().0.unwrap().body, b"first");
+    }
+
+    #[test]
+    fn duplicates_are_kept_past_the_window() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition {
+                dedup_window: Some(Duration::from_millis(50)),
+                ..QueueDefinition::new("tasks")
+            }],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message_with_id(b"first", "1")));
+        thread::sleep(Duration::from_millis(100));
+        assert!(broker.publish(&tasks, message_with_id(b"again", "1")));
+        assert!(broker.publish(&tasks, message_with_id(b"duplicate", "1")));
+
+        assert_eq!(tasks.message_count(), 2);
+        assert_eq!(tasks.pop().0.unwrap().body, b"first");
+        assert_eq!(tasks.pop().0.unwrap().body, b"again");
+    }
+
+    #[test]
+    fn duplicates_are_kept_without_window() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message_with_id(b"first", "1")));
+        assert!(broker.publish(&tasks, message_with_id(b"again", "1")));
+
+        assert_eq!(tasks.message_count(), 2);
+    }
+
+    #[test]
+    fn rejected_messages_are_not_remembered() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition {
+                max_length: Some(1),
+                dedup_window: Some(Duration::from_secs(60)),
+                ..QueueDefinition::new("tasks")
+            }],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message_with_id(b"first", "1")));
+        assert!(!broker.publish(&tasks, message_with_id(b"second", "2")));
+        tasks.pop();
+        assert!(broker.publish(&tasks, message_with_id(b"second", "2")));
+
+        assert_eq!(tasks.pop().0.unwrap().body, b"second");
+    }
+
+    #[test]
+    fn memory_only_declares_the_durable_queues_as_non_durable() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition {
+                durable: true,
+                ..QueueDefinition::new("tasks")
+            }],
+            memory_only: true,
+            ..config::Queue::default()
+        });
+
+        assert!(broker.is_memory_only());
+        assert!(!queue(&broker, "tasks").definition().durable);
+        let declared = broker.declare(
+            DEFAULT_VHOST,
+            QueueDefinition {
+                durable: true,
+                ..QueueDefinition::new("events")
+            },
+        );
+        assert!(!declared.definition().durable);
+    }
+
+    fn delayed_message(body: &[u8], delay_ms: i64) -> Message {
+        let mut headers = FieldTable::new();
+        headers.insert("x-delay".into(), FieldValue::I64(delay_ms));
+
+        Message {
+            properties: Properties {
+                headers: Some(headers),
+                ..Properties::default()
+            },
+            ..message(body)
+        }
+    }
+
+    #[test]
+    fn delayed_messages_are_queued_in_the_order_they_are_due() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            enable_delayed: true,
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, delayed_message(b"third", 150)));
+        assert!(broker.publish(&tasks, delayed_message(b"first", 50)));
+        assert!(broker.publish(&tasks, delayed_message(b"second", 100)));
+        assert!(broker.publish(&tasks, message(b"now")));
+        broker.expire();
+        assert_eq!(tasks.message_count(), 1);
+
+        thread::sleep(Duration::from_millis(200));
+        broker.expire();
+        let bodies: Vec<_> = std::iter::from_fn(|| tasks.pop().0)
+            .map(|message| message.body)
+            .collect();
+        assert_eq!(bodies, [&b"now"[..], b"first", b"second", b"third"]);
+    }
+
+    #[test]
+    fn delays_are_ignored_unless_enabled() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, delayed_message(b"delayed", 60_000)));
+
+        assert_eq!(tasks.message_count(), 1);
+    }
+
+    #[test]
+    fn deliveries_are_held_back_at_the_maximum_of_unacknowledged_messages() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks"), QueueDefinition::new("jobs")],
+            max_unacked_total: Some(2),
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        let jobs = queue(&broker, "jobs");
+        let tasks_consumer = Arc::new(Holder::default());
+        let jobs_consumer = Arc::new(Holder::default());
+        assert!(tasks.add_consumer(tasks_consumer.clone(), || ()));
+        assert!(jobs.add_consumer(jobs_consumer.clone(), || ()));
+
+        // The maximum is shared by the queues.
+        assert!(broker.publish(&tasks, message(b"first")));
+        assert!(broker.publish(&jobs, message(b"second")));
+        assert!(broker.publish(&jobs, message(b"third")));
+        assert_eq!(tasks_consumer.held().len(), 1);
+        assert_eq!(jobs_consumer.held().len(), 1);
+        assert_eq!(jobs.message_count(), 1);
+
+        // Acknowledging a message of another queue releases the delivery held back.
+        let acked = tasks_consumer.take_unacked(&tasks, Instant::now() + Duration::from_secs(1));
+        broker.ack(acked);
+        let held: Vec<_> = jobs_consumer.held().into_iter().map(|m| m.body).collect();
+        assert_eq!(held, vec![b"second".to_vec(), b"third".to_vec()]);
+        assert_eq!(jobs.message_count(), 0);
+    }
+
+    #[test]
+    fn messages_are_not_got_at_the_maximum_of_unacknowledged_messages() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            max_unacked_total: Some(1),
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        assert!(broker.publish(&tasks, message(b"first")));
+        assert!(broker.publish(&tasks, message(b"second")));
+        assert!(broker.publish(&tasks, message(b"third")));
+
+        let first = broker.get(&tasks, false).unwrap();
+        assert!(broker.get(&tasks, false).is_none());
+        // The messages settled right away are not held back.
+        assert_eq!(broker.get(&tasks, true).unwrap().body, b"second");
+
+        broker.ack(vec![first]);
+        assert_eq!(broker.get(&tasks, false).unwrap().body, b"third");
+    }
+
+    #[test]
+    fn lag_counts_the_messages_left_unacknowledged() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        let consumer = Arc::new(Holder::default());
+        assert!(tasks.add_consumer(consumer.clone(), || ()));
+
+        assert!(broker.publish(&tasks, message(b"task")));
+        thread::sleep(Duration::from_millis(200));
+        let lag = tasks.lag();
+
+        assert_eq!(lag.ready, 0);
+        assert_eq!(lag.unacked, 1);
+        assert!(lag.oldest_age.unwrap() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn queues_without_a_ttl_inherit_the_default_one() {
+        let broker = broker(config::Queue {
+            definitions: vec![
+                QueueDefinition::new("tasks"),
+                QueueDefinition {
+                    message_ttl: Some(Duration::from_secs(5)),
+                    ..QueueDefinition::new("jobs")
+                },
+            ],
+            default_message_ttl: Some(Duration::from_secs(30)),
+            ..config::Queue::default()
+        });
+        let declared = broker.declare(DEFAULT_VHOST, QueueDefinition::new("declared"));
+
+        let ttl = |queue: &Queue| queue.definition().message_ttl;
+        assert_eq!(ttl(&queue(&broker, "tasks")), Some(Duration::from_secs(30)));
+        assert_eq!(ttl(&queue(&broker, "jobs")), Some(Duration::from_secs(5)));
+        assert_eq!(ttl(&declared), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn queues_keep_no_ttl_without_a_default_one() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            ..config::Queue::default()
+        });
+
+        assert_eq!(queue(&broker, "tasks").definition().message_ttl, None);
+    }
+
+    #[test]
+    fn messages_expire_after_the_default_ttl() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            default_message_ttl: Some(Duration::from_millis(50)),
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+
+        assert!(broker.publish(&tasks, message(b"task")));
+        thread::sleep(Duration::from_millis(100));
+        broker.expire();
+
+        assert_eq!(tasks.message_count(), 0);
+    }
+
+    #[test]
+    fn purge_drops_the_waiting_messages() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        for body in [&b"first"[..], b"second", b"third"] {
+            assert!(broker.publish(&tasks, message(body)));
+        }
+
+        assert_eq!(broker.purge(&tasks), 3);
+        assert_eq!(tasks.message_count(), 0);
+        assert_eq!(broker.purge(&tasks), 0);
+        assert!(broker.queue(DEFAULT_VHOST, "tasks").is_some());
+    }
+
+    #[test]
+    fn purge_leaves_the_delivered_messages_to_their_consumer() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        assert!(broker.publish(&tasks, message(b"first")));
+        let consumer = Arc::new(Holder::default());
+        assert!(tasks.add_consumer(consumer.clone(), || ()));
+        broker.dispatch(&tasks);
+        assert!(broker.publish(&tasks, message(b"second")));
+
+        assert_eq!(broker.purge(&tasks), 0);
+        assert_eq!(consumer.held().len(), 2);
+    }
+
+    #[test]
+    fn delete_removes_the_queue_along_with_its_messages() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        assert!(broker.publish(&tasks, message(b"first")));
+        assert!(broker.publish(&tasks, message(b"second")));
+
+        assert_eq!(broker.delete(&tasks, false).unwrap(), 2);
+        assert!(tasks.is_deleted());
+        assert!(broker.queue(DEFAULT_VHOST, "tasks").is_none());
+    }
+
+    #[test]
+    fn delete_of_a_consumed_queue_is_refused_unless_forced() {
+        let broker = broker(config::Queue {
+            definitions: vec![QueueDefinition::new("tasks")],
+            ..config::Queue::default()
+        });
+        let tasks = queue(&broker, "tasks");
+        assert!(broker.publish(&tasks, message(b"first")));
+        assert!(broker.publish(&tasks, message(b"second")));
+        // The messages are only pushed to the consumer once the queue is dispatched again.
+        let consumer = Arc::new(Holder::default());
+        assert!(tasks.add_consumer(consumer.clone(), || ()));
+
+        match broker.delete(&tasks, false) {
+            Err(InUse(consumers)) => assert_eq!(consumers, 1),
+            Ok(_) => panic!("the queue was deleted"),
+        }
+        assert!(!tasks.is_deleted());
+        assert!(!consumer.cancelled.load(Ordering::SeqCst));
+
+        assert_eq!(tasks.message_count(), 2);
+
+        assert_eq!(broker.delete(&tasks, true).unwrap(), 2);
+        assert!(tasks.is_deleted());
+        assert!(broker.queue(DEFAULT_VHOST, "tasks").is_none());
+        assert!(consumer.cancelled.load(Ordering::SeqCst));
+    }
+}
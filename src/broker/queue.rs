@@ -0,0 +1,1037 @@
+use super::{Message, Selector, UnackedCount};
+use crate::{
+    config::{OverflowBehavior, QueueDefinition},
+    metrics::QueueCounters,
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// A consumer of a queue, which the messages of the queue are pushed to.
+pub trait Consumer: Send + Sync {
+    /// Delivers a message of `queue` to the consumer. The message is handed back if the consumer
+    /// can't take it, for instance when it has too many messages left unacknowledged.
+    fn deliver(&self, queue: &Arc<Queue>, message: Message) -> Option<Message>;
+
+    /// Returns `true` if the consumer acknowledges the messages delivered to it. Otherwise, they
+    /// are settled as soon as they're delivered.
+    fn requires_ack(&self) -> bool;
+
+    /// Returns the number of messages of `queue` delivered to the consumer and still
+    /// unacknowledged, along with when the oldest of them was pushed to the queue.
+    fn unacked(&self, queue: &Arc<Queue>) -> (usize, Option<Instant>);
+
+    /// Takes back the messages of `queue` delivered to the consumer before `deadline` which are
+    /// still unacknowledged, in the order they were delivered.
+    fn take_unacked(&self, queue: &Arc<Queue>, deadline: Instant) -> Vec<Message>;
+
+    /// Returns the selector of the messages delivered to the consumer, or `None` if it takes
+    /// every message.
+    fn selector(&self) -> Option<&Selector>;
+
+    /// Tells the client that the consumer is cancelled by the broker, as its queue is deleted.
+    fn cancel(&self);
+}
+
+/// A queue of the broker.
+///
+/// A queue with a maximum priority delivers the messages with the highest priority first, the
+/// messages of a same priority being delivered in the order they were pushed. The messages with
+/// no priority have the priority 0, and the ones above the maximum have the maximum.
+///
+/// A queue with a single active consumer only pushes its messages to its oldest consumer, the
+/// others standing by until it's removed.
+///
+/// A lazy queue drops the body of its stored messages while they wait, the broker reading it
+/// back from the storage when they leave the queue.
+pub struct Queue {
+    /// The virtual host the queue belongs to.
+    vhost: String,
+    definition: QueueDefinition,
+    state: Mutex<State>,
+    counters: QueueCounters,
+    /// Whether the queue was deleted, so the messages handed back to it are dropped.
+    deleted: AtomicBool,
+}
+
+struct State {
+    /// The messages waiting in the queue, one list for each priority.
+    messages: Vec<VecDeque<Message>>,
+    consumers: VecDeque<Arc<dyn Consumer>>,
+    /// The identifiers of the messages published within the deduplication window of the queue.
+    seen: SeenIds,
+    /// The delayed messages, by when they're due then by the order they were published, which
+    /// are pushed to the queue once they're due.
+    delayed: BTreeMap<(Instant, u64), Message>,
+    /// The number of messages delayed so far, which orders the messages due at the same time.
+    delayed_count: u64,
+    /// The total size of the bodies of the messages waiting in the queue, as they were published.
+    bytes: u64,
+}
+
+/// How far behind the consumers of a queue are.
+pub struct Lag {
+    /// The number of messages waiting in the queue.
+    pub ready: usize,
+    /// The number of messages delivered to the consumers and still unacknowledged.
+    pub unacked: usize,
+    /// How long ago the oldest message waiting in the queue or left unacknowledged by a consumer
+    /// was pushed to the queue, or `None` if there's none.
+    pub oldest_age: Option<Duration>,
+}
+
+/// The identifiers of the messages published to a queue, along with when they were published.
+#[derive(Default)]
+struct SeenIds {
+    published_at: HashMap<String, Instant>,
+    /// The identifiers in the order they were published, so the oldest ones are evicted first.
+    order: VecDeque<(Instant, String)>,
+}
+
+impl Queue {
+    pub fn new(vhost: String, definition: QueueDefinition, counters: QueueCounters) -> Self {
+        let priorities = definition.max_priority.map_or(1, |max| max as usize + 1);
+
+        Self {
+            vhost,
+            definition,
+            state: Mutex::new(State {
+                messages: (0..priorities).map(|_| VecDeque::new()).collect(),
+                consumers: VecDeque::new(),
+                seen: SeenIds::default(),
+                delayed: BTreeMap::new(),
+                delayed_count: 0,
+                bytes: 0,
+            }),
+            counters,
+            deleted: AtomicBool::new(false),
+        }
+    }
+
+    pub fn vhost(&self) -> &str {
+        &self.vhost
+    }
+
+    pub fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    pub fn definition(&self) -> &QueueDefinition {
+        &self.definition
+    }
+
+    /// Returns the counters of the messages which went through the queue.
+    pub fn counters(&self) -> &QueueCounters {
+        &self.counters
+    }
+
+    /// Returns the number of messages waiting in the queue.
+    pub fn message_count(&self) -> usize {
+        self.state.lock().unwrap().len()
+    }
+
+    /// Returns how far behind the consumers of the queue are. The messages fetched by the clients
+    /// without consuming the queue are not counted among the unacknowledged ones.
+    pub fn lag(self: &Arc<Self>) -> Lag {
+        let (ready, oldest_ready, consumers) = {
+            let state = self.state.lock().unwrap();
+
+            // The requeued messages go back to the head, so the heads are the oldest messages.
+            let oldest_ready = state
+                .messages
+                .iter()
+                .filter_map(|messages| messages.front())
+                .map(|message| message.enqueued_at)
+                .min();
+            let consumers: Vec<_> = state.consumers.iter().cloned().collect();
+
+            (state.len(), oldest_ready, consumers)
+        };
+
+        let mut lag = Lag {
+            ready,
+            unacked: 0,
+            oldest_age: None,
+        };
+        let mut oldest = oldest_ready;
+        for consumer in consumers {
+            let (unacked, oldest_unacked) = consumer.unacked(self);
+
+            lag.unacked += unacked;
+            oldest = oldest.into_iter().chain(oldest_unacked).min();
+        }
+        lag.oldest_age = oldest.map(|oldest| oldest.elapsed());
+
+        lag
+    }
+
+    /// Returns the number of consumers of the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.state.lock().unwrap().consumers.len()
+    }
+
+    /// Returns `true` once the queue is deleted.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted.load(Ordering::SeqCst)
+    }
+
+    /// Adds a consumer to the queue, unless the queue already has its maximum number of
+    /// consumers. The messages are pushed to the consumers in turn, or only to the oldest one if
+    /// the queue has a single active consumer.
+    ///
+    /// `added` is called once the consumer is added, before any message is pushed to it.
+    pub fn add_consumer<F: FnOnce()>(&self, consumer: Arc<dyn Consumer>, added: F) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        if self.is_deleted() {
+            return false;
+        }
+
+        if let Some(max_consumers) = self.definition.max_consumers {
+            if state.consumers.len() >= max_consumers {
+                return false;
+            }
+        }
+
+        state.consumers.push_back(consumer);
+        added();
+
+        true
+    }
+
+    /// Removes a consumer from the queue. Once removed, no message is pushed to it anymore.
+    pub fn remove_consumer(&self, consumer: &Arc<dyn Consumer>) {
+        self.state
+            .lock()
+            .unwrap()
+            .consumers
+            .retain(|c| !Arc::ptr_eq(c, consumer));
+    }
+
+    /// Appends a message to the queue, starting its time-to-live.
+    ///
+    /// The queue is full when it holds its maximum length, or when the message would take it past
+    /// its maximum size. The message is then dropped if the queue rejects the publications once
+    /// full. Otherwise, the messages at the head of the queue are taken until there's room for it,
+    /// and they're returned. A message larger than the maximum size on its own is always dropped.
+    pub(super) fn push(&self, mut message: Message) -> Result<Vec<Message>, Full> {
+        let priority = self.priority(&message);
+        let mut state = self.state.lock().unwrap();
+        let mut dropped = Vec::new();
+
+        let max_length = self.definition.max_length;
+        let max_bytes = self.definition.max_length_bytes;
+        if max_bytes.is_some_and(|max_bytes| message.body_size > max_bytes) {
+            return Err(Full);
+        }
+        while max_length.is_some_and(|max_length| state.len() as u64 >= max_length)
+            || max_bytes.is_some_and(|max_bytes| state.bytes + message.body_size > max_bytes)
+        {
+            match self.definition.overflow {
+                OverflowBehavior::RejectPublish => return Err(Full),
+                OverflowBehavior::DropHead => match state.pop_head() {
+                    Some(head) => dropped.push(head),
+                    None => break,
+                },
+            }
+        }
+
+        message.enqueued_at = Instant::now();
+        message.expires_at = self
+            .definition
+            .message_ttl
+            .map(|ttl| message.enqueued_at + ttl);
+        if self.definition.lazy {
+            message.page_out();
+        }
+        state.push_back(priority, message);
+
+        Ok(dropped)
+    }
+
+    /// Holds a message published with a delay until `due`, when [`Queue::take_due`] hands it over
+    /// to be pushed to the queue.
+    pub(super) fn delay(&self, message: Message, due: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let count = state.delayed_count;
+
+        state.delayed.insert((due, count), message);
+        state.delayed_count += 1;
+    }
+
+    /// Takes the delayed messages which are due at `now`, in the order they're due, the messages
+    /// due at the same time being taken in the order they were published.
+    pub(super) fn take_due(&self, now: Instant) -> Vec<Message> {
+        let mut state = self.state.lock().unwrap();
+        let mut due = Vec::new();
+
+        while let Some(entry) = state.delayed.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            due.push(entry.remove());
+        }
+
+        due
+    }
+
+    /// Remembers the identifier of a message published to the queue, returning `true` if a
+    /// message with the same identifier was already published within the deduplication window of
+    /// the queue. The identifiers older than the window are evicted on the way.
+    pub(super) fn is_duplicate(&self, message: &Message) -> bool {
+        let (window, id) = match (self.definition.dedup_window, &message.properties.message_id) {
+            (Some(window), Some(id)) => (window, id),
+            _ => return false,
+        };
+        let now = Instant::now();
+        let seen = &mut self.state.lock().unwrap().seen;
+
+        while let Some((published_at, _)) = seen.order.front() {
+            if now.saturating_duration_since(*published_at) < window {
+                break;
+            }
+
+            let (published_at, id) = seen.order.pop_front().unwrap();
+            // The identifier may have been forgotten then published again since.
+            if seen.published_at.get(&id) == Some(&published_at) {
+                seen.published_at.remove(&id);
+            }
+        }
+
+        if seen.published_at.contains_key(id) {
+            return true;
+        }
+        seen.published_at.insert(id.clone(), now);
+        seen.order.push_back((now, id.clone()));
+
+        false
+    }
+
+    /// Forgets the identifier of a message which was not published in the end, so it can be
+    /// published again.
+    pub(super) fn forget_id(&self, message_id: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .seen
+            .published_at
+            .remove(message_id);
+    }
+
+    /// Puts messages back at the head of the queue, in the order they were delivered.
+    pub(super) fn requeue(&self, messages: Vec<Message>) {
+        let mut state = self.state.lock().unwrap();
+
+        for mut message in messages.into_iter().rev() {
+            message.redelivered = true;
+            message.redeliveries += 1;
+            if self.definition.lazy {
+                message.page_out();
+            }
+            state.push_front(self.priority(&message), message);
+        }
+    }
+
+    /// Takes every message of the queue, the delayed ones included, which are not delivered to a
+    /// consumer.
+    pub(super) fn purge(&self) -> Vec<Message> {
+        let mut state = self.state.lock().unwrap();
+        let mut purged: Vec<Message> = state
+            .messages
+            .iter_mut()
+            .flat_map(|m| m.drain(..))
+            .collect();
+
+        state.bytes = 0;
+        purged.extend(std::mem::take(&mut state.delayed).into_values());
+        purged
+    }
+
+    /// Marks the queue as deleted, taking its messages like [`Queue::purge`] along with its
+    /// consumers. No consumer can be added to the queue anymore.
+    pub(super) fn delete(&self) -> (Vec<Message>, Vec<Arc<dyn Consumer>>) {
+        let consumers = {
+            let mut state = self.state.lock().unwrap();
+
+            self.deleted.store(true, Ordering::SeqCst);
+            state.consumers.drain(..).collect()
+        };
+
+        (self.purge(), consumers)
+    }
+
+    /// Takes the message at the head of the queue. The expired messages met on the way are
+    /// returned along with it.
+    pub(super) fn pop(&self) -> (Option<Message>, Vec<Message>) {
+        let mut expired = Vec::new();
+        let message = self.state.lock().unwrap().next(&mut expired);
+
+        (message, expired)
+    }
+
+    /// Takes the expired messages at the head of the queue. As every message of the queue lives
+    /// for the same time-to-live, the messages of a priority expire in the order they were
+    /// pushed: the scan of each priority stops at its first message which is not expired.
+    pub(super) fn expire(&self) -> Vec<Message> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let mut expired = Vec::new();
+
+        for messages in &mut state.messages {
+            while messages
+                .front()
+                .is_some_and(|message| message.is_expired(now))
+            {
+                expired.extend(messages.pop_front());
+            }
+        }
+        state.bytes -= expired.iter().map(|message| message.body_size).sum::<u64>();
+
+        expired
+    }
+
+    /// Takes back the messages delivered to the consumers of the queue which are left
+    /// unacknowledged for longer than the acknowledgement timeout of the queue.
+    pub(super) fn take_timed_out(self: &Arc<Self>) -> Vec<Message> {
+        let deadline = match self
+            .definition
+            .ack_timeout
+            .and_then(|timeout| Instant::now().checked_sub(timeout))
+        {
+            Some(deadline) => deadline,
+            None => return Vec::new(),
+        };
+        let state = self.state.lock().unwrap();
+
+        state
+            .consumers
+            .iter()
+            .flat_map(|consumer| consumer.take_unacked(self, deadline))
+            .collect()
+    }
+
+    /// Pushes the messages of the queue to its consumers, until the queue is empty or no consumer
+    /// can take a message. The body of each message is loaded by `load` beforehand, the message
+    /// being skipped if it returns `false`.
+    ///
+    /// When a consumer has a selector, the messages no consumer takes are set aside rather than
+    /// stopping the delivery, so the following ones can go to the consumers selecting them. They
+    /// are put back at the head of the queue afterwards, in their order.
+    ///
+    /// The messages delivered to the consumers acknowledging them are counted in `unacked`, the
+    /// consumers being skipped once it reaches its maximum.
+    pub(super) fn dispatch<F>(self: &Arc<Self>, load: F, unacked: &UnackedCount) -> Dispatched
+    where
+        F: Fn(&mut Message) -> bool,
+    {
+        let mut dispatched = Dispatched::default();
+        let mut state = self.state.lock().unwrap();
+        let selective = state
+            .consumers
+            .iter()
+            .any(|consumer| consumer.selector().is_some());
+        let selects = |consumer: &Arc<dyn Consumer>, message: &Message| {
+            consumer
+                .selector()
+                .is_none_or(|selector| selector.matches(message))
+        };
+        let mut skipped = Vec::new();
+
+        'messages: while !state.consumers.is_empty() {
+            let mut message = match state.next(&mut dispatched.expired) {
+                Some(message) => message,
+                None => break,
+            };
+            if selective
+                && !state
+                    .consumers
+                    .iter()
+                    .any(|consumer| selects(consumer, &message))
+            {
+                skipped.push(message);
+                continue;
+            }
+            if !load(&mut message) {
+                continue;
+            }
+
+            // The active consumer is the oldest one, so it's the only one offered the messages.
+            let candidates = if self.definition.single_active_consumer {
+                1
+            } else {
+                state.consumers.len()
+            };
+            for _ in 0..candidates {
+                let consumer = if self.definition.single_active_consumer {
+                    state.consumers[0].clone()
+                } else {
+                    let consumer = state.consumers.pop_front().unwrap();
+                    state.consumers.push_back(consumer.clone());
+                    consumer
+                };
+                if !selects(&consumer, &message) {
+                    continue;
+                }
+
+                let requires_ack = consumer.requires_ack();
+                if requires_ack && !unacked.acquire() {
+                    continue;
+                }
+
+                let stored = message.stored;
+                message.decompress();
+                match consumer.deliver(self, message) {
+                    None => {
+                        dispatched.delivered += 1;
+                        if !requires_ack {
+                            dispatched.settled.extend(stored);
+                        }
+                        continue 'messages;
+                    }
+                    Some(rejected) => {
+                        if requires_ack {
+                            unacked.release(1);
+                        }
+                        message = rejected;
+                    }
+                }
+            }
+
+            if self.definition.lazy {
+                message.page_out();
+            }
+            if selective {
+                skipped.push(message);
+                continue;
+            }
+            state.push_front(self.priority(&message), message);
+            break;
+        }
+
+        for message in skipped.into_iter().rev() {
+            state.push_front(self.priority(&message), message);
+        }
+
+        dispatched
+    }
+
+    /// Returns the priority a message is queued with, which is always 0 unless the queue has a
+    /// maximum priority.
+    fn priority(&self, message: &Message) -> usize {
+        match self.definition.max_priority {
+            Some(max) => message.properties.priority.unwrap_or(0).min(max) as usize,
+            None => 0,
+        }
+    }
+}
+
+/// The error of a message pushed to a queue which holds its maximum length and rejects it.
+#[derive(Debug)]
+pub(super) struct Full;
+
+/// The error of a queue which can't be deleted, as it has consumers, whose number it holds.
+#[derive(Debug)]
+pub struct InUse(pub usize);
+
+/// What happened to the messages of a queue pushed to its consumers.
+#[derive(Default)]
+pub(super) struct Dispatched {
+    /// The number of messages delivered to a consumer.
+    pub delivered: u64,
+    /// The expired messages met on the way.
+    pub expired: Vec<Message>,
+    /// The storage identifiers of the messages settled by being delivered to a consumer which
+    /// doesn't acknowledge them.
+    pub settled: Vec<u64>,
+}
+
+impl State {
+    fn len(&self) -> usize {
+        self.messages.iter().map(VecDeque::len).sum()
+    }
+
+    fn push_back(&mut self, priority: usize, message: Message) {
+        self.bytes += message.body_size;
+        self.messages[priority].push_back(message);
+    }
+
+    fn push_front(&mut self, priority: usize, message: Message) {
+        self.bytes += message.body_size;
+        self.messages[priority].push_front(message);
+    }
+
+    /// Takes the message at the head of the lowest priority holding messages, to make room for
+    /// another one.
+    fn pop_head(&mut self) -> Option<Message> {
+        let message = self.messages.iter_mut().find_map(VecDeque::pop_front)?;
+
+        self.bytes -= message.body_size;
+        Some(message)
+    }
+
+    /// Takes the first message of the highest priority which is not expired, moving the expired
+    /// ones into `expired`.
+    fn next(&mut self, expired: &mut Vec<Message>) -> Option<Message> {
+        let now = Instant::now();
+
+        for messages in self.messages.iter_mut().rev() {
+            while let Some(message) = messages.pop_front() {
+                self.bytes -= message.body_size;
+                if message.is_expired(now) {
+                    expired.push(message);
+                } else {
+                    return Some(message);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        metrics::Registry,
+        protocol::{FieldTable, FieldValue, Properties},
+    };
+
+    /// A consumer taking every message delivered to it, among the ones its selector matches.
+    #[derive(Default)]
+    struct Taker {
+        delivered: Mutex<Vec<Message>>,
+        selector: Option<Selector>,
+    }
+
+    impl Taker {
+        fn taken(&self) -> Vec<String> {
+            let delivered = self.delivered.lock().unwrap();
+
+            delivered
+                .iter()
+                .map(|message| String::from_utf8(message.body.clone()).unwrap())
+                .collect()
+        }
+    }
+
+    impl Consumer for Taker {
+        fn deliver(&self, _queue: &Arc<Queue>, message: Message) -> Option<Message> {
+            self.delivered.lock().unwrap().push(message);
+            None
+        }
+
+        fn requires_ack(&self) -> bool {
+            false
+        }
+
+        fn unacked(&self, _queue: &Arc<Queue>) -> (usize, Option<Instant>) {
+            (0, None)
+        }
+
+        fn take_unacked(&self, _queue: &Arc<Queue>, _deadline: Instant) -> Vec<Message> {
+            Vec::new()
+        }
+
+        fn selector(&self) -> Option<&Selector> {
+            self.selector.as_ref()
+        }
+
+        fn cancel(&self) {}
+    }
+
+    fn queue(definition: QueueDefinition) -> Arc<Queue> {
+        Arc::new(Queue::new(
+            "/".into(),
+            definition,
+            Registry::new(Vec::new()).queue_counters(),
+        ))
+    }
+
+    fn message(body: &str, priority: Option<u8>) -> Message {
+        Message::new(
+            String::new(),
+            "tasks".into(),
+            Properties {
+                priority,
+                ..Properties::default()
+            },
+            body.as_bytes().to_vec(),
+        )
+    }
+
+    /// Pops every message of `queue`, returning their bodies in the order they're taken.
+    fn drain(queue: &Queue) -> Vec<String> {
+        std::iter::from_fn(|| queue.pop().0)
+            .map(|message| String::from_utf8(message.body).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn consumers_are_added_up_to_the_maximum() {
+        let queue = queue(QueueDefinition {
+            max_consumers: Some(2),
+            ..QueueDefinition::new("tasks")
+        });
+        let consumers: Vec<Arc<dyn Consumer>> =
+            (0..3).map(|_| Arc::new(Taker::default()) as _).collect();
+
+        assert!(queue.add_consumer(consumers[0].clone(), || ()));
+        assert!(queue.add_consumer(consumers[1].clone(), || ()));
+        let mut added = false;
+        assert!(!queue.add_consumer(consumers[2].clone(), || added = true));
+        assert!(!added);
+        assert_eq!(queue.consumer_count(), 2);
+
+        queue.remove_consumer(&consumers[0]);
+        assert!(queue.add_consumer(consumers[2].clone(), || ()));
+        assert_eq!(queue.consumer_count(), 2);
+    }
+
+    #[test]
+    fn consumers_are_unbounded_without_maximum() {
+        let queue = queue(QueueDefinition::new("tasks"));
+
+        for _ in 0..100 {
+            assert!(queue.add_consumer(Arc::new(Taker::default()), || ()));
+        }
+        assert_eq!(queue.consumer_count(), 100);
+    }
+
+    #[test]
+    fn messages_are_delivered_by_priority_then_in_order() {
+        let queue = queue(QueueDefinition {
+            max_priority: Some(5),
+            ..QueueDefinition::new("tasks")
+        });
+
+        for (body, priority) in [
+            ("low 1", Some(1)),
+            ("high 1", Some(5)),
+            ("none 1", None),
+            ("low 2", Some(1)),
+            ("above 1", Some(9)),
+            ("zero 1", Some(0)),
+            ("high 2", Some(5)),
+        ] {
+            queue.push(message(body, priority)).unwrap();
+        }
+
+        assert_eq!(
+            drain(&queue),
+            ["high 1", "above 1", "high 2", "low 1", "low 2", "none 1", "zero 1"]
+        );
+    }
+
+    #[test]
+    fn priorities_are_ignored_without_maximum() {
+        let queue = queue(QueueDefinition::new("tasks"));
+
+        for (body, priority) in [("first", Some(1)), ("second", Some(9)), ("third", None)] {
+            queue.push(message(body, priority)).unwrap();
+        }
+
+        assert_eq!(drain(&queue), ["first", "second", "third"]);
+    }
+
+    #[test]
+    fn requeued_messages_keep_their_priority() {
+        let queue = queue(QueueDefinition {
+            max_priority: Some(2),
+            ..QueueDefinition::new("tasks")
+        });
+
+        queue.push(message("low", Some(0))).unwrap();
+        queue.push(message("high", Some(2))).unwrap();
+        let high = queue.pop().0.unwrap();
+        queue.push(message("middle", Some(1))).unwrap();
+        queue.requeue(vec![high]);
+
+        assert_eq!(drain(&queue), ["high", "middle", "low"]);
+    }
+
+    #[test]
+    fn full_queue_rejects_the_publications() {
+        let queue = queue(QueueDefinition {
+            max_length: Some(2),
+            ..QueueDefinition::new("tasks")
+        });
+
+        assert!(queue.push(message("first", None)).unwrap().is_empty());
+        assert!(queue.push(message("second", None)).unwrap().is_empty());
+        assert!(queue.push(message("third", None)).is_err());
+
+        assert_eq!(drain(&queue), ["first", "second"]);
+    }
+
+    #[test]
+    fn full_queue_drops_its_head() {
+        let queue = queue(QueueDefinition {
+            max_length: Some(2),
+            overflow: OverflowBehavior::DropHead,
+            ..QueueDefinition::new("tasks")
+        });
+
+        assert!(queue.push(message("first", None)).unwrap().is_empty());
+        assert!(queue.push(message("second", None)).unwrap().is_empty());
+        let dropped = queue.push(message("third", None)).unwrap();
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].body, b"first");
+        assert_eq!(drain(&queue), ["second", "third"]);
+    }
+
+    #[test]
+    fn full_priority_queue_drops_the_head_of_the_lowest_priority() {
+        let queue = queue(QueueDefinition {
+            max_length: Some(2),
+            max_priority: Some(1),
+            overflow: OverflowBehavior::DropHead,
+            ..QueueDefinition::new("tasks")
+        });
+
+        queue.push(message("high", Some(1))).unwrap();
+        queue.push(message("low", Some(0))).unwrap();
+        let dropped = queue.push(message("new", Some(1))).unwrap();
+
+        assert_eq!(dropped[0].body, b"low");
+        assert_eq!(drain(&queue), ["high", "new"]);
+    }
+
+    #[test]
+    fn queue_full_of_bytes_rejects_the_publications_below_its_maximum_length() {
+        let queue = queue(QueueDefinition {
+            max_length: Some(10),
+            max_length_bytes: Some(10),
+            ..QueueDefinition::new("tasks")
+        });
+
+        assert!(queue.push(message("first", None)).unwrap().is_empty());
+        assert!(queue.push(message("four", None)).unwrap().is_empty());
+        assert!(queue.push(message("second", None)).is_err());
+        assert!(queue.push(message("a", None)).unwrap().is_empty());
+
+        assert_eq!(drain(&queue), ["first", "four", "a"]);
+    }
+
+    #[test]
+    fn queue_at_its_maximum_length_rejects_the_publications_below_its_maximum_size() {
+        let queue = queue(QueueDefinition {
+            max_length: Some(2),
+            max_length_bytes: Some(1000),
+            ..QueueDefinition::new("tasks")
+        });
+
+        assert!(queue.push(message("first", None)).unwrap().is_empty());
+        assert!(queue.push(message("second", None)).unwrap().is_empty());
+        assert!(queue.push(message("third", None)).is_err());
+
+        assert_eq!(drain(&queue), ["first", "second"]);
+    }
+
+    #[test]
+    fn queue_full_of_bytes_drops_its_head_until_there_is_room() {
+        let queue = queue(QueueDefinition {
+            max_length_bytes: Some(12),
+            overflow: OverflowBehavior::DropHead,
+            ..QueueDefinition::new("tasks")
+        });
+
+        queue.push(message("one", None)).unwrap();
+        queue.push(message("two", None)).unwrap();
+        queue.push(message("three", None)).unwrap();
+        let dropped = queue.push(message("eleven", None)).unwrap();
+
+        let dropped: Vec<_> = dropped.iter().map(|message| &message.body[..]).collect();
+        assert_eq!(dropped, [&b"one"[..], b"two"]);
+        assert_eq!(drain(&queue), ["three", "eleven"]);
+    }
+
+    #[test]
+    fn message_larger_than_the_maximum_size_is_dropped_without_dropping_the_head() {
+        let queue = queue(QueueDefinition {
+            max_length_bytes: Some(4),
+            overflow: OverflowBehavior::DropHead,
+            ..QueueDefinition::new("tasks")
+        });
+
+        queue.push(message("one", None)).unwrap();
+        assert!(queue.push(message("large", None)).is_err());
+
+        assert_eq!(drain(&queue), ["one"]);
+    }
+
+    /// Pushes the messages of `queue` to its consumers, loading every message.
+    fn dispatch(queue: &Arc<Queue>) -> Dispatched {
+        queue.dispatch(|_| true, &UnackedCount::default())
+    }
+
+    #[test]
+    fn single_active_consumer_takes_every_message() {
+        let queue = queue(QueueDefinition {
+            single_active_consumer: true,
+            ..QueueDefinition::new("tasks")
+        });
+        let active = Arc::new(Taker::default());
+        let standby = Arc::new(Taker::default());
+        assert!(queue.add_consumer(active.clone(), || ()));
+        assert!(queue.add_consumer(standby.clone(), || ()));
+
+        for body in ["first", "second", "third"] {
+            queue.push(message(body, None)).unwrap();
+        }
+
+        assert_eq!(dispatch(&queue).delivered, 3);
+        assert_eq!(active.taken(), ["first", "second", "third"]);
+        assert!(standby.taken().is_empty());
+    }
+
+    #[test]
+    fn single_active_consumer_fails_over_to_the_oldest_standby() {
+        let queue = queue(QueueDefinition {
+            single_active_consumer: true,
+            ..QueueDefinition::new("tasks")
+        });
+        let active: Arc<dyn Consumer> = Arc::new(Taker::default());
+        let first_standby = Arc::new(Taker::default());
+        let second_standby = Arc::new(Taker::default());
+        assert!(queue.add_consumer(active.clone(), || ()));
+        assert!(queue.add_consumer(first_standby.clone(), || ()));
+        assert!(queue.add_consumer(second_standby.clone(), || ()));
+        queue.push(message("first", None)).unwrap();
+        dispatch(&queue);
+
+        queue.remove_consumer(&active);
+        queue.push(message("second", None)).unwrap();
+        queue.push(message("third", None)).unwrap();
+        dispatch(&queue);
+
+        assert_eq!(first_standby.taken(), ["second", "third"]);
+        assert!(second_standby.taken().is_empty());
+    }
+
+    #[test]
+    fn consumers_take_the_messages_in_turn_without_single_active_consumer() {
+        let queue = queue(QueueDefinition::new("tasks"));
+        let first = Arc::new(Taker::default());
+        let second = Arc::new(Taker::default());
+        assert!(queue.add_consumer(first.clone(), || ()));
+        assert!(queue.add_consumer(second.clone(), || ()));
+
+        for body in ["first", "second", "third"] {
+            queue.push(message(body, None)).unwrap();
+        }
+        dispatch(&queue);
+
+        assert_eq!(first.taken(), ["first", "third"]);
+        assert_eq!(second.taken(), ["second"]);
+    }
+
+    #[test]
+    fn delayed_messages_are_due_in_order() {
+        let queue = queue(QueueDefinition::new("tasks"));
+        let now = Instant::now();
+
+        queue.delay(message("late", None), now + Duration::from_secs(3));
+        queue.delay(message("early", None), now + Duration::from_secs(1));
+        queue.delay(message("middle 1", None), now + Duration::from_secs(2));
+        queue.delay(message("middle 2", None), now + Duration::from_secs(2));
+
+        assert!(queue.take_due(now).is_empty());
+        let bodies = |due: Vec<Message>| -> Vec<String> {
+            due.into_iter()
+                .map(|message| String::from_utf8(message.body).unwrap())
+                .collect()
+        };
+        assert_eq!(
+            bodies(queue.take_due(now + Duration::from_secs(1))),
+            ["early"]
+        );
+        assert_eq!(
+            bodies(queue.take_due(now + Duration::from_secs(5))),
+            ["middle 1", "middle 2", "late"]
+        );
+        assert!(queue.take_due(now + Duration::from_secs(5)).is_empty());
+    }
+
+    #[test]
+    fn delayed_messages_are_not_ready() {
+        let queue = queue(QueueDefinition::new("tasks"));
+
+        queue.delay(
+            message("delayed", None),
+            Instant::now() + Duration::from_secs(1),
+        );
+
+        assert_eq!(queue.message_count(), 0);
+        assert!(queue.pop().0.is_none());
+        assert_eq!(queue.purge().len(), 1);
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> FieldTable {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    FieldValue::LongStr(value.as_bytes().to_vec()),
+                )
+            })
+            .collect()
+    }
+
+    fn typed_message(body: &str, kind: &str) -> Message {
+        let mut message = message(body, None);
+        message.properties.headers = Some(headers(&[("type", kind)]));
+        message
+    }
+
+    fn selecting(kind: &str) -> Arc<Taker> {
+        Arc::new(Taker {
+            selector: Selector::from_table(&headers(&[("type", kind)])),
+            ..Taker::default()
+        })
+    }
+
+    #[test]
+    fn selected_messages_go_to_the_consumers_selecting_them() {
+        let queue = queue(QueueDefinition::new("tasks"));
+        let invoices = selecting("invoice");
+        let receipts = selecting("receipt");
+        assert!(queue.add_consumer(invoices.clone(), || ()));
+        assert!(queue.add_consumer(receipts.clone(), || ()));
+
+        queue.push(typed_message("receipt 1", "receipt")).unwrap();
+        queue.push(typed_message("invoice 1", "invoice")).unwrap();
+        queue.push(typed_message("order 1", "order")).unwrap();
+        queue.push(message("untyped", None)).unwrap();
+        queue.push(typed_message("invoice 2", "invoice")).unwrap();
+        dispatch(&queue);
+
+        assert_eq!(invoices.taken(), ["invoice 1", "invoice 2"]);
+        assert_eq!(receipts.taken(), ["receipt 1"]);
+        assert_eq!(drain(&queue), ["order 1", "untyped"]);
+    }
+
+    #[test]
+    fn messages_no_consumer_selects_are_left_to_the_others() {
+        let queue = queue(QueueDefinition::new("tasks"));
+        let invoices = selecting("invoice");
+        let anything = Arc::new(Taker::default());
+        assert!(queue.add_consumer(invoices.clone(), || ()));
+        assert!(queue.add_consumer(anything.clone(), || ()));
+
+        queue.push(typed_message("order 1", "order")).unwrap();
+        queue.push(typed_message("invoice 1", "invoice")).unwrap();
+        dispatch(&queue);
+
+        assert_eq!(anything.taken(), ["order 1"]);
+        assert_eq!(invoices.taken(), ["invoice 1"]);
+        assert_eq!(queue.message_count(), 0);
+    }
+}
@@ -0,0 +1,604 @@
+//! The storage of the messages of the durable queues.
+//!
+//! The messages are appended to segment files, named after their sequence number, along with the
+//! records of the messages settled since then. When the broker starts, the segments are replayed
+//! in order to recover the messages which were not settled. A new segment is started when the
+//! current one grows too large, and the oldest segments are deleted once every message they hold
+//! is settled.
+//!
+//! The messages of the lazy queues are recovered without their body, which is read back from
+//! its record when the message is delivered.
+
+use super::Message;
+use crate::{
+    config::{Compression, StorageConfig, DEFAULT_VHOST},
+    protocol::{self, ContentHeader, Decoder, Encoder, CLASS_BASIC},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The size a segment grows to before a new one is started.
+const SEGMENT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The extension of the segment files.
+const SEGMENT_EXTENSION: &str = "segment";
+
+/// How often the storage is opened again while waiting for it to be ready.
+const READY_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The record of a message appended to a queue.
+const RECORD_APPEND: u8 = 1;
+
+/// The record of a message settled, which must not be recovered anymore.
+const RECORD_REMOVE: u8 = 2;
+
+/// The record of a message appended to a queue, whose body is compressed. The compression is
+/// written after the name of the queue.
+const RECORD_APPEND_COMPRESSED: u8 = 3;
+
+/// The record of a message appended to a queue of a virtual host other than the default one. The
+/// name of the virtual host is written before the name of the queue, and the compression after
+/// it, `0` meaning that the body is not compressed.
+const RECORD_APPEND_VHOST: u8 = 4;
+
+/// A message recovered from the storage, along with the virtual host and the name of its queue.
+pub type Recovered = (String, String, Message);
+
+/// The storage of the messages of the durable queues.
+pub struct Storage {
+    path: PathBuf,
+    sync: bool,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// The segment the records are appended to.
+    current: u64,
+    file: File,
+    size: u64,
+    /// The number of messages of each segment which are not settled yet.
+    segments: BTreeMap<u64, usize>,
+    /// Where each message which is not settled yet is stored.
+    locations: HashMap<u64, Location>,
+    next_id: u64,
+}
+
+/// Where the record of a message is stored.
+#[derive(Copy, Clone)]
+struct Location {
+    segment: u64,
+    /// The offset of the record in its segment.
+    offset: u64,
+}
+
+impl Storage {
+    /// Opens the storage, creating its directory if needed. The messages which were not settled
+    /// are returned in the order they were appended, along with the virtual host and the name of
+    /// their queue. The messages of the queues for which `is_lazy` returns `true` are returned
+    /// without their body.
+    ///
+    /// The records are always appended to a new segment, so opening the storage checks that its
+    /// directory is writable. When the storage waits to be ready, its directory is not created,
+    /// and opening it is retried until it succeeds or the wait is over.
+    pub fn open<F>(config: &StorageConfig, is_lazy: F) -> io::Result<(Self, Vec<Recovered>)>
+    where
+        F: Fn(&str, &str) -> bool,
+    {
+        let wait = match config.wait_ready {
+            Some(wait) => wait,
+            None => return Self::try_open(config, &is_lazy),
+        };
+        let deadline = Instant::now() + wait;
+
+        loop {
+            match Self::try_open(config, &is_lazy) {
+                Ok(opened) => return Ok(opened),
+                Err(err) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining == Duration::from_secs(0) {
+                        return Err(err);
+                    }
+
+                    log::warn!(
+                        "The storage at {} is not ready, retrying: {}",
+                        config.path,
+                        err
+                    );
+                    thread::sleep(remaining.min(READY_RETRY_INTERVAL));
+                }
+            }
+        }
+    }
+
+    fn try_open<F>(config: &StorageConfig, is_lazy: &F) -> io::Result<(Self, Vec<Recovered>)>
+    where
+        F: Fn(&str, &str) -> bool,
+    {
+        let path = PathBuf::from(&config.path);
+        if config.wait_ready.is_some() {
+            if !fs::metadata(&path)?.is_dir() {
+                return Err(io::Error::other("the path is not a directory"));
+            }
+        } else {
+            fs::create_dir_all(&path)?;
+        }
+
+        let mut numbers = Vec::new();
+        for entry in fs::read_dir(&path)? {
+            if let Some(number) = segment_number(&entry?.path()) {
+                numbers.push(number);
+            }
+        }
+        numbers.sort_unstable();
+
+        let mut recovered = BTreeMap::new();
+        let mut next_id = 0;
+        for &number in &numbers {
+            let segment = segment_path(&path, number);
+            let raw = fs::read(&segment)?;
+
+            if let Err(err) = replay(&raw, number, &is_lazy, &mut recovered, &mut next_id) {
+                log::warn!(
+                    "Ignored the end of segment {}, which is corrupted: {}",
+                    segment.display(),
+                    err
+                );
+            }
+        }
+
+        let mut segments: BTreeMap<u64, usize> = numbers.iter().map(|&n| (n, 0)).collect();
+        let mut locations = HashMap::new();
+        let mut messages = Vec::new();
+        for (id, (location, vhost, queue, message)) in recovered {
+            *segments.get_mut(&location.segment).unwrap() += 1;
+            locations.insert(id, location);
+            messages.push((vhost, queue, message));
+        }
+
+        let current = numbers.last().map_or(1, |number| number + 1);
+        let file = create_segment(&path, current)?;
+        segments.insert(current, 0);
+
+        let storage = Self {
+            path,
+            sync: config.sync,
+            state: Mutex::new(State {
+                current,
+                file,
+                size: 0,
+                segments,
+                locations,
+                next_id,
+            }),
+        };
+        storage.compact(&mut storage.state.lock().unwrap());
+
+        Ok((storage, messages))
+    }
+
+    /// Appends a message of `queue`, in the virtual host `vhost`, to the storage, returning the
+    /// identifier it's stored under. The record is flushed to the disk if `sync` is set, or if
+    /// the storage flushes every write when it's `None`.
+    pub fn append(
+        &self,
+        vhost: &str,
+        queue: &str,
+        message: &Message,
+        sync: Option<bool>,
+    ) -> io::Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        let mut encoder = Encoder::new();
+
+        // The messages of the default virtual host are written with the records predating the
+        // virtual hosts, so a storage without other virtual hosts is still read by older brokers.
+        match (vhost, compression_byte(message.compression)) {
+            (DEFAULT_VHOST, Some(compression)) => {
+                encoder.write_u8(RECORD_APPEND_COMPRESSED);
+                encoder.write_u64(id);
+                encoder.write_short_str(queue);
+                encoder.write_u8(compression);
+            }
+            (DEFAULT_VHOST, None) => {
+                encoder.write_u8(RECORD_APPEND);
+                encoder.write_u64(id);
+                encoder.write_short_str(queue);
+            }
+            (vhost, compression) => {
+                encoder.write_u8(RECORD_APPEND_VHOST);
+                encoder.write_u64(id);
+                encoder.write_short_str(vhost);
+                encoder.write_short_str(queue);
+                encoder.write_u8(compression.unwrap_or(0));
+            }
+        }
+        encode_message(&mut encoder, message);
+        let location = Location {
+            segment: state.current,
+            offset: state.size,
+        };
+        self.write(&mut state, record(encoder), self.syncs(sync))?;
+
+        state.next_id += 1;
+        state.locations.insert(id, location);
+        *state.segments.get_mut(&location.segment).unwrap() += 1;
+
+        if state.size >= SEGMENT_MAX_BYTES {
+            if let Err(err) = self.roll(&mut state) {
+                log::warn!("Could not start a new segment of the storage: {}", err);
+            }
+        }
+
+        Ok(id)
+    }
+
+    /// Removes settled messages from the storage. The identifiers which are not stored anymore
+    /// are ignored.
+    pub fn remove(&self, ids: &[u64]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut records = Vec::new();
+
+        for &id in ids {
+            if state.locations.contains_key(&id) {
+                let mut encoder = Encoder::new();
+
+                encoder.write_u8(RECORD_REMOVE);
+                encoder.write_u64(id);
+                records.extend(record(encoder));
+            }
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.write(&mut state, records, self.sync)?;
+
+        for id in ids {
+            if let Some(location) = state.locations.remove(id) {
+                *state.segments.get_mut(&location.segment).unwrap() -= 1;
+            }
+        }
+        self.compact(&mut state);
+
+        Ok(())
+    }
+
+    /// Reads back the body of the message stored under `id` from its record, for a lazy queue
+    /// delivering it. The body is returned as it's stored, along with its compression.
+    pub fn read_body(&self, id: u64) -> io::Result<(Vec<u8>, Compression)> {
+        let location = self
+            .state
+            .lock()
+            .unwrap()
+            .locations
+            .get(&id)
+            .copied()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the message is not stored"))?;
+
+        let mut file = File::open(segment_path(&self.path, location.segment))?;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut len = [0; 4];
+        file.read_exact(&mut len)?;
+        let mut payload = vec![0; u32::from_be_bytes(len) as usize];
+        file.read_exact(&mut payload)?;
+
+        let mut decoder = Decoder::new(&payload);
+        let kind = decoder.read_u8().map_err(invalid_data)?;
+        decoder.read_u64().map_err(invalid_data)?;
+        let (_, _, message) = decode_append(kind, &mut decoder).map_err(invalid_data)?;
+
+        Ok((message.body, message.compression))
+    }
+
+    /// Returns `true` if a message of a queue whose definition sets `sync` is flushed to the disk
+    /// once it's appended, the definition overriding the storage.
+    fn syncs(&self, sync: Option<bool>) -> bool {
+        sync.unwrap_or(self.sync)
+    }
+
+    /// Writes records to the current segment, then flushes them to the disk if `sync` is set. If
+    /// the write fails, a new segment is started, as the current one may end with a partial
+    /// record.
+    fn write(&self, state: &mut State, records: Vec<u8>, sync: bool) -> io::Result<()> {
+        let written = state.file.write_all(&records).and_then(|_| {
+            if sync {
+                state.file.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+
+        if let Err(err) = written {
+            let _ = self.roll(state);
+            return Err(err);
+        }
+        state.size += records.len() as u64;
+
+        Ok(())
+    }
+
+    /// Starts a new segment, the records being appended to it from now on.
+    fn roll(&self, state: &mut State) -> io::Result<()> {
+        let next = state.current + 1;
+
+        state.file = create_segment(&self.path, next)?;
+        state.current = next;
+        state.size = 0;
+        state.segments.insert(next, 0);
+        self.compact(state);
+
+        Ok(())
+    }
+
+    /// Deletes the oldest segments whose messages are all settled. A segment is only deleted once
+    /// the older ones are, as it may hold the records settling their messages.
+    fn compact(&self, state: &mut State) {
+        while let Some((&number, &count)) = state.segments.iter().next() {
+            if count > 0 || number == state.current {
+                break;
+            }
+
+            let segment = segment_path(&self.path, number);
+            if let Err(err) = fs::remove_file(&segment) {
+                log::warn!("Could not delete segment {}: {}", segment.display(), err);
+                break;
+            }
+            state.segments.remove(&number);
+        }
+    }
+}
+
+/// Returns the path of the segment numbered `number`.
+fn segment_path(path: &Path, number: u64) -> PathBuf {
+    path.join(format!("{:020}.{}", number, SEGMENT_EXTENSION))
+}
+
+/// Returns the number of the segment stored at `path`, if it's a segment.
+fn segment_number(path: &Path) -> Option<u64> {
+    if path.extension()? != SEGMENT_EXTENSION {
+        return None;
+    }
+
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+fn create_segment(path: &Path, number: u64) -> io::Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .open(segment_path(path, number))
+}
+
+/// Prefixes the payload of a record with its length.
+fn record(encoder: Encoder) -> Vec<u8> {
+    let payload = encoder.into_inner();
+    let mut encoder = Encoder::new();
+
+    encoder.write_long_str(&payload);
+    encoder.into_inner()
+}
+
+/// Replays the records of a segment into `recovered`, which maps the identifier of each message
+/// which is not settled to its location, its virtual host, its queue and itself. `next_id` is
+/// moved past every identifier met. The body of the messages of the lazy queues is dropped.
+///
+/// The records preceding a corrupted one are replayed before the error is returned.
+fn replay(
+    raw: &[u8],
+    number: u64,
+    is_lazy: &dyn Fn(&str, &str) -> bool,
+    recovered: &mut BTreeMap<u64, (Location, String, String, Message)>,
+    next_id: &mut u64,
+) -> Result<(), protocol::Error> {
+    let mut records = Decoder::new(raw);
+
+    while !records.is_empty() {
+        let location = Location {
+            segment: number,
+            offset: (raw.len() - records.remaining()) as u64,
+        };
+        let payload = records.read_long_str()?;
+        let mut decoder = Decoder::new(&payload);
+        let kind = decoder.read_u8()?;
+        let id = decoder.read_u64()?;
+
+        match kind {
+            RECORD_APPEND | RECORD_APPEND_COMPRESSED | RECORD_APPEND_VHOST => {
+                let (vhost, queue, mut message) = decode_append(kind, &mut decoder)?;
+
+                message.stored = Some(id);
+                if is_lazy(&vhost, &queue) {
+                    message.page_out();
+                }
+                recovered.insert(id, (location, vhost, queue, message));
+            }
+            RECORD_REMOVE => {
+                recovered.remove(&id);
+            }
+            _ => return Err(protocol::Error::Syntax("unknown record")),
+        }
+        *next_id = (*next_id).max(id + 1);
+    }
+
+    Ok(())
+}
+
+/// Decodes the rest of the record of a message appended to a queue, after its kind and its
+/// identifier, returning the virtual host and the name of the queue along with the message.
+fn decode_append(
+    kind: u8,
+    decoder: &mut Decoder,
+) -> Result<(String, String, Message), protocol::Error> {
+    let vhost = match kind {
+        RECORD_APPEND_VHOST => decoder.read_short_str()?,
+        _ => DEFAULT_VHOST.to_string(),
+    };
+    let queue = decoder.read_short_str()?;
+    let compression = match kind {
+        RECORD_APPEND_COMPRESSED => compression_from_byte(decoder.read_u8()?)?,
+        RECORD_APPEND_VHOST => match decoder.read_u8()? {
+            0 => Compression::None,
+            byte => compression_from_byte(byte)?,
+        },
+        RECORD_APPEND => Compression::None,
+        _ => return Err(protocol::Error::Syntax("unknown record")),
+    };
+    let mut message = decode_message(decoder)?;
+    message.compression = compression;
+
+    Ok((vhost, queue, message))
+}
+
+fn invalid_data(err: protocol::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// Returns the byte a compression is written as, or `None` if the body is not compressed.
+fn compression_byte(compression: Compression) -> Option<u8> {
+    match compression {
+        Compression::None => None,
+        Compression::Gzip => Some(1),
+        Compression::Zstd => Some(2),
+    }
+}
+
+fn compression_from_byte(byte: u8) -> Result<Compression, protocol::Error> {
+    match byte {
+        1 => Ok(Compression::Gzip),
+        2 => Ok(Compression::Zstd),
+        _ => Err(protocol::Error::Syntax("unknown compression")),
+    }
+}
+
+fn encode_message(encoder: &mut Encoder, message: &Message) {
+    let mut header = Encoder::new();
+
+    ContentHeader {
+        class_id: CLASS_BASIC,
+        body_size: message.body.len() as u64,
+        properties: message.properties.clone(),
+    }
+    .encode(&mut header);
+
+    encoder.write_short_str(&message.exchange);
+    encoder.write_short_str(&message.routing_key);
+    encoder.write_u32(message.deaths.len() as u32);
+    for death in &message.deaths {
+        encoder.write_short_str(death);
+    }
+    encoder.write_long_str(&header.into_inner());
+    encoder.write_long_str(&message.body);
+}
+
+fn decode_message(decoder: &mut Decoder) -> Result<Message, protocol::Error> {
+    let exchange = decoder.read_short_str()?;
+    let routing_key = decoder.read_short_str()?;
+    let mut deaths = Vec::new();
+    for _ in 0..decoder.read_u32()? {
+        deaths.push(decoder.read_short_str()?);
+    }
+    let header = ContentHeader::decode(&decoder.read_long_str()?)?;
+    let body = decoder.read_long_str()?;
+
+    let mut message = Message::new(exchange, routing_key, header.properties, body);
+    message.deaths = deaths;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Properties;
+
+    /// Returns the configuration of a storage in an empty directory of the temporary directory,
+    /// dedicated to the test `name`.
+    fn storage_config(name: &str, sync: bool) -> StorageConfig {
+        let dir = std::env::temp_dir().join(format!("another-mq-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        StorageConfig {
+            path: dir.display().to_string(),
+            sync,
+            wait_ready: None,
+        }
+    }
+
+    fn message(body: &[u8]) -> Message {
+        Message::new(
+            String::new(),
+            "tasks".into(),
+            Properties::default(),
+            body.to_vec(),
+        )
+    }
+
+    #[test]
+    fn sync_of_the_queue_overrides_the_one_of_the_storage() {
+        for sync in [false, true] {
+            let config = storage_config(&format!("sync-{}", sync), sync);
+            let (storage, _) = Storage::open(&config, |_, _| false).unwrap();
+
+            assert_eq!(storage.syncs(None), sync);
+            assert!(storage.syncs(Some(true)));
+            assert!(!storage.syncs(Some(false)));
+            for queue_sync in [None, Some(true), Some(false)] {
+                storage
+                    .append(DEFAULT_VHOST, "tasks", &message(b"task"), queue_sync)
+                    .unwrap();
+            }
+            fs::remove_dir_all(&config.path).unwrap();
+        }
+    }
+
+    #[test]
+    fn storage_waits_for_its_directory_to_appear() {
+        let config = StorageConfig {
+            wait_ready: Some(Duration::from_secs(10)),
+            ..storage_config("wait-ready", false)
+        };
+        let path = config.path.clone();
+        let started_at = Instant::now();
+        let mount = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            fs::create_dir_all(path).unwrap();
+        });
+
+        let opened = Storage::open(&config, |_, _| false);
+
+        mount.join().unwrap();
+        assert!(opened.is_ok());
+        assert!(started_at.elapsed() >= Duration::from_millis(300));
+        fs::remove_dir_all(&config.path).unwrap();
+    }
+
+    #[test]
+    fn storage_gives_up_once_the_wait_is_over() {
+        let config = StorageConfig {
+            wait_ready: Some(Duration::from_millis(200)),
+            ..storage_config("wait-ready-missing", false)
+        };
+        let started_at = Instant::now();
+
+        let opened = Storage::open(&config, |_, _| false);
+
+        assert_eq!(opened.err().unwrap().kind(), io::ErrorKind::NotFound);
+        assert!(started_at.elapsed() >= Duration::from_millis(200));
+        assert!(!Path::new(&config.path).exists());
+    }
+
+    #[test]
+    fn storage_creates_its_directory_without_waiting() {
+        let config = storage_config("no-wait", false);
+
+        assert!(Storage::open(&config, |_, _| false).is_ok());
+        assert!(Path::new(&config.path).is_dir());
+        fs::remove_dir_all(&config.path).unwrap();
+    }
+}
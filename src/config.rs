@@ -1,14 +1,28 @@
+pub mod builder;
+#[cfg(feature = "json")]
+mod json;
+mod merge;
+#[cfg(feature = "yaml")]
+mod yaml;
+
+use crate::{auth, protocol::FRAME_MIN_SIZE, timestamp};
 use log::Level;
 use serde::{
     de::{self, Deserializer, Unexpected, Visitor},
-    Deserialize,
+    Deserialize, Serialize, Serializer,
 };
+#[cfg(target_os = "macos")]
+use std::process::Command;
 use std::{
-    convert::Into,
-    env, fmt, fs,
-    net::{IpAddr, Ipv4Addr},
-    path::Path,
-    process::Command,
+    collections::HashMap,
+    env, error, fmt, fs,
+    io::{self, Read},
+    iter,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::{Duration, SystemTime},
 };
 
 /// The default value of the listener's hostname.
@@ -17,7 +31,65 @@ const DEFAULT_LISTENER_HOSTNAME: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
 /// The default value of the listener's port.
 const DEFAULT_LISTENER_PORT: u16 = 5672;
 
-/// This data structure is holding the configuration defined by the user of `another-mq`. This 
+/// The number of pending connections queued by the listeners, by default.
+const DEFAULT_LISTEN_BACKLOG: u32 = 1024;
+
+/// The maximum size of the frames exchanged with the AMQP clients by default, in bytes.
+const DEFAULT_MAX_FRAME_BYTES: u32 = 131_072;
+
+/// How long the application waits for the connections to close when it stops, by default.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the clients have to complete the handshake of their connection by default.
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the listeners wait after failing to accept a connection by default.
+const DEFAULT_ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How often the expired messages are removed from the queues, by default.
+const DEFAULT_EXPIRY_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The size from which the bodies of the messages are compressed by default, in bytes.
+const DEFAULT_COMPRESSION_THRESHOLD_BYTES: u64 = 1024;
+
+/// The minimum delay between two posts to the webhook of a queue alert, by default.
+const DEFAULT_ALERT_MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The default port of a syslog server.
+pub const SYSLOG_DEFAULT_PORT: u16 = 514;
+
+/// The delay between two attempts to reconnect to a syslog server over TCP, by default.
+pub const SYSLOG_DEFAULT_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the buffered log entries are written, by default.
+const DEFAULT_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The first port which is not a privileged port.
+const PRIVILEGED_PORTS_END: u16 = 1024;
+
+/// The default port of the HTTP listener serving the metrics.
+const DEFAULT_METRICS_PORT: u16 = 9090;
+
+/// The bounds of the buckets of the histogram of the message sizes, in bytes, by default: from
+/// 64 bytes to 4 MiB, each bound being 4 times the previous one.
+const DEFAULT_SIZE_BUCKETS: [f64; 9] = [
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0,
+];
+
+/// The default port of the HTTP listener serving the admin API.
+const DEFAULT_ADMIN_PORT: u16 = 9091;
+
+/// The address the HTTP listeners of the metrics and the admin API are bound on by default, so
+/// they're only reachable from the host itself.
+const DEFAULT_HTTP_BIND_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+/// The pattern of an access control rule matching every queue.
+const ACL_WILDCARD: &str = "*";
+
+/// The name of the default virtual host, holding the queues of the queue namespace.
+pub const DEFAULT_VHOST: &str = "/";
+
+/// This data structure is holding the configuration defined by the user of `another-mq`. This
 /// configuration is loaded from a TOML file which can be edited by the user to fit its needs.
 ///
 /// # Location of configuration file
@@ -27,7 +99,7 @@ const DEFAULT_LISTENER_PORT: u16 = 5672;
 ///
 /// | Platform   | Default configuration file path                   |
 /// | ---------- | ------------------------------------------------- |
-/// | Windows    | `%APPDATA\another-mq\another-mq.toml`             |
+/// | Windows    | `%APPDATA%\another-mq\another-mq.toml`            |
 /// | MacOS      | `$(brew --prefix)/etc/another-mq/another-mq.toml` |
 /// | Linux/Unix | `$ANOTHERMQ_HOME/etc/another-mq/another-mq.toml`  |
 ///
@@ -35,360 +107,5220 @@ const DEFAULT_LISTENER_PORT: u16 = 5672;
 /// >
 /// > For MacOS platform, if `brew` is not installed, the default configuration file path will be the same as
 /// > as for Linux/Unix.
-#[derive(Clone, Debug, Deserialize)]
+/// >
+/// > For Windows platform, if `%APPDATA%` is not defined, as for some service accounts, the default configuration
+/// > file path will be `%ANOTHERMQ_HOME%\etc\another-mq\another-mq.toml`.
+///
+/// # Includes
+///
+/// The configuration file can be split into several files, listed by a top-level `include` key
+/// such as `include = ["auth.toml", "queues.toml"]`. Their paths are relative to the directory of
+/// the file including them, and they can include other files in turn. Their tables are merged
+/// into the configuration: the later includes override the earlier ones, and the including file
+/// overrides them all. Including a file which is already being included is an error.
+///
+/// # Unknown keys
+///
+/// The keys which are not part of the configuration are ignored, so a file written for a newer
+/// version of `another-mq` can still be loaded. When started with `--strict-config`, the
+/// application rejects them instead, reporting the first one along with the file defining it (see
+/// [`Config::from_file_strict`]).
+///
+/// # Checking
+///
+/// A configuration file can be checked without starting the broker with `another-mq check-config
+/// [<path>]`, which reports every error of the file instead of the first one (see
+/// [`Config::validate_file`]).
+///
+/// # Virtual hosts
+///
+/// The queues are isolated in virtual hosts, so the queues of different environments can share
+/// their names on a same broker. The queues of the queue namespace belong to the default virtual
+/// host `/`, and the other virtual hosts are defined by `[[vhost]]` tables holding their own
+/// queue definitions:
+///
+/// ```toml
+/// [[vhost]]
+/// name = "staging"
+///
+/// [[vhost.definitions]]
+/// name = "tasks"
+/// ```
+///
+/// The clients select their virtual host when they open their connection, and they only see its
+/// queues. The clients speaking the text protocol always use the default virtual host.
+///
+/// # Building
+///
+/// When the broker is embedded, the configuration can be built without a configuration file with
+/// a [`ConfigBuilder`](builder::ConfigBuilder), starting from the default configuration.
+///
+/// # Environment variables
+///
+/// The values of the configuration file can reference environment variables, such as
+/// `password_hash = "${PASSWORD_HASH}"`, so the secrets don't have to be written in the file. The
+/// references are replaced by the values of their variables before the file is parsed, wherever
+/// they are, even in the comments: a variable which is not set is an error, and `$${` is written
+/// as a literal `${`. The values are inserted as they are, so they must be escaped if they hold
+/// characters like quotes.
+///
+/// Once the configuration file is loaded, its values can be overridden by environment variables
+/// (see [`Config::apply_env_overrides`]). The precedence is the following: environment variables
+/// first, then the configuration file and finally the default values.
+///
+/// # Reloading
+///
+/// The configuration can be reloaded while the application is running (see [`Config::reload`]),
+/// which happens when it receives `SIGHUP` on Unix. Only some namespaces take effect live:
+///
+/// | Namespace | Reloadable                                                         |
+/// | --------- | ------------------------------------------------------------------ |
+/// | `admin`   | No, the application must be restarted                              |
+/// | `auth`    | Yes, for the connections opened after the reload                   |
+/// | `log`     | Yes, the sinks left unchanged staying open                         |
+/// | `metrics` | No, the application must be restarted                              |
+/// | `queue`   | Yes, the new definitions are declared but existing queues are kept |
+/// | `vhost`   | Yes, like the queue namespace                                      |
+/// | `network` | No, the application must be restarted                              |
+///
+/// The storage of the queue namespace and its memory-only mode are not reloadable either, as
+/// they're only applied when the application starts.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct Config {
+    /// The admin namespace.
+    #[serde(default)]
+    pub admin: Admin,
+
+    /// The auth namespace.
+    #[serde(default)]
+    pub auth: Auth,
+
     /// The log namespace.
+    #[serde(default)]
     pub log: Log,
 
+    /// The metrics namespace.
+    #[serde(default)]
+    pub metrics: Metrics,
+
     /// The network namespace.
+    #[serde(default)]
     pub network: Network,
 
     /// The queue namespace.
+    #[serde(default)]
     pub queue: Queue,
+
+    /// The virtual hosts other than the default one, defined by `[[vhost]]` tables.
+    #[serde(default, rename = "vhost")]
+    pub vhosts: Vec<VirtualHost>,
 }
 
 impl Config {
-    /// Loads the configuration from an arbitrary TOML file specified by the user.
+    /// Loads the configuration from an arbitrary configuration file specified by the user.
+    ///
+    /// If the configuration could not be loaded by the application, a default instance of the
+    /// `Config` structure will be returned instead, the error being logged as a warning. Use
+    /// [`Config::try_from_file`] to handle the errors instead.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
-        let raw = match fs::read_to_string(path) {
-            Ok(raw) => raw,
-            Err(_) => {
-                return Self::default();
-            }
-        };
-
-        match toml::from_str(&raw) {
-            Ok(config) => config,
-            Err(_) => Self::default(),
-        }
+        Self::try_from_file(path).unwrap_or_else(|err| {
+            log::warn!(
+                "Using the default configuration, as it could not be loaded: {}",
+                err
+            );
+            Self::default()
+        })
     }
 
     /// Loads the configuration from the default TOML configuration file.
     ///
     /// If the configuration could not be loaded by the application, a default instance of the
-    /// `Config` structure will be returned instead.
-    #[cfg(target_os = "windows")]
+    /// `Config` structure will be returned instead. Use [`Config::try_from_config_file`] to
+    /// handle the errors instead.
     pub fn from_config_file() -> Self {
-        let config_path = env::var("APPDATA")
-            .expect("%APPDATA% environment variable is not defined on your system!");
-        let config_path = config_path + "/another-mq/another-mq.toml";
-
-        let raw = match fs::read_to_string(config_path) {
-            Ok(raw) => raw,
-            Err(_) => {
-                return Self::default();
-            }
-        };
+        Self::from_file(config_file_path())
+    }
 
-        match toml::from_str(&raw) {
-            Ok(config) => config,
-            Err(_) => Self::default(),
-        }
+    /// Loads the configuration from an arbitrary configuration file specified by the user.
+    ///
+    /// The format of the file is chosen from its extension (see [`ConfigFormat`]), an unknown
+    /// extension being an error. The files it includes are merged into it (see
+    /// [Includes](Config#includes)).
+    ///
+    /// A missing file is not an error: the default configuration is used instead, unless the
+    /// `ANOTHERMQ_REQUIRE_CONFIG` environment variable is `true`, so a deployment which lost its
+    /// file fails loudly. However, a file which can't be read or which holds an invalid
+    /// configuration is reported as an error. In both cases, the environment overrides are applied
+    /// on top of the loaded configuration before validating it.
+    pub fn try_from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::load(path.as_ref(), false, false)
     }
 
-    /// Loads the configuration from the default TOML configuration file.
+    /// Loads the configuration from an arbitrary configuration file, like
+    /// [`Config::try_from_file`], but rejects the keys which are not part of the configuration.
     ///
-    /// If the configuration could not be loaded by the application, a default instance of the
-    /// `Config` structure will be returned instead.
-    #[cfg(target_os = "macos")]
-    pub fn from_config_file() -> Self {
-        let install_prefix = Command::new("brew").arg("--prefix").output();
-        let install_prefix = match install_prefix {
-            Ok(output) => String::from_utf8(output.stdout).unwrap(),
-            Err(_) => env::var("ANOTHERMQ_HOME").unwrap_or_else(|_| "".into()),
-        };
+    /// By default, the unknown keys are ignored so a configuration file written for a newer
+    /// version of the application can still be loaded. In strict mode, the first unknown key is
+    /// reported along with the file defining it, so a typo like `prot` instead of `port` doesn't
+    /// go unnoticed.
+    pub fn from_file_strict<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::load(path.as_ref(), true, false)
+    }
 
-        let config_path = install_prefix + "/etc/another-mq/another-mq.toml";
+    /// Loads the configuration from the default TOML configuration file in strict mode.
+    ///
+    /// See [`Config::from_file_strict`] for the handling of the unknown keys.
+    pub fn from_config_file_strict() -> Result<Self, ConfigError> {
+        Self::from_file_strict(config_file_path())
+    }
 
-        let raw = match fs::read_to_string(config_path) {
-            Ok(raw) => raw,
-            Err(_) => {
-                return Self::default();
-            }
-        };
+    /// Loads the configuration from `reader`, such as the standard input, rather than from a
+    /// file. The whole input is read, then deserialized from `format`.
+    ///
+    /// Like [`Config::try_from_file`], the references to the environment variables are replaced,
+    /// and the environment overrides are applied before validating the configuration. However,
+    /// the input can't include other files, and the path of `auth.users_file` is relative to the
+    /// current directory.
+    pub fn from_reader<R: Read>(reader: R, format: ConfigFormat) -> Result<Self, ConfigError> {
+        Self::load_reader(reader, format, false)
+    }
 
-        match toml::from_str(&raw) {
-            Ok(config) => config,
-            Err(_) => Self::default(),
-        }
+    /// Loads the configuration from `reader`, like [`Config::from_reader`], but rejects the keys
+    /// which are not part of the configuration.
+    ///
+    /// See [`Config::from_file_strict`] for the handling of the unknown keys.
+    pub fn from_reader_strict<R: Read>(
+        reader: R,
+        format: ConfigFormat,
+    ) -> Result<Self, ConfigError> {
+        Self::load_reader(reader, format, true)
     }
 
-    /// Loads the configuration from the default TOML configuration file.
+    /// Builds the configuration from the environment alone, for the deployments configuring the
+    /// broker without any file. The default configuration is overridden by the variables listed
+    /// by [`Config::apply_env_overrides`], which are the complete set of variables recognized,
+    /// then validated.
     ///
-    /// If the configuration could not be loaded by the application, a default instance of the
-    /// `Config` structure will be returned instead.
-    #[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
-    pub fn from_config_file() -> Self {
-        let home_prefix = env::var("ANOTHERMQ_HOME").unwrap_or_else(|_| "".into());
-        let config_path = home_prefix + "/etc/another-mq/another-mq.toml";
+    /// No configuration file is read, so the fields without a variable keep their default value.
+    /// The queue definitions, the virtual hosts, the users, the listeners and the TLS options,
+    /// among others, can only be defined by a file.
+    pub fn from_env_only() -> Result<Self, ConfigError> {
+        let mut config = Self::default();
 
-        let raw = match fs::read_to_string(config_path) {
-            Ok(raw) => raw,
-            Err(_) => {
-                return Self::default();
-            }
-        };
+        config.apply_env_overrides()?;
+        config.validate()?;
 
-        match toml::from_str(&raw) {
-            Ok(config) => config,
-            Err(_) => Self::default(),
-        }
+        Ok(config)
     }
-}
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            log: Log::default(),
-            network: Network::default(),
-            queue: Queue::default(),
+    /// Checks an arbitrary configuration file without loading it, such as in a CI pipeline.
+    ///
+    /// Unlike [`Config::try_from_file`], every error is returned instead of the first one, so a
+    /// broken configuration can be fixed at once. The file is checked on its own, the environment
+    /// overrides not being applied, and a missing file is an error. A file which can't be read or
+    /// parsed is reported alone, as its namespaces can't be checked then.
+    pub fn validate_file<P: AsRef<Path>>(path: P) -> Result<(), Vec<ConfigError>> {
+        let mut config = Self::parse_file(path.as_ref(), false).map_err(|err| vec![err])?;
+        let errors = config.validation_errors();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
     }
-}
 
-/// The log namespace of the configuration. By default, log entries are emitted
-/// on the standard output of the application. But a logfile or a syslog server
-/// can also be used to collect log entries.
-#[derive(Clone, Debug, Deserialize)]
-pub struct Log {
-    /// The minimum level of an entry to be added to the application log.
-    #[serde(default = "Log::default_level")]
-    pub level: Level,
+    /// Checks the default TOML configuration file without loading it.
+    ///
+    /// See [`Config::validate_file`] for the errors which are returned.
+    pub fn validate_config_file() -> Result<(), Vec<ConfigError>> {
+        Self::validate_file(config_file_path())
+    }
 
-    /// The path to the logfile of the application.
-    pub file: Option<String>,
+    /// Loads the configuration from `path`. A missing file stands for the default configuration,
+    /// unless the file is `required`, or `ANOTHERMQ_REQUIRE_CONFIG` requires one.
+    fn load(path: &Path, strict: bool, required: bool) -> Result<Self, ConfigError> {
+        let mut config = match Self::parse_file(path, strict) {
+            Ok(config) => config,
+            Err(ConfigError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+                if required || env_override("ANOTHERMQ_REQUIRE_CONFIG")? == Some(true) {
+                    return Err(ConfigError::Missing {
+                        path: path.display().to_string(),
+                    });
+                }
+                Self::default()
+            }
+            Err(err) => return Err(err),
+        };
 
-    /// The syslog configuration of the application.
-    pub syslog: Option<Syslog>,
-}
+        config.apply_env_overrides()?;
+        config.validate()?;
 
-impl Log {
-    fn default_level() -> Level {
-        Level::Info
+        Ok(config)
     }
-}
 
-impl Default for Log {
-    fn default() -> Self {
-        Self {
-            level: Self::default_level(),
-            file: None,
-            syslog: None,
+    fn load_reader<R: Read>(
+        mut reader: R,
+        format: ConfigFormat,
+        strict: bool,
+    ) -> Result<Self, ConfigError> {
+        let mut raw = String::new();
+        reader.read_to_string(&mut raw)?;
+
+        let value = format.parse_value(&interpolate(&raw)?)?;
+        if value.get("include").is_some() {
+            return Err(ConfigError::Invalid {
+                field: "include".into(),
+                reason: "the configuration read from a stream can't include files".into(),
+            });
+        }
+        let mut config = Self::deserialize(value.clone())?;
+
+        if strict {
+            check_unknown_keys(&config, &value, &[("the input".into(), value.clone())])?;
         }
+        config.auth.load_users_file(Path::new(""))?;
+        config.apply_env_overrides()?;
+        config.validate()?;
+
+        Ok(config)
     }
-}
 
-/// The syslog configuration of the application log.
-#[derive(Clone, Debug, Deserialize)]
-pub struct Syslog {
-    pub host: Option<IpAddr>,
-    pub port: Option<u16>,
-    pub protocol: SyslogProtocol,
-    pub facility: SyslogFacility,
-    pub process: String,
-}
+    /// Reads a configuration file along with the files it includes, without validating it.
+    fn parse_file(path: &Path, strict: bool) -> Result<Self, ConfigError> {
+        let format = ConfigFormat::from_path(path)?;
+        let raw = interpolate(&fs::read_to_string(path)?)?;
+        let mut sources = Vec::new();
+        let value = format.parse_value(&raw)?;
+        let value = merge_includes(path, value, &mut Vec::new(), &mut sources)?;
+        let mut config = Self::deserialize(value.clone())?;
 
-impl Default for Syslog {
-    fn default() -> Self {
-        Self {
-            host: None,
-            port: None,
-            protocol: SyslogProtocol::Rfc3164,
-            facility: SyslogFacility::User,
-            process: String::new(),
+        if strict {
+            check_unknown_keys(&config, &value, &sources)?;
         }
-    }
-}
+        config
+            .auth
+            .load_users_file(path.parent().unwrap_or_else(|| Path::new("")))?;
 
-/// The syslog protocol to use.
-#[derive(Copy, Clone, Debug)]
-pub enum SyslogProtocol {
-    Rfc3164,
-    Rfc5424,
-}
+        Ok(config)
+    }
 
-impl<'de> Deserialize<'de> for SyslogProtocol {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        struct SyslogProtocolVisitor;
+    /// Loads the configuration from the default TOML configuration file.
+    ///
+    /// See [`Config::try_from_file`] for the handling of the errors.
+    pub fn try_from_config_file() -> Result<Self, ConfigError> {
+        Self::try_from_file(config_file_path())
+    }
 
-        impl<'de> Visitor<'de> for SyslogProtocolVisitor {
-            type Value = SyslogProtocol;
+    /// Reloads the configuration from an arbitrary configuration file, like
+    /// [`Config::try_from_file`].
+    ///
+    /// Only the hot-reloadable namespaces (`auth`, `log`, `queue` and the virtual hosts) are
+    /// replaced. The changes of the `admin`, `metrics` and `network` namespaces are not applied, as
+    /// they require the application to be restarted, nor are the changes of `queue.storage` and
+    /// `queue.memory_only` for the same reason. If the file holds an invalid configuration,
+    /// an error is returned and the configuration is left untouched.
+    ///
+    /// Unlike when the configuration is first loaded, the file must exist: a file missing while
+    /// it's being deployed would otherwise reload the default configuration, which drops every
+    /// user and lets any client connect.
+    pub fn reload<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ConfigError> {
+        let mut config = Self::load(path.as_ref(), false, true)?;
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("Expecting rfc3164 or rfc5424")
-            }
+        // The port picked from the range is not a change of the network namespace.
+        if config.network.port_range.is_some()
+            && config.network.port_range == self.network.port_range
+        {
+            config.network.port = self.network.port;
+        }
 
-            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                match value {
-                    "rfc3164" | "RFC3164" => Ok(SyslogProtocol::Rfc3164),
-                    "rfc5424" | "RFC5424" => Ok(SyslogProtocol::Rfc5424),
-                    _ => Err(de::Error::invalid_value(
-                        Unexpected::Str(value),
-                        &"Unknown syslog protocol!",
-                    )),
-                }
-            }
+        if config.admin != self.admin {
+            log::warn!("The changes of the admin namespace require a restart to take effect");
+        }
+        if config.metrics != self.metrics {
+            log::warn!("The changes of the metrics namespace require a restart to take effect");
+        }
+        if config.network != self.network {
+            log::warn!("The changes of the network namespace require a restart to take effect");
+        }
+        if config.queue.storage != self.queue.storage {
+            log::warn!("The changes of the queue storage require a restart to take effect");
         }
+        if config.queue.memory_only != self.queue.memory_only {
+            log::warn!("The changes of the memory-only mode require a restart to take effect");
+        }
+        // The live configuration reports the storage the broker runs with until it's restarted.
+        config.queue.storage = self.queue.storage.clone();
+        config.queue.memory_only = self.queue.memory_only;
 
-        deserializer.deserialize_enum(
-            "SyslogProtocol",
-            &["rfc3164", "RFC3164", "rfc5324", "RFC5424"],
-            SyslogProtocolVisitor,
-        )
-    }
-}
+        self.auth = config.auth;
+        self.log = config.log;
+        self.queue = config.queue;
+        self.vhosts = config.vhosts;
 
-/// The syslog facility to use.
-#[derive(Copy, Clone, Debug)]
-pub enum SyslogFacility {
-    Kern,
-    User,
-    Mail,
-    Daemon,
-    Auth,
-    Syslog,
-    Lpr,
-    News,
-    Uucp,
-    Cron,
-    AuthPriv,
-    Ftp,
-    Local0,
-    Local1,
-    Local2,
-    Local3,
-    Local4,
-    Local5,
-    Local6,
-    Local7,
-}
+        Ok(())
+    }
 
-impl Into<syslog::Facility> for SyslogFacility {
-    fn into(self) -> syslog::Facility {
-        use syslog::Facility;
+    /// Reloads the configuration from the default TOML configuration file.
+    ///
+    /// See [`Config::reload`] for the namespaces which are reloaded.
+    pub fn reload_config_file(&mut self) -> Result<(), ConfigError> {
+        self.reload(config_file_path())
+    }
 
-        match self {
-            Self::Kern => Facility::LOG_KERN,
-            Self::User => Facility::LOG_USER,
-            Self::Mail => Facility::LOG_MAIL,
-            Self::Daemon => Facility::LOG_DAEMON,
-            Self::Auth => Facility::LOG_AUTH,
-            Self::Syslog => Facility::LOG_SYSLOG,
-            Self::Lpr => Facility::LOG_LPR,
-            Self::News => Facility::LOG_NEWS,
-            Self::Uucp => Facility::LOG_UUCP,
-            Self::Cron => Facility::LOG_CRON,
-            Self::AuthPriv => Facility::LOG_AUTHPRIV,
-            Self::Ftp => Facility::LOG_FTP,
-            Self::Local0 => Facility::LOG_LOCAL0,
-            Self::Local1 => Facility::LOG_LOCAL1,
-            Self::Local2 => Facility::LOG_LOCAL2,
-            Self::Local3 => Facility::LOG_LOCAL3,
-            Self::Local4 => Facility::LOG_LOCAL4,
-            Self::Local5 => Facility::LOG_LOCAL5,
-            Self::Local6 => Facility::LOG_LOCAL6,
-            Self::Local7 => Facility::LOG_LOCAL7,
-        }
+    /// Serializes the configuration to TOML, once the configuration file, the environment
+    /// overrides and the default values are merged. This is what the application runs with, and
+    /// it can be loaded back as a configuration file.
+    ///
+    /// The secrets, such as passwords, are redacted with `#[serde(serialize_with =
+    /// "serialize_redacted")]`.
+    pub fn effective_toml(&self) -> String {
+        // The values are converted first, as the serializer only accepts the tables once every
+        // other value of their parent is written.
+        toml::Value::try_from(self)
+            .and_then(|value| toml::to_string(&value))
+            .expect("The configuration is serializable to TOML")
     }
-}
 
-impl<'de> Deserialize<'de> for SyslogFacility {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        struct SyslogFacilityVisitor;
+    /// Summarizes the configuration the broker runs with on a single line of `key=value` pairs:
+    /// the addresses of the listeners, the log level, the number of queues declared by the
+    /// configuration, across every virtual host, and whether TLS and the authentication are
+    /// enabled. The paths, such as the ones of the TLS certificates, are left out.
+    pub fn summary(&self) -> String {
+        let listeners: Vec<_> = self
+            .network
+            .effective_listeners()
+            .iter()
+            .map(|listener| match listener.protocol {
+                ListenerProtocol::Native => listener.address().to_string(),
+                ListenerProtocol::Text => format!("{}/text", listener.address()),
+            })
+            .collect();
+        let queues = self.queue.definitions.len()
+            + self
+                .vhosts
+                .iter()
+                .map(|vhost| vhost.definitions.len())
+                .sum::<usize>();
 
-        impl<'de> Visitor<'de> for SyslogFacilityVisitor {
-            type Value = SyslogFacility;
+        format!(
+            "listeners={} unix_socket={} log_level={} queues={} tls={} auth={}",
+            listeners.join(","),
+            self.network.unix_socket.is_some(),
+            self.log.level.to_string().to_ascii_lowercase(),
+            queues,
+            self.network.tls.is_some(),
+            !self.auth.allows_anonymous()
+        )
+    }
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("Expecting a syslog facility")
-            }
+    /// Checks that each namespace of the configuration holds a usable configuration. The values
+    /// which default to another field, like the port of the syslog server, are filled in.
+    ///
+    /// Only the first error is returned, use [`Config::validation_errors`] to get all of them.
+    pub fn validate(&mut self) -> Result<(), ConfigError> {
+        match self.validation_errors().into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
 
-            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                match value {
-                    "kern" => Ok(Self::Value::Kern),
-                    "user" => Ok(Self::Value::User),
-                    "mail" => Ok(Self::Value::Mail),
-                    "daemon" => Ok(Self::Value::Daemon),
-                    "auth" => Ok(Self::Value::Auth),
-                    "syslog" => Ok(Self::Value::Syslog),
-                    "lpr" => Ok(Self::Value::Lpr),
-                    "news" => Ok(Self::Value::News),
-                    "uucp" => Ok(Self::Value::Uucp),
-                    "cron" => Ok(Self::Value::Cron),
-                    "authpriv" => Ok(Self::Value::AuthPriv),
-                    "ftp" => Ok(Self::Value::Ftp),
-                    "local0" => Ok(Self::Value::Local0),
-                    "local1" => Ok(Self::Value::Local1),
-                    "local2" => Ok(Self::Value::Local2),
-                    "local3" => Ok(Self::Value::Local3),
-                    "local4" => Ok(Self::Value::Local4),
-                    "local5" => Ok(Self::Value::Local5),
-                    "local6" => Ok(Self::Value::Local6),
-                    "local7" => Ok(Self::Value::Local7),
-                    _ => Err(de::Error::invalid_value(
-                        Unexpected::Str(value),
-                        &"Unknown syslog facility!",
-                    )),
-                }
-            }
+    /// Checks that each namespace of the configuration holds a usable configuration, like
+    /// [`Config::validate`], but returns every error found instead of the first one.
+    pub fn validation_errors(&mut self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+
+        self.admin
+            .validate(&self.auth, &self.network, &self.metrics, &mut errors);
+        self.auth.validate(&self.queue, &self.vhosts, &mut errors);
+        self.log.validate(&mut errors);
+        self.network.validate(&mut errors);
+        self.metrics.validate(&self.network, &mut errors);
+        self.queue.validate(&mut errors);
+        for (i, vhost) in self.vhosts.iter().enumerate() {
+            vhost.validate(i, &self.vhosts[..i], &self.queue, &mut errors);
         }
 
-        deserializer.deserialize_enum(
-            "SyslogFacility",
-            &[
-                "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron",
-                "authpriv", "ftp", "local0", "local1", "local2", "local3", "local4", "local5",
-                "local6", "local7",
-            ],
-            SyslogFacilityVisitor,
-        )
+        errors
     }
-}
 
-/// The network namespace of the application's configuration. It's here where the
-/// hostname and the port used for instance.
-#[derive(Clone, Debug, Deserialize)]
-pub struct Network {
-    /// The hostname that the application should use to open its sockets.
-    #[serde(default = "Network::default_hostname")]
-    pub hostname: IpAddr,
+    /// Checks that the addresses of the listeners of the network namespace, then the ones of the
+    /// metrics and of the admin API if they're enabled, can be bound (see
+    /// [`Network::check_bindable`]).
+    pub fn check_bindable(&self) -> Result<(), ConfigError> {
+        self.network.check_bindable()?;
 
-    /// The port to use to open the application's sockets.
-    #[serde(default = "Network::default_port")]
-    pub port: u16,
-}
+        let http_listeners = [
+            (
+                self.metrics.enabled,
+                "metrics.bind_address",
+                self.metrics.address(),
+            ),
+            (
+                self.admin.enabled,
+                "admin.bind_address",
+                self.admin.address(),
+            ),
+        ];
+        for (enabled, field, address) in http_listeners {
+            if enabled {
+                TcpListener::bind(address).map_err(|err| bind_error(field.into(), address, err))?;
+            }
+        }
 
-impl Network {
-    fn default_hostname() -> IpAddr {
-        DEFAULT_LISTENER_HOSTNAME
+        Ok(())
     }
 
-    fn default_port() -> u16 {
-        DEFAULT_LISTENER_PORT
+    /// Returns `true` if the virtual host named `name` is defined, the default virtual host
+    /// always being.
+    pub fn has_vhost(&self, name: &str) -> bool {
+        name == DEFAULT_VHOST || self.vhosts.iter().any(|vhost| vhost.name == name)
     }
-}
 
-impl Default for Network {
-    fn default() -> Self {
-        Self {
-            hostname: Self::default_hostname(),
-            port: Self::default_port(),
+    /// Overrides the values of the configuration with the ones defined in the environment.
+    ///
+    /// Each variable is named after the namespace and the field it overrides, prefixed by
+    /// `ANOTHERMQ_`. The values are parsed into the type of the field they override.
+    ///
+    /// | Variable                                            | Field                                     |
+    /// | --------------------------------------------------- | ----------------------------------------- |
+    /// | `ANOTHERMQ_ADMIN_ENABLED`                           | `admin.enabled`                           |
+    /// | `ANOTHERMQ_ADMIN_PORT`                              | `admin.port`                              |
+    /// | `ANOTHERMQ_ADMIN_BIND_ADDRESS`                      | `admin.bind_address`                      |
+    /// | `ANOTHERMQ_ADMIN_ALLOW_MUTATIONS`                   | `admin.allow_mutations`                   |
+    /// | `ANOTHERMQ_LOG_LEVEL`                               | `log.level`                               |
+    /// | `ANOTHERMQ_LOG_FORMAT`                              | `log.format`                              |
+    /// | `ANOTHERMQ_LOG_TIMESTAMP_FORMAT`                    | `log.timestamp_format`                    |
+    /// | `ANOTHERMQ_LOG_UTC`                                 | `log.utc`                                 |
+    /// | `ANOTHERMQ_LOG_STDOUT`                              | `log.stdout`                              |
+    /// | `ANOTHERMQ_LOG_FILE`                                | `log.file`                                |
+    /// | `ANOTHERMQ_LOG_FILE_ENABLED`                        | `log.file_enabled`                        |
+    /// | `ANOTHERMQ_LOG_ASYNC_BUFFER`                        | `log.async_buffer`                        |
+    /// | `ANOTHERMQ_LOG_FLUSH_INTERVAL`                      | `log.flush_interval`                      |
+    /// | `ANOTHERMQ_LOG_SYSLOG_ENABLED`                      | `log.syslog.enabled`                      |
+    /// | `ANOTHERMQ_LOG_SYSLOG_HOST`                         | `log.syslog.host`                         |
+    /// | `ANOTHERMQ_LOG_SYSLOG_PORT`                         | `log.syslog.port`                         |
+    /// | `ANOTHERMQ_LOG_SYSLOG_PROTOCOL`                     | `log.syslog.protocol`                     |
+    /// | `ANOTHERMQ_LOG_SYSLOG_TRANSPORT`                    | `log.syslog.transport`                    |
+    /// | `ANOTHERMQ_LOG_SYSLOG_FACILITY`                     | `log.syslog.facility`                     |
+    /// | `ANOTHERMQ_LOG_SYSLOG_PROCESS`                      | `log.syslog.process`                      |
+    /// | `ANOTHERMQ_LOG_SYSLOG_INCLUDE_PID`                  | `log.syslog.include_pid`                  |
+    /// | `ANOTHERMQ_LOG_SYSLOG_APP_NAME`                     | `log.syslog.app_name`                     |
+    /// | `ANOTHERMQ_LOG_SYSLOG_RECONNECT_INTERVAL`           | `log.syslog.reconnect_interval`           |
+    /// | `ANOTHERMQ_LOG_SYSLOG_MAX_RECONNECT_ATTEMPTS`       | `log.syslog.max_reconnect_attempts`       |
+    /// | `ANOTHERMQ_METRICS_ENABLED`                         | `metrics.enabled`                         |
+    /// | `ANOTHERMQ_METRICS_PORT`                            | `metrics.port`                            |
+    /// | `ANOTHERMQ_METRICS_BIND_ADDRESS`                    | `metrics.bind_address`                    |
+    /// | `ANOTHERMQ_NETWORK_HOSTNAME`                        | `network.hostname`                        |
+    /// | `ANOTHERMQ_NETWORK_PORT`                            | `network.port`                            |
+    /// | `ANOTHERMQ_NETWORK_UNIX_SOCKET`                     | `network.unix_socket`                     |
+    /// | `ANOTHERMQ_NETWORK_BACKLOG`                         | `network.backlog`                         |
+    /// | `ANOTHERMQ_NETWORK_WORKER_THREADS`                  | `network.worker_threads`                  |
+    /// | `ANOTHERMQ_NETWORK_MAX_CONNECTIONS`                 | `network.max_connections`                 |
+    /// | `ANOTHERMQ_NETWORK_REUSE_ADDRESS`                   | `network.reuse_address`                   |
+    /// | `ANOTHERMQ_NETWORK_REUSE_PORT`                      | `network.reuse_port`                      |
+    /// | `ANOTHERMQ_NETWORK_PROXY_PROTOCOL`                  | `network.proxy_protocol`                  |
+    /// | `ANOTHERMQ_NETWORK_ACCEPT_BACKOFF`                  | `network.accept_backoff`                  |
+    /// | `ANOTHERMQ_NETWORK_SHUTDOWN_TIMEOUT`                | `network.shutdown_timeout`                |
+    /// | `ANOTHERMQ_NETWORK_IDLE_TIMEOUT`                    | `network.idle_timeout`                    |
+    /// | `ANOTHERMQ_NETWORK_HANDSHAKE_TIMEOUT`               | `network.handshake_timeout`               |
+    /// | `ANOTHERMQ_NETWORK_HEARTBEAT`                       | `network.heartbeat`                       |
+    /// | `ANOTHERMQ_NETWORK_MAX_FRAME_BYTES`                 | `network.max_frame_bytes`                 |
+    /// | `ANOTHERMQ_NETWORK_TCP_NODELAY`                     | `network.tcp_nodelay`                     |
+    /// | `ANOTHERMQ_NETWORK_TCP_KEEPALIVE`                   | `network.tcp_keepalive`                   |
+    /// | `ANOTHERMQ_NETWORK_RECV_BUFFER_BYTES`               | `network.recv_buffer_bytes`               |
+    /// | `ANOTHERMQ_NETWORK_SEND_BUFFER_BYTES`               | `network.send_buffer_bytes`               |
+    /// | `ANOTHERMQ_NETWORK_RATE_LIMIT_MESSAGES_PER_SECOND`  | `network.rate_limit.messages_per_second`  |
+    /// | `ANOTHERMQ_NETWORK_RATE_LIMIT_BYTES_PER_SECOND`     | `network.rate_limit.bytes_per_second`     |
+    /// | `ANOTHERMQ_NETWORK_RATE_LIMIT_DISCONNECT_ON_EXCEED` | `network.rate_limit.disconnect_on_exceed` |
+    /// | `ANOTHERMQ_QUEUE_MAX_MESSAGE_BYTES`                 | `queue.max_message_bytes`                 |
+    /// | `ANOTHERMQ_QUEUE_MAX_UNACKED_TOTAL`                 | `queue.max_unacked_total`                 |
+    /// | `ANOTHERMQ_QUEUE_DEFAULT_QUEUE`                     | `queue.default_queue`                     |
+    /// | `ANOTHERMQ_QUEUE_EXPIRY_SCAN_INTERVAL`              | `queue.expiry_scan_interval`              |
+    /// | `ANOTHERMQ_QUEUE_DEFAULT_MESSAGE_TTL`               | `queue.default_message_ttl`               |
+    /// | `ANOTHERMQ_QUEUE_COMPRESSION`                       | `queue.compression`                       |
+    /// | `ANOTHERMQ_QUEUE_COMPRESSION_THRESHOLD_BYTES`       | `queue.compression_threshold_bytes`       |
+    /// | `ANOTHERMQ_QUEUE_STORAGE_PATH`                      | `queue.storage.path`                      |
+    /// | `ANOTHERMQ_QUEUE_STORAGE_SYNC`                      | `queue.storage.sync`                      |
+    /// | `ANOTHERMQ_QUEUE_STORAGE_WAIT_READY`                | `queue.storage.wait_ready`                |
+    /// | `ANOTHERMQ_QUEUE_MEMORY_ONLY`                       | `queue.memory_only`                       |
+    /// | `ANOTHERMQ_QUEUE_ENABLE_DELAYED`                    | `queue.enable_delayed`                    |
+    /// | `ANOTHERMQ_QUEUE_ENABLE_SELECTORS`                  | `queue.enable_selectors`                  |
+    ///
+    /// Setting one of the `ANOTHERMQ_LOG_SYSLOG_*` variables enables the syslog configuration if
+    /// it was not defined in the configuration file. Likewise, setting one of the
+    /// `ANOTHERMQ_NETWORK_RATE_LIMIT_*` variables enables the rate limit, and setting one of the
+    /// `ANOTHERMQ_QUEUE_STORAGE_*` variables enables the storage, whose path must then be defined.
+    ///
+    /// If one of the variables could not be parsed, an error is returned and the configuration is
+    /// left untouched.
+    pub fn apply_env_overrides(&mut self) -> Result<(), ConfigError> {
+        let mut config = self.clone();
+
+        if let Some(enabled) = env_override("ANOTHERMQ_ADMIN_ENABLED")? {
+            config.admin.enabled = enabled;
         }
+        if let Some(port) = env_override("ANOTHERMQ_ADMIN_PORT")? {
+            config.admin.port = port;
+        }
+        if let Some(bind_address) = env_override("ANOTHERMQ_ADMIN_BIND_ADDRESS")? {
+            config.admin.bind_address = Some(bind_address);
+        }
+        if let Some(allow_mutations) = env_override("ANOTHERMQ_ADMIN_ALLOW_MUTATIONS")? {
+            config.admin.allow_mutations = allow_mutations;
+        }
+
+        if let Some(level) = env_override("ANOTHERMQ_LOG_LEVEL")? {
+            config.log.level = level;
+        }
+        if let Some(format) = env_override("ANOTHERMQ_LOG_FORMAT")? {
+            config.log.format = format;
+        }
+        if let Some(timestamp_format) = env_override("ANOTHERMQ_LOG_TIMESTAMP_FORMAT")? {
+            config.log.timestamp_format = Some(timestamp_format);
+        }
+        if let Some(utc) = env_override("ANOTHERMQ_LOG_UTC")? {
+            config.log.utc = utc;
+        }
+        if let Some(stdout) = env_override("ANOTHERMQ_LOG_STDOUT")? {
+            config.log.stdout = stdout;
+        }
+        if let Some(file) = env_override("ANOTHERMQ_LOG_FILE")? {
+            config.log.file = Some(file);
+        }
+        if let Some(file_enabled) = env_override("ANOTHERMQ_LOG_FILE_ENABLED")? {
+            config.log.file_enabled = file_enabled;
+        }
+        if let Some(async_buffer) = env_override("ANOTHERMQ_LOG_ASYNC_BUFFER")? {
+            config.log.async_buffer = Some(async_buffer);
+        }
+        if let Some(flush_interval) =
+            env_override::<humantime::Duration>("ANOTHERMQ_LOG_FLUSH_INTERVAL")?
+        {
+            config.log.flush_interval = Some(flush_interval.into());
+        }
+
+        let syslog_enabled = env_override("ANOTHERMQ_LOG_SYSLOG_ENABLED")?;
+        let syslog_host = env_override("ANOTHERMQ_LOG_SYSLOG_HOST")?;
+        let syslog_port = env_override("ANOTHERMQ_LOG_SYSLOG_PORT")?;
+        let syslog_protocol = env_override("ANOTHERMQ_LOG_SYSLOG_PROTOCOL")?;
+        let syslog_transport = env_override("ANOTHERMQ_LOG_SYSLOG_TRANSPORT")?;
+        let syslog_facility = env_override("ANOTHERMQ_LOG_SYSLOG_FACILITY")?;
+        let syslog_process = env_override("ANOTHERMQ_LOG_SYSLOG_PROCESS")?;
+        let syslog_include_pid = env_override("ANOTHERMQ_LOG_SYSLOG_INCLUDE_PID")?;
+        let syslog_app_name = env_override("ANOTHERMQ_LOG_SYSLOG_APP_NAME")?;
+        let syslog_reconnect_interval =
+            env_override::<humantime::Duration>("ANOTHERMQ_LOG_SYSLOG_RECONNECT_INTERVAL")?;
+        let syslog_max_reconnect_attempts =
+            env_override("ANOTHERMQ_LOG_SYSLOG_MAX_RECONNECT_ATTEMPTS")?;
+
+        if syslog_enabled.is_some()
+            || syslog_host.is_some()
+            || syslog_port.is_some()
+            || syslog_protocol.is_some()
+            || syslog_transport.is_some()
+            || syslog_facility.is_some()
+            || syslog_process.is_some()
+            || syslog_include_pid.is_some()
+            || syslog_app_name.is_some()
+            || syslog_reconnect_interval.is_some()
+            || syslog_max_reconnect_attempts.is_some()
+        {
+            let syslog = config.log.syslog.get_or_insert_with(Syslog::default);
+
+            if let Some(enabled) = syslog_enabled {
+                syslog.enabled = enabled;
+            }
+            if syslog_host.is_some() {
+                syslog.host = syslog_host;
+            }
+            if syslog_port.is_some() {
+                syslog.port = syslog_port;
+            }
+            if let Some(protocol) = syslog_protocol {
+                syslog.protocol = protocol;
+            }
+            if let Some(transport) = syslog_transport {
+                syslog.transport = transport;
+            }
+            if let Some(facility) = syslog_facility {
+                syslog.facility = facility;
+            }
+            if let Some(process) = syslog_process {
+                syslog.process = process;
+            }
+            if let Some(include_pid) = syslog_include_pid {
+                syslog.include_pid = include_pid;
+            }
+            if syslog_app_name.is_some() {
+                syslog.app_name = syslog_app_name;
+            }
+            if let Some(interval) = syslog_reconnect_interval {
+                syslog.reconnect_interval = Some(interval.into());
+            }
+            if syslog_max_reconnect_attempts.is_some() {
+                syslog.max_reconnect_attempts = syslog_max_reconnect_attempts;
+            }
+        }
+
+        if let Some(enabled) = env_override("ANOTHERMQ_METRICS_ENABLED")? {
+            config.metrics.enabled = enabled;
+        }
+        if let Some(port) = env_override("ANOTHERMQ_METRICS_PORT")? {
+            config.metrics.port = port;
+        }
+        if let Some(bind_address) = env_override("ANOTHERMQ_METRICS_BIND_ADDRESS")? {
+            config.metrics.bind_address = Some(bind_address);
+        }
+
+        if let Some(hostname) = env_override("ANOTHERMQ_NETWORK_HOSTNAME")? {
+            config.network.hostname = hostname;
+        }
+        if let Some(port) = env_override("ANOTHERMQ_NETWORK_PORT")? {
+            config.network.port = port;
+        }
+        if let Some(unix_socket) = env_override("ANOTHERMQ_NETWORK_UNIX_SOCKET")? {
+            config.network.unix_socket = Some(unix_socket);
+        }
+        if let Some(backlog) = env_override("ANOTHERMQ_NETWORK_BACKLOG")? {
+            config.network.backlog = Some(backlog);
+        }
+        if let Some(worker_threads) = env_override("ANOTHERMQ_NETWORK_WORKER_THREADS")? {
+            config.network.worker_threads = Some(worker_threads);
+        }
+        if let Some(max_connections) = env_override("ANOTHERMQ_NETWORK_MAX_CONNECTIONS")? {
+            config.network.max_connections = Some(max_connections);
+        }
+        if let Some(reuse_address) = env_override("ANOTHERMQ_NETWORK_REUSE_ADDRESS")? {
+            config.network.reuse_address = reuse_address;
+        }
+        if let Some(reuse_port) = env_override("ANOTHERMQ_NETWORK_REUSE_PORT")? {
+            config.network.reuse_port = reuse_port;
+        }
+        if let Some(proxy_protocol) = env_override("ANOTHERMQ_NETWORK_PROXY_PROTOCOL")? {
+            config.network.proxy_protocol = proxy_protocol;
+        }
+        if let Some(backoff) =
+            env_override::<humantime::Duration>("ANOTHERMQ_NETWORK_ACCEPT_BACKOFF")?
+        {
+            config.network.accept_backoff = backoff.into();
+        }
+        if let Some(timeout) =
+            env_override::<humantime::Duration>("ANOTHERMQ_NETWORK_SHUTDOWN_TIMEOUT")?
+        {
+            config.network.shutdown_timeout = timeout.into();
+        }
+        if let Some(timeout) =
+            env_override::<humantime::Duration>("ANOTHERMQ_NETWORK_IDLE_TIMEOUT")?
+        {
+            config.network.idle_timeout = Some(timeout.into());
+        }
+        if let Some(timeout) =
+            env_override::<humantime::Duration>("ANOTHERMQ_NETWORK_HANDSHAKE_TIMEOUT")?
+        {
+            config.network.handshake_timeout = timeout.into();
+        }
+        if let Some(heartbeat) = env_override::<humantime::Duration>("ANOTHERMQ_NETWORK_HEARTBEAT")?
+        {
+            config.network.heartbeat = Some(heartbeat.into());
+        }
+        if let Some(max_frame_bytes) = env_override("ANOTHERMQ_NETWORK_MAX_FRAME_BYTES")? {
+            config.network.max_frame_bytes = max_frame_bytes;
+        }
+        if let Some(nodelay) = env_override("ANOTHERMQ_NETWORK_TCP_NODELAY")? {
+            config.network.tcp_nodelay = nodelay;
+        }
+        if let Some(keepalive) =
+            env_override::<humantime::Duration>("ANOTHERMQ_NETWORK_TCP_KEEPALIVE")?
+        {
+            config.network.tcp_keepalive = Some(keepalive.into());
+        }
+        if let Some(recv_buffer_bytes) = env_override("ANOTHERMQ_NETWORK_RECV_BUFFER_BYTES")? {
+            config.network.recv_buffer_bytes = Some(recv_buffer_bytes);
+        }
+        if let Some(send_buffer_bytes) = env_override("ANOTHERMQ_NETWORK_SEND_BUFFER_BYTES")? {
+            config.network.send_buffer_bytes = Some(send_buffer_bytes);
+        }
+
+        let rate_limit_messages = env_override("ANOTHERMQ_NETWORK_RATE_LIMIT_MESSAGES_PER_SECOND")?;
+        let rate_limit_bytes = env_override("ANOTHERMQ_NETWORK_RATE_LIMIT_BYTES_PER_SECOND")?;
+        let rate_limit_disconnect =
+            env_override("ANOTHERMQ_NETWORK_RATE_LIMIT_DISCONNECT_ON_EXCEED")?;
+
+        if rate_limit_messages.is_some()
+            || rate_limit_bytes.is_some()
+            || rate_limit_disconnect.is_some()
+        {
+            let rate_limit = config.network.rate_limit.get_or_insert(RateLimit {
+                messages_per_second: None,
+                bytes_per_second: None,
+                disconnect_on_exceed: false,
+            });
+
+            if rate_limit_messages.is_some() {
+                rate_limit.messages_per_second = rate_limit_messages;
+            }
+            if rate_limit_bytes.is_some() {
+                rate_limit.bytes_per_second = rate_limit_bytes;
+            }
+            if let Some(disconnect) = rate_limit_disconnect {
+                rate_limit.disconnect_on_exceed = disconnect;
+            }
+        }
+
+        if let Some(max_message_bytes) = env_override("ANOTHERMQ_QUEUE_MAX_MESSAGE_BYTES")? {
+            config.queue.max_message_bytes = Some(max_message_bytes);
+        }
+        if let Some(max_unacked) = env_override("ANOTHERMQ_QUEUE_MAX_UNACKED_TOTAL")? {
+            config.queue.max_unacked_total = Some(max_unacked);
+        }
+        if let Some(default_queue) = env_override("ANOTHERMQ_QUEUE_DEFAULT_QUEUE")? {
+            config.queue.default_queue = Some(default_queue);
+        }
+
+        if let Some(interval) =
+            env_override::<humantime::Duration>("ANOTHERMQ_QUEUE_EXPIRY_SCAN_INTERVAL")?
+        {
+            config.queue.expiry_scan_interval = interval.into();
+        }
+        if let Some(ttl) =
+            env_override::<humantime::Duration>("ANOTHERMQ_QUEUE_DEFAULT_MESSAGE_TTL")?
+        {
+            config.queue.default_message_ttl = Some(ttl.into());
+        }
+
+        if let Some(compression) = env_override("ANOTHERMQ_QUEUE_COMPRESSION")? {
+            config.queue.compression = Some(compression);
+        }
+        if let Some(threshold) = env_override("ANOTHERMQ_QUEUE_COMPRESSION_THRESHOLD_BYTES")? {
+            config.queue.compression_threshold_bytes = threshold;
+        }
+
+        let storage_path = env_override("ANOTHERMQ_QUEUE_STORAGE_PATH")?;
+        let storage_sync = env_override("ANOTHERMQ_QUEUE_STORAGE_SYNC")?;
+        let storage_wait_ready =
+            env_override::<humantime::Duration>("ANOTHERMQ_QUEUE_STORAGE_WAIT_READY")?;
+
+        if storage_path.is_some() || storage_sync.is_some() || storage_wait_ready.is_some() {
+            let storage = config.queue.storage.get_or_insert_with(|| StorageConfig {
+                path: String::new(),
+                sync: false,
+                wait_ready: None,
+            });
+
+            if let Some(path) = storage_path {
+                storage.path = path;
+            }
+            if let Some(sync) = storage_sync {
+                storage.sync = sync;
+            }
+            if let Some(wait_ready) = storage_wait_ready {
+                storage.wait_ready = Some(wait_ready.into());
+            }
+        }
+        if let Some(memory_only) = env_override("ANOTHERMQ_QUEUE_MEMORY_ONLY")? {
+            config.queue.memory_only = memory_only;
+        }
+        if let Some(enable_delayed) = env_override("ANOTHERMQ_QUEUE_ENABLE_DELAYED")? {
+            config.queue.enable_delayed = enable_delayed;
+        }
+        if let Some(enable_selectors) = env_override("ANOTHERMQ_QUEUE_ENABLE_SELECTORS")? {
+            config.queue.enable_selectors = enable_selectors;
+        }
+
+        *self = config;
+
+        Ok(())
+    }
+}
+
+/// Merges the files included by the configuration file at `path`, whose content is `value`, into
+/// it. `including` holds the files being included, to detect the circular includes.
+///
+/// The value of each file loaded is pushed to `sources`, in the order they are merged.
+fn merge_includes(
+    path: &Path,
+    mut value: toml::Value,
+    including: &mut Vec<PathBuf>,
+    sources: &mut Vec<(PathBuf, toml::Value)>,
+) -> Result<toml::Value, ConfigError> {
+    let includes = match value
+        .as_table_mut()
+        .and_then(|table| table.remove("include"))
+    {
+        Some(toml::Value::Array(includes)) => includes,
+        Some(_) => {
+            return Err(ConfigError::Invalid {
+                field: "include".into(),
+                reason: "expected a list of paths".into(),
+            })
+        }
+        None => {
+            sources.push((path.to_path_buf(), value.clone()));
+            return Ok(value);
+        }
+    };
+
+    let canonical = fs::canonicalize(path)?;
+    including.push(canonical);
+
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut merged = toml::Value::Table(Default::default());
+    for include in includes {
+        let include = match include {
+            toml::Value::String(include) => directory.join(include),
+            _ => {
+                return Err(ConfigError::Invalid {
+                    field: "include".into(),
+                    reason: "expected a list of paths".into(),
+                })
+            }
+        };
+
+        let included = load_include(&include, including, sources).map_err(|err| match err {
+            err @ ConfigError::Include { .. } => err,
+            err => ConfigError::Include {
+                path: include.display().to_string(),
+                reason: err.to_string(),
+            },
+        })?;
+        merge(&mut merged, included);
+    }
+    sources.push((path.to_path_buf(), value.clone()));
+    merge(&mut merged, value);
+
+    including.pop();
+
+    Ok(merged)
+}
+
+/// Loads a file included by the configuration file, along with the files it includes.
+fn load_include(
+    path: &Path,
+    including: &mut Vec<PathBuf>,
+    sources: &mut Vec<(PathBuf, toml::Value)>,
+) -> Result<toml::Value, ConfigError> {
+    if including.contains(&fs::canonicalize(path)?) {
+        return Err(ConfigError::Include {
+            path: path.display().to_string(),
+            reason: "the file is already being included".into(),
+        });
+    }
+
+    let format = ConfigFormat::from_path(path)?;
+    let value = format.parse_value(&interpolate(&fs::read_to_string(path)?)?)?;
+
+    merge_includes(path, value, including, sources)
+}
+
+/// Replaces the `${VAR}` references of the content of a configuration file by the values of their
+/// environment variables, `$${` being replaced by a literal `${`.
+fn interpolate(raw: &str) -> Result<String, ConfigError> {
+    let mut interpolated = String::with_capacity(raw.len());
+    let mut rest = raw;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            interpolated.push_str(&rest[..start - 1]);
+            interpolated.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        interpolated.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let end = rest.find('}').unwrap_or(rest.len());
+        let name = &rest[..end];
+        if end == rest.len()
+            || name.is_empty()
+            || !name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_')
+        {
+            let reference: String = rest.chars().take_while(|&c| c != '\n').collect();
+
+            return Err(ConfigError::Interpolation {
+                reason: format!("${{{} is not a reference to a variable", reference),
+            });
+        }
+
+        match env::var(name) {
+            Ok(value) => interpolated.push_str(&value),
+            Err(env::VarError::NotPresent) => {
+                return Err(ConfigError::Interpolation {
+                    reason: format!("the variable {} is not set", name),
+                })
+            }
+            Err(env::VarError::NotUnicode(_)) => {
+                return Err(ConfigError::Interpolation {
+                    reason: format!("the value of the variable {} is not valid unicode", name),
+                })
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    interpolated.push_str(rest);
+
+    Ok(interpolated)
+}
+
+/// Merges `value` into `base`. The tables are merged key by key, any other value of `value`
+/// replacing the one of `base`.
+fn merge(base: &mut toml::Value, value: toml::Value) {
+    match (base, value) {
+        (toml::Value::Table(base), toml::Value::Table(table)) => {
+            for (key, value) in table {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// Returns the error of the address at `field`, which could not be bound because of `err`.
+fn bind_error(field: String, address: SocketAddr, err: io::Error) -> ConfigError {
+    let reason = match err.kind() {
+        io::ErrorKind::AddrInUse => format!("the address {} is already in use", address),
+        io::ErrorKind::AddrNotAvailable => {
+            format!("the address {} is not an address of this host", address)
+        }
+        io::ErrorKind::PermissionDenied => {
+            format!("the address {} can't be bound without privileges", address)
+        }
+        _ => format!("could not bind the address {}: {}", address, err),
+    };
+
+    ConfigError::Invalid { field, reason }
+}
+
+/// A step of the path leading to a value of the configuration.
+enum KeySegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Checks that every key of `value`, the merged configuration files, is known by `config`.
+///
+/// The known keys are the ones of the serialized configuration: every field which is defined in
+/// a file is serialized, the optional fields only being omitted when they are undefined. The
+/// unknown key is reported along with the last file of `sources` defining it, which is the one
+/// whose value is used.
+fn check_unknown_keys(
+    config: &Config,
+    value: &toml::Value,
+    sources: &[(PathBuf, toml::Value)],
+) -> Result<(), ConfigError> {
+    let known = toml::Value::try_from(config).expect("The configuration is serializable to TOML");
+    let segments = match find_unknown_key(value, &known) {
+        Some(segments) => segments,
+        None => return Ok(()),
+    };
+
+    let mut key = String::new();
+    for segment in &segments {
+        match segment {
+            KeySegment::Key(name) if key.is_empty() => key.push_str(name),
+            KeySegment::Key(name) => {
+                key.push('.');
+                key.push_str(name);
+            }
+            KeySegment::Index(index) => key.push_str(&format!("[{}]", index)),
+        }
+    }
+    let path = sources
+        .iter()
+        .rev()
+        .find(|(_, value)| lookup(value, &segments))
+        .map_or_else(String::new, |(path, _)| path.display().to_string());
+
+    Err(ConfigError::UnknownKey { key, path })
+}
+
+/// Returns the path of the first key of `value` which is not in `known`.
+fn find_unknown_key(value: &toml::Value, known: &toml::Value) -> Option<Vec<KeySegment>> {
+    let (segment, mut segments) = match (value, known) {
+        (toml::Value::Table(table), toml::Value::Table(known)) => {
+            table.iter().find_map(|(key, value)| {
+                let segments = match known.get(key) {
+                    Some(known) => find_unknown_key(value, known)?,
+                    None => Vec::new(),
+                };
+                Some((KeySegment::Key(key.clone()), segments))
+            })?
+        }
+        (toml::Value::Array(array), toml::Value::Array(known)) => array
+            .iter()
+            .zip(known)
+            .enumerate()
+            .find_map(|(index, (value, known))| {
+                Some((KeySegment::Index(index), find_unknown_key(value, known)?))
+            })?,
+        _ => return None,
+    };
+
+    segments.insert(0, segment);
+    Some(segments)
+}
+
+/// Returns whether `value` holds a value at the end of `segments`.
+fn lookup(value: &toml::Value, segments: &[KeySegment]) -> bool {
+    match segments.split_first() {
+        Some((KeySegment::Key(key), rest)) => value.get(key).is_some_and(|v| lookup(v, rest)),
+        Some((KeySegment::Index(index), rest)) => value.get(index).is_some_and(|v| lookup(v, rest)),
+        None => true,
+    }
+}
+
+/// Returns the path of the default configuration file.
+#[cfg(target_os = "windows")]
+fn config_file_path() -> PathBuf {
+    // Joining the components uses the separator of the platform, and it's not doubled when
+    // %APPDATA% ends with one.
+    if let Some(config_path) = env::var_os("APPDATA") {
+        return PathBuf::from(config_path)
+            .join("another-mq")
+            .join("another-mq.toml");
+    }
+
+    // The service accounts may have no %APPDATA%.
+    log::warn!("%APPDATA% is not defined, the configuration file is looked up in %ANOTHERMQ_HOME%");
+    let home_prefix = env::var_os("ANOTHERMQ_HOME").unwrap_or_else(|| "\\".into());
+
+    PathBuf::from(home_prefix)
+        .join("etc")
+        .join("another-mq")
+        .join("another-mq.toml")
+}
+
+/// Returns the path of the default configuration file.
+#[cfg(target_os = "macos")]
+fn config_file_path() -> PathBuf {
+    let install_prefix = Command::new("brew").arg("--prefix").output();
+    let install_prefix = match install_prefix {
+        Ok(output) => String::from_utf8(output.stdout).unwrap(),
+        Err(_) => env::var("ANOTHERMQ_HOME").unwrap_or_else(|_| "".into()),
+    };
+
+    (install_prefix + "/etc/another-mq/another-mq.toml").into()
+}
+
+/// Returns the path of the default configuration file.
+#[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+fn config_file_path() -> PathBuf {
+    let home_prefix = env::var("ANOTHERMQ_HOME").unwrap_or_else(|_| "".into());
+
+    (home_prefix + "/etc/another-mq/another-mq.toml").into()
+}
+
+/// Reads the environment variable `name` and parses its value into the type of the field it
+/// overrides. `None` is returned when the variable is not defined.
+fn env_override<T>(name: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let value = match env::var(name) {
+        Ok(value) => value,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(value)) => {
+            return Err(ConfigError::Env {
+                name: name.into(),
+                value: value.to_string_lossy().into_owned(),
+                reason: "the value is not valid unicode".into(),
+            });
+        }
+    };
+
+    match value.parse() {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(err) => Err(ConfigError::Env {
+            name: name.into(),
+            reason: err.to_string(),
+            value,
+        }),
+    }
+}
+
+/// The formats of the configuration files, chosen from their extension.
+///
+/// | Format | Extensions       | Feature |
+/// | ------ | ---------------- | ------- |
+/// | TOML   | `.toml` or none  |         |
+/// | YAML   | `.yaml`, `.yml`  | `yaml`  |
+/// | JSON   | `.json`          | `json`  |
+///
+/// > YAML files are only supported when `another-mq` is built with the `yaml` feature, which
+/// > requires the libyaml library. JSON files are only supported with the `json` feature.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Returns the format of a configuration file. A file without an extension is a TOML file.
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        let extension = match path.extension() {
+            Some(extension) => extension.to_string_lossy(),
+            None => return Ok(Self::Toml),
+        };
+
+        match extension.as_ref() {
+            "toml" => Ok(Self::Toml),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "json" => Ok(Self::Json),
+            _ => Err(ConfigError::Format {
+                extension: extension.into_owned(),
+                reason: "unknown extension".into(),
+            }),
+        }
+    }
+
+    /// Parses a document written in this format into the TOML value it stands for.
+    fn parse_value(self, raw: &str) -> Result<toml::Value, ConfigError> {
+        match self {
+            Self::Toml => Ok(toml::from_str(raw)?),
+            #[cfg(feature = "yaml")]
+            Self::Yaml => Ok(yaml::parse(raw)?),
+            #[cfg(feature = "json")]
+            Self::Json => Ok(json::parse(raw)?),
+            #[allow(unreachable_patterns)]
+            format => Err(ConfigError::Format {
+                extension: format.extension().into(),
+                reason: format!(
+                    "another-mq is built without the `{}` feature",
+                    format.extension()
+                ),
+            }),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "toml" => Ok(Self::Toml),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "json" => Ok(Self::Json),
+            _ => Err("Unknown configuration format!"),
+        }
+    }
+}
+
+/// The errors which can occur while loading the configuration of the application.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    Io(io::Error),
+
+    /// The configuration file does not exist, while it's reloaded or `ANOTHERMQ_REQUIRE_CONFIG`
+    /// requires one.
+    Missing { path: String },
+
+    /// The configuration file does not hold a valid configuration.
+    Parse(toml::de::Error),
+
+    /// The format of the configuration file is not supported.
+    Format { extension: String, reason: String },
+
+    /// An environment variable overriding the configuration holds an invalid value.
+    Env {
+        name: String,
+        value: String,
+        reason: String,
+    },
+
+    /// A field of the configuration holds a value which can't be used by the application.
+    Invalid { field: String, reason: String },
+
+    /// A file included by the configuration file could not be loaded.
+    Include { path: String, reason: String },
+
+    /// A key of the configuration file is not part of the configuration, which is only an error
+    /// when loading it in strict mode.
+    UnknownKey { key: String, path: String },
+
+    /// A variable referenced by the configuration file could not be interpolated.
+    Interpolation { reason: String },
+
+    /// A queue definition has the name of a queue defined before it in the same virtual host. The
+    /// names of the queues are case-sensitive, as in AMQP, so `tasks` and `Tasks` are two queues.
+    DuplicateQueue { field: String, name: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "could not read the configuration file: {}", err),
+            Self::Missing { path } => write!(f, "the configuration file {} does not exist", path),
+            Self::Parse(err) => write!(f, "invalid configuration file: {}", err),
+            Self::Format { extension, reason } => write!(
+                f,
+                "unsupported configuration file format .{}: {}",
+                extension, reason
+            ),
+            Self::Env {
+                name,
+                value,
+                reason,
+            } => write!(f, "invalid value {:?} for {}: {}", value, name, reason),
+            Self::Invalid { field, reason } => write!(f, "invalid value for {}: {}", field, reason),
+            Self::Include { path, reason } => {
+                write!(f, "could not include the file {}: {}", path, reason)
+            }
+            Self::UnknownKey { key, path } => write!(f, "unknown key {} in {}", key, path),
+            Self::Interpolation { reason } => {
+                write!(
+                    f,
+                    "could not interpolate the configuration file: {}",
+                    reason
+                )
+            }
+            Self::DuplicateQueue { field, name } => write!(
+                f,
+                "invalid value for {}: the queue {} is already defined",
+                field, name
+            ),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Parse(err) => Some(err),
+            Self::Missing { .. }
+            | Self::Format { .. }
+            | Self::Env { .. }
+            | Self::Invalid { .. }
+            | Self::Include { .. }
+            | Self::UnknownKey { .. }
+            | Self::Interpolation { .. }
+            | Self::DuplicateQueue { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Parse(err)
+    }
+}
+
+/// The admin namespace of the configuration.
+///
+/// When enabled, a read-only JSON API is served over HTTP to inspect the live state of the broker:
+///
+/// | Path           | Response                                                              |
+/// | -------------- | --------------------------------------------------------------------- |
+/// | `/queues`      | The queues, with their virtual host, messages and consumers          |
+/// | `/connections` | The open connections, with the address of their client and uptime    |
+/// | `/health`      | `{"status":"ok"}`, or a `503` status once the shutdown is requested  |
+///
+/// # Mutations
+///
+/// When `allow_mutations` is set, the queues can also be emptied or removed without restarting the
+/// broker, for instance to clean up after an incident:
+///
+/// | Request                       | Effect                                                      |
+/// | ----------------------------- | ----------------------------------------------------------- |
+/// | `POST /queues/<name>/purge`   | Drops the messages waiting in the queue                     |
+/// | `DELETE /queues/<name>`       | Removes the queue along with its messages                   |
+///
+/// The requests name a queue of the default virtual host, unless they name another one with a
+/// `vhost` query parameter. They must be authenticated with the Basic credentials of a user of
+/// the auth namespace, so they require the auth namespace to define users. A queue with
+/// consumers is only deleted if the request sets `force=true`, its consumers being cancelled,
+/// and the request is answered by `409 Conflict` otherwise. The queues defined by the
+/// configuration are declared again once it's reloaded.
+///
+/// The HTTP listener is bound on `127.0.0.1` unless `bind_address` is defined, rather than on the
+/// `hostname` of the network namespace, so the admin API is kept off the public interfaces.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Admin {
+    /// Whether the admin API is served. No HTTP listener is opened when disabled.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// The port of the HTTP listener serving the admin API.
+    #[serde(default = "Admin::default_port")]
+    pub port: u16,
+
+    /// The address of the HTTP listener serving the admin API, such as the one of a management
+    /// network. It's `127.0.0.1` when undefined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<IpAddr>,
+
+    /// Whether the queues can be purged and deleted through the admin API (see
+    /// [Mutations](Admin#mutations)). The API is read-only when unset.
+    #[serde(default)]
+    pub allow_mutations: bool,
+}
+
+impl Admin {
+    /// Checks that the admin namespace holds a usable configuration. The port of the HTTP
+    /// listener must not be one of the ports of the listeners of the network namespace, nor the
+    /// one of the metrics. The mutations require the auth namespace `auth` to define users. Each
+    /// error is added to `errors`.
+    pub fn validate(
+        &self,
+        auth: &Auth,
+        network: &Network,
+        metrics: &Metrics,
+        errors: &mut Vec<ConfigError>,
+    ) {
+        if !self.enabled {
+            if self.allow_mutations {
+                log::warn!("admin.allow_mutations is ignored, as the admin API is disabled");
+            }
+            return;
+        }
+
+        if self.allow_mutations && auth.allows_anonymous() {
+            errors.push(ConfigError::Invalid {
+                field: "admin.allow_mutations".into(),
+                reason: "the mutations require the auth namespace to define users".into(),
+            });
+        }
+
+        errors.extend(Network::validate_port("admin.port", self.port).err());
+
+        let reason = if network
+            .effective_listeners()
+            .iter()
+            .any(|listener| listener.port == self.port)
+        {
+            format!("the port {} is already used by a listener", self.port)
+        } else if metrics.enabled && metrics.port == self.port {
+            format!("the port {} is already used by the metrics", self.port)
+        } else {
+            return;
+        };
+        errors.push(ConfigError::Invalid {
+            field: "admin.port".into(),
+            reason,
+        });
+    }
+
+    /// Returns the socket address of the HTTP listener serving the admin API.
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(
+            self.bind_address.unwrap_or(DEFAULT_HTTP_BIND_ADDRESS),
+            self.port,
+        )
+    }
+
+    fn default_port() -> u16 {
+        DEFAULT_ADMIN_PORT
+    }
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: Self::default_port(),
+            bind_address: None,
+            allow_mutations: false,
+        }
+    }
+}
+
+/// The auth namespace of the configuration, holding the users allowed to connect to the broker.
+///
+/// > When no user is defined, every client can connect anonymously.
+///
+/// # Users file
+///
+/// So the password hashes are not committed along with the configuration file, the users can be
+/// defined by a separate TOML file holding `[[users]]` tables, referenced by `users_file` with a
+/// path relative to the configuration file. Its users are added to the ones of the namespace, a
+/// user being defined only once across both. A warning is logged if the file is readable by every
+/// user on Unix.
+///
+/// # Access control
+///
+/// The queues a user can publish to and consume from are restricted by the `[[auth.acl]]` rules,
+/// each granting a user the queues named by its patterns. A pattern is either the name of a queue
+/// defined by the queue namespace, or `*` which matches every queue:
+///
+/// ```toml
+/// [[auth.acl]]
+/// username = "producer"
+/// allow_publish = ["tasks"]
+/// allow_consume = ["results"]
+/// ```
+///
+/// A rule grants the queues of the default virtual host, unless it names another virtual host
+/// with `vhost = "staging"`, its patterns then naming the queues of this virtual host.
+///
+/// Once a rule is defined, the users are refused any queue which is not granted to them by a
+/// rule, including the users without rules. Without rules, every user can use every queue.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Auth {
+    /// The algorithm of the password hashes of the users, bcrypt by default.
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+
+    /// The users allowed to connect, defined by `[[auth.users]]` tables.
+    #[serde(default)]
+    pub users: Vec<User>,
+
+    /// The path of the file defining other users allowed to connect.
+    pub users_file: Option<String>,
+
+    /// The users loaded from the users file.
+    #[serde(skip)]
+    pub file_users: Vec<User>,
+
+    /// The rules granting the users the queues they can use, defined by `[[auth.acl]]` tables.
+    #[serde(default)]
+    pub acl: Vec<AclRule>,
+}
+
+impl Auth {
+    /// Returns `true` if the clients can connect without credentials, which is the case when no
+    /// user is defined.
+    pub fn allows_anonymous(&self) -> bool {
+        self.users().next().is_none()
+    }
+
+    /// Returns the users of the namespace, then the ones of the users file.
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.iter().chain(&self.file_users)
+    }
+
+    /// Loads the users of the users file, if the namespace references one. A relative path is
+    /// relative to `directory`, the directory of the configuration file.
+    fn load_users_file(&mut self, directory: &Path) -> Result<(), ConfigError> {
+        #[derive(Deserialize)]
+        struct UsersFile {
+            #[serde(default)]
+            users: Vec<User>,
+        }
+
+        let path = match &self.users_file {
+            Some(path) => directory.join(path),
+            None => return Ok(()),
+        };
+        let invalid = |reason: String| ConfigError::Invalid {
+            field: "auth.users_file".into(),
+            reason: format!("could not load {}: {}", path.display(), reason),
+        };
+        let raw = fs::read_to_string(&path).map_err(|err| invalid(err.to_string()))?;
+        let file: UsersFile = toml::from_str(&raw).map_err(|err| invalid(err.to_string()))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if fs::metadata(&path).is_ok_and(|metadata| metadata.permissions().mode() & 0o004 != 0)
+            {
+                log::warn!(
+                    "The users file {} is readable by every user, its permissions should be restricted",
+                    path.display()
+                );
+            }
+        }
+
+        self.file_users = file.users;
+        Ok(())
+    }
+
+    /// Returns the user named `username`, if it's defined.
+    pub fn user(&self, username: &str) -> Option<&User> {
+        self.users().find(|user| user.username == username)
+    }
+
+    /// Checks the credentials of a user against the users of the namespace.
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        self.user(username)
+            .is_some_and(|user| match self.hash_algorithm {
+                HashAlgorithm::Bcrypt => auth::verify_password(password, &user.password_hash),
+                HashAlgorithm::Argon2 => {
+                    auth::verify_argon2_password(password, &user.password_hash)
+                }
+            })
+    }
+
+    /// Returns `true` if `username` can publish messages to the queue named `queue` of the
+    /// virtual host `vhost`.
+    pub fn allows_publish(&self, username: &str, vhost: &str, queue: &str) -> bool {
+        self.acl.is_empty()
+            || self.acl.iter().any(|rule| {
+                rule.applies_to(username, vhost) && AclRule::matches(&rule.allow_publish, queue)
+            })
+    }
+
+    /// Returns `true` if `username` can consume the queue named `queue` of the virtual host
+    /// `vhost`, or get its messages.
+    pub fn allows_consume(&self, username: &str, vhost: &str, queue: &str) -> bool {
+        self.acl.is_empty()
+            || self.acl.iter().any(|rule| {
+                rule.applies_to(username, vhost) && AclRule::matches(&rule.allow_consume, queue)
+            })
+    }
+
+    /// Checks that the auth namespace holds a usable configuration, adding each error to
+    /// `errors`. The queues named by the access control rules must be defined by `queue`, or by
+    /// the virtual host of their rule among `vhosts`.
+    pub fn validate(&self, queue: &Queue, vhosts: &[VirtualHost], errors: &mut Vec<ConfigError>) {
+        #[cfg(not(feature = "argon2"))]
+        if self.hash_algorithm == HashAlgorithm::Argon2 {
+            errors.push(ConfigError::Invalid {
+                field: "auth.hash_algorithm".into(),
+                reason: "another-mq is built without the `argon2` feature".into(),
+            });
+        }
+
+        // The bcrypt hashes are the default, which only matter once a user is defined.
+        #[cfg(not(feature = "bcrypt"))]
+        if self.hash_algorithm == HashAlgorithm::Bcrypt
+            && !(self.users.is_empty() && self.file_users.is_empty())
+        {
+            errors.push(ConfigError::Invalid {
+                field: "auth.hash_algorithm".into(),
+                reason: "another-mq is built without the `bcrypt` feature".into(),
+            });
+        }
+
+        let sources = [
+            ("auth.users", &self.users),
+            ("auth.users_file.users", &self.file_users),
+        ];
+        let mut defined: Vec<&str> = Vec::new();
+        for (source, users) in &sources {
+            for (i, user) in users.iter().enumerate() {
+                if user.username.is_empty() {
+                    errors.push(ConfigError::Invalid {
+                        field: format!("{}[{}].username", source, i),
+                        reason: "the username must not be empty".into(),
+                    });
+                }
+                if defined.contains(&user.username.as_str()) {
+                    errors.push(ConfigError::Invalid {
+                        field: format!("{}[{}].username", source, i),
+                        reason: format!("the user {} is already defined", user.username),
+                    });
+                }
+                defined.push(&user.username);
+
+                let (is_hash, algorithm) = match self.hash_algorithm {
+                    HashAlgorithm::Bcrypt => {
+                        (auth::is_bcrypt_hash(&user.password_hash), "a bcrypt")
+                    }
+                    HashAlgorithm::Argon2 => {
+                        (auth::is_argon2_hash(&user.password_hash), "an argon2")
+                    }
+                };
+                if !is_hash {
+                    errors.push(ConfigError::Invalid {
+                        field: format!("{}[{}].password_hash", source, i),
+                        reason: format!("the password hash must be {} hash", algorithm),
+                    });
+                }
+
+                if user.max_connections == Some(0) {
+                    errors.push(ConfigError::Invalid {
+                        field: format!("{}[{}].max_connections", source, i),
+                        reason: "the maximum number of connections must not be 0".into(),
+                    });
+                }
+                if user.max_channels == Some(0) {
+                    errors.push(ConfigError::Invalid {
+                        field: format!("{}[{}].max_channels", source, i),
+                        reason: "the maximum number of channels must not be 0".into(),
+                    });
+                }
+            }
+        }
+
+        for (i, rule) in self.acl.iter().enumerate() {
+            if !self.users().any(|user| user.username == rule.username) {
+                errors.push(ConfigError::Invalid {
+                    field: format!("auth.acl[{}].username", i),
+                    reason: format!("the user {} is not defined", rule.username),
+                });
+            }
+
+            let definitions = match rule.vhost.as_deref() {
+                None | Some(DEFAULT_VHOST) => &queue.definitions,
+                Some(name) => match vhosts.iter().find(|vhost| vhost.name == name) {
+                    Some(vhost) => &vhost.definitions,
+                    None => {
+                        errors.push(ConfigError::Invalid {
+                            field: format!("auth.acl[{}].vhost", i),
+                            reason: format!("the virtual host {} is not defined", name),
+                        });
+                        continue;
+                    }
+                },
+            };
+            let patterns = [
+                ("allow_publish", &rule.allow_publish),
+                ("allow_consume", &rule.allow_consume),
+            ];
+            for (field, patterns) in &patterns {
+                for (j, pattern) in patterns.iter().enumerate() {
+                    if pattern != ACL_WILDCARD && !definitions.iter().any(|d| &d.name == pattern) {
+                        errors.push(ConfigError::Invalid {
+                            field: format!("auth.acl[{}].{}[{}]", i, field, j),
+                            reason: format!("the queue {} is not defined", pattern),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A rule granting a user the queues it can use, each queue being named by a pattern.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AclRule {
+    /// The user granted the queues, which must be defined by the namespace.
+    pub username: String,
+
+    /// The virtual host of the queues granted, the default one when undefined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vhost: Option<String>,
+
+    /// The patterns of the queues the user can publish messages to.
+    #[serde(default)]
+    pub allow_publish: Vec<String>,
+
+    /// The patterns of the queues the user can consume, or get the messages of.
+    #[serde(default)]
+    pub allow_consume: Vec<String>,
+}
+
+impl AclRule {
+    /// Returns `true` if the rule grants `username` queues of the virtual host `vhost`.
+    fn applies_to(&self, username: &str, vhost: &str) -> bool {
+        self.username == username && self.vhost.as_deref().unwrap_or(DEFAULT_VHOST) == vhost
+    }
+
+    /// Returns `true` if one of `patterns` matches the queue named `queue`.
+    fn matches(patterns: &[String], queue: &str) -> bool {
+        patterns
+            .iter()
+            .any(|pattern| pattern == ACL_WILDCARD || pattern == queue)
+    }
+}
+
+/// A user allowed to connect to the broker.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct User {
+    pub username: String,
+
+    /// The hash of the password of the user, computed with the hash algorithm of the namespace. A
+    /// bcrypt hash can be generated with `htpasswd -nbBC 10 "" <password> | tr -d :`, and an
+    /// argon2 hash with `echo -n <password> | argon2 <salt> -id -e`.
+    #[serde(serialize_with = "serialize_redacted")]
+    pub password_hash: String,
+
+    /// The maximum number of connections the user can open at once, whatever the listener. Once
+    /// it's reached, the clients logging in as the user are refused. They're unbounded when
+    /// undefined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+
+    /// The maximum number of channels the user can open at once, across all of its connections.
+    /// A client opening a channel beyond it has its connection closed. They're only bounded by
+    /// the maximum of channels of each connection when undefined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_channels: Option<usize>,
+}
+
+/// The algorithm of the password hashes of the users.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum HashAlgorithm {
+    /// The hashes start with `$2b$`, `$2a$` or `$2y$`. They are only supported on Unix when
+    /// `another-mq` is built with the `bcrypt` feature, which requires the libxcrypt library.
+    #[default]
+    Bcrypt,
+    /// The hashes are encoded in the PHC format, like `$argon2id$v=19$m=65536,t=2,p=1$...`. They
+    /// are only supported when `another-mq` is built with the `argon2` feature, which requires the
+    /// libargon2 library.
+    Argon2,
+}
+
+impl<'de> Deserialize<'de> for HashAlgorithm {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct HashAlgorithmVisitor;
+
+        impl<'de> Visitor<'de> for HashAlgorithmVisitor {
+            type Value = HashAlgorithm;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting bcrypt or argon2")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(HashAlgorithmVisitor)
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "bcrypt" | "Bcrypt" | "BCRYPT" => Ok(Self::Bcrypt),
+            "argon2" | "Argon2" | "ARGON2" => Ok(Self::Argon2),
+            _ => Err("Unknown hash algorithm!"),
+        }
+    }
+}
+
+impl Serialize for HashAlgorithm {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Bcrypt => "bcrypt",
+            Self::Argon2 => "argon2",
+        })
+    }
+}
+
+/// The log namespace of the configuration. By default, log entries are emitted
+/// on the standard output of the application. But a logfile or a syslog server
+/// can also be used to collect log entries, each entry being written to every
+/// sink configured.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Log {
+    /// The minimum level of an entry to be added to the application log.
+    #[serde(default = "Log::default_level")]
+    pub level: Level,
+
+    /// The minimum levels of the entries of some targets, such as `"another_mq::broker" =
+    /// "debug"`, overriding `level`. A level applies to the modules nested in its target, the
+    /// most specific target winning. A target ending in `::*`, such as `"another_mq::broker::*"
+    /// = "trace"`, only applies to the modules nested in it, so a whole subsystem is turned up
+    /// with one line.
+    ///
+    /// The entries of a module are filtered by the target naming it exactly first, then by the
+    /// most specific target ending in `::*` or naming a module it's nested in, then by `level`.
+    #[serde(default)]
+    pub targets: HashMap<String, Level>,
+
+    /// The format of the entries of the application log.
+    #[serde(default = "Log::default_format")]
+    pub format: LogFormat,
+
+    /// The format of the timestamps of the entries, written with the conversions of `strftime`
+    /// such as `%Y-%m-%d %H:%M:%S`. The timestamps are written as defined by RFC 3339 when
+    /// undefined. The entries sent to the syslog server are timestamped by the syslog protocol
+    /// instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_format: Option<String>,
+
+    /// Whether the timestamps are written in UTC, which is the default, rather than in the local
+    /// time zone.
+    #[serde(default = "Log::default_utc")]
+    pub utc: bool,
+
+    /// Whether the entries are written to the standard output, in addition to the logfile and
+    /// the syslog server. Nothing is logged when it's disabled without any other sink.
+    #[serde(default = "Log::default_stdout")]
+    pub stdout: bool,
+
+    /// The path to the logfile of the application.
+    pub file: Option<String>,
+
+    /// Whether the entries are appended to the logfile, so it can be left alone for a while,
+    /// such as when its disk is full, without losing its path. It's enabled by default. Like the
+    /// standard output and the syslog server, the logfile is opened or closed on a reload
+    /// without disturbing the other sinks.
+    #[serde(default = "Log::default_file_enabled")]
+    pub file_enabled: bool,
+
+    /// The rotation of the logfile. The logfile grows indefinitely when undefined.
+    pub rotation: Option<Rotation>,
+
+    /// The syslog configuration of the application.
+    pub syslog: Option<Syslog>,
+
+    /// The number of entries buffered before they're written to the sinks, so the threads
+    /// logging them don't wait for the writes. The buffered entries are written by a thread of
+    /// their own every flush interval, or as soon as the buffer is full, and the errors are
+    /// written right away along with the entries buffered before them. The entries are written
+    /// as they're logged when undefined.
+    pub async_buffer: Option<usize>,
+
+    /// How often the buffered entries are written, written in a human readable form such as
+    /// `500ms`. It's 1 second when undefined, and it's ignored without `async_buffer`.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub flush_interval: Option<Duration>,
+}
+
+impl Log {
+    fn default_level() -> Level {
+        Level::Info
+    }
+
+    fn default_format() -> LogFormat {
+        LogFormat::Text
+    }
+
+    fn default_stdout() -> bool {
+        true
+    }
+
+    fn default_file_enabled() -> bool {
+        true
+    }
+
+    fn default_utc() -> bool {
+        true
+    }
+
+    /// Returns how often the buffered entries are written.
+    pub fn effective_flush_interval(&self) -> Duration {
+        self.flush_interval.unwrap_or(DEFAULT_LOG_FLUSH_INTERVAL)
+    }
+
+    /// Checks that the rotation of the logfile can be applied and that the syslog server can be
+    /// reached, adding each error to `errors`. The timestamp format is checked by formatting the
+    /// current time with it.
+    pub fn validate(&mut self, errors: &mut Vec<ConfigError>) {
+        if let Some(format) = &self.timestamp_format {
+            if format.is_empty() {
+                errors.push(ConfigError::Invalid {
+                    field: "log.timestamp_format".into(),
+                    reason: "the timestamp format must not be empty".into(),
+                });
+            } else if let Err(reason) = timestamp::format(format, SystemTime::now(), self.utc) {
+                errors.push(ConfigError::Invalid {
+                    field: "log.timestamp_format".into(),
+                    reason,
+                });
+            }
+        }
+
+        if let Some(syslog) = &mut self.syslog {
+            errors.extend(syslog.validate().err());
+        }
+
+        for target in self.targets.keys() {
+            let module = target.strip_suffix("::*").unwrap_or(target);
+
+            if module.is_empty() {
+                errors.push(ConfigError::Invalid {
+                    field: "log.targets".into(),
+                    reason: "the name of a target must not be empty".into(),
+                });
+            } else if module.contains('*') {
+                errors.push(ConfigError::Invalid {
+                    field: "log.targets".into(),
+                    reason: format!("the target {} can only end in the ::* glob", target),
+                });
+            }
+        }
+
+        if let Some(rotation) = &self.rotation {
+            if rotation.max_size_bytes == 0 {
+                errors.push(ConfigError::Invalid {
+                    field: "log.rotation.max_size_bytes".into(),
+                    reason: "the maximum size of the logfile must not be 0".into(),
+                });
+            }
+            if self.file.is_none() {
+                log::warn!("log.rotation is ignored, as no logfile is defined");
+            }
+        }
+
+        if self.async_buffer == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: "log.async_buffer".into(),
+                reason: "the buffer must hold at least one entry".into(),
+            });
+        }
+        if let Some(flush_interval) = self.flush_interval {
+            if flush_interval == Duration::from_secs(0) {
+                errors.push(ConfigError::Invalid {
+                    field: "log.flush_interval".into(),
+                    reason: "the interval between the flushes must not be 0".into(),
+                });
+            }
+            if self.async_buffer.is_none() {
+                log::warn!("log.flush_interval is ignored, as no log.async_buffer is defined");
+            }
+        }
+    }
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            level: Self::default_level(),
+            targets: HashMap::new(),
+            format: Self::default_format(),
+            timestamp_format: None,
+            utc: Self::default_utc(),
+            stdout: Self::default_stdout(),
+            file: None,
+            file_enabled: Self::default_file_enabled(),
+            rotation: None,
+            syslog: None,
+            async_buffer: None,
+            flush_interval: None,
+        }
+    }
+}
+
+/// The format of the entries of the application log.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LogFormat {
+    /// A human readable line per entry.
+    Text,
+    /// A single-line JSON object per entry, holding the `timestamp`, the `level`, the `target`
+    /// and the `message` of the entry.
+    Json,
+}
+
+impl<'de> Deserialize<'de> for LogFormat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct LogFormatVisitor;
+
+        impl<'de> Visitor<'de> for LogFormatVisitor {
+            type Value = LogFormat;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting text or json")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(LogFormatVisitor)
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" | "Text" | "TEXT" => Ok(Self::Text),
+            "json" | "Json" | "JSON" => Ok(Self::Json),
+            _ => Err("Unknown log format!"),
+        }
+    }
+}
+
+impl Serialize for LogFormat {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        })
+    }
+}
+
+/// The rotation of the logfile of the application.
+///
+/// Once the logfile exceeds `max_size_bytes`, it is renamed with the `.1` suffix and the
+/// previously rotated files are shifted, `.1` becoming `.2` and so on. Only the `max_files` most
+/// recent rotated files are kept.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Rotation {
+    /// The size of the logfile, in bytes, above which it is rotated.
+    pub max_size_bytes: u64,
+
+    /// The number of rotated files to keep.
+    pub max_files: usize,
+}
+
+/// The syslog configuration of the application log.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Syslog {
+    /// Whether the entries are sent to the syslog server, which is the default. The connection to
+    /// the server is opened or closed on a reload without disturbing the other sinks.
+    #[serde(default = "Syslog::default_enabled")]
+    pub enabled: bool,
+
+    /// The host of the syslog server, either an IP address or a name such as `syslog.internal`.
+    /// A name is resolved each time the connection is opened, so the TCP transport follows the
+    /// server when its address changes.
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub protocol: SyslogProtocol,
+    #[serde(default = "Syslog::default_transport")]
+    pub transport: SyslogTransport,
+    pub facility: SyslogFacility,
+    pub process: String,
+
+    /// Whether the PID of the broker is attached to every message.
+    #[serde(default = "Syslog::default_include_pid")]
+    pub include_pid: bool,
+
+    /// The APP-NAME of the RFC 5424 messages, which is the process by default. The TAG of the
+    /// RFC 3164 messages is always the process.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_name: Option<String>,
+
+    /// The parameters of the structured data element attached to every message, such as the
+    /// region or the instance of the deployment. Only the RFC 5424 protocol supports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_data: Option<HashMap<String, String>>,
+
+    /// The delay between two attempts to reconnect to the syslog server once the TCP connection
+    /// dropped, which is 5 seconds by default. The messages logged meanwhile are buffered and
+    /// sent once the connection is back, then written to the standard error once the buffer is
+    /// full.
+    ///
+    /// The attempts are also made when the server, or the local daemon, can't be reached as the
+    /// log is configured, which never keeps the broker from starting: a warning is written to the
+    /// standard error, and the other sinks keep receiving the entries. Over UDP or a Unix socket,
+    /// the messages are written to the standard error until the connection is made.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub reconnect_interval: Option<Duration>,
+
+    /// How many attempts are made to reconnect to the syslog server before giving up, the
+    /// messages being written to the standard error from then on. The attempts never stop when
+    /// undefined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reconnect_attempts: Option<u32>,
+}
+
+impl Syslog {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_transport() -> SyslogTransport {
+        SyslogTransport::Udp
+    }
+
+    fn default_include_pid() -> bool {
+        true
+    }
+
+    /// Checks that a host is defined when the messages are sent to a remote syslog server, and
+    /// fills in the default syslog port when the host has no port.
+    ///
+    /// Without a host, the messages are sent to the local syslog daemon, which is only supported
+    /// on Unix over a datagram socket. So a port or the TCP transport require a host.
+    ///
+    /// The structured data is rejected with the RFC 3164 protocol, and its parameter names must
+    /// be valid RFC 5424 names: at most 32 printable ASCII characters, except `=`, `]`, `"` and
+    /// spaces.
+    ///
+    /// The app name must be a valid RFC 5424 APP-NAME: at most 48 printable ASCII characters.
+    ///
+    /// The reconnect interval must not be 0, as every message logged while the server is down
+    /// would then wait for an attempt.
+    pub fn validate(&mut self) -> Result<(), ConfigError> {
+        if self.reconnect_interval == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::Invalid {
+                field: "log.syslog.reconnect_interval".into(),
+                reason: "the reconnect interval must not be 0".into(),
+            });
+        }
+
+        if let Some(app_name) = &self.app_name {
+            if app_name.is_empty()
+                || app_name.len() > 48
+                || !app_name.bytes().all(|c| c.is_ascii_graphic())
+            {
+                return Err(ConfigError::Invalid {
+                    field: "log.syslog.app_name".into(),
+                    reason: format!("{:?} is not a valid app name", app_name),
+                });
+            }
+        }
+
+        if let Some(structured_data) = &self.structured_data {
+            if let SyslogProtocol::Rfc3164 = self.protocol {
+                return Err(ConfigError::Invalid {
+                    field: "log.syslog.structured_data".into(),
+                    reason: "the structured data requires the RFC 5424 protocol".into(),
+                });
+            }
+
+            let is_valid_name = |name: &str| {
+                !name.is_empty()
+                    && name.len() <= 32
+                    && name
+                        .bytes()
+                        .all(|c| c.is_ascii_graphic() && !matches!(c, b'=' | b']' | b'"'))
+            };
+            if let Some(name) = structured_data.keys().find(|name| !is_valid_name(name)) {
+                return Err(ConfigError::Invalid {
+                    field: "log.syslog.structured_data".into(),
+                    reason: format!("{:?} is not a valid parameter name", name),
+                });
+            }
+        }
+
+        if self.host.as_deref() == Some("") {
+            return Err(ConfigError::Invalid {
+                field: "log.syslog.host".into(),
+                reason: "the host of the syslog server must not be empty".into(),
+            });
+        }
+
+        if self.host.is_none() {
+            let reason = if self.port.is_some() {
+                "a port is defined but the host of the syslog server is missing"
+            } else if let SyslogTransport::Tcp = self.transport {
+                "the TCP transport requires the host of the syslog server"
+            } else if cfg!(not(unix)) {
+                "the local syslog daemon is only supported on Unix"
+            } else {
+                return Ok(());
+            };
+
+            return Err(ConfigError::Invalid {
+                field: "log.syslog.host".into(),
+                reason: reason.into(),
+            });
+        }
+
+        self.port.get_or_insert(SYSLOG_DEFAULT_PORT);
+
+        Ok(())
+    }
+}
+
+impl Default for Syslog {
+    fn default() -> Self {
+        Self {
+            enabled: Self::default_enabled(),
+            host: None,
+            port: None,
+            protocol: SyslogProtocol::Rfc3164,
+            transport: Self::default_transport(),
+            facility: SyslogFacility::User,
+            process: String::new(),
+            include_pid: Self::default_include_pid(),
+            app_name: None,
+            structured_data: None,
+            reconnect_interval: None,
+            max_reconnect_attempts: None,
+        }
+    }
+}
+
+/// The syslog protocol to use.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SyslogProtocol {
+    Rfc3164,
+    Rfc5424,
+}
+
+impl<'de> Deserialize<'de> for SyslogProtocol {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SyslogProtocolVisitor;
+
+        impl<'de> Visitor<'de> for SyslogProtocolVisitor {
+            type Value = SyslogProtocol;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting rfc3164 or rfc5424")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(SyslogProtocolVisitor)
+    }
+}
+
+impl FromStr for SyslogProtocol {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "rfc3164" | "RFC3164" => Ok(Self::Rfc3164),
+            "rfc5424" | "RFC5424" => Ok(Self::Rfc5424),
+            _ => Err("Unknown syslog protocol!"),
+        }
+    }
+}
+
+impl Serialize for SyslogProtocol {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Rfc3164 => "rfc3164",
+            Self::Rfc5424 => "rfc5424",
+        })
+    }
+}
+
+/// The transport of the messages sent to a syslog server.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SyslogTransport {
+    Udp,
+    /// The connection is reopened when it fails.
+    Tcp,
+}
+
+impl<'de> Deserialize<'de> for SyslogTransport {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SyslogTransportVisitor;
+
+        impl<'de> Visitor<'de> for SyslogTransportVisitor {
+            type Value = SyslogTransport;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting udp or tcp")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(SyslogTransportVisitor)
+    }
+}
+
+impl FromStr for SyslogTransport {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "udp" | "UDP" => Ok(Self::Udp),
+            "tcp" | "TCP" => Ok(Self::Tcp),
+            _ => Err("Unknown syslog transport!"),
+        }
+    }
+}
+
+impl Serialize for SyslogTransport {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Udp => "udp",
+            Self::Tcp => "tcp",
+        })
+    }
+}
+
+/// The syslog facility to use.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SyslogFacility {
+    Kern,
+    User,
+    Mail,
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    AuthPriv,
+    Ftp,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl From<SyslogFacility> for syslog::Facility {
+    fn from(facility: SyslogFacility) -> Self {
+        use syslog::Facility;
+
+        match facility {
+            SyslogFacility::Kern => Facility::LOG_KERN,
+            SyslogFacility::User => Facility::LOG_USER,
+            SyslogFacility::Mail => Facility::LOG_MAIL,
+            SyslogFacility::Daemon => Facility::LOG_DAEMON,
+            SyslogFacility::Auth => Facility::LOG_AUTH,
+            SyslogFacility::Syslog => Facility::LOG_SYSLOG,
+            SyslogFacility::Lpr => Facility::LOG_LPR,
+            SyslogFacility::News => Facility::LOG_NEWS,
+            SyslogFacility::Uucp => Facility::LOG_UUCP,
+            SyslogFacility::Cron => Facility::LOG_CRON,
+            SyslogFacility::AuthPriv => Facility::LOG_AUTHPRIV,
+            SyslogFacility::Ftp => Facility::LOG_FTP,
+            SyslogFacility::Local0 => Facility::LOG_LOCAL0,
+            SyslogFacility::Local1 => Facility::LOG_LOCAL1,
+            SyslogFacility::Local2 => Facility::LOG_LOCAL2,
+            SyslogFacility::Local3 => Facility::LOG_LOCAL3,
+            SyslogFacility::Local4 => Facility::LOG_LOCAL4,
+            SyslogFacility::Local5 => Facility::LOG_LOCAL5,
+            SyslogFacility::Local6 => Facility::LOG_LOCAL6,
+            SyslogFacility::Local7 => Facility::LOG_LOCAL7,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SyslogFacility {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SyslogFacilityVisitor;
+
+        impl<'de> Visitor<'de> for SyslogFacilityVisitor {
+            type Value = SyslogFacility;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting a syslog facility")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(SyslogFacilityVisitor)
+    }
+}
+
+impl FromStr for SyslogFacility {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "kern" => Ok(Self::Kern),
+            "user" => Ok(Self::User),
+            "mail" => Ok(Self::Mail),
+            "daemon" => Ok(Self::Daemon),
+            "auth" => Ok(Self::Auth),
+            "syslog" => Ok(Self::Syslog),
+            "lpr" => Ok(Self::Lpr),
+            "news" => Ok(Self::News),
+            "uucp" => Ok(Self::Uucp),
+            "cron" => Ok(Self::Cron),
+            "authpriv" => Ok(Self::AuthPriv),
+            "ftp" => Ok(Self::Ftp),
+            "local0" => Ok(Self::Local0),
+            "local1" => Ok(Self::Local1),
+            "local2" => Ok(Self::Local2),
+            "local3" => Ok(Self::Local3),
+            "local4" => Ok(Self::Local4),
+            "local5" => Ok(Self::Local5),
+            "local6" => Ok(Self::Local6),
+            "local7" => Ok(Self::Local7),
+            _ => Err("Unknown syslog facility!"),
+        }
+    }
+}
+
+impl Serialize for SyslogFacility {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Kern => "kern",
+            Self::User => "user",
+            Self::Mail => "mail",
+            Self::Daemon => "daemon",
+            Self::Auth => "auth",
+            Self::Syslog => "syslog",
+            Self::Lpr => "lpr",
+            Self::News => "news",
+            Self::Uucp => "uucp",
+            Self::Cron => "cron",
+            Self::AuthPriv => "authpriv",
+            Self::Ftp => "ftp",
+            Self::Local0 => "local0",
+            Self::Local1 => "local1",
+            Self::Local2 => "local2",
+            Self::Local3 => "local3",
+            Self::Local4 => "local4",
+            Self::Local5 => "local5",
+            Self::Local6 => "local6",
+            Self::Local7 => "local7",
+        })
+    }
+}
+
+/// The metrics namespace of the application's configuration.
+///
+/// When enabled, the metrics of the broker are served over HTTP at `/metrics`, in the text format
+/// of Prometheus. The HTTP listener is bound on `127.0.0.1` unless `bind_address` is defined,
+/// rather than on the `hostname` of the network namespace, so the metrics are kept off the public
+/// interfaces.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Metrics {
+    /// Whether the metrics are served. No HTTP listener is opened when disabled.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// The port of the HTTP listener serving the metrics.
+    #[serde(default = "Metrics::default_port")]
+    pub port: u16,
+
+    /// The upper bounds of the buckets of the histogram of the sizes of the bodies published to
+    /// each queue, in bytes, in increasing order. The bounds are powers of 4 from 64 bytes to
+    /// 4 MiB when undefined. They're only updated once the broker is restarted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_buckets: Option<Vec<f64>>,
+
+    /// The address of the HTTP listener serving the metrics, such as the one of a management
+    /// network. It's `127.0.0.1` when undefined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<IpAddr>,
+}
+
+impl Metrics {
+    /// Checks that the metrics namespace holds a usable configuration. The port of the HTTP
+    /// listener must not be one of the ports of the listeners of the network namespace. Each error
+    /// is added to `errors`.
+    ///
+    /// The bounds of the buckets must be positive numbers in increasing order.
+    pub fn validate(&self, network: &Network, errors: &mut Vec<ConfigError>) {
+        if !self.enabled {
+            if self.size_buckets.is_some() {
+                log::warn!("metrics.size_buckets is ignored, as the metrics are disabled");
+            }
+            return;
+        }
+
+        errors.extend(Network::validate_port("metrics.port", self.port).err());
+
+        if let Some(buckets) = &self.size_buckets {
+            let reason = if buckets.is_empty() {
+                Some("at least one bucket must be defined")
+            } else if buckets
+                .iter()
+                .any(|bound| !bound.is_finite() || *bound <= 0.0)
+            {
+                Some("the bounds of the buckets must be positive numbers")
+            } else if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+                Some("the bounds of the buckets must be in increasing order")
+            } else {
+                None
+            };
+
+            if let Some(reason) = reason {
+                errors.push(ConfigError::Invalid {
+                    field: "metrics.size_buckets".into(),
+                    reason: reason.into(),
+                });
+            }
+        }
+
+        if network
+            .effective_listeners()
+            .iter()
+            .any(|listener| listener.port == self.port)
+        {
+            errors.push(ConfigError::Invalid {
+                field: "metrics.port".into(),
+                reason: format!("the port {} is already used by a listener", self.port),
+            });
+        }
+    }
+
+    /// Returns the upper bounds of the buckets of the histogram of the message sizes.
+    pub fn effective_size_buckets(&self) -> Vec<f64> {
+        self.size_buckets
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SIZE_BUCKETS.to_vec())
+    }
+
+    /// Returns the socket address of the HTTP listener serving the metrics.
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(
+            self.bind_address.unwrap_or(DEFAULT_HTTP_BIND_ADDRESS),
+            self.port,
+        )
+    }
+
+    fn default_port() -> u16 {
+        DEFAULT_METRICS_PORT
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: Self::default_port(),
+            size_buckets: None,
+            bind_address: None,
+        }
+    }
+}
+
+/// The network namespace of the application's configuration. It's here where the
+/// hostname and the port used for instance.
+///
+/// The broker can listen on several addresses by declaring `[[network.listener]]` tables. When
+/// none is declared, the broker listens on the `hostname` and the `port` of the namespace.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Network {
+    /// The hostname that the application should use to open its sockets.
+    #[serde(default = "Network::default_hostname")]
+    pub hostname: IpAddr,
+
+    /// The port to use to open the application's sockets.
+    #[serde(default = "Network::default_port")]
+    pub port: u16,
+
+    /// The range of ports the broker falls back to when `port` is in use, written as its first
+    /// and last ports such as `[5900, 5999]`, both included. The broker listens on the first free
+    /// port of the range, which it logs, and the live configuration then reports it as `port`.
+    /// It's meant for the brokers started side by side, such as by the test harnesses. The
+    /// listeners declared by `[[network.listener]]` never use it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_range: Option<(u16, u16)>,
+
+    /// The listeners of the application, replacing `hostname` and `port` when defined.
+    #[serde(default, rename = "listener")]
+    pub listeners: Vec<Listener>,
+
+    /// The path of a Unix socket on which the broker listens, in addition to its listeners, for
+    /// the clients running on the same host. The connections of the socket are never encrypted,
+    /// even when TLS is configured. Only supported on Unix.
+    pub unix_socket: Option<String>,
+
+    /// The number of connections queued by each listener until they're accepted, so the
+    /// connection storms don't get the clients refused. The OS may lower it to its own maximum.
+    /// It's 1024 when undefined.
+    pub backlog: Option<u32>,
+
+    /// The number of worker threads the broker is sized for, which is the number of logical CPUs
+    /// when undefined. It only takes effect once the broker is restarted.
+    ///
+    /// The broker has no pool of worker threads: each connection is served by threads of its own,
+    /// so the number of threads follows the number of connections. The value is only a hint,
+    /// logged as the broker starts.
+    pub worker_threads: Option<usize>,
+
+    /// The TLS configuration of the listeners. When defined, the listeners only accept TLS
+    /// connections.
+    pub tls: Option<TlsConfig>,
+
+    /// The maximum number of connections accepted by each listener, unless the listener defines
+    /// its own limit. The connections are unbounded when undefined.
+    pub max_connections: Option<usize>,
+
+    /// Whether `SO_REUSEADDR` is set on the sockets of the listeners before they're bound, unless
+    /// a listener defines its own, so a restarted broker can bind its ports while the connections
+    /// of the previous one linger. It's set by default.
+    #[serde(default = "Network::default_reuse_address")]
+    pub reuse_address: bool,
+
+    /// Whether `SO_REUSEPORT` is set on the sockets of the listeners before they're bound, unless
+    /// a listener defines its own, so two brokers can listen on the same ports while one takes
+    /// over from the other. Only Unix supports it.
+    #[serde(default)]
+    pub reuse_port: bool,
+
+    /// Whether the connections of the listeners start with the header of the PROXY protocol,
+    /// version 1 or 2, unless a listener defines its own. It's meant for the listeners behind a
+    /// load balancer, so the address of the client it holds is the one checked against
+    /// `allow_cidrs` and written to the log, rather than the address of the load balancer. The
+    /// connections whose header is malformed are closed. The Unix socket never expects it.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+
+    /// How long a listener waits before accepting the next connection once it failed to accept
+    /// one, such as when the application runs out of file descriptors. The delay is doubled on
+    /// each consecutive failure, up to 5 seconds unless the backoff is longer, and it's reset once
+    /// a connection is accepted.
+    #[serde(
+        default = "Network::default_accept_backoff",
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub accept_backoff: Duration,
+
+    /// How long the application waits for the clients to close their connections when it's
+    /// asked to stop, before stopping anyway.
+    #[serde(
+        default = "Network::default_shutdown_timeout",
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub shutdown_timeout: Duration,
+
+    /// The signals stopping the application right away, without waiting for the clients to close
+    /// their connections, such as `["SIGINT"]`, which is the default so Ctrl-C doesn't wait for
+    /// the shutdown timeout. The other ones of `SIGINT` and `SIGTERM` start a graceful shutdown,
+    /// and the application stops right away once it receives any of them again. Only Unix handles
+    /// the signals.
+    #[serde(default = "Network::default_immediate_shutdown_signals")]
+    pub immediate_shutdown_signals: Vec<ShutdownSignal>,
+
+    /// How long a connection can stay without receiving any frame from its client, heartbeats
+    /// included, before being closed. The connections are never closed for being idle when
+    /// undefined.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub idle_timeout: Option<Duration>,
+
+    /// How long an AMQP client has to complete the handshake of its connection, from its TLS
+    /// handshake to the opening of its virtual host, before the connection is closed. Unlike the
+    /// idle timeout, it bounds the whole handshake rather than the silence between two frames, so
+    /// a client sending its bytes one at a time can't hold a connection slot for longer. The TLS
+    /// handshakes of the text listeners are bounded too, but not the authentication of their
+    /// clients, who type it by hand.
+    #[serde(
+        default = "Network::default_handshake_timeout",
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub handshake_timeout: Duration,
+
+    /// The heartbeat interval advertised to the AMQP clients when their connection is negotiated,
+    /// in whole seconds. The broker sends a heartbeat whenever it sent nothing else for the
+    /// interval, and it closes the connections of the clients which sent nothing for two
+    /// intervals. The heartbeats are disabled when undefined or `0s`, or when the client disables
+    /// them.
+    ///
+    /// As the heartbeats of a client count as frames received from it, a connection whose client
+    /// sends them is only closed by the idle timeout if it's shorter than two intervals.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub heartbeat: Option<Duration>,
+
+    /// The maximum size of the frames exchanged with the AMQP clients, in bytes, header and
+    /// frame-end octet included. It's advertised to the clients when their connection is
+    /// negotiated, and a connection whose client sends a larger frame is closed before the frame
+    /// is read, whatever the size of the messages. It's 131072 bytes by default, and it must be
+    /// at least 4096 bytes, the minimum of the protocol.
+    #[serde(default = "Network::default_max_frame_bytes")]
+    pub max_frame_bytes: u32,
+
+    /// Whether Nagle's algorithm is disabled on the sockets of the connections, so the frames
+    /// are sent right away instead of being coalesced. It's enabled by default, as the latency of
+    /// the deliveries matters more than the number of packets.
+    #[serde(default = "Network::default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+
+    /// How long a connection can stay silent before the OS probes its client, then the interval
+    /// between the probes, so the dead connections are detected. The OS keepalive is left
+    /// disabled when undefined.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tcp_keepalive: Option<Duration>,
+
+    /// The size of the receive buffer of the sockets of the TCP connections, in bytes, set by
+    /// `SO_RCVBUF`. A larger buffer lets the clients on a link with a high bandwidth-delay product
+    /// publish faster. The OS may clamp it, such as Linux to its `net.core.rmem_max` setting, so
+    /// the size it ends up with is logged. The OS default is kept when undefined.
+    pub recv_buffer_bytes: Option<usize>,
+
+    /// The size of the send buffer of the sockets of the TCP connections, in bytes, set by
+    /// `SO_SNDBUF`, as `recv_buffer_bytes` does for the deliveries. Linux clamps it to its
+    /// `net.core.wmem_max` setting. The OS default is kept when undefined.
+    pub send_buffer_bytes: Option<usize>,
+
+    /// The rate at which each connection can publish messages. The connections are not limited
+    /// when undefined.
+    pub rate_limit: Option<RateLimit>,
+
+    /// The ranges of addresses the clients can connect from, written in the CIDR notation such as
+    /// `10.0.0.0/8` or `fd00::/8`. The connections from other addresses are closed as soon as
+    /// they're accepted. Every address is allowed when undefined or empty, and the clients of the
+    /// Unix socket are always allowed.
+    pub allow_cidrs: Option<Vec<String>>,
+}
+
+impl Network {
+    /// Returns the listeners that the application should open. The `hostname` and the `port` of
+    /// the namespace are used as a single listener if no listener is declared.
+    ///
+    /// The settings of the namespace are applied to the listeners which do not define their own.
+    pub fn effective_listeners(&self) -> Vec<Listener> {
+        let mut listeners = if self.listeners.is_empty() {
+            vec![Listener {
+                hostname: self.hostname,
+                port: self.port,
+                max_connections: None,
+                protocol: ListenerProtocol::Native,
+                reuse_address: None,
+                reuse_port: None,
+                proxy_protocol: None,
+                max_messages_per_second: None,
+                recv_buffer_bytes: None,
+                send_buffer_bytes: None,
+            }]
+        } else {
+            self.listeners.clone()
+        };
+
+        for listener in &mut listeners {
+            listener.max_connections = listener.max_connections.or(self.max_connections);
+            listener.reuse_address = listener.reuse_address.or(Some(self.reuse_address));
+            listener.reuse_port = listener.reuse_port.or(Some(self.reuse_port));
+            listener.proxy_protocol = listener.proxy_protocol.or(Some(self.proxy_protocol));
+            listener.recv_buffer_bytes = listener.recv_buffer_bytes.or(self.recv_buffer_bytes);
+            listener.send_buffer_bytes = listener.send_buffer_bytes.or(self.send_buffer_bytes);
+        }
+
+        listeners
+    }
+
+    /// Returns the ports the listener of the namespace tries in turn when no listener is declared:
+    /// `port`, then each port of `port_range` in order.
+    pub fn ports(&self) -> impl Iterator<Item = u16> + '_ {
+        let range = self
+            .port_range
+            .into_iter()
+            .flat_map(|(first, last)| first..=last)
+            .filter(move |port| *port != self.port);
+
+        iter::once(self.port).chain(range)
+    }
+
+    /// Returns the number of connections queued by each listener until they're accepted.
+    pub fn effective_backlog(&self) -> u32 {
+        self.backlog.unwrap_or(DEFAULT_LISTEN_BACKLOG)
+    }
+
+    /// Returns the number of worker threads the broker is sized for, falling back to the number
+    /// of logical CPUs, or to a single thread if the OS can't tell.
+    pub fn effective_worker_threads(&self) -> usize {
+        self.worker_threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get()))
+    }
+
+    /// Returns the ranges of addresses the clients can connect from, or `None` if every address is
+    /// allowed. The ranges which can't be parsed are rejected by the validation of the namespace.
+    pub fn allowed_ranges(&self) -> Option<Vec<Cidr>> {
+        match &self.allow_cidrs {
+            Some(cidrs) if !cidrs.is_empty() => {
+                Some(cidrs.iter().filter_map(|cidr| cidr.parse().ok()).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks that the address of each listener can be bound, by binding them all then releasing
+    /// them. It's meant to be called before the listeners are actually bound, so the error names
+    /// the listener which can't be bound rather than only the error of the OS.
+    ///
+    /// The addresses are held until every listener is checked, so two listeners sharing the same
+    /// address are reported as well, unless both set `SO_REUSEPORT`. The socket options of each
+    /// listener are set as they will be, so a port shared with another broker is accepted. With a
+    /// port range, the listener of the namespace is checked on the first free port.
+    pub fn check_bindable(&self) -> Result<(), ConfigError> {
+        let mut bound = Vec::new();
+
+        for (i, listener) in self.effective_listeners().iter().enumerate() {
+            let address = listener.address();
+            let ports: Vec<_> = if self.listeners.is_empty() {
+                self.ports().collect()
+            } else {
+                vec![listener.port]
+            };
+            let err = match crate::server::bind_first_free(listener, ports) {
+                Ok(socket) => {
+                    bound.push(socket);
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            let field = match self.port_range {
+                Some((first, last)) if self.listeners.is_empty() => {
+                    if err.kind() == io::ErrorKind::AddrInUse {
+                        return Err(ConfigError::Invalid {
+                            field: "network.port_range".into(),
+                            reason: format!(
+                                "the port {} and every port of the range {}-{} are in use",
+                                self.port, first, last
+                            ),
+                        });
+                    }
+                    "network.port_range".into()
+                }
+                _ if self.listeners.is_empty() => "network.port".into(),
+                _ => format!("network.listener[{}]", i),
+            };
+
+            return Err(bind_error(field, address, err));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the network namespace holds a usable configuration.
+    ///
+    /// The port `0` is rejected since it would let the OS pick an ephemeral port unknown by the
+    /// clients. A privileged port (below 1024) is accepted, but a warning is emitted since binding
+    /// it usually needs elevated privileges. Each error is added to `errors`.
+    pub fn validate(&self, errors: &mut Vec<ConfigError>) {
+        if let Some(tls) = &self.tls {
+            errors.extend(tls.validate().err());
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            errors.extend(rate_limit.validate().err());
+        }
+
+        for (i, cidr) in self.allow_cidrs.iter().flatten().enumerate() {
+            if let Err(reason) = cidr.parse::<Cidr>() {
+                errors.push(ConfigError::Invalid {
+                    field: format!("network.allow_cidrs[{}]", i),
+                    reason,
+                });
+            }
+        }
+
+        if self.backlog == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: "network.backlog".into(),
+                reason: "the backlog must not be 0".into(),
+            });
+        }
+
+        if self.worker_threads == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: "network.worker_threads".into(),
+                reason: "at least one worker thread is required".into(),
+            });
+        }
+
+        match &self.unix_socket {
+            Some(path) if path.is_empty() => errors.push(ConfigError::Invalid {
+                field: "network.unix_socket".into(),
+                reason: "the path of the Unix socket must not be empty".into(),
+            }),
+            #[cfg(not(unix))]
+            Some(_) => errors.push(ConfigError::Invalid {
+                field: "network.unix_socket".into(),
+                reason: "the Unix sockets are only supported on Unix".into(),
+            }),
+            _ => {}
+        }
+
+        if self.idle_timeout == Some(Duration::from_secs(0)) {
+            errors.push(ConfigError::Invalid {
+                field: "network.idle_timeout".into(),
+                reason: "the idle timeout must not be 0".into(),
+            });
+        }
+
+        if self.handshake_timeout == Duration::from_secs(0) {
+            errors.push(ConfigError::Invalid {
+                field: "network.handshake_timeout".into(),
+                reason: "the handshake timeout must not be 0".into(),
+            });
+        }
+
+        if let Some(heartbeat) = self.heartbeat {
+            if heartbeat.subsec_nanos() != 0 || heartbeat.as_secs() > u16::MAX as u64 {
+                errors.push(ConfigError::Invalid {
+                    field: "network.heartbeat".into(),
+                    reason: format!(
+                        "the heartbeat must be a whole number of seconds, up to {}s",
+                        u16::MAX
+                    ),
+                });
+            }
+        }
+
+        if self.max_frame_bytes < FRAME_MIN_SIZE {
+            errors.push(ConfigError::Invalid {
+                field: "network.max_frame_bytes".into(),
+                reason: format!(
+                    "the maximum size of the frames must be at least {} bytes",
+                    FRAME_MIN_SIZE
+                ),
+            });
+        }
+
+        match self.tcp_keepalive {
+            Some(keepalive) if keepalive.as_secs() == 0 => errors.push(ConfigError::Invalid {
+                field: "network.tcp_keepalive".into(),
+                reason: "the keepalive must be at least 1s".into(),
+            }),
+            #[cfg(not(unix))]
+            Some(_) => errors.push(ConfigError::Invalid {
+                field: "network.tcp_keepalive".into(),
+                reason: "the keepalive is only supported on Unix".into(),
+            }),
+            _ => {}
+        }
+
+        errors.extend(
+            Self::validate_buffer_bytes("network.recv_buffer_bytes", self.recv_buffer_bytes).err(),
+        );
+        errors.extend(
+            Self::validate_buffer_bytes("network.send_buffer_bytes", self.send_buffer_bytes).err(),
+        );
+
+        #[cfg(not(unix))]
+        if self.reuse_port {
+            errors.push(ConfigError::Invalid {
+                field: "network.reuse_port".into(),
+                reason: "SO_REUSEPORT is only supported on Unix".into(),
+            });
+        }
+
+        if self.listeners.is_empty() {
+            errors.extend(Self::validate_port("network.port", self.port).err());
+        }
+
+        if let Some((first, last)) = self.port_range {
+            if !self.listeners.is_empty() {
+                log::warn!("network.port_range is ignored, as listeners are declared");
+            }
+            if first > last {
+                errors.push(ConfigError::Invalid {
+                    field: "network.port_range".into(),
+                    reason: format!(
+                        "the first port of the range, {}, must not be above its last one, {}",
+                        first, last
+                    ),
+                });
+            }
+            errors.extend(Self::validate_port("network.port_range", first).err());
+        }
+
+        for (i, listener) in self.listeners.iter().enumerate() {
+            let field = format!("network.listener[{}].port", i);
+            errors.extend(Self::validate_port(&field, listener.port).err());
+
+            match listener.max_messages_per_second {
+                Some(0) => errors.push(ConfigError::Invalid {
+                    field: format!("network.listener[{}].max_messages_per_second", i),
+                    reason: "the rate limit must not be 0".into(),
+                }),
+                Some(_) if listener.protocol == ListenerProtocol::Text => log::warn!(
+                    "network.listener[{}].max_messages_per_second is ignored, as the listener \
+                     speaks the text protocol",
+                    i
+                ),
+                _ => {}
+            }
+
+            let field = format!("network.listener[{}].recv_buffer_bytes", i);
+            errors.extend(Self::validate_buffer_bytes(&field, listener.recv_buffer_bytes).err());
+            let field = format!("network.listener[{}].send_buffer_bytes", i);
+            errors.extend(Self::validate_buffer_bytes(&field, listener.send_buffer_bytes).err());
+
+            #[cfg(not(unix))]
+            if listener.reuse_port == Some(true) {
+                errors.push(ConfigError::Invalid {
+                    field: format!("network.listener[{}].reuse_port", i),
+                    reason: "SO_REUSEPORT is only supported on Unix".into(),
+                });
+            }
+        }
+    }
+
+    /// Checks the size of a buffer of the sockets, which the OS takes as a C `int`.
+    fn validate_buffer_bytes(field: &str, bytes: Option<usize>) -> Result<(), ConfigError> {
+        let reason = match bytes {
+            Some(0) => "the size of the socket buffers must not be 0".to_string(),
+            Some(bytes) if bytes > i32::MAX as usize => format!(
+                "the size of the socket buffers must be at most {} bytes",
+                i32::MAX
+            ),
+            #[cfg(not(unix))]
+            Some(_) => "the socket buffers are only supported on Unix".to_string(),
+            _ => return Ok(()),
+        };
+
+        Err(ConfigError::Invalid {
+            field: field.into(),
+            reason,
+        })
+    }
+
+    fn validate_port(field: &str, port: u16) -> Result<(), ConfigError> {
+        if port == 0 {
+            return Err(ConfigError::Invalid {
+                field: field.into(),
+                reason: "the port must not be 0".into(),
+            });
+        }
+
+        if port < PRIVILEGED_PORTS_END {
+            log::warn!(
+                "The port {} is a privileged port, binding it may require elevated privileges",
+                port
+            );
+        }
+
+        Ok(())
+    }
+
+    fn default_hostname() -> IpAddr {
+        DEFAULT_LISTENER_HOSTNAME
+    }
+
+    fn default_port() -> u16 {
+        DEFAULT_LISTENER_PORT
+    }
+
+    fn default_accept_backoff() -> Duration {
+        DEFAULT_ACCEPT_BACKOFF
+    }
+
+    fn default_shutdown_timeout() -> Duration {
+        DEFAULT_SHUTDOWN_TIMEOUT
+    }
+
+    fn default_handshake_timeout() -> Duration {
+        DEFAULT_HANDSHAKE_TIMEOUT
+    }
+
+    fn default_immediate_shutdown_signals() -> Vec<ShutdownSignal> {
+        vec![ShutdownSignal::Interrupt]
+    }
+
+    fn default_max_frame_bytes() -> u32 {
+        DEFAULT_MAX_FRAME_BYTES
+    }
+
+    fn default_tcp_nodelay() -> bool {
+        true
+    }
+
+    fn default_reuse_address() -> bool {
+        true
+    }
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self {
+            hostname: Self::default_hostname(),
+            port: Self::default_port(),
+            port_range: None,
+            listeners: Vec::new(),
+            unix_socket: None,
+            backlog: None,
+            worker_threads: None,
+            tls: None,
+            max_connections: None,
+            reuse_address: Self::default_reuse_address(),
+            reuse_port: false,
+            proxy_protocol: false,
+            accept_backoff: Self::default_accept_backoff(),
+            shutdown_timeout: Self::default_shutdown_timeout(),
+            immediate_shutdown_signals: Self::default_immediate_shutdown_signals(),
+            idle_timeout: None,
+            handshake_timeout: Self::default_handshake_timeout(),
+            heartbeat: None,
+            max_frame_bytes: Self::default_max_frame_bytes(),
+            tcp_nodelay: Self::default_tcp_nodelay(),
+            tcp_keepalive: None,
+            recv_buffer_bytes: None,
+            send_buffer_bytes: None,
+            rate_limit: None,
+            allow_cidrs: None,
+        }
+    }
+}
+
+/// A range of IPv4 or IPv6 addresses, written in the CIDR notation: an address followed by the
+/// length of the prefix shared by the addresses of the range, such as `192.168.0.0/16`. An address
+/// without a prefix length is a range holding only this address.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Returns `true` if `address` belongs to the range. The IPv4 addresses mapped to IPv6, as
+    /// they're seen by a listener bound to an IPv6 address, belong to the IPv4 ranges.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = match address {
+            IpAddr::V6(v6) if self.address.is_ipv4() => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => return false,
+            },
+            address => address,
+        };
+
+        match (self.address, address) {
+            (IpAddr::V4(range), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("{} is not an IPv4 or an IPv6 address", address))?;
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => match prefix_len.parse::<u8>() {
+                Ok(prefix_len) if prefix_len <= max_len => prefix_len,
+                _ => {
+                    return Err(format!(
+                        "the prefix length {} is not between 0 and {}",
+                        prefix_len, max_len
+                    ))
+                }
+            },
+            None => max_len,
+        };
+
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+/// An address on which the application accepts the connections of its clients.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Listener {
+    /// The hostname that the listener should use to open its socket.
+    #[serde(default = "Network::default_hostname")]
+    pub hostname: IpAddr,
+
+    /// The port to use to open the listener's socket.
+    #[serde(default = "Network::default_port")]
+    pub port: u16,
+
+    /// The maximum number of connections accepted by the listener.
+    pub max_connections: Option<usize>,
+
+    /// The protocol spoken by the clients of the listener.
+    #[serde(default)]
+    pub protocol: ListenerProtocol,
+
+    /// Whether `SO_REUSEADDR` is set on the socket of the listener, overriding the network
+    /// namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reuse_address: Option<bool>,
+
+    /// Whether `SO_REUSEPORT` is set on the socket of the listener, overriding the network
+    /// namespace. Only Unix supports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reuse_port: Option<bool>,
+
+    /// Whether the connections of the listener start with the header of the PROXY protocol,
+    /// overriding the network namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<bool>,
+
+    /// The number of messages the connections of the listener can publish each second, all
+    /// together. The connections exceeding it are slowed down, as for the rate limit of the
+    /// network namespace. Both limits apply to the connections of the listener: a message is read
+    /// once it's under the limit of its connection and under the one of its listener.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_messages_per_second: Option<u32>,
+
+    /// The size of the receive buffer of the sockets of the connections of the listener, in bytes,
+    /// overriding the network namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recv_buffer_bytes: Option<usize>,
+
+    /// The size of the send buffer of the sockets of the connections of the listener, in bytes,
+    /// overriding the network namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_buffer_bytes: Option<usize>,
+}
+
+impl Listener {
+    /// Returns the socket address of the listener.
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::new(self.hostname, self.port)
+    }
+}
+
+/// The protocol spoken on a listener.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ListenerProtocol {
+    /// The AMQP 0-9-1 protocol, used by the client libraries.
+    #[default]
+    Native,
+    /// A line-based protocol, meant to inspect the broker by hand with tools like `nc`.
+    Text,
+}
+
+impl<'de> Deserialize<'de> for ListenerProtocol {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ListenerProtocolVisitor;
+
+        impl<'de> Visitor<'de> for ListenerProtocolVisitor {
+            type Value = ListenerProtocol;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting native or text")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(ListenerProtocolVisitor)
+    }
+}
+
+impl FromStr for ListenerProtocol {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "native" | "Native" | "NATIVE" => Ok(Self::Native),
+            "text" | "Text" | "TEXT" => Ok(Self::Text),
+            _ => Err("Unknown listener protocol!"),
+        }
+    }
+}
+
+impl Serialize for ListenerProtocol {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Native => "native",
+            Self::Text => "text",
+        })
+    }
+}
+
+/// A signal asking the application to stop.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShutdownSignal {
+    /// `SIGINT`, usually sent from a terminal.
+    Interrupt,
+    /// `SIGTERM`, sent by the service managers and the orchestrators.
+    Terminate,
+}
+
+impl<'de> Deserialize<'de> for ShutdownSignal {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ShutdownSignalVisitor;
+
+        impl<'de> Visitor<'de> for ShutdownSignalVisitor {
+            type Value = ShutdownSignal;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting SIGINT or SIGTERM")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(ShutdownSignalVisitor)
+    }
+}
+
+impl FromStr for ShutdownSignal {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_uppercase().as_str() {
+            "SIGINT" | "INT" => Ok(Self::Interrupt),
+            "SIGTERM" | "TERM" => Ok(Self::Terminate),
+            _ => Err("Unknown shutdown signal!"),
+        }
+    }
+}
+
+impl Serialize for ShutdownSignal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Interrupt => "SIGINT",
+            Self::Terminate => "SIGTERM",
+        })
+    }
+}
+
+/// The TLS configuration of the listeners, defined by the `[network.tls]` table. The certificates
+/// and the private key are PEM files.
+///
+/// Each `[[network.tls.cert]]` entry serves its certificate to the clients requesting its server
+/// name through SNI, the certificate of the table being served to the other clients:
+///
+/// ```toml
+/// [network.tls]
+/// cert_file = "/etc/another-mq/default.pem"
+/// key_file = "/etc/another-mq/default.key"
+///
+/// [[network.tls.cert]]
+/// server_name = "*.tenant.example.com"
+/// cert_file = "/etc/another-mq/tenant.pem"
+/// key_file = "/etc/another-mq/tenant.key"
+/// ```
+///
+/// > TLS connections are only served when `another-mq` is built with the `tls` feature, which
+/// > requires the OpenSSL library.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TlsConfig {
+    /// The path to the certificate chain of the listeners.
+    pub cert_file: String,
+
+    /// The path to the private key of the certificate.
+    pub key_file: String,
+
+    /// The path to the certificates of the authorities trusted to sign the certificates of the
+    /// clients. When defined, the clients must present a certificate signed by one of them.
+    pub ca_file: Option<String>,
+
+    /// The certificates selected by the server name the clients request, defined by the
+    /// `[[network.tls.cert]]` entries.
+    #[serde(default, rename = "cert", skip_serializing_if = "Vec::is_empty")]
+    pub certs: Vec<TlsCert>,
+}
+
+/// A certificate served to the clients requesting its server name, defined by a
+/// `[[network.tls.cert]]` entry.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TlsCert {
+    /// The server name the certificate is served for, compared regardless of the case. A name
+    /// starting with `*.` matches a single label in its place, so `*.example.com` matches
+    /// `mq.example.com` but neither `example.com` nor `a.mq.example.com`. The exact names take
+    /// precedence over the wildcards.
+    pub server_name: String,
+
+    /// The path to the certificate chain.
+    pub cert_file: String,
+
+    /// The path to the private key of the certificate.
+    pub key_file: String,
+}
+
+impl TlsConfig {
+    /// Reads the certificates and the private key, and builds the TLS context of the listeners.
+    #[cfg(feature = "tls")]
+    pub fn load(&self) -> Result<crate::tls::TlsAcceptor, ConfigError> {
+        crate::tls::TlsAcceptor::new(self)
+    }
+
+    #[cfg(feature = "tls")]
+    fn validate(&self) -> Result<(), ConfigError> {
+        for (i, cert) in self.certs.iter().enumerate() {
+            let name = cert.server_name.to_ascii_lowercase();
+            let labels = name.strip_prefix("*.").unwrap_or(&name);
+
+            let reason = if labels.is_empty() {
+                "the server name must not be empty".to_string()
+            } else if labels.contains('*') {
+                "the server name can only start with the *. wildcard".to_string()
+            } else if self.certs[..i]
+                .iter()
+                .any(|other| other.server_name.eq_ignore_ascii_case(&name))
+            {
+                format!("the server name {} is defined twice", cert.server_name)
+            } else {
+                continue;
+            };
+
+            return Err(ConfigError::Invalid {
+                field: format!("network.tls.cert[{}].server_name", i),
+                reason,
+            });
+        }
+
+        self.load().map(|_| ())
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn validate(&self) -> Result<(), ConfigError> {
+        Err(ConfigError::Invalid {
+            field: "network.tls".into(),
+            reason: "another-mq is built without the `tls` feature".into(),
+        })
+    }
+}
+
+/// The rate at which each connection can publish messages, defined by the `[network.rate_limit]`
+/// table. Each limit is enforced by a token bucket holding up to a second of publications, so a
+/// client can publish in bursts as long as it stays under the limit on average.
+///
+/// A connection exceeding its limit is slowed down: the broker stops reading from it until it's
+/// back under the limit, so the client waits for its socket to accept the next frames. The
+/// `max_messages_per_second` of a listener limits its connections all together in the same way,
+/// on top of the limit of each connection.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RateLimit {
+    /// The number of messages a connection can publish each second.
+    pub messages_per_second: Option<u32>,
+
+    /// The number of bytes of bodies a connection can publish each second.
+    pub bytes_per_second: Option<u64>,
+
+    /// Whether a connection exceeding its limit is closed instead of being slowed down.
+    #[serde(default)]
+    pub disconnect_on_exceed: bool,
+}
+
+impl RateLimit {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.messages_per_second == Some(0) {
+            return Err(ConfigError::Invalid {
+                field: "network.rate_limit.messages_per_second".into(),
+                reason: "the rate limit must not be 0".into(),
+            });
+        }
+
+        if self.bytes_per_second == Some(0) {
+            return Err(ConfigError::Invalid {
+                field: "network.rate_limit.bytes_per_second".into(),
+                reason: "the rate limit must not be 0".into(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The queue namespace of the application's configuration.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Queue {
+    /// The queues declared by the broker when it starts, defined by `[[queue.definitions]]`
+    /// tables.
+    #[serde(default)]
+    pub definitions: Vec<QueueDefinition>,
+
+    /// The maximum size of the body of a message, in bytes. The messages exceeding it are
+    /// rejected when they're published. There is no limit by default.
+    pub max_message_bytes: Option<u64>,
+
+    /// The maximum number of messages left unacknowledged across every queue of the broker, as a
+    /// last resort against the consumers which stop acknowledging their messages on many queues
+    /// at once, whatever the prefetch limits of the channels and of the queues. Once it's
+    /// reached, no message is delivered to the consumers acknowledging their messages, nor got
+    /// by the clients without `no_ack`, until enough messages are settled. There is no limit by
+    /// default.
+    pub max_unacked_total: Option<u64>,
+
+    /// The queue receiving the messages published with a routing key which matches no queue,
+    /// which must be defined by the namespace. They're dropped when undefined.
+    pub default_queue: Option<String>,
+
+    /// How often the queues with a time-to-live are swept for expired messages, which are then
+    /// dead-lettered. Without the sweep, the messages would only expire when they reach the head
+    /// of their queue. The queues with an acknowledgement timeout are checked for the messages
+    /// left unacknowledged at the same time.
+    #[serde(
+        default = "Queue::default_expiry_scan_interval",
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub expiry_scan_interval: Duration,
+
+    /// The time-to-live of the messages of the queues whose definition sets no `message_ttl`,
+    /// whether they're defined by the namespace, by a virtual host, or declared by a client. The
+    /// `message_ttl` of a definition always takes precedence over it. It applies to the queues as
+    /// they're declared, so the queues already declared keep their time-to-live when it changes.
+    /// The messages never expire by default.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub default_message_ttl: Option<Duration>,
+
+    /// Where the messages of the durable queues are stored, so they survive a restart of the
+    /// broker. Without it, every message is only held in memory.
+    pub storage: Option<StorageConfig>,
+
+    /// Whether the broker never writes the messages to the disk, for the throwaway brokers of
+    /// the tests and of the CI. Every queue is declared as a non-durable queue, whatever its
+    /// definition or the client declaring it asks for, and the storage must not be defined.
+    /// Like the storage, it only takes effect once the broker is restarted.
+    #[serde(default)]
+    pub memory_only: bool,
+
+    /// How the bodies of the messages are compressed while they're held by the queues and the
+    /// storage, `gzip` or `zstd`. The bodies are decompressed before being delivered, so the
+    /// clients never see them compressed. They're held as they're published when undefined.
+    pub compression: Option<Compression>,
+
+    /// The size from which the bodies of the messages are compressed, in bytes. The smaller
+    /// bodies are held as they're published, as compressing them would hardly save anything.
+    #[serde(default = "Queue::default_compression_threshold_bytes")]
+    pub compression_threshold_bytes: u64,
+
+    /// Whether the messages published with an `x-delay` header, holding a number of milliseconds,
+    /// are held by their queue until the delay elapses, such as for the backoff of the retries.
+    /// The delayed messages are moved to their queue every expiry scan interval, so they may be
+    /// late by up to an interval. A delayed message of a durable queue is stored when it's
+    /// published, and it's delivered right away if the broker restarts before it's due. The header
+    /// is ignored by default.
+    #[serde(default)]
+    pub enable_delayed: bool,
+
+    /// Whether the consumers can pass an `x-selector` argument, a table of headers such as
+    /// `type = "invoice"`, to only receive the messages holding each of these headers with the
+    /// same string value. The messages a consumer doesn't select are left to the other
+    /// consumers of the queue. Each message is then matched against the selectors of the
+    /// consumers, and a queue whose consumers select none of its first messages looks past
+    /// them on each delivery, so the selectors are disabled by default.
+    #[serde(default)]
+    pub enable_selectors: bool,
+}
+
+impl Queue {
+    /// Checks that the queue namespace holds a usable configuration, adding each error to
+    /// `errors`.
+    pub fn validate(&self, errors: &mut Vec<ConfigError>) {
+        if let Some(storage) = &self.storage {
+            errors.extend(storage.validate().err());
+
+            if self.memory_only {
+                errors.push(ConfigError::Invalid {
+                    field: "queue.memory_only".into(),
+                    reason: "a memory-only broker must not define the storage".into(),
+                });
+            }
+        }
+
+        if let Some(compression) = self.compression {
+            errors.extend(compression.validate().err());
+        }
+
+        if self.max_message_bytes == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: "queue.max_message_bytes".into(),
+                reason: "the maximum size of a message must not be 0".into(),
+            });
+        }
+
+        if self.max_unacked_total == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: "queue.max_unacked_total".into(),
+                reason: "the maximum number of unacknowledged messages must not be 0".into(),
+            });
+        }
+
+        if self.expiry_scan_interval == Duration::from_secs(0) {
+            errors.push(ConfigError::Invalid {
+                field: "queue.expiry_scan_interval".into(),
+                reason: "the interval between the expiry scans must not be 0".into(),
+            });
+        }
+
+        if self.default_message_ttl == Some(Duration::from_secs(0)) {
+            errors.push(ConfigError::Invalid {
+                field: "queue.default_message_ttl".into(),
+                reason: "the time-to-live of the messages must not be 0".into(),
+            });
+        }
+
+        if let Some(default_queue) = &self.default_queue {
+            if !self.definitions.iter().any(|d| &d.name == default_queue) {
+                errors.push(ConfigError::Invalid {
+                    field: "queue.default_queue".into(),
+                    reason: format!("the queue {} is not defined", default_queue),
+                });
+            }
+        }
+
+        validate_definitions(
+            "queue.definitions",
+            &self.definitions,
+            self.storage.is_some(),
+            errors,
+        );
+    }
+
+    fn default_expiry_scan_interval() -> Duration {
+        DEFAULT_EXPIRY_SCAN_INTERVAL
+    }
+
+    fn default_compression_threshold_bytes() -> u64 {
+        DEFAULT_COMPRESSION_THRESHOLD_BYTES
+    }
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self {
+            definitions: Vec::new(),
+            max_message_bytes: None,
+            max_unacked_total: None,
+            default_queue: None,
+            expiry_scan_interval: Self::default_expiry_scan_interval(),
+            default_message_ttl: None,
+            storage: None,
+            memory_only: false,
+            compression: None,
+            compression_threshold_bytes: Self::default_compression_threshold_bytes(),
+            enable_delayed: false,
+            enable_selectors: false,
+        }
+    }
+}
+
+/// Checks the queue definitions found at `field`, adding each error to `errors`. The dead-letter
+/// queues must be defined alongside them, and the lazy queues require a storage, as told by
+/// `has_storage`. Each name must be defined once, the names differing by their case only being
+/// distinct queues.
+fn validate_definitions(
+    field: &str,
+    definitions: &[QueueDefinition],
+    has_storage: bool,
+    errors: &mut Vec<ConfigError>,
+) {
+    for (i, definition) in definitions.iter().enumerate() {
+        if definition.name.is_empty() {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].name", field, i),
+                reason: "the name of a queue must not be empty".into(),
+            });
+        } else if definitions[..i]
+            .iter()
+            .any(|previous| previous.name == definition.name)
+        {
+            errors.push(ConfigError::DuplicateQueue {
+                field: format!("{}[{}].name", field, i),
+                name: definition.name.clone(),
+            });
+        }
+
+        if definition.max_length == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].max_length", field, i),
+                reason: "the maximum length of a queue must not be 0".into(),
+            });
+        }
+
+        if definition.max_length_bytes == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].max_length_bytes", field, i),
+                reason: "the maximum size of a queue must not be 0".into(),
+            });
+        }
+
+        if definition.dedup_window == Some(Duration::from_secs(0)) {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].dedup_window", field, i),
+                reason: "the deduplication window must not be 0".into(),
+            });
+        }
+
+        if definition.ack_timeout == Some(Duration::from_secs(0)) {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].ack_timeout", field, i),
+                reason: "the acknowledgement timeout must not be 0".into(),
+            });
+        }
+
+        if definition.delivery_mode == DeliveryMode::AtMostOnce {
+            if definition.ack_timeout.is_some() {
+                errors.push(ConfigError::Invalid {
+                    field: format!("{}[{}].ack_timeout", field, i),
+                    reason: "the messages delivered at most once are never acknowledged".into(),
+                });
+            }
+            if definition.max_redeliveries.is_some() {
+                errors.push(ConfigError::Invalid {
+                    field: format!("{}[{}].max_redeliveries", field, i),
+                    reason: "the messages delivered at most once are never redelivered".into(),
+                });
+            }
+            if definition.prefetch_count.is_some() {
+                errors.push(ConfigError::Invalid {
+                    field: format!("{}[{}].prefetch_count", field, i),
+                    reason: "the messages delivered at most once are never acknowledged".into(),
+                });
+            }
+        }
+
+        if definition.lazy {
+            let reason = if !definition.durable {
+                Some("a lazy queue must be durable")
+            } else if !has_storage {
+                Some("a lazy queue requires the storage of the queue namespace")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                errors.push(ConfigError::Invalid {
+                    field: format!("{}[{}].lazy", field, i),
+                    reason: reason.into(),
+                });
+            }
+        }
+
+        if definition.sync == Some(true) && !definition.durable {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].sync", field, i),
+                reason: "the messages of a queue which is not durable are never stored".into(),
+            });
+        } else if definition.sync.is_some() && !has_storage {
+            log::warn!(
+                "{}[{}].sync is ignored, as the queue namespace defines no storage",
+                field,
+                i
+            );
+        }
+
+        if definition.max_consumers == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].max_consumers", field, i),
+                reason: "the maximum number of consumers must not be 0".into(),
+            });
+        }
+        if definition.prefetch_count == Some(0) {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].prefetch_count", field, i),
+                reason: "the prefetch count must not be 0".into(),
+            });
+        }
+
+        for (j, binding) in definition.bindings.iter().enumerate() {
+            if let Err(reason) = binding.validate() {
+                errors.push(ConfigError::Invalid {
+                    field: format!("{}[{}].bindings[{}]", field, i, j),
+                    reason,
+                });
+            }
+        }
+
+        for (j, alert) in definition.alerts.iter().enumerate() {
+            alert.validate(&format!("{}[{}].alert[{}]", field, i, j), errors);
+        }
+
+        let is_valid_label = |name: &str| {
+            name.bytes()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == b'_')
+                && name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_')
+                && !name.starts_with("__")
+        };
+        for name in definition.metrics_labels.iter().flat_map(HashMap::keys) {
+            let reason = if name == "vhost" || name == "queue" {
+                format!("the label {} is reserved by the broker", name)
+            } else if !is_valid_label(name) {
+                format!("{:?} is not a valid label name", name)
+            } else {
+                continue;
+            };
+            errors.push(ConfigError::Invalid {
+                field: format!("{}[{}].metrics_labels", field, i),
+                reason,
+            });
+        }
+
+        if let Some(dead_letter) = &definition.dead_letter {
+            if !definitions.iter().any(|d| d.name == dead_letter.queue) {
+                errors.push(ConfigError::Invalid {
+                    field: format!("{}[{}].dead_letter.queue", field, i),
+                    reason: format!("the queue {} is not defined", dead_letter.queue),
+                });
+            }
+        }
+    }
+}
+
+/// How the bodies of the messages are compressed while they're held by the broker.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Compression {
+    /// The bodies are held as they're published.
+    #[default]
+    None,
+    /// The bodies are compressed with gzip, which needs the `gzip` feature.
+    Gzip,
+    /// The bodies are compressed with zstd, which needs the `zstd` feature.
+    Zstd,
+}
+
+impl Compression {
+    fn validate(self) -> Result<(), ConfigError> {
+        let feature = match self {
+            Self::None => return Ok(()),
+            Self::Gzip if cfg!(feature = "gzip") => return Ok(()),
+            Self::Zstd if cfg!(feature = "zstd") => return Ok(()),
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        };
+
+        Err(ConfigError::Invalid {
+            field: "queue.compression".into(),
+            reason: format!("another-mq is built without the `{}` feature", feature),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Compression {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CompressionVisitor;
+
+        impl<'de> Visitor<'de> for CompressionVisitor {
+            type Value = Compression;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting none, gzip or zstd")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(CompressionVisitor)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err("Unknown compression!"),
+        }
+    }
+}
+
+impl Serialize for Compression {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        })
+    }
+}
+
+/// The storage of the messages of the durable queues, defined by the `[queue.storage]` table.
+///
+/// The messages are appended to segment files, which are replayed when the broker starts. A
+/// segment is deleted once the messages it holds, and those of the older segments, are settled.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StorageConfig {
+    /// The directory holding the segment files. It's created if it doesn't exist, and it must be
+    /// writable by the broker.
+    pub path: String,
+
+    /// Whether each write is flushed to the disk before going on. It's slower, but no message is
+    /// lost if the system crashes. The queues can override it with their own `sync`.
+    #[serde(default)]
+    pub sync: bool,
+
+    /// How long the broker waits for the storage to be ready when it starts, such as a network
+    /// volume mounted after the broker is started, before giving up. The directory is then not
+    /// created by the broker, which retries opening it every second until it exists and it's
+    /// writable. The broker gives up right away when undefined.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub wait_ready: Option<Duration>,
+}
+
+impl StorageConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.path.is_empty() {
+            return Err(ConfigError::Invalid {
+                field: "queue.storage.path".into(),
+                reason: "the path of the storage must not be empty".into(),
+            });
+        }
+        if self.wait_ready == Some(Duration::from_secs(0)) {
+            return Err(ConfigError::Invalid {
+                field: "queue.storage.wait_ready".into(),
+                reason: "the wait for the storage must not be 0".into(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The definition of a queue declared by the broker.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct QueueDefinition {
+    /// The name of the queue, which is case-sensitive. It must be unique among the definitions of
+    /// its virtual host.
+    pub name: String,
+
+    /// Whether the queue should survive a restart of the broker.
+    #[serde(default)]
+    pub durable: bool,
+
+    /// Whether the bodies of the messages waiting in the queue are left in the storage, only the
+    /// rest of the messages being held in memory, so a large backlog doesn't exhaust it. The body
+    /// of a message is read back from the storage each time it's delivered, which lowers the
+    /// throughput of the queue, all the more on a slow disk. The queue must be durable, and the
+    /// queue namespace must define a storage.
+    #[serde(default)]
+    pub lazy: bool,
+
+    /// Whether each message of the queue written to the storage is flushed to the disk before
+    /// going on, overriding the `sync` of the storage, so only the critical queues pay for it.
+    /// The queue follows the storage when undefined. Flushing a message requires the queue to be
+    /// durable.
+    pub sync: Option<bool>,
+
+    /// The maximum number of messages held by the queue.
+    pub max_length: Option<u64>,
+
+    /// The maximum total size of the bodies of the messages held by the queue, in bytes, as they
+    /// were published, whatever their compression. It bounds the memory of the queues whose
+    /// messages vary widely in size, alongside `max_length`: the queue is full once either
+    /// limit is reached. A message larger than the limit on its own is always dropped.
+    pub max_length_bytes: Option<u64>,
+
+    /// What happens to the messages published to the queue once it's full, holding its maximum
+    /// length or its maximum size.
+    #[serde(default)]
+    pub overflow: OverflowBehavior,
+
+    /// The maximum number of consumers of the queue. Once it's reached, the clients trying to
+    /// consume the queue are refused. The consumers are unbounded when undefined.
+    pub max_consumers: Option<usize>,
+
+    /// The maximum number of messages delivered to each consumer of the queue and left
+    /// unacknowledged, so a slow consumer is not flooded. Once it's reached, the messages are held
+    /// back until the consumer acknowledges some, and they go to the other consumers meanwhile.
+    /// It applies alongside the prefetch count of the channel of the consumer, and it doesn't
+    /// apply to the consumers with the `no-ack` flag. The deliveries are unbounded when undefined.
+    pub prefetch_count: Option<u32>,
+
+    /// Whether the messages are delivered to a single consumer at a time, so they're handled in
+    /// the order they were published, redeliveries included. The other consumers stand by in the
+    /// order they consumed the queue, and the first of them takes over once the active consumer
+    /// is cancelled or its channel is closed.
+    #[serde(default)]
+    pub single_active_consumer: bool,
+
+    /// The maximum priority of the messages, which turns the queue into a priority queue: the
+    /// messages with the highest priority, from 0 to the maximum, are delivered first. The
+    /// messages are delivered in the order they were published when undefined.
+    pub max_priority: Option<u8>,
+
+    /// How long a message can stay in the queue before being discarded, written in a human
+    /// readable form such as `30s` or `1h 30m`. The `default_message_ttl` of the queue namespace
+    /// applies when undefined.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub message_ttl: Option<Duration>,
+
+    /// How long the identifiers of the messages published to the queue are remembered, so a
+    /// message published again with the same `message-id` property within the window is
+    /// discarded. The messages without identifier are never discarded. The duplicates are kept
+    /// when undefined.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dedup_window: Option<Duration>,
+
+    /// How long a message delivered to a consumer can stay unacknowledged before being requeued
+    /// for another consumer, in case the consumer is stuck. A consumer acknowledging it afterwards
+    /// gets the error of an unknown delivery tag. The messages are checked every expiry scan
+    /// interval of the queue namespace. They can stay unacknowledged forever when undefined.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ack_timeout: Option<Duration>,
+
+    /// Whether the messages of the queue are delivered at least once, being redelivered until a
+    /// consumer acknowledges them, or at most once, being settled as soon as they're delivered.
+    #[serde(default)]
+    pub delivery_mode: DeliveryMode,
+
+    /// Whether the messages delivered to the consumers of the queue are settled as soon as
+    /// they're delivered, or kept until the consumers acknowledge them. It applies to the
+    /// consumers which neither set the `no-ack` flag nor pass an `x-ack-mode` argument of `auto`
+    /// or `manual`, and the consumers of a queue in the `auto` mode must not acknowledge its
+    /// messages. The messages of a queue delivering them at most once are always settled.
+    #[serde(default)]
+    pub default_ack_mode: AckMode,
+
+    /// The maximum number of times a message is requeued after being delivered, once rejected
+    /// by a consumer or left unacknowledged past the acknowledgement timeout. A message which
+    /// would be redelivered once more is dead-lettered instead. The redeliveries are unbounded
+    /// when undefined.
+    pub max_redeliveries: Option<u32>,
+
+    /// Where the messages rejected by the consumers or expired are republished, instead of being
+    /// dropped.
+    pub dead_letter: Option<DeadLetterConfig>,
+
+    /// The labels added to the metrics of the queue, alongside its virtual host and its name,
+    /// such as `team = "billing"`, so the queues can be grouped in the dashboards. The names must
+    /// be valid Prometheus label names, and `vhost` and `queue` are reserved. The labels of a
+    /// queue already declared are only updated once the broker is restarted.
+    pub metrics_labels: Option<HashMap<String, String>>,
+
+    /// The patterns binding the queue to the `amq.topic` exchange, such as `orders.#`: a message
+    /// published to the exchange is routed to every queue with a pattern matching its routing key
+    /// (see [`BindingPattern`]). The bindings of a queue already declared are only updated once
+    /// the broker is restarted.
+    #[serde(default)]
+    pub bindings: Vec<BindingPattern>,
+
+    /// The alerts posted to a webhook when the queue holds too many messages, defined by the
+    /// `[[queue.definitions.alert]]` entries. The alerts of a queue already declared are only
+    /// updated once the broker is restarted.
+    #[serde(default, rename = "alert", skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<Alert>,
+}
+
+impl QueueDefinition {
+    /// Creates the definition of a queue which is neither durable nor bounded.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            durable: false,
+            lazy: false,
+            sync: None,
+            max_length: None,
+            max_length_bytes: None,
+            overflow: OverflowBehavior::RejectPublish,
+            max_consumers: None,
+            prefetch_count: None,
+            single_active_consumer: false,
+            max_priority: None,
+            message_ttl: None,
+            dedup_window: None,
+            ack_timeout: None,
+            delivery_mode: DeliveryMode::AtLeastOnce,
+            default_ack_mode: AckMode::Manual,
+            max_redeliveries: None,
+            dead_letter: None,
+            metrics_labels: None,
+            bindings: Vec::new(),
+            alerts: Vec::new(),
+        }
+    }
+}
+
+/// An alert of a queue, defined by a `[[queue.definitions.alert]]` entry, posted to a webhook once
+/// the queue holds `depth_threshold` messages, such as when a backlog forms, and posted again once
+/// the queue is back to `clear_threshold` messages. The depth of the queues is checked every
+/// second.
+///
+/// The webhook receives a JSON object such as `{"vhost":"/","queue":"orders","state":"firing",
+/// "depth":1250,"depth_threshold":1000}`, whose `state` is `resolved` once the queue is back under
+/// the threshold. The alert is posted again by the next check if the webhook doesn't answer with a
+/// `2xx` status, once `min_interval` has elapsed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Alert {
+    /// The number of messages from which the alert is firing.
+    pub depth_threshold: u64,
+
+    /// The number of messages from which the alert is resolved, below `depth_threshold` so the
+    /// alert doesn't flap when the depth of the queue hovers around the threshold. It's half of
+    /// `depth_threshold` by default.
+    pub clear_threshold: Option<u64>,
+
+    /// The URL the alert is posted to, such as `http://alerts.example.com:8080/hooks/mq`. Only
+    /// the `http` scheme is supported.
+    pub webhook_url: String,
+
+    /// The minimum delay between two posts of the alert, which is a minute by default. The
+    /// changes of the state of the alert in the meantime are only posted once it has elapsed, the
+    /// alert being posted in its last state, if it's not already.
+    #[serde(
+        default = "Alert::default_min_interval",
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub min_interval: Duration,
+}
+
+impl Alert {
+    /// Returns the number of messages from which the alert is resolved.
+    pub fn effective_clear_threshold(&self) -> u64 {
+        self.clear_threshold.unwrap_or(self.depth_threshold / 2)
+    }
+
+    /// Checks the alert found at `field`, adding each error to `errors`.
+    fn validate(&self, field: &str, errors: &mut Vec<ConfigError>) {
+        if self.depth_threshold == 0 {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}.depth_threshold", field),
+                reason: "the threshold of an alert must not be 0".into(),
+            });
+        }
+        if self
+            .clear_threshold
+            .is_some_and(|clear| clear >= self.depth_threshold)
+        {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}.clear_threshold", field),
+                reason: "the alert must be cleared below its threshold".into(),
+            });
+        }
+        if let Err(reason) = crate::http::Url::parse(&self.webhook_url) {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}.webhook_url", field),
+                reason,
+            });
+        }
+        if self.min_interval == Duration::from_secs(0) {
+            errors.push(ConfigError::Invalid {
+                field: format!("{}.min_interval", field),
+                reason: "the interval between the posts of an alert must not be 0".into(),
+            });
+        }
+    }
+
+    fn default_min_interval() -> Duration {
+        DEFAULT_ALERT_MIN_INTERVAL
+    }
+}
+
+/// A pattern matched against the routing keys of the messages published to the `amq.topic`
+/// exchange. The keys and the patterns are made of words separated by dots, such as
+/// `orders.eu.created`. In a pattern, the word `*` matches exactly one word, and `#` matches zero
+/// or more words, so `orders.*.created` and `orders.#` both match `orders.eu.created`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct BindingPattern(String);
+
+impl BindingPattern {
+    /// Returns `true` if `routing_key` matches the pattern.
+    pub fn matches(&self, routing_key: &str) -> bool {
+        let pattern: Vec<_> = self.0.split('.').collect();
+        let words: Vec<_> = routing_key.split('.').collect();
+
+        matches_words(&pattern, &words)
+    }
+
+    /// Checks that the wildcards of the pattern are words of their own.
+    fn validate(&self) -> Result<(), String> {
+        if self.0.is_empty() {
+            return Err("the binding pattern must not be empty".into());
+        }
+
+        match self
+            .0
+            .split('.')
+            .find(|word| *word != "*" && *word != "#" && (word.contains('*') || word.contains('#')))
+        {
+            Some(word) => Err(format!(
+                "the word {} of the binding pattern {} mixes a wildcard with other characters",
+                word, self.0
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns `true` if the words of a routing key match the words of a binding pattern.
+fn matches_words(pattern: &[&str], words: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => words.is_empty(),
+        Some((&"#", rest)) => {
+            (0..=words.len()).any(|skipped| matches_words(rest, &words[skipped..]))
+        }
+        Some((&"*", rest)) => !words.is_empty() && matches_words(rest, &words[1..]),
+        Some((word, rest)) => words.first() == Some(word) && matches_words(rest, &words[1..]),
+    }
+}
+
+/// How many times the messages of a queue are delivered.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum DeliveryMode {
+    /// The messages are settled as soon as they're delivered, so they're lost if the consumer
+    /// fails to handle them. The queue must be consumed with the `no-ack` flag, which spares the
+    /// broker from keeping the messages until they're acknowledged.
+    AtMostOnce,
+    /// The messages are kept until a consumer acknowledges them, and they're delivered again if
+    /// their consumer rejects them or goes away before acknowledging them, unless it consumes the
+    /// queue with the `no-ack` flag.
+    #[default]
+    AtLeastOnce,
+}
+
+impl<'de> Deserialize<'de> for DeliveryMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct DeliveryModeVisitor;
+
+        impl<'de> Visitor<'de> for DeliveryModeVisitor {
+            type Value = DeliveryMode;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting at_most_once or at_least_once")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(DeliveryModeVisitor)
+    }
+}
+
+impl FromStr for DeliveryMode {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "at_most_once" | "atmostonce" => Ok(Self::AtMostOnce),
+            "at_least_once" | "atleastonce" => Ok(Self::AtLeastOnce),
+            _ => Err("Unknown delivery mode!"),
+        }
+    }
+}
+
+impl Serialize for DeliveryMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::AtMostOnce => "at_most_once",
+            Self::AtLeastOnce => "at_least_once",
+        })
     }
 }
 
-/// The queue namespace of the application's configuration.
-#[derive(Clone, Debug, Default, Deserialize)]
-pub struct Queue;
+/// How the messages delivered to a consumer are acknowledged.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum AckMode {
+    /// The messages are settled as soon as they're delivered, as if the consumer set the `no-ack`
+    /// flag, so they're lost if the consumer goes away before handling them.
+    Auto,
+    /// The messages are kept until the consumer acknowledges them, and they're delivered again if
+    /// the consumer goes away before acknowledging them.
+    #[default]
+    Manual,
+}
+
+impl<'de> Deserialize<'de> for AckMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AckModeVisitor;
+
+        impl<'de> Visitor<'de> for AckModeVisitor {
+            type Value = AckMode;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting auto or manual")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(AckModeVisitor)
+    }
+}
+
+impl FromStr for AckMode {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "manual" => Ok(Self::Manual),
+            _ => Err("Unknown acknowledgement mode!"),
+        }
+    }
+}
+
+impl Serialize for AckMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Auto => "auto",
+            Self::Manual => "manual",
+        })
+    }
+}
+
+/// What happens to the messages published to a queue which holds its maximum length or its
+/// maximum size.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum OverflowBehavior {
+    /// The messages published are dropped, the queue being left as it is.
+    #[default]
+    RejectPublish,
+    /// The messages at the head of the queue are taken until there's room for the message
+    /// published, and they're dead-lettered. With a maximum priority, the head of the lowest
+    /// priority holding messages is taken first.
+    DropHead,
+}
+
+impl<'de> Deserialize<'de> for OverflowBehavior {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct OverflowBehaviorVisitor;
+
+        impl<'de> Visitor<'de> for OverflowBehaviorVisitor {
+            type Value = OverflowBehavior;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("Expecting reject_publish or drop_head")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value
+                    .parse()
+                    .map_err(|err| de::Error::invalid_value(Unexpected::Str(value), &err))
+            }
+        }
+
+        deserializer.deserialize_str(OverflowBehaviorVisitor)
+    }
+}
+
+impl FromStr for OverflowBehavior {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject_publish" | "RejectPublish" | "REJECT_PUBLISH" => Ok(Self::RejectPublish),
+            "drop_head" | "DropHead" | "DROP_HEAD" => Ok(Self::DropHead),
+            _ => Err("Unknown overflow behavior!"),
+        }
+    }
+}
+
+impl Serialize for OverflowBehavior {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::RejectPublish => "reject_publish",
+            Self::DropHead => "drop_head",
+        })
+    }
+}
+
+/// The queue receiving the dead letters of another queue: the messages negatively acknowledged
+/// without being requeued, the expired messages, and the ones taken from the head of the queue
+/// when it overflows.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DeadLetterConfig {
+    /// The name of the queue receiving the dead letters, which must be defined in the same
+    /// virtual host.
+    pub queue: String,
+
+    /// The routing key replacing the one of the dead letters. They keep their routing key when
+    /// undefined.
+    pub routing_key: Option<String>,
+}
+
+/// A virtual host, isolating its queues from the ones of the other virtual hosts.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VirtualHost {
+    /// The name the clients select the virtual host by.
+    pub name: String,
+
+    /// The queues declared in the virtual host by the broker when it starts, defined by
+    /// `[[vhost.definitions]]` tables.
+    #[serde(default)]
+    pub definitions: Vec<QueueDefinition>,
+}
+
+impl VirtualHost {
+    /// Checks that the virtual host at `index` holds a usable configuration, adding each error to
+    /// `errors`. Its name must not be the one of the default virtual host, nor of one of
+    /// `previous`, the virtual hosts defined before it. Its queues are stored by the storage of
+    /// the queue namespace `queue`.
+    pub fn validate(
+        &self,
+        index: usize,
+        previous: &[VirtualHost],
+        queue: &Queue,
+        errors: &mut Vec<ConfigError>,
+    ) {
+        let reason = if self.name.is_empty() {
+            Some("the name of a virtual host must not be empty".to_string())
+        } else if self.name == DEFAULT_VHOST {
+            Some(format!(
+                "{} is the name of the default virtual host",
+                self.name
+            ))
+        } else if previous.iter().any(|vhost| vhost.name == self.name) {
+            Some(format!("the virtual host {} is already defined", self.name))
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            errors.push(ConfigError::Invalid {
+                field: format!("vhost[{}].name", index),
+                reason,
+            });
+        }
+
+        validate_definitions(
+            &format!("vhost[{}].definitions", index),
+            &self.definitions,
+            queue.storage.is_some(),
+            errors,
+        );
+    }
+}
+
+/// Deserializes a duration written in a human readable form, such as `30s` or `1h 30m`.
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct DurationVisitor;
+
+    impl<'de> Visitor<'de> for DurationVisitor {
+        type Value = Duration;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("Expecting a duration such as 30s or 1h 30m")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            humantime::parse_duration(value)
+                .map_err(|_| de::Error::invalid_value(Unexpected::Str(value), &"Invalid duration!"))
+        }
+    }
+
+    deserializer.deserialize_str(DurationVisitor)
+}
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+/// Serializes a duration in the human readable form read by [`deserialize_duration`].
+fn serialize_duration<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(&humantime::format_duration(*duration))
+}
+
+fn serialize_optional_duration<S>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match duration {
+        Some(duration) => serialize_duration(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serializes a secret, such as a password, without disclosing it in the dumps of the
+/// configuration.
+fn serialize_redacted<T, S>(_: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str("<redacted>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Serializes the tests reading the environment, which is shared by the whole process.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Sets the variables of `vars` until the guard returned is dropped, the other tests reading
+    /// the environment waiting for it meanwhile.
+    fn set_env(vars: &[(&str, &str)]) -> EnvGuard {
+        let lock = ENV.lock().unwrap_or_else(|err| err.into_inner());
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+
+        EnvGuard {
+            names: vars.iter().map(|(name, _)| name.to_string()).collect(),
+            _lock: lock,
+        }
+    }
+
+    struct EnvGuard {
+        names: Vec<String>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl Drop for EnvGuard {
+        fn drop(&mut self) {
+            for name in &self.names {
+                env::remove_var(name);
+            }
+        }
+    }
+
+    #[test]
+    fn env_overrides_replace_the_fields() {
+        let _env = set_env(&[
+            ("ANOTHERMQ_NETWORK_PORT", "5673"),
+            ("ANOTHERMQ_NETWORK_HOSTNAME", "127.0.0.1"),
+            ("ANOTHERMQ_LOG_LEVEL", "debug"),
+        ]);
+        let mut config = Config::default();
+
+        config.apply_env_overrides().unwrap();
+
+        assert_eq!(config.network.port, 5673);
+        assert_eq!(config.network.hostname, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(config.log.level, log::Level::Debug);
+    }
+
+    #[test]
+    fn env_overrides_take_precedence_over_the_file() {
+        let _env = set_env(&[("ANOTHERMQ_NETWORK_PORT", "5673")]);
+        let raw = "[network]\nport = 5674\nhostname = \"127.0.0.1\"\n";
+
+        let config = Config::from_reader(raw.as_bytes(), ConfigFormat::Toml).unwrap();
+
+        assert_eq!(config.network.port, 5673);
+        assert_eq!(config.network.hostname, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn env_override_with_an_invalid_value_is_an_error() {
+        let _env = set_env(&[("ANOTHERMQ_NETWORK_PORT", "70000")]);
+        let mut config = Config::default();
+
+        match config.apply_env_overrides() {
+            Err(ConfigError::Env { name, value, .. }) => {
+                assert_eq!(name, "ANOTHERMQ_NETWORK_PORT");
+                assert_eq!(value, "70000");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(config.network.port, DEFAULT_LISTENER_PORT);
+    }
+
+    #[test]
+    fn configuration_is_built_from_the_environment_alone() {
+        let _env = set_env(&[
+            ("ANOTHERMQ_NETWORK_PORT", "5673"),
+            ("ANOTHERMQ_QUEUE_MAX_UNACKED_TOTAL", "100"),
+        ]);
+
+        let config = Config::from_env_only().unwrap();
+
+        assert_eq!(config.network.port, 5673);
+        assert_eq!(config.queue.max_unacked_total, Some(100));
+        assert_eq!(config.log, Config::default().log);
+    }
+
+    #[test]
+    fn configuration_built_from_the_environment_is_validated() {
+        {
+            let _env = set_env(&[("ANOTHERMQ_NETWORK_PORT", "port")]);
+            assert!(matches!(
+                Config::from_env_only(),
+                Err(ConfigError::Env { name, .. }) if name == "ANOTHERMQ_NETWORK_PORT"
+            ));
+        }
+
+        let _env = set_env(&[("ANOTHERMQ_QUEUE_MAX_UNACKED_TOTAL", "0")]);
+        assert!(matches!(
+            Config::from_env_only(),
+            Err(ConfigError::Invalid { field, .. }) if field == "queue.max_unacked_total"
+        ));
+    }
+
+    /// Returns the fields reported by the validation of `config`, in order.
+    fn invalid_fields(config: &mut Config) -> Vec<String> {
+        config
+            .validation_errors()
+            .into_iter()
+            .map(|err| match err {
+                ConfigError::Invalid { field, .. } | ConfigError::DuplicateQueue { field, .. } => {
+                    field
+                }
+                err => panic!("unexpected error {}", err),
+            })
+            .collect()
+    }
+
+    /// Parses a TOML configuration without reading the environment.
+    fn parse(raw: &str) -> Config {
+        toml::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn port_0_is_rejected() {
+        let mut config = parse("[network]\nport = 0\n");
+
+        assert_eq!(invalid_fields(&mut config), vec!["network.port"]);
+    }
+
+    #[test]
+    fn privileged_port_is_accepted() {
+        assert!(Network::validate_port("network.port", 80).is_ok());
+        assert!(invalid_fields(&mut parse("[network]\nport = 80\n")).is_empty());
+    }
+
+    #[test]
+    fn default_port_is_accepted() {
+        assert!(Network::validate_port("network.port", 5672).is_ok());
+        assert!(invalid_fields(&mut Config::default()).is_empty());
+    }
+
+    /// Returns a syslog configuration sending the entries to `host`, if any.
+    fn syslog(host: Option<&str>, port: Option<u16>) -> Syslog {
+        Syslog {
+            host: host.map(String::from),
+            port,
+            ..Syslog::default()
+        }
+    }
+
+    fn invalid_field(result: Result<(), ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid { field, .. }) => field,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn syslog_with_a_port_and_no_host_is_rejected() {
+        let mut syslog = syslog(None, Some(514));
+
+        assert_eq!(invalid_field(syslog.validate()), "log.syslog.host");
+    }
+
+    #[test]
+    fn syslog_over_tcp_without_host_is_rejected() {
+        let mut syslog = Syslog {
+            transport: SyslogTransport::Tcp,
+            ..syslog(None, None)
+        };
+
+        assert_eq!(invalid_field(syslog.validate()), "log.syslog.host");
+    }
+
+    #[test]
+    fn syslog_with_an_empty_host_is_rejected() {
+        let mut syslog = syslog(Some(""), None);
+
+        assert_eq!(invalid_field(syslog.validate()), "log.syslog.host");
+    }
+
+    #[test]
+    fn syslog_without_port_gets_the_default_one() {
+        let mut syslog = syslog(Some("syslog.internal"), None);
+
+        syslog.validate().unwrap();
+
+        assert_eq!(syslog.port, Some(SYSLOG_DEFAULT_PORT));
+    }
+
+    #[test]
+    fn syslog_keeps_its_port() {
+        let mut syslog = syslog(Some("syslog.internal"), Some(1514));
+
+        syslog.validate().unwrap();
+
+        assert_eq!(syslog.port, Some(1514));
+    }
+
+    #[test]
+    fn half_configured_syslog_fails_the_validation_of_the_config() {
+        let mut config = parse(
+            "[log.syslog]\nport = 514\nprotocol = \"rfc5424\"\nfacility = \"user\"\nprocess = \"mq\"\n",
+        );
+
+        assert_eq!(invalid_fields(&mut config), vec!["log.syslog.host"]);
+    }
+
+    #[test]
+    fn effective_toml_parses_back_to_the_same_config() {
+        let mut config = parse(
+            r#"
+            [network]
+            port = 5673
+            idle_timeout = "90s"
+            allow_cidrs = ["10.0.0.0/8"]
+
+            [[network.listener]]
+            hostname = "127.0.0.1"
+            port = 5674
+            protocol = "text"
+
+            [log]
+            level = "debug"
+            file = "/var/log/another-mq.log"
+
+            [log.rotation]
+            max_size_bytes = 1048576
+            max_files = 3
+
+            [queue]
+            max_message_bytes = 65536
+            default_message_ttl = "1m"
+
+            [[queue.definitions]]
+            name = "tasks"
+            durable = true
+            max_priority = 9
+            message_ttl = "30s"
+
+            [[vhost]]
+            name = "staging"
+
+            [[vhost.definitions]]
+            name = "events"
+            "#,
+        );
+        config.validate().unwrap();
+
+        let dumped: Config = toml::from_str(&config.effective_toml()).unwrap();
+
+        assert_eq!(dumped, config);
+    }
+
+    #[test]
+    fn effective_toml_of_the_default_config_parses_back() {
+        let config = Config::default();
+
+        let dumped: Config = toml::from_str(&config.effective_toml()).unwrap();
+
+        assert_eq!(dumped, config);
+    }
+
+    #[test]
+    fn effective_toml_redacts_the_password_hashes() {
+        let hash = "$2b$10$abcdefghijklmnopqrstuuj7m4XYqbm74SxoBcXyw8to0CQ061DVG";
+        let config = parse(&format!(
+            "[[auth.users]]\nusername = \"guest\"\npassword_hash = \"{}\"\n",
+            hash
+        ));
+
+        let dumped = config.effective_toml();
+
+        assert!(!dumped.contains(hash));
+        assert!(dumped.contains("<redacted>"));
+    }
+
+    #[test]
+    fn interpolation_replaces_the_references() {
+        let _env = set_env(&[
+            ("ANOTHERMQ_TEST_HASH", "$argon2id$v=19"),
+            ("ANOTHERMQ_TEST_USER", "guest"),
+        ]);
+        let raw = "user = \"${ANOTHERMQ_TEST_USER}\"\nhash = \"${ANOTHERMQ_TEST_HASH}\"\n";
+
+        assert_eq!(
+            interpolate(raw).unwrap(),
+            "user = \"guest\"\nhash = \"$argon2id$v=19\"\n"
+        );
+    }
+
+    #[test]
+    fn interpolation_keeps_the_escaped_references() {
+        let _env = set_env(&[("ANOTHERMQ_TEST_USER", "guest")]);
+
+        assert_eq!(
+            interpolate("a = \"$${ANOTHERMQ_TEST_USER}\"\nb = \"$5 ${ANOTHERMQ_TEST_USER}\"")
+                .unwrap(),
+            "a = \"${ANOTHERMQ_TEST_USER}\"\nb = \"$5 guest\""
+        );
+    }
+
+    #[test]
+    fn interpolation_of_an_unset_variable_is_an_error() {
+        let _env = set_env(&[]);
+
+        match interpolate("hash = \"${ANOTHERMQ_TEST_UNSET}\"") {
+            Err(ConfigError::Interpolation { reason }) => {
+                assert_eq!(reason, "the variable ANOTHERMQ_TEST_UNSET is not set")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn interpolation_of_an_invalid_reference_is_an_error() {
+        for raw in [
+            "a = \"${}\"",
+            "a = \"${NOT CLOSED\"\nb = 1",
+            "a = \"${A-B}\"",
+        ] {
+            assert!(
+                matches!(interpolate(raw), Err(ConfigError::Interpolation { .. })),
+                "{:?} was interpolated",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn interpolation_applies_before_deserialization() {
+        let _env = set_env(&[("ANOTHERMQ_TEST_PORT", "5675")]);
+        let raw = interpolate("[network]\nport = ${ANOTHERMQ_TEST_PORT}\n").unwrap();
+
+        assert_eq!(parse(&raw).network.port, 5675);
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn config_file_path_is_joined_to_appdata() {
+        let expected = PathBuf::from(r"C:\Users\guest\AppData\Roaming\another-mq\another-mq.toml");
+
+        for appdata in [
+            r"C:\Users\guest\AppData\Roaming",
+            r"C:\Users\guest\AppData\Roaming\",
+        ] {
+            let _env = set_env(&[("APPDATA", appdata)]);
+
+            assert_eq!(config_file_path(), expected);
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn config_file_path_falls_back_to_the_home_without_appdata() {
+        let _env = set_env(&[("ANOTHERMQ_HOME", r"D:\another-mq")]);
+        let appdata = env::var_os("APPDATA");
+        env::remove_var("APPDATA");
+
+        let path = config_file_path();
+
+        if let Some(appdata) = appdata {
+            env::set_var("APPDATA", appdata);
+        }
+        assert_eq!(
+            path,
+            PathBuf::from(r"D:\another-mq\etc\another-mq\another-mq.toml")
+        );
+    }
+
+    #[test]
+    fn memory_only_with_a_storage_is_rejected() {
+        let mut config =
+            parse("[queue]\nmemory_only = true\n[queue.storage]\npath = \"/var/lib/another-mq\"\n");
+
+        assert_eq!(invalid_fields(&mut config), vec!["queue.memory_only"]);
+    }
+
+    #[test]
+    fn memory_only_accepts_the_durable_definitions() {
+        let mut config = parse(
+            "[queue]\nmemory_only = true\n[[queue.definitions]]\nname = \"tasks\"\ndurable = true\n",
+        );
+
+        assert!(invalid_fields(&mut config).is_empty());
+    }
+
+    #[test]
+    fn sync_of_a_queue_requires_it_to_be_durable() {
+        let mut config = parse(
+            "[queue.storage]\npath = \"/var/lib/another-mq\"\n\
+             [[queue.definitions]]\nname = \"tasks\"\nsync = true\n\
+             [[queue.definitions]]\nname = \"events\"\nsync = false\n\
+             [[queue.definitions]]\nname = \"orders\"\ndurable = true\nsync = true\n",
+        );
+
+        assert_eq!(
+            invalid_fields(&mut config),
+            vec!["queue.definitions[0].sync"]
+        );
+    }
+
+    #[test]
+    fn duplicate_queue_definition_is_rejected() {
+        let mut config = parse(
+            "[[queue.definitions]]\nname = \"tasks\"\n\
+             [[queue.definitions]]\nname = \"events\"\n\
+             [[queue.definitions]]\nname = \"tasks\"\ndurable = true\n",
+        );
+
+        match config.validation_errors().as_slice() {
+            [ConfigError::DuplicateQueue { field, name }] => {
+                assert_eq!(field, "queue.definitions[2].name");
+                assert_eq!(name, "tasks");
+            }
+            errors => panic!("unexpected errors {:?}", errors),
+        }
+    }
+
+    #[test]
+    fn queue_names_differing_by_their_case_are_distinct() {
+        let mut config = parse(
+            "[[queue.definitions]]\nname = \"tasks\"\n[[queue.definitions]]\nname = \"Tasks\"\n",
+        );
+
+        assert!(invalid_fields(&mut config).is_empty());
+    }
+
+    #[test]
+    fn duplicate_queue_definition_is_only_rejected_within_a_virtual_host() {
+        let mut config = parse(
+            "[[queue.definitions]]\nname = \"tasks\"\n\
+             [[vhost]]\nname = \"staging\"\n[[vhost.definitions]]\nname = \"tasks\"\n\
+             [[vhost]]\nname = \"dev\"\n[[vhost.definitions]]\nname = \"tasks\"\n\
+             [[vhost.definitions]]\nname = \"tasks\"\n",
+        );
+
+        assert_eq!(
+            invalid_fields(&mut config),
+            vec!["vhost[1].definitions[1].name"]
+        );
+    }
+
+    /// Writes `raw` to the configuration file `name` of a directory dedicated to the test, whose
+    /// path is returned.
+    fn config_file(name: &str, raw: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("another-mq-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, raw).unwrap();
+        path
+    }
+
+    #[test]
+    fn reload_of_a_missing_file_keeps_the_configuration() {
+        let _env = set_env(&[]);
+        let path = config_file("reload-missing", "");
+        fs::remove_file(&path).unwrap();
+        let mut config = Config::default();
+        config.auth.users.push(User {
+            username: "guest".into(),
+            password_hash: "$2b$04$0acWwhKGPAxXGZi.b9EZ0OjzxXakOS9KOlYB.DJRnN5UGbm.MAH3S".into(),
+            max_connections: None,
+            max_channels: None,
+        });
+        config.queue.definitions.push(QueueDefinition::new("tasks"));
+        let live = config.clone();
+
+        match config.reload(&path) {
+            Err(ConfigError::Missing { path: missing }) => {
+                assert_eq!(missing, path.display().to_string())
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(config.auth.users, live.auth.users);
+        assert!(!config.auth.allows_anonymous());
+        assert_eq!(config, live);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reload_keeps_the_storage_until_a_restart() {
+        let _env = set_env(&[]);
+        let path = config_file(
+            "reload-storage",
+            "[queue]\nmemory_only = true\n\n[[queue.definitions]]\nname = \"tasks\"\n",
+        );
+        let mut config = Config::default();
+        config.queue.storage = Some(StorageConfig {
+            path: "/var/lib/another-mq".into(),
+            sync: false,
+            wait_ready: None,
+        });
+        let storage = config.queue.storage.clone();
+
+        config.reload(&path).unwrap();
+
+        assert_eq!(config.queue.storage, storage);
+        assert!(!config.queue.memory_only);
+        assert_eq!(
+            config.queue.definitions,
+            vec![QueueDefinition::new("tasks")]
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn worker_threads_are_parsed() {
+        let mut config = parse("[network]\nworker_threads = 4\n");
+
+        assert!(invalid_fields(&mut config).is_empty());
+        assert_eq!(config.network.effective_worker_threads(), 4);
+    }
+
+    #[test]
+    fn worker_threads_fall_back_to_the_logical_cpus() {
+        let cpus = thread::available_parallelism().unwrap().get();
+
+        assert_eq!(Network::default().effective_worker_threads(), cpus);
+    }
+
+    #[test]
+    fn no_worker_thread_is_rejected() {
+        let mut config = parse("[network]\nworker_threads = 0\n");
+
+        assert_eq!(invalid_fields(&mut config), vec!["network.worker_threads"]);
+    }
+}
@@ -0,0 +1,246 @@
+//! The builder of the configuration, for the applications embedding the broker which don't load it
+//! from a configuration file.
+
+use super::{
+    Config, Listener, ListenerProtocol, LogFormat, QueueDefinition, StorageConfig, User,
+    VirtualHost,
+};
+use log::Level;
+use std::net::IpAddr;
+
+/// Builds a configuration field by field, starting from the default configuration.
+///
+/// ```
+/// use another_mq::config::{builder::ConfigBuilder, QueueDefinition};
+/// use log::Level;
+///
+/// let config = ConfigBuilder::new()
+///     .listener("127.0.0.1".parse().unwrap(), 5672)
+///     .log_level(Level::Debug)
+///     .queue(QueueDefinition::new("tasks"))
+///     .build();
+/// ```
+///
+/// The configuration built is not validated, which is up to [`Config::validate`].
+#[derive(Clone, Debug, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Creates a builder holding the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an AMQP listener bound to `hostname` and `port`. Once a listener is added, the
+    /// hostname and the port of the network namespace are not listened on anymore.
+    pub fn listener(self, hostname: IpAddr, port: u16) -> Self {
+        self.listener_with_protocol(hostname, port, ListenerProtocol::Native)
+    }
+
+    /// Adds a listener bound to `hostname` and `port`, whose clients speak `protocol`.
+    pub fn listener_with_protocol(
+        mut self,
+        hostname: IpAddr,
+        port: u16,
+        protocol: ListenerProtocol,
+    ) -> Self {
+        self.config.network.listeners.push(Listener {
+            hostname,
+            port,
+            max_connections: None,
+            protocol,
+            reuse_address: None,
+            reuse_port: None,
+            proxy_protocol: None,
+            max_messages_per_second: None,
+            recv_buffer_bytes: None,
+            send_buffer_bytes: None,
+        });
+        self
+    }
+
+    /// Sets the maximum number of connections accepted by each listener.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.config.network.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Sets the minimum level of the entries of the application log.
+    pub fn log_level(mut self, level: Level) -> Self {
+        self.config.log.level = level;
+        self
+    }
+
+    /// Sets the format of the entries of the application log.
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.config.log.format = format;
+        self
+    }
+
+    /// Sets whether the entries of the application log are written to the standard output.
+    pub fn log_stdout(mut self, stdout: bool) -> Self {
+        self.config.log.stdout = stdout;
+        self
+    }
+
+    /// Appends the entries of the application log to the logfile at `path`, in addition to the
+    /// standard output.
+    pub fn log_file<S: Into<String>>(mut self, path: S) -> Self {
+        self.config.log.file = Some(path.into());
+        self
+    }
+
+    /// Adds a user allowed to connect, with the hash of its password.
+    pub fn user<U, P>(mut self, username: U, password_hash: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.config.auth.users.push(User {
+            username: username.into(),
+            password_hash: password_hash.into(),
+            max_connections: None,
+            max_channels: None,
+        });
+        self
+    }
+
+    /// Adds a queue declared by the broker when it starts.
+    pub fn queue(mut self, definition: QueueDefinition) -> Self {
+        self.config.queue.definitions.push(definition);
+        self
+    }
+
+    /// Adds a virtual host, along with the queues declared in it by the broker when it starts.
+    pub fn vhost(mut self, vhost: VirtualHost) -> Self {
+        self.config.vhosts.push(vhost);
+        self
+    }
+
+    /// Stores the messages of the durable queues in the directory at `path`.
+    pub fn storage<S: Into<String>>(mut self, path: S) -> Self {
+        self.config.queue.storage = Some(StorageConfig {
+            path: path.into(),
+            sync: false,
+            wait_ready: None,
+        });
+        self
+    }
+
+    /// Never writes the messages to the disk, every queue being declared as a non-durable queue.
+    pub fn memory_only(mut self) -> Self {
+        self.config.queue.memory_only = true;
+        self
+    }
+
+    /// Serves the metrics over HTTP on `port`.
+    pub fn metrics(mut self, port: u16) -> Self {
+        self.config.metrics.enabled = true;
+        self.config.metrics.port = port;
+        self
+    }
+
+    /// Returns the configuration built.
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_builds_the_default_configuration() {
+        assert_eq!(ConfigBuilder::new().build(), Config::default());
+    }
+
+    #[test]
+    fn build_sets_the_fields() {
+        let hostname: IpAddr = "127.0.0.1".parse().unwrap();
+        let vhost = VirtualHost {
+            name: "staging".into(),
+            definitions: vec![QueueDefinition::new("events")],
+        };
+
+        let config = ConfigBuilder::new()
+            .listener(hostname, 5673)
+            .listener_with_protocol(hostname, 5674, ListenerProtocol::Text)
+            .max_connections(10)
+            .log_level(Level::Debug)
+            .log_format(LogFormat::Json)
+            .log_stdout(false)
+            .log_file("another-mq.log")
+            .user("guest", "$argon2id$hash")
+            .queue(QueueDefinition::new("tasks"))
+            .vhost(vhost.clone())
+            .storage("/var/lib/another-mq")
+            .metrics(9100)
+            .build();
+
+        let mut expected = Config::default();
+        for (port, protocol) in [
+            (5673, ListenerProtocol::Native),
+            (5674, ListenerProtocol::Text),
+        ] {
+            expected.network.listeners.push(Listener {
+                hostname,
+                port,
+                max_connections: None,
+                protocol,
+                reuse_address: None,
+                reuse_port: None,
+                proxy_protocol: None,
+                max_messages_per_second: None,
+                recv_buffer_bytes: None,
+                send_buffer_bytes: None,
+            });
+        }
+        expected.network.max_connections = Some(10);
+        expected.log.level = Level::Debug;
+        expected.log.format = LogFormat::Json;
+        expected.log.stdout = false;
+        expected.log.file = Some("another-mq.log".into());
+        expected.auth.users.push(User {
+            username: "guest".into(),
+            password_hash: "$argon2id$hash".into(),
+            max_connections: None,
+            max_channels: None,
+        });
+        expected
+            .queue
+            .definitions
+            .push(QueueDefinition::new("tasks"));
+        expected.vhosts.push(vhost);
+        expected.queue.storage = Some(StorageConfig {
+            path: "/var/lib/another-mq".into(),
+            sync: false,
+            wait_ready: None,
+        });
+        expected.metrics.enabled = true;
+        expected.metrics.port = 9100;
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn memory_only_turns_off_the_storage() {
+        let config = ConfigBuilder::new().memory_only().build();
+
+        assert!(config.queue.memory_only);
+        assert_eq!(config.queue.storage, None);
+    }
+
+    #[test]
+    fn built_configuration_can_be_validated() {
+        let mut config = ConfigBuilder::new()
+            .listener("127.0.0.1".parse().unwrap(), 5673)
+            .queue(QueueDefinition::new("tasks"))
+            .queue(QueueDefinition::new("tasks"))
+            .build();
+
+        assert!(config.validate().is_err());
+    }
+}
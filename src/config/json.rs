@@ -0,0 +1,237 @@
+//! The reader of the configuration files written in JSON.
+//!
+//! The document is converted to the TOML value the configuration is deserialized from. As TOML
+//! has no null value, the `null` members of an object are treated as missing.
+
+use serde::de::Error as _;
+use std::str;
+use toml::{value::Table, Value};
+
+/// Parses a JSON document holding an object.
+pub fn parse(raw: &str) -> Result<Value, toml::de::Error> {
+    let mut parser = Parser {
+        input: raw.as_bytes(),
+        position: 0,
+    };
+
+    parser.skip_whitespace();
+    if parser.peek() != Some(b'{') {
+        return Err(parser.error("expected an object"));
+    }
+
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return Err(parser.error("trailing characters"));
+    }
+
+    value.ok_or_else(|| parser.error("expected an object"))
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Parses a value, `None` standing for `null`.
+    fn value(&mut self) -> Result<Option<Value>, toml::de::Error> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'{') => self.object().map(Some),
+            Some(b'[') => self.array().map(Some),
+            Some(b'"') => self.string().map(|value| Some(Value::String(value))),
+            Some(b't') => self.keyword("true").map(|_| Some(Value::Boolean(true))),
+            Some(b'f') => self.keyword("false").map(|_| Some(Value::Boolean(false))),
+            Some(b'n') => self.keyword("null").map(|_| None),
+            Some(b'-') | Some(b'0'..=b'9') => self.number().map(Some),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of file")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, toml::de::Error> {
+        let mut table = Table::new();
+        self.position += 1;
+
+        self.skip_whitespace();
+        if self.next_if(b'}') {
+            return Ok(Value::Table(table));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected the name of a member"));
+            }
+            let key = self.string()?;
+
+            self.skip_whitespace();
+            if !self.next_if(b':') {
+                return Err(self.error("expected `:`"));
+            }
+            if let Some(value) = self.value()? {
+                table.insert(key, value);
+            }
+
+            self.skip_whitespace();
+            if self.next_if(b'}') {
+                return Ok(Value::Table(table));
+            }
+            if !self.next_if(b',') {
+                return Err(self.error("expected `,` or `}`"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, toml::de::Error> {
+        let mut array = Vec::new();
+        self.position += 1;
+
+        self.skip_whitespace();
+        if self.next_if(b']') {
+            return Ok(Value::Array(array));
+        }
+
+        loop {
+            match self.value()? {
+                Some(value) => array.push(value),
+                None => return Err(self.error("null values are not supported in arrays")),
+            }
+
+            self.skip_whitespace();
+            if self.next_if(b']') {
+                return Ok(Value::Array(array));
+            }
+            if !self.next_if(b',') {
+                return Err(self.error("expected `,` or `]`"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, toml::de::Error> {
+        let mut value = Vec::new();
+        self.position += 1;
+
+        loop {
+            match self.next() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let escaped = match self.next() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    let mut buffer = [0; 4];
+                    value.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(c) if c < 0x20 => return Err(self.error("control character in a string")),
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+
+        // The input is a `str` and the escapes are encoded, so the bytes are valid UTF-8.
+        String::from_utf8(value).map_err(|_| self.error("invalid UTF-8 in a string"))
+    }
+
+    /// Parses the code point of a `\u` escape, combining the surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char, toml::de::Error> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !(self.next_if(b'\\') && self.next_if(b'u')) {
+                return Err(self.error("unpaired surrogate in a string"));
+            }
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate in a string"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, toml::de::Error> {
+        let digits = self
+            .input
+            .get(self.position..self.position + 4)
+            .and_then(|digits| str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.position += 4;
+
+        Ok(digits)
+    }
+
+    fn number(&mut self) -> Result<Value, toml::de::Error> {
+        let start = self.position;
+        while let Some(b'0'..=b'9') | Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e')
+        | Some(b'E') = self.peek()
+        {
+            self.position += 1;
+        }
+
+        // The scanned bytes are ASCII.
+        let number = str::from_utf8(&self.input[start..self.position]).unwrap();
+        if let Ok(integer) = number.parse() {
+            return Ok(Value::Integer(integer));
+        }
+        match number.parse() {
+            Ok(float) => Ok(Value::Float(float)),
+            Err(_) => Err(self.error("invalid number")),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), toml::de::Error> {
+        if self.input[self.position..].starts_with(keyword.as_bytes()) {
+            self.position += keyword.len();
+            Ok(())
+        } else {
+            Err(self.error("expected a value"))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.position += 1;
+        Some(c)
+    }
+
+    fn next_if(&mut self, expected: u8) -> bool {
+        let matches = self.peek() == Some(expected);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    /// Builds an error located at the current position of the parser.
+    fn error(&self, reason: &str) -> toml::de::Error {
+        let consumed = &self.input[..self.position.min(self.input.len())];
+        let line = consumed.iter().filter(|&&c| c == b'\n').count() + 1;
+        let column = consumed.iter().rev().take_while(|&&c| c != b'\n').count() + 1;
+
+        toml::de::Error::custom(format!("{} at line {} column {}", reason, line, column))
+    }
+}
@@ -0,0 +1,397 @@
+//! The merging of a configuration into another, for the applications building their
+//! configuration from layers, such as a base configuration and the overlay of an environment.
+
+use super::{Admin, Auth, Config, Log, Metrics, Network, Queue, VirtualHost};
+
+/// Moves each of `$fields`, the fields of an overlay destructured into bindings of the same name,
+/// into `$base` if it differs from its value in `$defaults`.
+macro_rules! merge_fields {
+    ($base:expr, $defaults:expr, $($field:ident),* $(,)?) => {{
+        let defaults = $defaults;
+        $(
+            if $field != defaults.$field {
+                $base.$field = $field;
+            }
+        )*
+    }};
+}
+
+impl Config {
+    /// Merges `other` into the configuration, `other` taking precedence. Each namespace is merged
+    /// field by field:
+    ///
+    /// - A field of `other` overrides the one of the configuration if it differs from its default
+    ///   value, so a field left to its default in `other` never overrides it. An optional field
+    ///   thus overrides it when it's `Some`, a table such as `queue.storage` being replaced as a
+    ///   whole.
+    /// - The queue definitions, the virtual hosts and the users are merged by name: an entry of
+    ///   `other` replaces the entry of the configuration with the same name, in place, and the
+    ///   other entries of `other` are appended in their order. The virtual hosts with the same
+    ///   name have their queue definitions merged by name in turn.
+    /// - Any other list, such as `network.listener` or `auth.acl`, replaces the one of the
+    ///   configuration unless it's empty.
+    ///
+    /// The merged configuration is not validated, which is up to [`Config::validate`].
+    pub fn merge(&mut self, other: Config) {
+        let Config {
+            admin,
+            auth,
+            log,
+            metrics,
+            network,
+            queue,
+            vhosts,
+        } = other;
+
+        self.admin.merge(admin);
+        self.auth.merge(auth);
+        self.log.merge(log);
+        self.metrics.merge(metrics);
+        self.network.merge(network);
+        self.queue.merge(queue);
+
+        for vhost in vhosts {
+            let VirtualHost { name, definitions } = vhost;
+
+            match self
+                .vhosts
+                .iter_mut()
+                .find(|existing| existing.name == name)
+            {
+                Some(existing) => {
+                    merge_by_name(&mut existing.definitions, definitions, |d| &d.name)
+                }
+                None => self.vhosts.push(VirtualHost { name, definitions }),
+            }
+        }
+    }
+}
+
+impl Admin {
+    fn merge(&mut self, other: Self) {
+        let Admin {
+            enabled,
+            port,
+            bind_address,
+            allow_mutations,
+        } = other;
+
+        merge_fields!(
+            self,
+            Self::default(),
+            enabled,
+            port,
+            bind_address,
+            allow_mutations
+        );
+    }
+}
+
+impl Auth {
+    fn merge(&mut self, other: Self) {
+        let Auth {
+            hash_algorithm,
+            users,
+            users_file,
+            file_users,
+            acl,
+        } = other;
+
+        merge_by_name(&mut self.users, users, |user| &user.username);
+        merge_by_name(&mut self.file_users, file_users, |user| &user.username);
+        merge_fields!(self, Self::default(), hash_algorithm, users_file, acl);
+    }
+}
+
+impl Log {
+    fn merge(&mut self, other: Self) {
+        let Log {
+            level,
+            targets,
+            format,
+            timestamp_format,
+            utc,
+            stdout,
+            file,
+            file_enabled,
+            rotation,
+            syslog,
+            async_buffer,
+            flush_interval,
+        } = other;
+
+        merge_fields!(
+            self,
+            Self::default(),
+            level,
+            targets,
+            format,
+            timestamp_format,
+            utc,
+            stdout,
+            file,
+            file_enabled,
+            rotation,
+            syslog,
+            async_buffer,
+            flush_interval,
+        );
+    }
+}
+
+impl Metrics {
+    fn merge(&mut self, other: Self) {
+        let Metrics {
+            enabled,
+            port,
+            size_buckets,
+            bind_address,
+        } = other;
+
+        merge_fields!(
+            self,
+            Self::default(),
+            enabled,
+            port,
+            size_buckets,
+            bind_address
+        );
+    }
+}
+
+impl Network {
+    fn merge(&mut self, other: Self) {
+        let Network {
+            hostname,
+            port,
+            port_range,
+            listeners,
+            unix_socket,
+            backlog,
+            worker_threads,
+            tls,
+            max_connections,
+            reuse_address,
+            reuse_port,
+            proxy_protocol,
+            accept_backoff,
+            shutdown_timeout,
+            immediate_shutdown_signals,
+            idle_timeout,
+            handshake_timeout,
+            heartbeat,
+            max_frame_bytes,
+            tcp_nodelay,
+            tcp_keepalive,
+            recv_buffer_bytes,
+            send_buffer_bytes,
+            rate_limit,
+            allow_cidrs,
+        } = other;
+
+        merge_fields!(
+            self,
+            Self::default(),
+            hostname,
+            port,
+            port_range,
+            listeners,
+            unix_socket,
+            backlog,
+            worker_threads,
+            tls,
+            max_connections,
+            reuse_address,
+            reuse_port,
+            proxy_protocol,
+            accept_backoff,
+            shutdown_timeout,
+            immediate_shutdown_signals,
+            idle_timeout,
+            handshake_timeout,
+            heartbeat,
+            max_frame_bytes,
+            tcp_nodelay,
+            tcp_keepalive,
+            recv_buffer_bytes,
+            send_buffer_bytes,
+            rate_limit,
+            allow_cidrs,
+        );
+    }
+}
+
+impl Queue {
+    fn merge(&mut self, other: Self) {
+        let Queue {
+            definitions,
+            max_message_bytes,
+            max_unacked_total,
+            default_queue,
+            expiry_scan_interval,
+            default_message_ttl,
+            storage,
+            memory_only,
+            compression,
+            compression_threshold_bytes,
+            enable_delayed,
+            enable_selectors,
+        } = other;
+
+        merge_by_name(&mut self.definitions, definitions, |d| &d.name);
+        merge_fields!(
+            self,
+            Self::default(),
+            max_message_bytes,
+            max_unacked_total,
+            default_queue,
+            expiry_scan_interval,
+            default_message_ttl,
+            storage,
+            memory_only,
+            compression,
+            compression_threshold_bytes,
+            enable_delayed,
+            enable_selectors,
+        );
+    }
+}
+
+/// Merges the entries of `other` into `base`, each entry replacing the one of `base` with the same
+/// name in place, or being appended if there's none.
+fn merge_by_name<T, F>(base: &mut Vec<T>, other: Vec<T>, name: F)
+where
+    F: Fn(&T) -> &String,
+{
+    for entry in other {
+        match base
+            .iter()
+            .position(|existing| name(existing) == name(&entry))
+        {
+            Some(i) => base[i] = entry,
+            None => base.push(entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Config {
+        toml::from_str(raw).unwrap()
+    }
+
+    fn merged(base: &str, overlay: &str) -> Config {
+        let mut config = parse(base);
+        config.merge(parse(overlay));
+        config
+    }
+
+    #[test]
+    fn fields_of_the_overlay_take_precedence() {
+        let config = merged(
+            "[network]\nport = 5673\n[log]\nlevel = \"debug\"\n",
+            "[network]\nport = 5674\n",
+        );
+
+        assert_eq!(config.network.port, 5674);
+        assert_eq!(config.log.level, log::Level::Debug);
+    }
+
+    #[test]
+    fn default_fields_of_the_overlay_are_ignored() {
+        let config = merged(
+            "[network]\nport = 5673\n[queue]\nmax_message_bytes = 10\n",
+            "[network]\nport = 5672\n",
+        );
+
+        assert_eq!(config.network.port, 5673);
+        assert_eq!(config.queue.max_message_bytes, Some(10));
+    }
+
+    #[test]
+    fn optional_fields_of_the_overlay_take_precedence_when_set() {
+        let config = merged(
+            "[queue]\nmax_message_bytes = 10\n[queue.storage]\npath = \"/base\"\nsync = true\n",
+            "[queue]\nmax_message_bytes = 20\n[queue.storage]\npath = \"/overlay\"\n",
+        );
+
+        assert_eq!(config.queue.max_message_bytes, Some(20));
+        let storage = config.queue.storage.unwrap();
+        assert_eq!(storage.path, "/overlay");
+        assert!(!storage.sync);
+    }
+
+    #[test]
+    fn queue_definitions_are_merged_by_name() {
+        let config = merged(
+            "[[queue.definitions]]\nname = \"tasks\"\ndurable = true\n\
+             [[queue.definitions]]\nname = \"events\"\n",
+            "[[queue.definitions]]\nname = \"orders\"\n\
+             [[queue.definitions]]\nname = \"tasks\"\nmax_length = 5\n",
+        );
+
+        let names: Vec<_> = config
+            .queue
+            .definitions
+            .iter()
+            .map(|definition| definition.name.as_str())
+            .collect();
+        assert_eq!(names, ["tasks", "events", "orders"]);
+        let tasks = &config.queue.definitions[0];
+        assert_eq!(tasks.max_length, Some(5));
+        assert!(!tasks.durable);
+    }
+
+    #[test]
+    fn virtual_hosts_are_merged_by_name() {
+        let config = merged(
+            "[[vhost]]\nname = \"staging\"\n\
+             [[vhost.definitions]]\nname = \"tasks\"\n\
+             [[vhost]]\nname = \"dev\"\n",
+            "[[vhost]]\nname = \"staging\"\n\
+             [[vhost.definitions]]\nname = \"events\"\n\
+             [[vhost]]\nname = \"prod\"\n",
+        );
+
+        let names: Vec<_> = config.vhosts.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["staging", "dev", "prod"]);
+        let definitions: Vec<_> = config.vhosts[0]
+            .definitions
+            .iter()
+            .map(|definition| definition.name.as_str())
+            .collect();
+        assert_eq!(definitions, ["tasks", "events"]);
+    }
+
+    #[test]
+    fn users_are_merged_by_name() {
+        let config = merged(
+            "[[auth.users]]\nusername = \"guest\"\npassword_hash = \"base\"\n\
+             [[auth.users]]\nusername = \"admin\"\npassword_hash = \"admin\"\n",
+            "[[auth.users]]\nusername = \"guest\"\npassword_hash = \"overlay\"\n",
+        );
+
+        let users: Vec<_> = config
+            .auth
+            .users
+            .iter()
+            .map(|user| (user.username.as_str(), user.password_hash.as_str()))
+            .collect();
+        assert_eq!(users, [("guest", "overlay"), ("admin", "admin")]);
+    }
+
+    #[test]
+    fn other_lists_are_replaced_unless_empty() {
+        let base = "[[network.listener]]\nport = 5673\n[[network.listener]]\nport = 5674\n";
+
+        let config = merged(base, "[[network.listener]]\nport = 5675\n");
+        let ports: Vec<_> = config.network.listeners.iter().map(|l| l.port).collect();
+        assert_eq!(ports, [5675]);
+
+        let config = merged(base, "[network]\nport = 5680\n");
+        let ports: Vec<_> = config.network.listeners.iter().map(|l| l.port).collect();
+        assert_eq!(ports, [5673, 5674]);
+    }
+}
@@ -0,0 +1,299 @@
+//! The reader of the configuration files written in YAML, built on top of the libyaml library of
+//! the system.
+//!
+//! The document is converted to the TOML value the configuration is deserialized from. As TOML
+//! has no null value, the null members of a mapping are treated as missing. The plain scalars are
+//! resolved like the core schema of YAML 1.2 does: booleans, integers and floats are recognized,
+//! everything else is a string.
+
+use serde::de::Error as _;
+use std::{ffi::CStr, mem, slice};
+use toml::{value::Table, Value};
+
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::{c_char, c_int, c_uchar};
+
+    pub const YAML_STREAM_START_EVENT: c_int = 1;
+    pub const YAML_STREAM_END_EVENT: c_int = 2;
+    pub const YAML_DOCUMENT_START_EVENT: c_int = 3;
+    pub const YAML_DOCUMENT_END_EVENT: c_int = 4;
+    pub const YAML_ALIAS_EVENT: c_int = 5;
+    pub const YAML_SCALAR_EVENT: c_int = 6;
+    pub const YAML_SEQUENCE_START_EVENT: c_int = 7;
+    pub const YAML_SEQUENCE_END_EVENT: c_int = 8;
+    pub const YAML_MAPPING_START_EVENT: c_int = 9;
+    pub const YAML_MAPPING_END_EVENT: c_int = 10;
+
+    pub const YAML_PLAIN_SCALAR_STYLE: c_int = 1;
+
+    #[repr(C)]
+    pub struct yaml_mark_t {
+        pub index: usize,
+        pub line: usize,
+        pub column: usize,
+    }
+
+    /// The parser state. Only the error fields are read, the private fields are covered by an
+    /// opaque buffer larger than them.
+    #[repr(C)]
+    pub struct yaml_parser_t {
+        pub error: c_int,
+        pub problem: *const c_char,
+        pub problem_offset: usize,
+        pub problem_value: c_int,
+        pub problem_mark: yaml_mark_t,
+        pub context: *const c_char,
+        pub context_mark: yaml_mark_t,
+        _private: [u64; 128],
+    }
+
+    /// The data of a scalar event, the largest member of the union holding the event data.
+    #[repr(C)]
+    pub struct yaml_scalar_t {
+        pub anchor: *mut c_uchar,
+        pub tag: *mut c_uchar,
+        pub value: *mut c_uchar,
+        pub length: usize,
+        pub plain_implicit: c_int,
+        pub quoted_implicit: c_int,
+        pub style: c_int,
+    }
+
+    #[repr(C)]
+    pub struct yaml_event_t {
+        pub event_type: c_int,
+        pub scalar: yaml_scalar_t,
+        pub start_mark: yaml_mark_t,
+        pub end_mark: yaml_mark_t,
+    }
+
+    #[link(name = "yaml")]
+    extern "C" {
+        pub fn yaml_parser_initialize(parser: *mut yaml_parser_t) -> c_int;
+        pub fn yaml_parser_delete(parser: *mut yaml_parser_t);
+        pub fn yaml_parser_set_input_string(
+            parser: *mut yaml_parser_t,
+            input: *const c_uchar,
+            size: usize,
+        );
+        pub fn yaml_parser_parse(parser: *mut yaml_parser_t, event: *mut yaml_event_t) -> c_int;
+        pub fn yaml_event_delete(event: *mut yaml_event_t);
+    }
+}
+
+/// Parses a YAML document holding a mapping. An empty document stands for an empty mapping.
+pub fn parse(raw: &str) -> Result<Value, toml::de::Error> {
+    let mut parser = Parser::new(raw)?;
+
+    if !matches!(parser.next()?, Event::StreamStart) {
+        return Err(parser.error("expected a stream"));
+    }
+    let value = match parser.next()? {
+        Event::StreamEnd => return Ok(Value::Table(Table::new())),
+        Event::DocumentStart => match parser.node()? {
+            Some(value @ Value::Table(_)) => value,
+            None => Value::Table(Table::new()),
+            Some(_) => return Err(parser.error("expected a mapping")),
+        },
+        _ => return Err(parser.error("expected a document")),
+    };
+    if !matches!(parser.next()?, Event::DocumentEnd) {
+        return Err(parser.error("expected the end of the document"));
+    }
+
+    match parser.next()? {
+        Event::StreamEnd => Ok(value),
+        _ => Err(parser.error("expected a single document")),
+    }
+}
+
+enum Event {
+    StreamStart,
+    StreamEnd,
+    DocumentStart,
+    DocumentEnd,
+    Scalar { value: String, plain: bool },
+    SequenceStart,
+    SequenceEnd,
+    MappingStart,
+    MappingEnd,
+    Alias,
+}
+
+struct Parser<'a> {
+    // The parser is boxed, as libyaml does not support moving it once initialized.
+    parser: Box<ffi::yaml_parser_t>,
+    line: usize,
+    column: usize,
+    _input: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Result<Self, toml::de::Error> {
+        let mut parser: Box<ffi::yaml_parser_t> = Box::new(unsafe { mem::zeroed() });
+
+        if unsafe { ffi::yaml_parser_initialize(&mut *parser) } == 0 {
+            return Err(toml::de::Error::custom(
+                "could not initialize the YAML parser",
+            ));
+        }
+        unsafe { ffi::yaml_parser_set_input_string(&mut *parser, input.as_ptr(), input.len()) };
+
+        Ok(Self {
+            parser,
+            line: 0,
+            column: 0,
+            _input: input,
+        })
+    }
+
+    /// Parses a node, `None` standing for a null scalar.
+    fn node(&mut self) -> Result<Option<Value>, toml::de::Error> {
+        let event = self.next()?;
+        self.node_from(event)
+    }
+
+    /// Parses a node whose first event was already consumed.
+    fn node_from(&mut self, event: Event) -> Result<Option<Value>, toml::de::Error> {
+        match event {
+            Event::Scalar { value, plain } => Ok(resolve(value, plain)),
+            Event::SequenceStart => {
+                let mut array = Vec::new();
+
+                loop {
+                    match self.next()? {
+                        Event::SequenceEnd => return Ok(Some(Value::Array(array))),
+                        event => match self.node_from(event)? {
+                            Some(value) => array.push(value),
+                            None => {
+                                return Err(self.error("null values are not supported in sequences"))
+                            }
+                        },
+                    }
+                }
+            }
+            Event::MappingStart => {
+                let mut table = Table::new();
+
+                loop {
+                    let key = match self.next()? {
+                        Event::MappingEnd => return Ok(Some(Value::Table(table))),
+                        Event::Scalar { value, .. } => value,
+                        _ => return Err(self.error("the keys of a mapping must be scalars")),
+                    };
+                    if let Some(value) = self.node()? {
+                        table.insert(key, value);
+                    }
+                }
+            }
+            Event::Alias => Err(self.error("anchors and aliases are not supported")),
+            _ => Err(self.error("expected a node")),
+        }
+    }
+
+    fn next(&mut self) -> Result<Event, toml::de::Error> {
+        let mut event: ffi::yaml_event_t = unsafe { mem::zeroed() };
+
+        if unsafe { ffi::yaml_parser_parse(&mut *self.parser, &mut event) } == 0 {
+            return Err(self.parser_error());
+        }
+        self.line = event.start_mark.line;
+        self.column = event.start_mark.column;
+
+        let parsed = match event.event_type {
+            ffi::YAML_STREAM_START_EVENT => Event::StreamStart,
+            ffi::YAML_STREAM_END_EVENT => Event::StreamEnd,
+            ffi::YAML_DOCUMENT_START_EVENT => Event::DocumentStart,
+            ffi::YAML_DOCUMENT_END_EVENT => Event::DocumentEnd,
+            ffi::YAML_SCALAR_EVENT => {
+                let bytes =
+                    unsafe { slice::from_raw_parts(event.scalar.value, event.scalar.length) };
+
+                // libyaml only accepts UTF-8 input, the scalars are valid UTF-8.
+                Event::Scalar {
+                    value: String::from_utf8_lossy(bytes).into_owned(),
+                    plain: event.scalar.style == ffi::YAML_PLAIN_SCALAR_STYLE,
+                }
+            }
+            ffi::YAML_SEQUENCE_START_EVENT => Event::SequenceStart,
+            ffi::YAML_SEQUENCE_END_EVENT => Event::SequenceEnd,
+            ffi::YAML_MAPPING_START_EVENT => Event::MappingStart,
+            ffi::YAML_MAPPING_END_EVENT => Event::MappingEnd,
+            ffi::YAML_ALIAS_EVENT => Event::Alias,
+            // libyaml only produces an empty event once the stream has ended.
+            _ => Event::StreamEnd,
+        };
+        unsafe { ffi::yaml_event_delete(&mut event) };
+
+        Ok(parsed)
+    }
+
+    /// Builds an error located at the last event of the parser.
+    fn error(&self, reason: &str) -> toml::de::Error {
+        toml::de::Error::custom(format!(
+            "{} at line {} column {}",
+            reason,
+            self.line + 1,
+            self.column + 1
+        ))
+    }
+
+    /// Builds the error reported by libyaml.
+    fn parser_error(&self) -> toml::de::Error {
+        let problem = if self.parser.problem.is_null() {
+            "invalid YAML document".into()
+        } else {
+            unsafe { CStr::from_ptr(self.parser.problem) }.to_string_lossy()
+        };
+        let mark = &self.parser.problem_mark;
+
+        toml::de::Error::custom(format!(
+            "{} at line {} column {}",
+            problem,
+            mark.line + 1,
+            mark.column + 1
+        ))
+    }
+}
+
+impl Drop for Parser<'_> {
+    fn drop(&mut self) {
+        unsafe { ffi::yaml_parser_delete(&mut *self.parser) };
+    }
+}
+
+/// Resolves the type of a scalar. Only the plain scalars can be null, booleans or numbers.
+fn resolve(value: String, plain: bool) -> Option<Value> {
+    if !plain {
+        return Some(Value::String(value));
+    }
+
+    let resolved = match value.as_str() {
+        "" | "~" | "null" | "Null" | "NULL" => return None,
+        "true" | "True" | "TRUE" => Value::Boolean(true),
+        "false" | "False" | "FALSE" => Value::Boolean(false),
+        ".inf" | ".Inf" | ".INF" | "+.inf" | "+.Inf" | "+.INF" => Value::Float(f64::INFINITY),
+        "-.inf" | "-.Inf" | "-.INF" => Value::Float(f64::NEG_INFINITY),
+        ".nan" | ".NaN" | ".NAN" => Value::Float(f64::NAN),
+        number if is_number(number) => match number.parse() {
+            Ok(integer) => Value::Integer(integer),
+            Err(_) => match number.parse() {
+                Ok(float) => Value::Float(float),
+                Err(_) => Value::String(value),
+            },
+        },
+        _ => Value::String(value),
+    };
+
+    Some(resolved)
+}
+
+/// Checks that a plain scalar only holds the characters of a decimal number, as the Rust parsers
+/// accept words like `inf` which are strings in YAML.
+fn is_number(value: &str) -> bool {
+    value.bytes().any(|c| c.is_ascii_digit())
+        && value
+            .bytes()
+            .all(|c| matches!(c, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E'))
+}
@@ -0,0 +1,2097 @@
+//! The handling of the connection of a client, from the negotiation of the protocol to its
+//! closing.
+
+mod channel;
+mod outbox;
+mod rate_limiter;
+
+pub use rate_limiter::ListenerRateLimiter;
+
+use crate::{
+    auth,
+    broker::{Broker, Message, Queue, Selector, TOPIC_EXCHANGE},
+    config::{AckMode, Config, DeliveryMode, QueueDefinition, DEFAULT_VHOST},
+    protocol::{
+        self, reply_code, ContentHeader, FieldTable, FieldValue, Frame, Method, CLASS_BASIC,
+        FRAME_MIN_SIZE, PROTOCOL_HEADER,
+    },
+    shutdown::Shutdown,
+    stream::{Peer, Stream},
+};
+use channel::{Channel, Publication};
+use outbox::Outbox;
+use rate_limiter::RateLimiter;
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read, Write},
+    sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// The maximum number of channels that a client can open on a connection.
+const CHANNEL_MAX: u16 = 2047;
+
+/// The prefix of the names reserved by the broker.
+const RESERVED_PREFIX: &str = "amq.";
+
+/// How long the broker waits for a client to acknowledge the closing of its connection.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The connection of a client to the broker.
+pub struct Connection {
+    reader: BufReader<Stream>,
+    outbox: Outbox,
+    writer: JoinHandle<()>,
+    peer: Peer,
+    broker: Arc<Broker>,
+    config: Arc<RwLock<Config>>,
+    channel_max: u16,
+    frame_max: u32,
+    idle_timeout: Option<Duration>,
+    /// When the client must have completed the handshake of the connection, until it's open.
+    handshake_deadline: Option<Instant>,
+    /// The interval of the heartbeats, once negotiated with the client.
+    heartbeat: Option<Duration>,
+    rate_limiter: Option<RateLimiter>,
+    /// The rate limit shared by the connections of the listener the client connected to.
+    listener_rate_limiter: Option<Arc<ListenerRateLimiter>>,
+    /// The user the client logged in as, which is empty for an anonymous client.
+    username: String,
+    /// The maximum number of channels of the user, across all of its connections.
+    max_channels: Option<usize>,
+    /// The virtual host the client opened, whose queues it uses.
+    vhost: String,
+    channels: HashMap<u16, Channel>,
+    shutdown: Arc<Shutdown>,
+
+    /// The number of consumer tags generated for the consumers started without a tag.
+    generated_tags: u64,
+}
+
+impl Connection {
+    pub fn new(
+        stream: Stream,
+        peer: Peer,
+        broker: Arc<Broker>,
+        config: Arc<RwLock<Config>>,
+        shutdown: Arc<Shutdown>,
+        listener_rate_limiter: Option<Arc<ListenerRateLimiter>>,
+    ) -> io::Result<Self> {
+        // The idle connections are detected by the read timeout of their stream, which expires
+        // once the client sent nothing for the whole timeout.
+        let (idle_timeout, handshake_timeout, rate_limiter) = {
+            let network = &config.read().unwrap().network;
+            (
+                network.idle_timeout,
+                network.handshake_timeout,
+                network.rate_limit.as_ref().map(RateLimiter::new),
+            )
+        };
+        stream.set_read_timeout(idle_timeout)?;
+
+        let (outbox, writer) = Outbox::spawn(stream.try_clone()?, peer.clone())?;
+
+        Ok(Self {
+            reader: BufReader::new(stream),
+            outbox,
+            writer,
+            peer,
+            broker,
+            config,
+            channel_max: CHANNEL_MAX,
+            frame_max: FRAME_MIN_SIZE,
+            idle_timeout,
+            handshake_deadline: Some(Instant::now() + handshake_timeout),
+            heartbeat: None,
+            rate_limiter,
+            listener_rate_limiter,
+            username: String::new(),
+            max_channels: None,
+            vhost: DEFAULT_VHOST.into(),
+            channels: HashMap::new(),
+            shutdown,
+            generated_tags: 0,
+        })
+    }
+
+    /// Serves the client until its connection is closed.
+    pub fn run(mut self) {
+        self.broker.metrics().connections.inc();
+        let id = self.broker.connections().add(self.peer.clone());
+        self.serve_until_closed();
+        self.broker.usage().disconnect(&self.username);
+        self.broker.connections().remove(id);
+        self.broker.metrics().connections.dec();
+
+        // The writer stops once the frames left in the outbox are written.
+        drop(self.outbox);
+        let _ = self.writer.join();
+    }
+
+    fn serve_until_closed(&mut self) {
+        match self.serve() {
+            Ok(()) => log::info!("Connection from {} closed", self.peer),
+            Err(Error::UnsupportedProtocol) => {
+                log::info!(
+                    "Connection from {} speaks an unsupported protocol",
+                    self.peer
+                )
+            }
+            Err(Error::HandshakeTimeout) => {
+                let config = self.config.read().unwrap();
+                log::info!(
+                    "Connection from {} closed, as its handshake took longer than {}",
+                    self.peer,
+                    humantime::format_duration(config.network.handshake_timeout)
+                )
+            }
+            Err(Error::Idle) => {
+                // The read timeout is the shortest of the idle timeout and two heartbeats.
+                let missed_heartbeats = match (self.heartbeat, self.idle_timeout) {
+                    (Some(heartbeat), Some(idle_timeout)) => 2 * heartbeat <= idle_timeout,
+                    (heartbeat, _) => heartbeat.is_some(),
+                };
+
+                if missed_heartbeats {
+                    log::info!(
+                        "Connection from {} closed after missing two heartbeats",
+                        self.peer
+                    )
+                } else if let Some(idle_timeout) = self.idle_timeout {
+                    log::info!(
+                        "Connection from {} closed after being idle for {}",
+                        self.peer,
+                        humantime::format_duration(idle_timeout)
+                    )
+                }
+            }
+            Err(Error::Protocol(protocol::Error::Io(err)))
+                if err.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                log::info!("Connection from {} closed by the client", self.peer)
+            }
+            Err(Error::Protocol(protocol::Error::Io(err))) => {
+                log::info!("Connection from {} lost: {}", self.peer, err)
+            }
+            Err(Error::Protocol(err)) => {
+                let exception = Exception::new(err.reply_code(), err.to_string(), (0, 0));
+                self.close(exception);
+            }
+            Err(Error::Exception(exception)) | Err(Error::ChannelException(_, exception)) => {
+                self.close(exception)
+            }
+        }
+    }
+
+    fn serve(&mut self) -> Result<(), Error> {
+        self.open()?;
+        self.handshake_deadline = None;
+        self.reader
+            .get_ref()
+            .set_read_timeout(self.read_timeout())
+            .map_err(protocol::Error::from)?;
+
+        // Once open, the connection is awaited by the shutdown, until its channels are released.
+        let outbox = self.outbox.clone();
+        let _guard = self.shutdown.watch_connection(move || {
+            let close = Method::ConnectionClose {
+                reply_code: reply_code::CONNECTION_FORCED,
+                reply_text: "the broker is shutting down".into(),
+                class_id: 0,
+                method_id: 0,
+            };
+            let _ = outbox.send(vec![Frame::Method(0, close)]);
+        });
+
+        let result = self.serve_channels();
+        for (_, mut state) in self.channels.drain() {
+            state.release(&self.broker);
+            self.broker.usage().close_channel(&self.username);
+        }
+
+        result
+    }
+
+    fn serve_channels(&mut self) -> Result<(), Error> {
+        loop {
+            match self.read_frame()? {
+                Frame::Heartbeat => {}
+                Frame::Method(0, Method::ConnectionClose { .. }) => {
+                    self.send(0, Method::ConnectionCloseOk)?;
+                    return Ok(());
+                }
+                // The broker asked the client to close the connection as it's shutting down.
+                Frame::Method(0, Method::ConnectionCloseOk) if self.shutdown.is_requested() => {
+                    return Ok(());
+                }
+                Frame::Method(0, method) => {
+                    return Err(Exception::new(
+                        reply_code::COMMAND_INVALID,
+                        "unexpected method on channel 0",
+                        method.id(),
+                    )
+                    .into());
+                }
+                Frame::Header(0, _) | Frame::Body(0, _) => {
+                    return Err(Exception::new(
+                        reply_code::UNEXPECTED_FRAME,
+                        "unexpected content frame on channel 0",
+                        (0, 0),
+                    )
+                    .into());
+                }
+                frame => {
+                    let result = match frame {
+                        Frame::Method(channel, method) => {
+                            self.handle_channel_method(channel, method)
+                        }
+                        Frame::Header(channel, header) => self.receive_header(channel, *header),
+                        Frame::Body(channel, body) => self.receive_body(channel, body),
+                        Frame::Heartbeat => Ok(()),
+                    };
+
+                    match result {
+                        Err(Error::ChannelException(channel, exception)) => {
+                            self.close_channel(channel, exception)?
+                        }
+                        result => result?,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Negotiates the protocol and the parameters of the connection with the client.
+    fn open(&mut self) -> Result<(), Error> {
+        let mut header = [0; 8];
+        self.read(|reader| Ok(reader.read_exact(&mut header)?))?;
+
+        if header != PROTOCOL_HEADER {
+            self.reader
+                .get_mut()
+                .write_all(&PROTOCOL_HEADER)
+                .map_err(protocol::Error::from)?;
+            return Err(Error::UnsupportedProtocol);
+        }
+
+        let mut server_properties = FieldTable::new();
+        server_properties.insert("product".into(), "another-mq".into());
+        server_properties.insert("version".into(), env!("CARGO_PKG_VERSION").into());
+        server_properties.insert("platform".into(), "Rust".into());
+
+        self.send(
+            0,
+            Method::ConnectionStart {
+                version_major: 0,
+                version_minor: 9,
+                server_properties,
+                mechanisms: b"PLAIN".to_vec(),
+                locales: b"en_US".to_vec(),
+            },
+        )?;
+
+        match self.expect_method()? {
+            Method::ConnectionStartOk {
+                mechanism,
+                response,
+                ..
+            } => {
+                let auth = &self.config.read().unwrap().auth;
+
+                match auth::authenticate(auth, &mechanism, &response) {
+                    Ok(username) if username.is_empty() => {}
+                    Ok(username) => {
+                        let user = auth.user(&username);
+                        let max_connections = user.and_then(|user| user.max_connections);
+
+                        if !self.broker.usage().connect(&username, max_connections) {
+                            return Err(Exception::new(
+                                reply_code::NOT_ALLOWED,
+                                format!(
+                                    "user {} already has its maximum of {} connections",
+                                    username,
+                                    max_connections.unwrap_or_default()
+                                ),
+                                (0, 0),
+                            )
+                            .into());
+                        }
+
+                        log::debug!("Connection from {} logged in as {}", self.peer, username);
+                        self.max_channels = user.and_then(|user| user.max_channels);
+                        self.username = username;
+                    }
+                    Err(err) => {
+                        return Err(Exception::new(
+                            reply_code::ACCESS_REFUSED,
+                            err.to_string(),
+                            (0, 0),
+                        )
+                        .into());
+                    }
+                }
+            }
+            method => return Err(Error::unexpected(&method)),
+        }
+
+        // The heartbeat is validated to fit in the field of the tuning.
+        let (heartbeat, max_frame_bytes) = {
+            let network = &self.config.read().unwrap().network;
+            (
+                network
+                    .heartbeat
+                    .map_or(0, |heartbeat| heartbeat.as_secs() as u16),
+                network.max_frame_bytes,
+            )
+        };
+
+        self.send(
+            0,
+            Method::ConnectionTune {
+                channel_max: CHANNEL_MAX,
+                frame_max: max_frame_bytes,
+                heartbeat,
+            },
+        )?;
+
+        match self.expect_method()? {
+            Method::ConnectionTuneOk {
+                channel_max,
+                frame_max,
+                heartbeat: client_heartbeat,
+            } => {
+                self.channel_max = negotiate(CHANNEL_MAX, channel_max);
+                self.frame_max = negotiate(max_frame_bytes, frame_max).max(FRAME_MIN_SIZE);
+
+                // Unlike the other limits, a heartbeat of 0 disables the heartbeats, whether it's
+                // the one of the broker or the one of the client.
+                if heartbeat != 0 && client_heartbeat != 0 {
+                    let interval = Duration::from_secs(heartbeat.min(client_heartbeat).into());
+
+                    self.start_heartbeat(interval)?;
+                }
+            }
+            method => return Err(Error::unexpected(&method)),
+        }
+
+        match self.expect_method()? {
+            Method::ConnectionOpen { virtual_host } => {
+                if !self.config.read().unwrap().has_vhost(&virtual_host) {
+                    return Err(Exception::new(
+                        reply_code::NOT_ALLOWED,
+                        format!("no virtual host named {}", virtual_host),
+                        (10, 40),
+                    )
+                    .into());
+                }
+
+                log::debug!("Connection from {} opened {}", self.peer, virtual_host);
+                self.vhost = virtual_host;
+            }
+            method => return Err(Error::unexpected(&method)),
+        }
+
+        self.send(0, Method::ConnectionOpenOk)
+    }
+
+    fn handle_channel_method(&mut self, channel: u16, method: Method) -> Result<(), Error> {
+        let method_id = method.id();
+
+        if channel > self.channel_max {
+            return Err(Exception::new(
+                reply_code::CHANNEL_ERROR,
+                format!("channel {} exceeds the maximum of channels", channel),
+                method_id,
+            )
+            .into());
+        }
+
+        if let Method::ChannelOpen = method {
+            if self.channels.contains_key(&channel) {
+                return Err(Exception::new(
+                    reply_code::CHANNEL_ERROR,
+                    format!("channel {} is already open", channel),
+                    method_id,
+                )
+                .into());
+            }
+
+            if !self
+                .broker
+                .usage()
+                .open_channel(&self.username, self.max_channels)
+            {
+                return Err(Exception::new(
+                    reply_code::NOT_ALLOWED,
+                    format!(
+                        "user {} already has its maximum of {} channels",
+                        self.username,
+                        self.max_channels.unwrap_or_default()
+                    ),
+                    method_id,
+                )
+                .into());
+            }
+
+            let state = Channel::new(channel, self.outbox.clone(), self.frame_max);
+            self.channels.insert(channel, state);
+            return self.send(channel, Method::ChannelOpenOk);
+        }
+
+        let (closing, publishing) = match self.channels.get(&channel) {
+            Some(state) => (state.closing, state.publication.is_some()),
+            None => {
+                return Err(Exception::new(
+                    reply_code::CHANNEL_ERROR,
+                    format!("channel {} is not open", channel),
+                    method_id,
+                )
+                .into());
+            }
+        };
+
+        // Once the broker closed a channel, every method is discarded until the client
+        // acknowledges the closing.
+        if closing {
+            return match method {
+                Method::ChannelClose { .. } => {
+                    self.remove_channel(channel);
+                    self.send(channel, Method::ChannelCloseOk)
+                }
+                Method::ChannelCloseOk => {
+                    self.remove_channel(channel);
+                    Ok(())
+                }
+                _ => Ok(()),
+            };
+        }
+
+        if publishing {
+            return Err(Exception::new(
+                reply_code::UNEXPECTED_FRAME,
+                format!(
+                    "expected the content of the message published on channel {}",
+                    channel
+                ),
+                method_id,
+            )
+            .into());
+        }
+
+        match method {
+            Method::ChannelClose { .. } => {
+                if let Some(mut state) = self.remove_channel(channel) {
+                    state.release(&self.broker);
+                }
+                self.send(channel, Method::ChannelCloseOk)
+            }
+            Method::QueueDeclare {
+                queue,
+                passive,
+                durable,
+                no_wait,
+                ..
+            } => self.declare_queue(channel, method_id, queue, passive, durable, no_wait),
+            Method::BasicQos { prefetch_count, .. } => {
+                let state = &self.channels[&channel];
+                state.set_prefetch_count(prefetch_count);
+                let queues = state.consumed_queues();
+
+                self.send(channel, Method::BasicQosOk)?;
+                self.dispatch(&queues);
+
+                Ok(())
+            }
+            Method::BasicConsume {
+                queue,
+                consumer_tag,
+                no_ack,
+                no_wait,
+                arguments,
+                ..
+            } => {
+                let queue = self.find_queue(channel, method_id, &queue)?;
+                let options = ConsumeOptions {
+                    no_ack: self.ack_mode(channel, method_id, &queue, no_ack, &arguments)?
+                        == AckMode::Auto,
+                    no_wait,
+                    selector: self.selector(channel, method_id, &arguments)?,
+                };
+
+                self.consume(channel, method_id, queue, consumer_tag, options)
+            }
+            Method::BasicCancel {
+                consumer_tag,
+                no_wait,
+            } => {
+                if let Some(state) = self.channels.get_mut(&channel) {
+                    // A standby consumer of the queue may take over the cancelled consumer.
+                    if let Some(queue) = state.cancel(&consumer_tag) {
+                        self.broker.dispatch(&queue);
+                    }
+                }
+                if no_wait {
+                    return Ok(());
+                }
+
+                self.send(channel, Method::BasicCancelOk { consumer_tag })
+            }
+            Method::BasicPublish {
+                exchange,
+                routing_key,
+                mandatory,
+                immediate,
+            } => {
+                if immediate {
+                    return Err(Exception::new(
+                        reply_code::NOT_IMPLEMENTED,
+                        "the immediate flag is not supported",
+                        method_id,
+                    )
+                    .into());
+                }
+                // Only the default exchange, routing the messages to the queue named by their
+                // routing key, and the topic exchange exist.
+                if !exchange.is_empty() && exchange != TOPIC_EXCHANGE {
+                    return Err(Error::channel(
+                        channel,
+                        reply_code::NOT_FOUND,
+                        format!("no exchange named {}", exchange),
+                        method_id,
+                    ));
+                }
+
+                if let Some(state) = self.channels.get_mut(&channel) {
+                    state.publication = Some(Publication::new(exchange, routing_key, mandatory));
+                }
+
+                Ok(())
+            }
+            Method::BasicGet { queue, no_ack } => self.get(channel, method_id, queue, no_ack),
+            Method::BasicAck {
+                delivery_tag,
+                multiple,
+            } => self.settle(channel, method_id, delivery_tag, multiple, None),
+            Method::BasicReject {
+                delivery_tag,
+                requeue,
+            } => self.settle(channel, method_id, delivery_tag, false, Some(requeue)),
+            Method::BasicNack {
+                delivery_tag,
+                multiple,
+                requeue,
+            } => self.settle(channel, method_id, delivery_tag, multiple, Some(requeue)),
+            _ => Err(Exception::new(
+                reply_code::COMMAND_INVALID,
+                "unexpected method on a channel",
+                method_id,
+            )
+            .into()),
+        }
+    }
+
+    fn declare_queue(
+        &mut self,
+        channel: u16,
+        method_id: (u16, u16),
+        name: String,
+        passive: bool,
+        durable: bool,
+        no_wait: bool,
+    ) -> Result<(), Error> {
+        let queue = if passive {
+            self.find_queue(channel, method_id, &name)?
+        } else {
+            let name = if name.is_empty() {
+                self.broker.generate_queue_name()
+            } else if name.starts_with(RESERVED_PREFIX) {
+                return Err(Error::channel(
+                    channel,
+                    reply_code::ACCESS_REFUSED,
+                    format!("the prefix {} is reserved by the broker", RESERVED_PREFIX),
+                    method_id,
+                ));
+            } else {
+                name
+            };
+
+            // The queues of a memory-only broker are never durable, whatever the client asks.
+            let durable = durable && !self.broker.is_memory_only();
+            let mut definition = QueueDefinition::new(name);
+            definition.durable = durable;
+
+            let queue = self.broker.declare(&self.vhost, definition);
+
+            if queue.definition().durable != durable {
+                return Err(Error::channel(
+                    channel,
+                    reply_code::PRECONDITION_FAILED,
+                    format!(
+                        "queue {} is already declared with another durability",
+                        queue.name()
+                    ),
+                    method_id,
+                ));
+            }
+
+            queue
+        };
+
+        if no_wait {
+            return Ok(());
+        }
+
+        self.send(
+            channel,
+            Method::QueueDeclareOk {
+                queue: queue.name().into(),
+                message_count: queue.message_count() as u32,
+                consumer_count: queue.consumer_count() as u32,
+            },
+        )
+    }
+
+    fn consume(
+        &mut self,
+        channel: u16,
+        method_id: (u16, u16),
+        queue: Arc<Queue>,
+        tag: String,
+        options: ConsumeOptions,
+    ) -> Result<(), Error> {
+        self.check_consume(channel, method_id, &queue, options.no_ack)?;
+        let tag = if tag.is_empty() {
+            self.generated_tags += 1;
+            format!("amq.ctag-{}", self.generated_tags)
+        } else {
+            tag
+        };
+
+        if self.channels[&channel].has_consumer(&tag) {
+            return Err(Exception::new(
+                reply_code::NOT_ALLOWED,
+                format!(
+                    "consumer tag {} is already used on channel {}",
+                    tag, channel
+                ),
+                method_id,
+            )
+            .into());
+        }
+
+        let state = self.channels.get_mut(&channel).unwrap();
+        if !state.consume(
+            tag,
+            queue.clone(),
+            options.no_ack,
+            options.no_wait,
+            options.selector,
+        ) {
+            return Err(Error::channel(
+                channel,
+                reply_code::ACCESS_REFUSED,
+                format!(
+                    "queue {} already has its maximum of {} consumers",
+                    queue.name(),
+                    queue.definition().max_consumers.unwrap_or_default()
+                ),
+                method_id,
+            ));
+        }
+        self.broker.dispatch(&queue);
+
+        Ok(())
+    }
+
+    fn get(
+        &mut self,
+        channel: u16,
+        method_id: (u16, u16),
+        name: String,
+        no_ack: bool,
+    ) -> Result<(), Error> {
+        let queue = self.find_queue(channel, method_id, &name)?;
+        self.check_consume(channel, method_id, &queue, no_ack)?;
+        let frames = match self.broker.get(&queue, no_ack) {
+            Some(message) => self.channels[&channel].get(&queue, message, no_ack),
+            None => vec![Frame::Method(channel, Method::BasicGetEmpty)],
+        };
+
+        Ok(self.outbox.send(frames)?)
+    }
+
+    /// Settles deliveries of `channel`. The messages are acknowledged if `requeue` is `None`,
+    /// and rejected otherwise.
+    fn settle(
+        &mut self,
+        channel: u16,
+        method_id: (u16, u16),
+        delivery_tag: u64,
+        multiple: bool,
+        requeue: Option<bool>,
+    ) -> Result<(), Error> {
+        let state = &self.channels[&channel];
+        let settled = match state.settle(delivery_tag, multiple) {
+            Some(settled) => settled,
+            None => {
+                return Err(Error::channel(
+                    channel,
+                    reply_code::PRECONDITION_FAILED,
+                    format!("unknown delivery tag {}", delivery_tag),
+                    method_id,
+                ));
+            }
+        };
+        let queues = state.consumed_queues();
+
+        for (queue, messages) in settled {
+            match requeue {
+                Some(requeue) => self.broker.reject(&queue, messages, requeue),
+                None => self.broker.ack(messages),
+            }
+        }
+
+        // The settled messages may have been holding back the deliveries of the channel.
+        self.dispatch(&queues);
+
+        Ok(())
+    }
+
+    fn receive_header(&mut self, channel: u16, header: ContentHeader) -> Result<(), Error> {
+        let max_message_bytes = self.broker.max_message_bytes();
+        let peer = self.peer.clone();
+        let publication = match self.publication(channel)? {
+            Some(publication) => publication,
+            None => return Ok(()),
+        };
+
+        if publication.header.is_some() {
+            return Err(Exception::new(
+                reply_code::UNEXPECTED_FRAME,
+                format!("unexpected content header on channel {}", channel),
+                (0, 0),
+            )
+            .into());
+        }
+        if header.class_id != CLASS_BASIC {
+            return Err(Exception::new(
+                reply_code::UNEXPECTED_FRAME,
+                format!(
+                    "content header of class {} on channel {}",
+                    header.class_id, channel
+                ),
+                (0, 0),
+            )
+            .into());
+        }
+
+        // The message is rejected as soon as its size is announced, before its body is received.
+        if let Some(max_message_bytes) = max_message_bytes {
+            if header.body_size > max_message_bytes {
+                log::warn!(
+                    "Rejected a message of {} bytes published by {} to {}, exceeding the maximum of {} bytes",
+                    header.body_size,
+                    peer,
+                    publication.routing_key,
+                    max_message_bytes
+                );
+
+                return Err(Error::channel(
+                    channel,
+                    reply_code::CONTENT_TOO_LARGE,
+                    format!(
+                        "the message exceeds the maximum of {} bytes",
+                        max_message_bytes
+                    ),
+                    (60, 40),
+                ));
+            }
+        }
+
+        publication.header = Some(header);
+        self.publish_if_complete(channel)
+    }
+
+    fn receive_body(&mut self, channel: u16, body: Vec<u8>) -> Result<(), Error> {
+        let publication = match self.publication(channel)? {
+            Some(publication) => publication,
+            None => return Ok(()),
+        };
+        let body_size = match &publication.header {
+            Some(header) => header.body_size,
+            None => {
+                return Err(Exception::new(
+                    reply_code::UNEXPECTED_FRAME,
+                    format!("content body without its header on channel {}", channel),
+                    (0, 0),
+                )
+                .into());
+            }
+        };
+
+        if (publication.body.len() + body.len()) as u64 > body_size {
+            return Err(Exception::new(
+                reply_code::FRAME_ERROR,
+                format!("content body exceeding its size on channel {}", channel),
+                (0, 0),
+            )
+            .into());
+        }
+
+        publication.body.extend_from_slice(&body);
+        self.publish_if_complete(channel)
+    }
+
+    /// Returns the message being published on `channel`, which the content frames belong to.
+    /// The content frames are discarded while the channel is closing.
+    fn publication(&mut self, channel: u16) -> Result<Option<&mut Publication>, Error> {
+        let state = match self.channels.get_mut(&channel) {
+            Some(state) => state,
+            None => {
+                return Err(Exception::new(
+                    reply_code::CHANNEL_ERROR,
+                    format!("channel {} is not open", channel),
+                    (0, 0),
+                )
+                .into());
+            }
+        };
+
+        if state.closing {
+            return Ok(None);
+        }
+
+        match &mut state.publication {
+            Some(publication) => Ok(Some(publication)),
+            None => Err(Exception::new(
+                reply_code::UNEXPECTED_FRAME,
+                format!("unexpected content frame on channel {}", channel),
+                (0, 0),
+            )
+            .into()),
+        }
+    }
+
+    /// Publishes the message of `channel` once its content is complete.
+    fn publish_if_complete(&mut self, channel: u16) -> Result<(), Error> {
+        let publication = match self.channels.get_mut(&channel) {
+            Some(state)
+                if state
+                    .publication
+                    .as_ref()
+                    .is_some_and(Publication::is_complete) =>
+            {
+                state.publication.take().unwrap()
+            }
+            _ => return Ok(()),
+        };
+        let Publication {
+            exchange,
+            routing_key,
+            mandatory,
+            header,
+            body,
+        } = publication;
+        let properties = header.map(|header| header.properties).unwrap_or_default();
+
+        // The connection is slowed down by not reading its next frames until it's back under its
+        // rate limit, and the listener under its own. The buckets of both limits refill at the
+        // same time, so the longest wait gets back under both.
+        let mut wait = Duration::ZERO;
+        if let Some(rate_limiter) = &mut self.rate_limiter {
+            match rate_limiter.acquire(body.len() as u64) {
+                Some(connection_wait) => wait = connection_wait,
+                None => {
+                    return Err(Exception::new(
+                        reply_code::RESOURCE_ERROR,
+                        "the connection exceeded its rate limit",
+                        (60, 40),
+                    )
+                    .into())
+                }
+            }
+        }
+        if let Some(listener_rate_limiter) = &self.listener_rate_limiter {
+            wait = wait.max(listener_rate_limiter.acquire());
+        }
+        if wait > Duration::ZERO {
+            log::debug!(
+                "Throttling connection from {} for {:?}, as it or its listener exceeded its rate limit",
+                self.peer,
+                wait
+            );
+            thread::sleep(wait);
+        }
+
+        let queues = if exchange == TOPIC_EXCHANGE {
+            self.broker.route(&self.vhost, &routing_key)
+        } else {
+            let queue = self.broker.queue(&self.vhost, &routing_key).or_else(|| {
+                let queue = self.broker.default_queue(&self.vhost)?;
+
+                log::info!(
+                    "Routed a message of connection from {} to the default queue {}, as no queue is named {}",
+                    self.peer,
+                    queue.name(),
+                    routing_key
+                );
+                Some(queue)
+            });
+            queue.into_iter().collect()
+        };
+
+        match queues.as_slice() {
+            // The message is only published once the client is allowed to publish to every queue
+            // it's routed to.
+            [_, ..] => {
+                for queue in &queues {
+                    if !self.config.read().unwrap().auth.allows_publish(
+                        &self.username,
+                        &self.vhost,
+                        queue.name(),
+                    ) {
+                        return Err(self.access_refused(channel, (60, 40), "publish to", queue));
+                    }
+                }
+
+                let message = Message::new(exchange, routing_key, properties, body);
+
+                for queue in &queues {
+                    if !self.broker.publish(queue, message.clone()) {
+                        log::debug!(
+                            "Dropped a message of connection from {} routed to {}, as the queue is full",
+                            self.peer,
+                            queue.name()
+                        );
+                    }
+                }
+                Ok(())
+            }
+            [] if mandatory => {
+                let frames = Frame::content(
+                    channel,
+                    Method::BasicReturn {
+                        reply_code: reply_code::NO_ROUTE,
+                        reply_text: "NO_ROUTE".into(),
+                        exchange,
+                        routing_key,
+                    },
+                    properties,
+                    &body,
+                    self.frame_max,
+                );
+
+                Ok(self.outbox.send(frames)?)
+            }
+            [] => {
+                log::debug!(
+                    "Dropped a message of connection from {} routed to {}, as no queue matches it",
+                    self.peer,
+                    routing_key
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the queue named `name`, for a method which can't declare it.
+    fn find_queue(
+        &self,
+        channel: u16,
+        method_id: (u16, u16),
+        name: &str,
+    ) -> Result<Arc<Queue>, Error> {
+        self.broker.queue(&self.vhost, name).ok_or_else(|| {
+            Error::channel(
+                channel,
+                reply_code::NOT_FOUND,
+                format!("no queue named {}", name),
+                method_id,
+            )
+        })
+    }
+
+    /// Returns how the messages of `queue` delivered to a consumer are acknowledged: the `no-ack`
+    /// flag settles them, otherwise the `x-ack-mode` argument of the consumer overrides the
+    /// default acknowledgement mode of the queue.
+    fn ack_mode(
+        &self,
+        channel: u16,
+        method_id: (u16, u16),
+        queue: &Queue,
+        no_ack: bool,
+        arguments: &FieldTable,
+    ) -> Result<AckMode, Error> {
+        let invalid = |reason: &str| {
+            Error::channel(channel, reply_code::PRECONDITION_FAILED, reason, method_id)
+        };
+        let requested = match arguments.get("x-ack-mode") {
+            Some(FieldValue::LongStr(mode)) => Some(
+                String::from_utf8_lossy(mode)
+                    .parse::<AckMode>()
+                    .map_err(|_| invalid("the x-ack-mode argument must be auto or manual"))?,
+            ),
+            Some(_) => return Err(invalid("the x-ack-mode argument must be a string")),
+            None => None,
+        };
+
+        match (no_ack, requested) {
+            (true, Some(AckMode::Manual)) => Err(invalid(
+                "the x-ack-mode argument manual conflicts with the no-ack flag",
+            )),
+            (true, _) => Ok(AckMode::Auto),
+            (false, Some(mode)) => Ok(mode),
+            (false, None) => Ok(queue.definition().default_ack_mode),
+        }
+    }
+
+    /// Returns the selector of the `x-selector` argument of a consumer, if it's passed and not
+    /// empty. The argument is refused unless the queue namespace enables the selectors.
+    fn selector(
+        &self,
+        channel: u16,
+        method_id: (u16, u16),
+        arguments: &FieldTable,
+    ) -> Result<Option<Selector>, Error> {
+        let invalid = |reason: &str| {
+            Error::channel(channel, reply_code::PRECONDITION_FAILED, reason, method_id)
+        };
+        let table = match arguments.get("x-selector") {
+            Some(FieldValue::Table(table)) => table,
+            Some(_) => return Err(invalid("the x-selector argument must be a table")),
+            None => return Ok(None),
+        };
+
+        if !self.broker.selectors_enabled() {
+            return Err(invalid("the selectors are disabled by the queue namespace"));
+        }
+        match Selector::from_table(table) {
+            Some(_) if table.is_empty() => Ok(None),
+            Some(selector) => Ok(Some(selector)),
+            None => Err(invalid(
+                "the headers of the x-selector argument must be strings",
+            )),
+        }
+    }
+
+    /// Checks that the user of the connection can consume `queue`, or get its messages, and that
+    /// it doesn't expect to acknowledge the messages of a queue which delivers them at most once.
+    fn check_consume(
+        &self,
+        channel: u16,
+        method_id: (u16, u16),
+        queue: &Queue,
+        no_ack: bool,
+    ) -> Result<(), Error> {
+        if !no_ack && queue.definition().delivery_mode == DeliveryMode::AtMostOnce {
+            return Err(Error::channel(
+                channel,
+                reply_code::PRECONDITION_FAILED,
+                format!(
+                    "queue {} delivers its messages at most once, it must be consumed with no-ack",
+                    queue.name()
+                ),
+                method_id,
+            ));
+        }
+
+        if self.config.read().unwrap().auth.allows_consume(
+            &self.username,
+            &self.vhost,
+            queue.name(),
+        ) {
+            return Ok(());
+        }
+
+        Err(self.access_refused(channel, method_id, "consume", queue))
+    }
+
+    /// Returns the error of the user of the connection trying to `action` a queue it's not
+    /// granted by the access control rules.
+    fn access_refused(
+        &self,
+        channel: u16,
+        method_id: (u16, u16),
+        action: &str,
+        queue: &Queue,
+    ) -> Error {
+        log::warn!(
+            "Connection from {} refused to {} queue {}, as user {} is not granted it",
+            self.peer,
+            action,
+            queue.name(),
+            self.username
+        );
+
+        Error::channel(
+            channel,
+            reply_code::ACCESS_REFUSED,
+            format!(
+                "user {} is not allowed to {} queue {}",
+                self.username,
+                action,
+                queue.name()
+            ),
+            method_id,
+        )
+    }
+
+    /// Pushes the messages of `queues` to their consumers.
+    fn dispatch(&self, queues: &[Arc<Queue>]) {
+        for queue in queues {
+            self.broker.dispatch(queue);
+        }
+    }
+
+    /// Removes `channel` once it's closed, so it doesn't count in the channels of the user anymore.
+    fn remove_channel(&mut self, channel: u16) -> Option<Channel> {
+        let state = self.channels.remove(&channel)?;
+
+        self.broker.usage().close_channel(&self.username);
+        Some(state)
+    }
+
+    /// Closes `channel` because of `exception`. The channel is kept until the client
+    /// acknowledges the closing.
+    fn close_channel(&mut self, channel: u16, exception: Exception) -> Result<(), Error> {
+        log::debug!(
+            "Closing channel {} of connection from {}: {}",
+            channel,
+            self.peer,
+            exception.reply_text
+        );
+
+        if let Some(state) = self.channels.get_mut(&channel) {
+            state.closing = true;
+            state.publication = None;
+            state.release(&self.broker);
+        }
+
+        self.send(
+            channel,
+            Method::ChannelClose {
+                reply_code: exception.reply_code,
+                reply_text: exception.reply_text,
+                class_id: exception.class_id,
+                method_id: exception.method_id,
+            },
+        )
+    }
+
+    /// Closes the connection because of `exception`, then waits for the client to acknowledge it.
+    fn close(&mut self, exception: Exception) {
+        log::warn!(
+            "Closing connection from {}: {}",
+            self.peer,
+            exception.reply_text
+        );
+
+        let close = Method::ConnectionClose {
+            reply_code: exception.reply_code,
+            reply_text: exception.reply_text,
+            class_id: exception.class_id,
+            method_id: exception.method_id,
+        };
+        // The closing is awaited for the close timeout, even if the handshake is not complete.
+        self.handshake_deadline = None;
+
+        if self.send(0, close).is_err()
+            || self
+                .reader
+                .get_ref()
+                .set_read_timeout(Some(CLOSE_TIMEOUT))
+                .is_err()
+        {
+            return;
+        }
+
+        // Every frame other than the acknowledgement of the closing is discarded.
+        while let Ok(frame) = self.read_frame() {
+            if let Frame::Method(0, Method::ConnectionCloseOk)
+            | Frame::Method(0, Method::ConnectionClose { .. }) = frame
+            {
+                break;
+            }
+        }
+    }
+
+    /// Sends the heartbeats to the client every `interval`, and closes the connection once the
+    /// client sent nothing for two intervals, unless the idle timeout is shorter.
+    fn start_heartbeat(&mut self, interval: Duration) -> Result<(), Error> {
+        self.heartbeat = Some(interval);
+        self.reader
+            .get_ref()
+            .set_read_timeout(self.read_timeout())
+            .map_err(protocol::Error::from)?;
+        self.outbox.set_heartbeat(interval);
+
+        Ok(())
+    }
+
+    /// Returns the read timeout of the stream, the shortest of the idle timeout and two
+    /// heartbeat intervals.
+    fn read_timeout(&self) -> Option<Duration> {
+        match (self.idle_timeout, self.heartbeat) {
+            (Some(idle_timeout), Some(interval)) => Some(idle_timeout.min(2 * interval)),
+            (None, Some(interval)) => Some(2 * interval),
+            (idle_timeout, None) => idle_timeout,
+        }
+    }
+
+    /// Reads the next method sent on the channel 0 while the connection is negotiated.
+    fn expect_method(&mut self) -> Result<Method, Error> {
+        loop {
+            match self.read_frame()? {
+                Frame::Heartbeat => {}
+                Frame::Method(0, method) => return Ok(method),
+                Frame::Method(_, method) => return Err(Error::unexpected(&method)),
+                Frame::Header(..) | Frame::Body(..) => {
+                    return Err(Exception::new(
+                        reply_code::UNEXPECTED_FRAME,
+                        "unexpected content frame",
+                        (0, 0),
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<Frame, Error> {
+        let frame_max = self.frame_max;
+
+        self.read(|reader| Frame::read(reader, frame_max))
+    }
+
+    /// Reads from the stream with `read`. Until the connection is open, each read of the stream
+    /// waits for the remaining time of the handshake at most, so the client can't hold it for
+    /// longer by sending its frames slowly.
+    fn read<T, F>(&mut self, read: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut HandshakeReader) -> Result<T, protocol::Error>,
+    {
+        let mut reader = HandshakeReader {
+            deadline: self.handshake_deadline,
+            timeout: self.read_timeout(),
+            reader: &mut self.reader,
+        };
+
+        read(&mut reader).map_err(|err| match (Error::from(err), self.handshake_deadline) {
+            (Error::Idle, Some(deadline)) if Instant::now() >= deadline => Error::HandshakeTimeout,
+            (err, _) => err,
+        })
+    }
+
+    fn send(&self, channel: u16, method: Method) -> Result<(), Error> {
+        Ok(self.outbox.send(vec![Frame::Method(channel, method)])?)
+    }
+}
+
+/// The reader of the stream of a connection, bounding its reads by the deadline of the handshake
+/// if there's one.
+struct HandshakeReader<'a> {
+    reader: &'a mut BufReader<Stream>,
+    deadline: Option<Instant>,
+    /// The read timeout of the stream once the connection is open.
+    timeout: Option<Duration>,
+}
+
+impl Read for HandshakeReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+
+            let timeout = self
+                .timeout
+                .map_or(remaining, |timeout| timeout.min(remaining));
+            self.reader.get_ref().set_read_timeout(Some(timeout))?;
+        }
+
+        self.reader.read(buf)
+    }
+}
+
+/// The options of a consumer started by the client.
+struct ConsumeOptions {
+    /// Whether the messages are settled as soon as they're delivered.
+    no_ack: bool,
+    no_wait: bool,
+    /// The headers selecting the messages delivered to the consumer.
+    selector: Option<Selector>,
+}
+
+/// Negotiates a limit of the connection, where `0` means that the client has no limit.
+fn negotiate<T: Copy + Default + Ord>(server: T, client: T) -> T {
+    if client == T::default() {
+        server
+    } else {
+        server.min(client)
+    }
+}
+
+/// The reasons for the broker to stop serving a connection.
+enum Error {
+    /// The client does not speak the protocol of the broker.
+    UnsupportedProtocol,
+
+    /// The stream of the connection failed or the client sent an invalid frame.
+    Protocol(protocol::Error),
+
+    /// The client did something forbidden, the connection must be closed.
+    Exception(Exception),
+
+    /// The client did something forbidden on a channel, which must be closed.
+    ChannelException(u16, Exception),
+
+    /// The client sent nothing for longer than the idle timeout or two heartbeat intervals, or
+    /// while the broker waited for it to acknowledge the closing of its connection.
+    Idle,
+
+    /// The client did not complete the handshake of its connection within the handshake timeout.
+    HandshakeTimeout,
+}
+
+impl Error {
+    fn channel<S: Into<String>>(
+        channel: u16,
+        reply_code: u16,
+        reply_text: S,
+        method_id: (u16, u16),
+    ) -> Self {
+        Self::ChannelException(channel, Exception::new(reply_code, reply_text, method_id))
+    }
+
+    fn unexpected(method: &Method) -> Self {
+        Exception::new(
+            reply_code::COMMAND_INVALID,
+            "unexpected method",
+            method.id(),
+        )
+        .into()
+    }
+}
+
+impl From<protocol::Error> for Error {
+    fn from(err: protocol::Error) -> Self {
+        match err {
+            // Only the reads time out, once the read timeout of the stream expired.
+            protocol::Error::Io(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                Self::Idle
+            }
+            err => Self::Protocol(err),
+        }
+    }
+}
+
+impl From<Exception> for Error {
+    fn from(exception: Exception) -> Self {
+        Self::Exception(exception)
+    }
+}
+
+/// The reply sent to a client when its connection or one of its channels is closed by the
+/// broker.
+struct Exception {
+    reply_code: u16,
+    reply_text: String,
+    class_id: u16,
+    method_id: u16,
+}
+
+impl Exception {
+    fn new<S: Into<String>>(reply_code: u16, reply_text: S, method_id: (u16, u16)) -> Self {
+        let (class_id, method_id) = method_id;
+
+        Self {
+            reply_code,
+            reply_text: reply_text.into(),
+            class_id,
+            method_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Properties;
+    use std::net::{TcpListener, TcpStream};
+
+    /// A client of the broker, speaking the protocol frame by frame.
+    struct Client {
+        stream: TcpStream,
+        broker: Arc<Broker>,
+    }
+
+    impl Client {
+        /// Connects to a broker running `config`, served by a connection of its own.
+        fn connect(config: Config) -> Self {
+            let broker = Broker::new(&config.queue, &config.vhosts, &config.metrics).unwrap();
+
+            Self::connect_to(Arc::new(broker), config)
+        }
+
+        /// Connects to `broker`, the connection being served with `config`.
+        fn connect_to(broker: Arc<Broker>, config: Config) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (socket, address) = listener.accept().unwrap();
+            let connection = Connection::new(
+                Stream::Tcp(socket),
+                Peer::Tcp(address),
+                broker.clone(),
+                Arc::new(RwLock::new(config)),
+                Arc::new(Shutdown::default()),
+                None,
+            )
+            .unwrap();
+            thread::spawn(move || connection.run());
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+
+            Self { stream, broker }
+        }
+
+        fn send(&mut self, frame: Frame) {
+            frame.write(&mut self.stream).unwrap();
+        }
+
+        fn send_method(&mut self, channel: u16, method: Method) {
+            self.send(Frame::Method(channel, method));
+        }
+
+        /// Returns the next frame sent by the broker, skipping the heartbeats.
+        fn receive(&mut self) -> Frame {
+            loop {
+                match Frame::read(&mut self.stream, 1 << 20).unwrap() {
+                    Frame::Heartbeat => {}
+                    frame => return frame,
+                }
+            }
+        }
+
+        fn receive_method(&mut self) -> Method {
+            match self.receive() {
+                Frame::Method(_, method) => method,
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+        }
+
+        /// Returns `true` once the broker closed the connection, or `false` if it's still open
+        /// after the read timeout of the client. The frames sent meanwhile are ignored.
+        fn is_closed(&mut self) -> bool {
+            let mut buf = [0; 1024];
+            loop {
+                match self.stream.read(&mut buf) {
+                    Ok(0) => return true,
+                    Ok(_) => {}
+                    Err(err) if err.kind() == io::ErrorKind::ConnectionReset => return true,
+                    Err(_) => return false,
+                }
+            }
+        }
+
+        /// Opens the connection on the default virtual host as `username`.
+        fn open_as(&mut self, username: &str, password: &str) -> Method {
+            self.stream.write_all(&PROTOCOL_HEADER).unwrap();
+            assert!(matches!(
+                self.receive_method(),
+                Method::ConnectionStart { .. }
+            ));
+            self.send_method(
+                0,
+                Method::ConnectionStartOk {
+                    client_properties: FieldTable::new(),
+                    mechanism: "PLAIN".into(),
+                    response: format!("\0{}\0{}", username, password).into_bytes(),
+                    locale: "en_US".into(),
+                },
+            );
+            match self.receive_method() {
+                Method::ConnectionTune { frame_max, .. } => self.send_method(
+                    0,
+                    Method::ConnectionTuneOk {
+                        channel_max: 0,
+                        frame_max,
+                        heartbeat: 0,
+                    },
+                ),
+                method => return method,
+            }
+            self.send_method(
+                0,
+                Method::ConnectionOpen {
+                    virtual_host: DEFAULT_VHOST.into(),
+                },
+            );
+
+            self.receive_method()
+        }
+
+        /// Opens the connection, then the channel 1.
+        fn open(&mut self) {
+            assert_eq!(self.open_as("guest", "guest"), Method::ConnectionOpenOk);
+            self.send_method(1, Method::ChannelOpen);
+            assert_eq!(self.receive_method(), Method::ChannelOpenOk);
+        }
+
+        /// Publishes `body` to the queue `queue` through the default exchange.
+        fn publish(&mut self, queue: &str, body: &[u8]) {
+            let publish = Method::BasicPublish {
+                exchange: String::new(),
+                routing_key: queue.into(),
+                mandatory: false,
+                immediate: false,
+            };
+            for frame in Frame::content(1, publish, Properties::default(), body, FRAME_MIN_SIZE) {
+                self.send(frame);
+            }
+        }
+
+        /// Returns the number of messages ready in `queue`, once the frames sent before are
+        /// handled.
+        fn message_count(&mut self, queue: &str) -> u32 {
+            self.send_method(
+                1,
+                Method::QueueDeclare {
+                    queue: queue.into(),
+                    passive: true,
+                    durable: false,
+                    exclusive: false,
+                    auto_delete: false,
+                    no_wait: false,
+                    arguments: FieldTable::new(),
+                },
+            );
+            match self.receive_method() {
+                Method::QueueDeclareOk { message_count, .. } => message_count,
+                method => panic!("unexpected method {:?}", method),
+            }
+        }
+
+        /// Consumes the queue `queue` on the channel 1, returning the tag of the consumer.
+        fn consume(&mut self, queue: &str, no_ack: bool, arguments: FieldTable) -> String {
+            self.send_method(
+                1,
+                Method::BasicConsume {
+                    queue: queue.into(),
+                    consumer_tag: String::new(),
+                    no_local: false,
+                    no_ack,
+                    exclusive: false,
+                    no_wait: false,
+                    arguments,
+                },
+            );
+            match self.receive_method() {
+                Method::BasicConsumeOk { consumer_tag } => consumer_tag,
+                method => panic!("unexpected method {:?}", method),
+            }
+        }
+
+        /// Returns the delivery tag and the body of the next message delivered to a consumer.
+        fn delivery(&mut self) -> (u64, Vec<u8>) {
+            let delivery_tag = match self.receive_method() {
+                Method::BasicDeliver { delivery_tag, .. } => delivery_tag,
+                method => panic!("unexpected method {:?}", method),
+            };
+            let body_size = match self.receive() {
+                Frame::Header(_, header) => header.body_size as usize,
+                frame => panic!("unexpected frame {:?}", frame),
+            };
+            let mut body = Vec::new();
+            while body.len() < body_size {
+                match self.receive() {
+                    Frame::Body(_, part) => body.extend(part),
+                    frame => panic!("unexpected frame {:?}", frame),
+                }
+            }
+
+            (delivery_tag, body)
+        }
+
+        /// Returns the reply code of the closing of the channel 1 by the broker.
+        fn channel_closed(&mut self) -> u16 {
+            match self.receive_method() {
+                Method::ChannelClose { reply_code, .. } => reply_code,
+                method => panic!("unexpected method {:?}", method),
+            }
+        }
+    }
+
+    /// Returns a configuration defining the queue `tasks`.
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.queue.definitions.push(QueueDefinition::new("tasks"));
+        config
+    }
+
+    #[test]
+    fn message_up_to_the_maximum_size_is_published() {
+        let mut config = config();
+        config.queue.max_message_bytes = Some(10);
+        let mut client = Client::connect(config);
+        client.open();
+
+        client.publish("tasks", &[0; 9]);
+        client.publish("tasks", &[0; 10]);
+
+        assert_eq!(client.message_count("tasks"), 2);
+    }
+
+    #[test]
+    fn message_over_the_maximum_size_is_rejected() {
+        let mut config = config();
+        config.queue.max_message_bytes = Some(10);
+        let mut client = Client::connect(config);
+        client.open();
+
+        client.publish("tasks", &[0; 11]);
+
+        assert_eq!(client.channel_closed(), reply_code::CONTENT_TOO_LARGE);
+        assert_eq!(
+            client.broker.queue("/", "tasks").unwrap().message_count(),
+            0
+        );
+    }
+
+    #[test]
+    fn idle_connection_is_closed() {
+        let mut config = config();
+        config.network.idle_timeout = Some(Duration::from_millis(200));
+        let mut client = Client::connect(config);
+        client.open();
+        let opened_at = Instant::now();
+
+        assert!(client.is_closed());
+        assert!(opened_at.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn connection_sending_heartbeats_is_not_idle() {
+        let mut config = config();
+        config.network.idle_timeout = Some(Duration::from_millis(200));
+        let mut client = Client::connect(config);
+        client.open();
+
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(100));
+            client.send(Frame::Heartbeat);
+        }
+
+        assert_eq!(client.message_count("tasks"), 0);
+    }
+
+    /// Waits for `condition` to hold, for up to a few seconds, as the broker handles the
+    /// disconnections of the clients on its own threads.
+    fn wait_until<F: Fn() -> bool>(condition: F) -> bool {
+        for _ in 0..100 {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        condition()
+    }
+
+    #[test]
+    fn messages_delivered_at_least_once_are_requeued_on_disconnect() {
+        let mut client = Client::connect(config());
+        client.open();
+        client.publish("tasks", b"task");
+        client.consume("tasks", false, FieldTable::new());
+        assert_eq!(client.delivery().1, b"task");
+
+        let broker = client.broker.clone();
+        let queue = broker.queue("/", "tasks").unwrap();
+        assert_eq!(queue.message_count(), 0);
+        drop(client);
+
+        assert!(wait_until(|| queue.consumer_count() == 0));
+        assert!(wait_until(|| queue.message_count() == 1));
+        assert!(broker.get(&queue, true).unwrap().redelivered);
+    }
+
+    #[test]
+    fn messages_delivered_at_most_once_are_lost_on_disconnect() {
+        let mut config = Config::default();
+        config.queue.definitions.push(QueueDefinition {
+            delivery_mode: DeliveryMode::AtMostOnce,
+            ..QueueDefinition::new("tasks")
+        });
+        let mut client = Client::connect(config);
+        client.open();
+        client.publish("tasks", b"task");
+        client.consume("tasks", true, FieldTable::new());
+        assert_eq!(client.delivery().1, b"task");
+
+        let queue = client.broker.queue("/", "tasks").unwrap();
+        drop(client);
+
+        assert!(wait_until(|| queue.consumer_count() == 0));
+        assert_eq!(queue.message_count(), 0);
+    }
+
+    #[test]
+    fn queue_delivered_at_most_once_must_be_consumed_with_no_ack() {
+        let mut config = Config::default();
+        config.queue.definitions.push(QueueDefinition {
+            delivery_mode: DeliveryMode::AtMostOnce,
+            ..QueueDefinition::new("tasks")
+        });
+        let mut client = Client::connect(config);
+        client.open();
+
+        client.send_method(
+            1,
+            Method::BasicConsume {
+                queue: "tasks".into(),
+                consumer_tag: String::new(),
+                no_local: false,
+                no_ack: false,
+                exclusive: false,
+                no_wait: false,
+                arguments: FieldTable::new(),
+            },
+        );
+
+        assert_eq!(client.channel_closed(), reply_code::PRECONDITION_FAILED);
+    }
+
+    #[test]
+    fn oversize_frame_closes_the_connection() {
+        let mut config = config();
+        config.network.max_frame_bytes = 4096;
+        let mut client = Client::connect(config);
+        client.open();
+
+        client.send(Frame::Body(1, vec![0; 4096]));
+
+        match client.receive_method() {
+            Method::ConnectionClose { reply_code, .. } => {
+                assert_eq!(reply_code, reply_code::FRAME_ERROR)
+            }
+            method => panic!("unexpected method {:?}", method),
+        }
+    }
+
+    /// Returns a configuration allowing the user `guest`, whose password is `guest`, to open up
+    /// to 2 connections and 1 channel.
+    #[cfg(all(unix, feature = "bcrypt"))]
+    fn limited_config() -> Config {
+        let mut config = config();
+        config.auth.users.push(crate::config::User {
+            username: "guest".into(),
+            password_hash: "$2b$04$0acWwhKGPAxXGZi.b9EZ0OjzxXakOS9KOlYB.DJRnN5UGbm.MAH3S".into(),
+            max_connections: Some(2),
+            max_channels: Some(1),
+        });
+        config
+    }
+
+    #[cfg(all(unix, feature = "bcrypt"))]
+    #[test]
+    fn user_connections_are_limited() {
+        let config = limited_config();
+        let broker = Arc::new(Broker::new(&config.queue, &config.vhosts, &config.metrics).unwrap());
+        let mut first = Client::connect_to(broker.clone(), config.clone());
+        let mut second = Client::connect_to(broker.clone(), config.clone());
+        let mut third = Client::connect_to(broker.clone(), config.clone());
+
+        assert_eq!(first.open_as("guest", "guest"), Method::ConnectionOpenOk);
+        assert_eq!(second.open_as("guest", "guest"), Method::ConnectionOpenOk);
+        match third.open_as("guest", "guest") {
+            Method::ConnectionClose { reply_code, .. } => {
+                assert_eq!(reply_code, reply_code::NOT_ALLOWED)
+            }
+            method => panic!("unexpected method {:?}", method),
+        }
+
+        // The slot of the connection closed is probed, then given back to the next one.
+        drop(first);
+        assert!(wait_until(|| broker.usage().connect("guest", Some(2))));
+        broker.usage().disconnect("guest");
+        let mut fourth = Client::connect_to(broker, config);
+        assert_eq!(fourth.open_as("guest", "guest"), Method::ConnectionOpenOk);
+    }
+
+    #[cfg(all(unix, feature = "bcrypt"))]
+    #[test]
+    fn user_channels_are_limited() {
+        let mut client = Client::connect(limited_config());
+        assert_eq!(client.open_as("guest", "guest"), Method::ConnectionOpenOk);
+
+        client.send_method(1, Method::ChannelOpen);
+        assert_eq!(client.receive_method(), Method::ChannelOpenOk);
+        client.send_method(2, Method::ChannelOpen);
+
+        match client.receive_method() {
+            Method::ConnectionClose { reply_code, .. } => {
+                assert_eq!(reply_code, reply_code::NOT_ALLOWED)
+            }
+            method => panic!("unexpected method {:?}", method),
+        }
+    }
+
+    #[test]
+    fn prefetch_stalls_the_deliveries_until_an_ack() {
+        let mut config = Config::default();
+        config.queue.definitions.push(QueueDefinition {
+            prefetch_count: Some(1),
+            ..QueueDefinition::new("tasks")
+        });
+        let mut client = Client::connect(config);
+        client.open();
+        for body in [&b"first"[..], b"second", b"third"] {
+            client.publish("tasks", body);
+        }
+        client.consume("tasks", false, FieldTable::new());
+
+        let (first, body) = client.delivery();
+        assert_eq!(body, b"first");
+        assert_eq!(client.message_count("tasks"), 2);
+
+        client.send_method(
+            1,
+            Method::BasicAck {
+                delivery_tag: first,
+                multiple: false,
+            },
+        );
+        assert_eq!(client.delivery().1, b"second");
+        assert_eq!(client.message_count("tasks"), 1);
+    }
+
+    #[test]
+    fn channel_prefetch_stalls_the_deliveries_until_an_ack() {
+        let mut client = Client::connect(config());
+        client.open();
+        client.send_method(
+            1,
+            Method::BasicQos {
+                prefetch_size: 0,
+                prefetch_count: 2,
+                global: false,
+            },
+        );
+        assert_eq!(client.receive_method(), Method::BasicQosOk);
+        for body in [&b"first"[..], b"second", b"third"] {
+            client.publish("tasks", body);
+        }
+        client.consume("tasks", false, FieldTable::new());
+
+        client.delivery();
+        let (second, _) = client.delivery();
+        assert_eq!(client.message_count("tasks"), 1);
+
+        client.send_method(
+            1,
+            Method::BasicAck {
+                delivery_tag: second,
+                multiple: true,
+            },
+        );
+        assert_eq!(client.delivery().1, b"third");
+        assert_eq!(client.message_count("tasks"), 0);
+    }
+
+    #[test]
+    fn deliveries_are_unbounded_without_prefetch() {
+        let mut client = Client::connect(config());
+        client.open();
+        for body in [&b"first"[..], b"second", b"third"] {
+            client.publish("tasks", body);
+        }
+        client.consume("tasks", false, FieldTable::new());
+
+        for _ in 0..3 {
+            client.delivery();
+        }
+        assert_eq!(client.message_count("tasks"), 0);
+    }
+
+    /// Returns the number of messages left in the queue `tasks` once a consumer passing
+    /// `ack_mode` as its `x-ack-mode` argument, if any, goes away without acknowledging the
+    /// message delivered to it.
+    fn left_after_crash(default_ack_mode: AckMode, ack_mode: Option<&str>) -> usize {
+        let mut config = Config::default();
+        config.queue.definitions.push(QueueDefinition {
+            default_ack_mode,
+            ..QueueDefinition::new("tasks")
+        });
+        let mut arguments = FieldTable::new();
+        if let Some(ack_mode) = ack_mode {
+            arguments.insert("x-ack-mode".into(), FieldValue::LongStr(ack_mode.into()));
+        }
+        let mut client = Client::connect(config);
+        client.open();
+        client.publish("tasks", b"task");
+        client.consume("tasks", false, arguments);
+        assert_eq!(client.delivery().1, b"task");
+
+        let broker = client.broker.clone();
+        drop(client);
+        // The connection is only counted out once its channels are released.
+        assert!(wait_until(|| broker.metrics().connections.get() == 0));
+
+        broker.queue("/", "tasks").unwrap().message_count()
+    }
+
+    #[test]
+    fn messages_survive_a_crash_in_the_manual_mode_only() {
+        assert_eq!(left_after_crash(AckMode::Manual, None), 1);
+        assert_eq!(left_after_crash(AckMode::Auto, None), 0);
+    }
+
+    #[test]
+    fn ack_mode_of_the_consumer_overrides_the_one_of_the_queue() {
+        assert_eq!(left_after_crash(AckMode::Auto, Some("manual")), 1);
+        assert_eq!(left_after_crash(AckMode::Manual, Some("AUTO")), 0);
+    }
+
+    #[test]
+    fn manual_ack_mode_conflicts_with_no_ack() {
+        let mut client = Client::connect(config());
+        client.open();
+        let mut arguments = FieldTable::new();
+        arguments.insert("x-ack-mode".into(), FieldValue::LongStr(b"manual".to_vec()));
+
+        client.send_method(
+            1,
+            Method::BasicConsume {
+                queue: "tasks".into(),
+                consumer_tag: String::new(),
+                no_local: false,
+                no_ack: true,
+                exclusive: false,
+                no_wait: false,
+                arguments,
+            },
+        );
+
+        assert_eq!(client.channel_closed(), reply_code::PRECONDITION_FAILED);
+    }
+
+    fn selector_arguments(kind: &str) -> FieldTable {
+        let mut selector = FieldTable::new();
+        selector.insert("type".into(), FieldValue::LongStr(kind.into()));
+        let mut arguments = FieldTable::new();
+        arguments.insert("x-selector".into(), FieldValue::Table(selector));
+        arguments
+    }
+
+    /// Publishes `body` to the queue `tasks` with the header `type` set to `kind`.
+    fn publish_typed(client: &mut Client, body: &[u8], kind: &str) {
+        let publish = Method::BasicPublish {
+            exchange: String::new(),
+            routing_key: "tasks".into(),
+            mandatory: false,
+            immediate: false,
+        };
+        let mut headers = FieldTable::new();
+        headers.insert("type".into(), FieldValue::LongStr(kind.into()));
+        let properties = Properties {
+            headers: Some(headers),
+            ..Properties::default()
+        };
+        for frame in Frame::content(1, publish, properties, body, FRAME_MIN_SIZE) {
+            client.send(frame);
+        }
+    }
+
+    #[test]
+    fn consumer_only_receives_the_messages_its_selector_matches() {
+        let mut config = config();
+        config.queue.enable_selectors = true;
+        let mut client = Client::connect(config);
+        client.open();
+        publish_typed(&mut client, b"receipt", "receipt");
+        publish_typed(&mut client, b"invoice", "invoice");
+
+        client.consume("tasks", true, selector_arguments("invoice"));
+
+        assert_eq!(client.delivery().1, b"invoice");
+        assert_eq!(client.message_count("tasks"), 1);
+    }
+
+    #[test]
+    fn selector_is_refused_unless_enabled() {
+        let mut client = Client::connect(config());
+        client.open();
+
+        client.send_method(
+            1,
+            Method::BasicConsume {
+                queue: "tasks".into(),
+                consumer_tag: String::new(),
+                no_local: false,
+                no_ack: true,
+                exclusive: false,
+                no_wait: false,
+                arguments: selector_arguments("invoice"),
+            },
+        );
+
+        assert_eq!(client.channel_closed(), reply_code::PRECONDITION_FAILED);
+    }
+
+    /// Returns a configuration defining the queue `tasks`, whose handshakes time out after
+    /// `timeout`.
+    fn handshake_config(timeout: Duration) -> Config {
+        let mut config = config();
+        config.network.handshake_timeout = timeout;
+        config
+    }
+
+    #[test]
+    fn handshake_stalled_mid_negotiation_is_closed() {
+        let mut client = Client::connect(handshake_config(Duration::from_millis(200)));
+        client.stream.write_all(&PROTOCOL_HEADER).unwrap();
+        assert!(matches!(
+            client.receive_method(),
+            Method::ConnectionStart { .. }
+        ));
+
+        // The client stalls instead of answering the start of the connection.
+        let stalled_at = Instant::now();
+        assert!(client.is_closed());
+        assert!(stalled_at.elapsed() < Duration::from_secs(2));
+
+        let broker = client.broker.clone();
+        assert!(wait_until(|| broker.metrics().connections.get() == 0));
+    }
+
+    #[test]
+    fn handshake_sent_slowly_is_closed_at_its_deadline() {
+        let mut client = Client::connect(handshake_config(Duration::from_millis(300)));
+
+        // Each byte comes well within the timeout, but the whole header doesn't.
+        let started_at = Instant::now();
+        for byte in PROTOCOL_HEADER.iter() {
+            if client.stream.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(client.is_closed());
+        assert!(started_at.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn open_connection_outlives_the_handshake_timeout() {
+        let mut client = Client::connect(handshake_config(Duration::from_millis(100)));
+        client.open();
+
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(client.message_count("tasks"), 0);
+    }
+}
@@ -0,0 +1,534 @@
+use super::outbox::Outbox;
+use crate::{
+    broker::{Broker, Consumer, Message, Queue, Selector},
+    protocol::{ContentHeader, Frame, Method},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// A channel opened by the client on its connection.
+pub struct Channel {
+    /// The number of the channel on its connection.
+    number: u16,
+
+    /// The outbox of the connection, which the frames of the channel are sent into.
+    outbox: Outbox,
+
+    /// The maximum size of the frames negotiated with the client.
+    frame_max: u32,
+
+    /// Whether the broker closed the channel and waits for the client to acknowledge it.
+    pub closing: bool,
+
+    /// The message being published by the client, until its content is received.
+    pub publication: Option<Publication>,
+
+    /// The consumers of the channel, by consumer tag.
+    consumers: HashMap<String, (Arc<Queue>, Arc<dyn Consumer>)>,
+
+    /// The deliveries of the channel, shared with its consumers.
+    deliveries: Arc<Mutex<Deliveries>>,
+}
+
+impl Channel {
+    pub fn new(number: u16, outbox: Outbox, frame_max: u32) -> Self {
+        Self {
+            number,
+            outbox,
+            frame_max,
+            closing: false,
+            publication: None,
+            consumers: HashMap::new(),
+            deliveries: Arc::default(),
+        }
+    }
+
+    /// Returns `true` if a consumer of the channel uses `tag`. The consumers cancelled by the
+    /// broker, once their queue is deleted, don't use their tag anymore.
+    pub fn has_consumer(&self, tag: &str) -> bool {
+        self.consumers
+            .get(tag)
+            .is_some_and(|(queue, _)| !queue.is_deleted())
+    }
+
+    /// Starts consuming `queue` under `tag`. Unless `no_wait` is set, the client is answered
+    /// with a `ConsumeOk` method before the first message is delivered. The messages are
+    /// delivered as `Deliver` methods, only the ones matching `selector` if it's defined.
+    ///
+    /// Returns `false` if the queue already has its maximum number of consumers.
+    pub fn consume(
+        &mut self,
+        tag: String,
+        queue: Arc<Queue>,
+        no_ack: bool,
+        no_wait: bool,
+        selector: Option<Selector>,
+    ) -> bool {
+        let consumer: Arc<dyn Consumer> = Arc::new(ChannelConsumer {
+            channel: self.number,
+            tag: tag.clone(),
+            no_ack,
+            selector,
+            outbox: self.outbox.clone(),
+            frame_max: self.frame_max,
+            deliveries: self.deliveries.clone(),
+        });
+        let added = || {
+            if !no_wait {
+                let consume_ok = Method::BasicConsumeOk {
+                    consumer_tag: tag.clone(),
+                };
+                let _ = self
+                    .outbox
+                    .send(vec![Frame::Method(self.number, consume_ok)]);
+            }
+        };
+
+        if !queue.add_consumer(consumer.clone(), added) {
+            return false;
+        }
+        self.consumers.insert(tag, (queue, consumer));
+
+        true
+    }
+
+    /// Stops the consumer using `tag`, returning the queue it consumed.
+    pub fn cancel(&mut self, tag: &str) -> Option<Arc<Queue>> {
+        let (queue, consumer) = self.consumers.remove(tag)?;
+
+        queue.remove_consumer(&consumer);
+
+        Some(queue)
+    }
+
+    /// Returns the queues consumed by the channel, whose deliveries may have been held back by
+    /// the prefetch limit of the channel.
+    pub fn consumed_queues(&self) -> Vec<Arc<Queue>> {
+        let mut queues: Vec<Arc<Queue>> = Vec::new();
+
+        for (queue, _) in self.consumers.values() {
+            if !queues.iter().any(|q| Arc::ptr_eq(q, queue)) {
+                queues.push(queue.clone());
+            }
+        }
+
+        queues
+    }
+
+    /// Limits the number of messages left unacknowledged by the consumers of the channel, `0`
+    /// meaning no limit.
+    pub fn set_prefetch_count(&self, prefetch_count: u16) {
+        self.deliveries.lock().unwrap().prefetch_count = prefetch_count;
+    }
+
+    /// Builds the frames answering a `Get` method with `message`. Unless `no_ack` is set, the
+    /// message is kept until the client acknowledges it.
+    pub fn get(&self, queue: &Arc<Queue>, message: Message, no_ack: bool) -> Vec<Frame> {
+        // The queue is never locked while the deliveries are, as it locks them when delivering.
+        let message_count = queue.message_count() as u32;
+        let mut deliveries = self.deliveries.lock().unwrap();
+        let delivery_tag = deliveries.next_tag();
+        let frames = Frame::content(
+            self.number,
+            Method::BasicGetOk {
+                delivery_tag,
+                redelivered: message.redelivered,
+                exchange: message.exchange.clone(),
+                routing_key: message.routing_key.clone(),
+                message_count,
+            },
+            message.properties.clone(),
+            &message.body,
+            self.frame_max,
+        );
+
+        if !no_ack {
+            deliveries.track(delivery_tag, queue.clone(), message, None);
+        }
+
+        frames
+    }
+
+    /// Settles the delivery identified by `delivery_tag`, or every delivery up to it if `multiple`
+    /// is set, `0` standing for every unacknowledged delivery. The settled messages are returned,
+    /// grouped by queue, or `None` if the tag does not identify an unacknowledged delivery.
+    pub fn settle(
+        &self,
+        delivery_tag: u64,
+        multiple: bool,
+    ) -> Option<Vec<(Arc<Queue>, Vec<Message>)>> {
+        let settled = self
+            .deliveries
+            .lock()
+            .unwrap()
+            .settle(delivery_tag, multiple)?;
+
+        Some(group(settled))
+    }
+
+    /// Releases the resources of the channel once it's closed: its consumers are stopped and the
+    /// messages they left unacknowledged are put back in their queues, which are pushed to the
+    /// consumers left, in case a standby one takes over.
+    pub fn release(&mut self, broker: &Broker) {
+        let queues = self.consumed_queues();
+        for (_, (queue, consumer)) in self.consumers.drain() {
+            queue.remove_consumer(&consumer);
+        }
+
+        let unacked = {
+            let mut deliveries = self.deliveries.lock().unwrap();
+
+            deliveries.consumed = 0;
+            deliveries.consumed_by.clear();
+            std::mem::take(&mut deliveries.unacked)
+        };
+
+        for (queue, messages) in group(unacked.into_values().collect()) {
+            broker.reject(&queue, messages, true);
+        }
+        for queue in queues {
+            broker.dispatch(&queue);
+        }
+    }
+}
+
+/// A message published by the client, whose content is not received yet.
+pub struct Publication {
+    pub exchange: String,
+    pub routing_key: String,
+    pub mandatory: bool,
+    pub header: Option<ContentHeader>,
+    pub body: Vec<u8>,
+}
+
+impl Publication {
+    pub fn new(exchange: String, routing_key: String, mandatory: bool) -> Self {
+        Self {
+            exchange,
+            routing_key,
+            mandatory,
+            header: None,
+            body: Vec::new(),
+        }
+    }
+
+    /// Returns `true` once the whole body announced by the content header is received.
+    pub fn is_complete(&self) -> bool {
+        match &self.header {
+            Some(header) => self.body.len() as u64 >= header.body_size,
+            None => false,
+        }
+    }
+}
+
+/// The messages delivered on a channel which are not acknowledged yet.
+#[derive(Default)]
+struct Deliveries {
+    /// The tag of the last delivery, the first delivery being tagged `1`.
+    last_tag: u64,
+    prefetch_count: u16,
+    unacked: BTreeMap<u64, Unacked>,
+
+    /// The number of unacknowledged messages delivered to the consumers, which the prefetch
+    /// limit applies to, unlike the messages got by the client.
+    consumed: usize,
+
+    /// The number of unacknowledged messages delivered to each consumer, by consumer tag, which
+    /// the prefetch count of their queue applies to.
+    consumed_by: HashMap<String, usize>,
+}
+
+impl Deliveries {
+    fn next_tag(&mut self) -> u64 {
+        self.last_tag += 1;
+        self.last_tag
+    }
+
+    /// Returns `true` if the consumer using `tag` can't be delivered another message of `queue`,
+    /// as the channel or the consumer has too many messages left unacknowledged.
+    fn is_full(&self, tag: &str, queue: &Queue) -> bool {
+        if self.prefetch_count != 0 && self.consumed >= self.prefetch_count as usize {
+            return true;
+        }
+
+        queue
+            .definition()
+            .prefetch_count
+            .is_some_and(|prefetch_count| {
+                self.consumed_by.get(tag).copied().unwrap_or_default() >= prefetch_count as usize
+            })
+    }
+
+    /// Keeps `message` until it's acknowledged, along with the consumer it was delivered to, or
+    /// `None` if it was got by the client.
+    fn track(
+        &mut self,
+        delivery_tag: u64,
+        queue: Arc<Queue>,
+        message: Message,
+        consumer: Option<String>,
+    ) {
+        if let Some(tag) = &consumer {
+            self.consumed += 1;
+            *self.consumed_by.entry(tag.clone()).or_default() += 1;
+        }
+
+        self.unacked.insert(
+            delivery_tag,
+            Unacked {
+                queue,
+                message,
+                consumer,
+                delivered_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Removes the unacknowledged deliveries settled by the client, as [`Channel::settle`] does.
+    fn settle(&mut self, delivery_tag: u64, multiple: bool) -> Option<Vec<Unacked>> {
+        let settled = if multiple {
+            let kept = match delivery_tag.checked_add(1) {
+                Some(next_tag) if delivery_tag != 0 => self.unacked.split_off(&next_tag),
+                // The tag `0` stands for every delivery, and no tag follows `u64::MAX`.
+                _ => BTreeMap::new(),
+            };
+            let settled = std::mem::replace(&mut self.unacked, kept);
+
+            if settled.is_empty() && delivery_tag != 0 {
+                return None;
+            }
+            settled.into_values().collect()
+        } else {
+            vec![self.unacked.remove(&delivery_tag)?]
+        };
+
+        for unacked in &settled {
+            self.untrack(unacked);
+        }
+
+        Some(settled)
+    }
+
+    /// Stops counting a message which is not unacknowledged anymore in the prefetch limits.
+    fn untrack(&mut self, unacked: &Unacked) {
+        if let Some(tag) = &unacked.consumer {
+            self.consumed -= 1;
+            if let Some(consumed) = self.consumed_by.get_mut(tag) {
+                *consumed -= 1;
+                if *consumed == 0 {
+                    self.consumed_by.remove(tag);
+                }
+            }
+        }
+    }
+}
+
+struct Unacked {
+    queue: Arc<Queue>,
+    message: Message,
+    delivered_at: Instant,
+
+    /// The tag of the consumer the message was delivered to, or `None` if it was got by the
+    /// client.
+    consumer: Option<String>,
+}
+
+/// Groups messages by the queue they come from, keeping their order.
+fn group(unacked: Vec<Unacked>) -> Vec<(Arc<Queue>, Vec<Message>)> {
+    let mut groups: Vec<(Arc<Queue>, Vec<Message>)> = Vec::new();
+
+    for Unacked { queue, message, .. } in unacked {
+        match groups.iter_mut().find(|(q, _)| Arc::ptr_eq(q, &queue)) {
+            Some((_, messages)) => messages.push(message),
+            None => groups.push((queue, vec![message])),
+        }
+    }
+
+    groups
+}
+
+/// A consumer started by the client on a channel.
+struct ChannelConsumer {
+    channel: u16,
+    tag: String,
+    no_ack: bool,
+    selector: Option<Selector>,
+    outbox: Outbox,
+    frame_max: u32,
+    deliveries: Arc<Mutex<Deliveries>>,
+}
+
+impl Consumer for ChannelConsumer {
+    fn deliver(&self, queue: &Arc<Queue>, message: Message) -> Option<Message> {
+        let mut deliveries = self.deliveries.lock().unwrap();
+
+        if !self.no_ack && deliveries.is_full(&self.tag, queue) {
+            return Some(message);
+        }
+
+        let delivery_tag = deliveries.next_tag();
+        let frames = Frame::content(
+            self.channel,
+            Method::BasicDeliver {
+                consumer_tag: self.tag.clone(),
+                delivery_tag,
+                redelivered: message.redelivered,
+                exchange: message.exchange.clone(),
+                routing_key: message.routing_key.clone(),
+            },
+            message.properties.clone(),
+            &message.body,
+            self.frame_max,
+        );
+
+        if self.outbox.send(frames).is_err() {
+            return Some(message);
+        }
+        if !self.no_ack {
+            deliveries.track(delivery_tag, queue.clone(), message, Some(self.tag.clone()));
+        }
+
+        None
+    }
+
+    fn requires_ack(&self) -> bool {
+        !self.no_ack
+    }
+
+    fn unacked(&self, queue: &Arc<Queue>) -> (usize, Option<Instant>) {
+        let deliveries = self.deliveries.lock().unwrap();
+        let unacked = deliveries.unacked.values().filter(|unacked| {
+            unacked.consumer.as_ref() == Some(&self.tag) && Arc::ptr_eq(&unacked.queue, queue)
+        });
+
+        unacked.fold((0, None), |(count, oldest), unacked| {
+            let enqueued_at = unacked.message.enqueued_at();
+            (
+                count + 1,
+                Some(oldest.map_or(enqueued_at, |oldest: Instant| oldest.min(enqueued_at))),
+            )
+        })
+    }
+
+    fn take_unacked(&self, queue: &Arc<Queue>, deadline: Instant) -> Vec<Message> {
+        let mut deliveries = self.deliveries.lock().unwrap();
+        let tags: Vec<u64> = deliveries
+            .unacked
+            .iter()
+            .filter(|(_, unacked)| {
+                Arc::ptr_eq(&unacked.queue, queue) && unacked.delivered_at <= deadline
+            })
+            .map(|(&tag, _)| tag)
+            .collect();
+
+        let mut messages = Vec::new();
+        for tag in tags {
+            let unacked = deliveries.unacked.remove(&tag).unwrap();
+
+            deliveries.untrack(&unacked);
+            messages.push(unacked.message);
+        }
+
+        messages
+    }
+
+    fn selector(&self) -> Option<&Selector> {
+        self.selector.as_ref()
+    }
+
+    fn cancel(&self) {
+        let cancel = Method::BasicCancel {
+            consumer_tag: self.tag.clone(),
+            no_wait: true,
+        };
+
+        let _ = self.outbox.send(vec![Frame::Method(self.channel, cancel)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::QueueDefinition, metrics::Registry, protocol::Properties};
+
+    /// Returns the deliveries of a channel, tagged from 1 to `count` and unacknowledged.
+    fn deliveries(count: u64) -> Deliveries {
+        let queue = Arc::new(Queue::new(
+            "/".into(),
+            QueueDefinition::new("tasks"),
+            Registry::new(Vec::new()).queue_counters(),
+        ));
+        let mut deliveries = Deliveries::default();
+
+        for _ in 0..count {
+            let tag = deliveries.next_tag();
+            let message = Message::new(
+                String::new(),
+                "tasks".into(),
+                Properties::default(),
+                tag.to_string().into_bytes(),
+            );
+            deliveries.track(tag, queue.clone(), message, Some("consumer".into()));
+        }
+
+        deliveries
+    }
+
+    fn tags(deliveries: &Deliveries) -> Vec<u64> {
+        deliveries.unacked.keys().copied().collect()
+    }
+
+    #[test]
+    fn settle_multiple_removes_the_deliveries_up_to_the_tag() {
+        let mut deliveries = deliveries(5);
+
+        let settled = deliveries.settle(3, true).unwrap();
+
+        let bodies: Vec<_> = settled.iter().map(|u| u.message.body.clone()).collect();
+        assert_eq!(bodies, vec![b"1".to_vec(), b"2".to_vec(), b"3".to_vec()]);
+        assert_eq!(tags(&deliveries), vec![4, 5]);
+        assert_eq!(deliveries.consumed, 2);
+        assert_eq!(deliveries.consumed_by["consumer"], 2);
+    }
+
+    #[test]
+    fn settle_multiple_with_the_last_tag_removes_every_delivery() {
+        let mut deliveries = deliveries(3);
+
+        assert_eq!(deliveries.settle(u64::MAX, true).unwrap().len(), 3);
+        assert!(deliveries.unacked.is_empty());
+        assert_eq!(deliveries.consumed, 0);
+        assert!(deliveries.consumed_by.is_empty());
+    }
+
+    #[test]
+    fn settle_multiple_with_tag_0_removes_every_delivery() {
+        let mut deliveries = deliveries(3);
+
+        assert_eq!(deliveries.settle(0, true).unwrap().len(), 3);
+        assert!(deliveries.unacked.is_empty());
+        assert_eq!(deliveries.settle(0, true).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn settle_multiple_below_every_delivery_is_unknown() {
+        let mut deliveries = deliveries(3);
+        deliveries.settle(2, true).unwrap();
+
+        assert!(deliveries.settle(1, true).is_none());
+        assert_eq!(tags(&deliveries), vec![3]);
+    }
+
+    #[test]
+    fn settle_single_removes_the_delivery_only() {
+        let mut deliveries = deliveries(3);
+
+        assert_eq!(deliveries.settle(2, false).unwrap().len(), 1);
+        assert_eq!(tags(&deliveries), vec![1, 3]);
+        assert!(deliveries.settle(2, false).is_none());
+        assert!(deliveries.settle(u64::MAX, false).is_none());
+    }
+}
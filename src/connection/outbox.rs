@@ -0,0 +1,91 @@
+use crate::{
+    protocol::{self, Frame},
+    stream::{Peer, Stream},
+};
+use std::{
+    io::{self, BufWriter, Write},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The frames waiting to be sent to a client, written by a dedicated thread so that the queues
+/// delivering messages to the client never wait for its socket.
+///
+/// The frames passed at once are written contiguously, so the frames of a content are never
+/// interleaved with the frames of another method.
+#[derive(Clone)]
+pub struct Outbox {
+    sender: Sender<Vec<Frame>>,
+    /// The interval of the heartbeats sent to the client, once negotiated.
+    heartbeat: Arc<Mutex<Option<Duration>>>,
+}
+
+impl Outbox {
+    /// Spawns the thread writing the frames into `stream`. The thread stops once every handle of
+    /// the outbox is dropped and the pending frames are written.
+    pub fn spawn(stream: Stream, peer: Peer) -> io::Result<(Self, JoinHandle<()>)> {
+        let (sender, receiver) = mpsc::channel();
+        let heartbeat = Arc::new(Mutex::new(None));
+        let interval = heartbeat.clone();
+        let handle = thread::Builder::new()
+            .name(format!("writer-{}", peer))
+            .spawn(move || {
+                if let Err(err) = write(BufWriter::new(stream), receiver, &interval) {
+                    log::debug!("Could not write to the connection from {}: {}", peer, err);
+                }
+            })?;
+
+        Ok((Self { sender, heartbeat }, handle))
+    }
+
+    /// Makes the writer send a heartbeat to the client whenever nothing else was written for
+    /// `interval`. It takes effect once the frames sent next are written.
+    pub fn set_heartbeat(&self, interval: Duration) {
+        *self.heartbeat.lock().unwrap() = Some(interval);
+    }
+
+    /// Sends frames to the client. It fails once the stream of the connection failed.
+    pub fn send(&self, frames: Vec<Frame>) -> Result<(), protocol::Error> {
+        self.sender.send(frames).map_err(|_| {
+            protocol::Error::Io(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the connection is not writable anymore",
+            ))
+        })
+    }
+}
+
+/// Writes the frames received from the outbox, flushing the stream whenever no more frames are
+/// waiting. A heartbeat is written whenever no frame was received for the interval of the
+/// heartbeats, if any.
+fn write(
+    mut writer: BufWriter<Stream>,
+    receiver: Receiver<Vec<Frame>>,
+    heartbeat: &Mutex<Option<Duration>>,
+) -> Result<(), protocol::Error> {
+    loop {
+        let received = match *heartbeat.lock().unwrap() {
+            Some(interval) => match receiver.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => Ok(vec![Frame::Heartbeat]),
+                received => received.map_err(|_| ()),
+            },
+            None => receiver.recv().map_err(|_| ()),
+        };
+        let frames = match received {
+            Ok(frames) => frames,
+            Err(()) => break,
+        };
+
+        for frame in frames.into_iter().chain(receiver.try_iter().flatten()) {
+            frame.write(&mut writer)?;
+        }
+
+        writer.flush()?;
+    }
+
+    Ok(())
+}
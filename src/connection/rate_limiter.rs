@@ -0,0 +1,196 @@
+use crate::config::RateLimit;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The rate limit of the publications of a connection, each of its limits being enforced by a
+/// token bucket.
+pub struct RateLimiter {
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    disconnect_on_exceed: bool,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimit) -> Self {
+        let now = Instant::now();
+
+        Self {
+            messages: config
+                .messages_per_second
+                .map(|rate| TokenBucket::new(rate as f64, now)),
+            bytes: config
+                .bytes_per_second
+                .map(|rate| TokenBucket::new(rate as f64, now)),
+            disconnect_on_exceed: config.disconnect_on_exceed,
+        }
+    }
+
+    /// Takes the tokens of a message whose body is `size` bytes long, returning how long the
+    /// connection must wait before reading its next frame to get back under its limits.
+    ///
+    /// When the connection must be closed once it exceeds its limits, `None` is returned instead
+    /// of waiting, and no token is taken.
+    pub fn acquire(&mut self, size: u64) -> Option<Duration> {
+        let now = Instant::now();
+        let mut buckets = [(&mut self.messages, 1.0), (&mut self.bytes, size as f64)];
+
+        for (bucket, amount) in &mut buckets {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                if self.disconnect_on_exceed && bucket.tokens < *amount {
+                    return None;
+                }
+            }
+        }
+
+        let mut wait = Duration::ZERO;
+        for (bucket, amount) in &mut buckets {
+            if let Some(bucket) = bucket {
+                wait = wait.max(bucket.take(*amount));
+            }
+        }
+
+        Some(wait)
+    }
+}
+
+/// The rate limit of the messages published by every connection of a listener, all together,
+/// enforced by a token bucket shared by the connections.
+pub struct ListenerRateLimiter {
+    messages: Mutex<TokenBucket>,
+}
+
+impl ListenerRateLimiter {
+    pub fn new(messages_per_second: u32) -> Self {
+        Self {
+            messages: Mutex::new(TokenBucket::new(messages_per_second as f64, Instant::now())),
+        }
+    }
+
+    /// Takes the token of a message, returning how long the connection must wait before reading
+    /// its next frame to get the listener back under its limit.
+    pub fn acquire(&self) -> Duration {
+        let mut messages = self.messages.lock().unwrap();
+
+        messages.refill(Instant::now());
+        messages.take(1.0)
+    }
+}
+
+/// A bucket holding up to a second of tokens, refilled at a constant rate.
+struct TokenBucket {
+    /// The tokens added each second, which is also the capacity of the bucket.
+    rate: f64,
+    /// The tokens left, which is negative once more tokens were taken than the bucket held.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+    }
+
+    /// Takes `amount` tokens, returning how long it takes for the bucket to be refilled back to
+    /// no token if it holds less than that.
+    fn take(&mut self, amount: f64) -> Duration {
+        self.tokens -= amount;
+
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limit(
+        messages_per_second: Option<u32>,
+        bytes_per_second: Option<u64>,
+        disconnect_on_exceed: bool,
+    ) -> RateLimit {
+        RateLimit {
+            messages_per_second,
+            bytes_per_second,
+            disconnect_on_exceed,
+        }
+    }
+
+    #[test]
+    fn burst_is_throttled_past_the_messages_per_second() {
+        let mut limiter = RateLimiter::new(&rate_limit(Some(10), None, false));
+
+        for _ in 0..10 {
+            assert_eq!(limiter.acquire(1 << 20), Some(Duration::ZERO));
+        }
+        let wait = limiter.acquire(0).unwrap();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100));
+        let wait = limiter.acquire(0).unwrap();
+        assert!(wait > Duration::from_millis(100) && wait <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn burst_is_throttled_past_the_bytes_per_second() {
+        let mut limiter = RateLimiter::new(&rate_limit(None, Some(1000), false));
+
+        assert_eq!(limiter.acquire(600), Some(Duration::ZERO));
+        let wait = limiter.acquire(600).unwrap();
+        assert!(wait > Duration::from_millis(100) && wait <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let mut limiter = RateLimiter::new(&rate_limit(Some(100), None, false));
+
+        for _ in 0..100 {
+            limiter.acquire(0);
+        }
+        assert!(limiter.acquire(0).unwrap() > Duration::ZERO);
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(limiter.acquire(0), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn burst_disconnects_when_asked() {
+        let mut limiter = RateLimiter::new(&rate_limit(Some(2), Some(1000), true));
+
+        assert_eq!(limiter.acquire(10), Some(Duration::ZERO));
+        assert_eq!(limiter.acquire(10), Some(Duration::ZERO));
+        assert_eq!(limiter.acquire(10), None);
+
+        let mut limiter = RateLimiter::new(&rate_limit(Some(2), Some(1000), true));
+        assert_eq!(limiter.acquire(2000), None);
+        // No token is taken by the message exceeding the limits.
+        assert_eq!(limiter.acquire(10), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn listener_burst_is_throttled() {
+        let limiter = ListenerRateLimiter::new(5);
+
+        for _ in 0..5 {
+            assert_eq!(limiter.acquire(), Duration::ZERO);
+        }
+        assert!(limiter.acquire() > Duration::ZERO);
+    }
+}
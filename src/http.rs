@@ -0,0 +1,339 @@
+//! The minimal HTTP server of the metrics and of the admin API, which answers one request per
+//! connection. The bodies of the requests are never read.
+//!
+//! It also holds the minimal client posting the alerts of the queues to their webhooks.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+/// How long the HTTP listener waits for a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum size of a request, headers included.
+const REQUEST_MAX_BYTES: u64 = 8 * 1024;
+
+/// How long the client waits for a server to accept its connection, to read its request, and to
+/// answer it.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request of a client.
+pub struct Request {
+    /// The method of the request, such as `GET`.
+    pub method: String,
+    /// The path of the request, without its query. It's not percent-decoded, so its segments can
+    /// be told apart before they're decoded with [`decode`].
+    pub path: String,
+    /// The parameters of the query, percent-decoded.
+    pub query: Vec<(String, String)>,
+    /// The username and the password of the `Authorization` header, if it uses the Basic scheme.
+    pub credentials: Option<(String, String)>,
+}
+
+impl Request {
+    /// Returns the value of the parameter of the query named `name`, if it's given.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The status, the content type and the body answering a request.
+pub struct Response {
+    /// The status line of the response, such as `200 OK`.
+    pub status: &'static str,
+    pub content_type: &'static str,
+    /// The headers of the response, other than the ones of its content and `Connection`.
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+/// Serves the requests of the clients of `listener` from a thread named `name`. Each request is
+/// answered by `handler`, or by `404 Not Found` if it returns `None`.
+pub fn spawn<F>(name: &str, listener: TcpListener, handler: F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Option<Response> + Send + 'static,
+{
+    let served = name.to_string();
+
+    thread::Builder::new().name(name.into()).spawn(move || {
+        for socket in listener.incoming() {
+            let result = socket.and_then(|socket| respond(socket, &handler));
+
+            if let Err(err) = result {
+                log::debug!("Could not serve the {}: {}", served, err);
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Answers the request of a client, the connection being closed once the response is written.
+fn respond<F>(socket: TcpStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Option<Response>,
+{
+    socket.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new((&socket).take(REQUEST_MAX_BYTES));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Only the `Authorization` header is used.
+    let mut credentials = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization") {
+                credentials = basic_credentials(value.trim());
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => {
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let request = Request {
+                method: method.into(),
+                path: path.into(),
+                query: query
+                    .split('&')
+                    .filter(|param| !param.is_empty())
+                    .map(|param| {
+                        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                        (decode(key), decode(value))
+                    })
+                    .collect(),
+                credentials,
+            };
+
+            handler(&request).unwrap_or_else(|| plain("404 Not Found", "Not Found\n"))
+        }
+        _ => plain("400 Bad Request", "Bad Request\n"),
+    };
+
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    let mut socket = &socket;
+    write!(socket, "{}Connection: close\r\n\r\n{}", head, response.body)?;
+    socket.flush()
+}
+
+/// Returns a response of the `text/plain` content type.
+pub fn plain(status: &'static str, body: &str) -> Response {
+    Response {
+        status,
+        content_type: "text/plain",
+        headers: Vec::new(),
+        body: body.into(),
+    }
+}
+
+/// Returns the response to a request whose method is not served on its path.
+pub fn method_not_allowed() -> Response {
+    plain("405 Method Not Allowed", "Method Not Allowed\n")
+}
+
+/// Decodes the percent-encoded bytes of a segment of a path or of a parameter of a query, a `+`
+/// standing for a space. The bytes which are not valid UTF-8 are replaced by `U+FFFD`.
+pub fn decode(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the username and the password of the value of an `Authorization` header, if it uses
+/// the Basic scheme.
+fn basic_credentials(value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = String::from_utf8(decode_base64(encoded.trim())?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+
+    Some((username.into(), password.into()))
+}
+
+/// Decodes the standard base64 encoding, padding included, returning `None` if `encoded` is not
+/// valid base64.
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=');
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+
+    for c in encoded.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(decoded)
+}
+
+/// A URL of the `http` scheme, such as `http://example.com:8080/hooks?key=value`.
+pub struct Url {
+    pub host: String,
+    /// The port of the URL, or 80 when it has none.
+    pub port: u16,
+    /// The path of the URL, starting with a `/`, along with its query.
+    pub path: String,
+}
+
+impl Url {
+    /// Parses `url`, whose host is either a name, an IPv4 address, or an IPv6 address between
+    /// brackets.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if url.starts_with("https://") => {
+                return Err("the https scheme is not supported, the URL must be an http one".into())
+            }
+            None => return Err(format!("{} is not an http URL", url)),
+        };
+        if url.contains(char::is_whitespace) {
+            return Err(format!("the URL {} contains whitespace", url));
+        }
+
+        // The fragment is never sent to the server.
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        if authority.contains('@') {
+            return Err("the URL must not hold credentials".into());
+        }
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(ipv6) => match ipv6.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').unwrap_or(port))),
+                None => return Err(format!("the host of the URL {} is malformed", url)),
+            },
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("the URL {} has no host", url));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("the port {} of the URL is invalid", port))?,
+            None => 80,
+        };
+
+        Ok(Self {
+            host: host.into(),
+            port,
+            path,
+        })
+    }
+}
+
+/// Posts `body`, a JSON document, to `url`, returning an error unless the server answers with a
+/// `2xx` status.
+pub fn post(url: &Url, body: &str) -> io::Result<()> {
+    let address = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("the host {} has no address", url.host),
+            )
+        })?;
+    let socket = TcpStream::connect_timeout(&address, CLIENT_TIMEOUT)?;
+    socket.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    socket.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let host = if url.host.contains(':') {
+        format!("[{}]:{}", url.host, url.port)
+    } else {
+        format!("{}:{}", url.host, url.port)
+    };
+    let mut writer = &socket;
+    write!(
+        writer,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        url.path,
+        host,
+        body.len(),
+        body
+    )?;
+    writer.flush()?;
+
+    // Only the status line of the response is read.
+    let mut status_line = String::new();
+    BufReader::new((&socket).take(REQUEST_MAX_BYTES)).read_line(&mut status_line)?;
+
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        Some(status) => Err(io::Error::other(format!(
+            "the server answered with the status {}",
+            status
+        ))),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the server sent no status",
+        )),
+    }
+}
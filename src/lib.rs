@@ -0,0 +1,23 @@
+//! The another-mq message broker, which the `another-mq` binary runs. The configuration can be
+//! built in code with [`config::builder::ConfigBuilder`] and layered with [`config::Config::merge`]
+//! by the applications embedding the broker.
+
+pub mod admin;
+mod auth;
+pub mod broker;
+pub mod config;
+mod connection;
+mod http;
+pub mod logging;
+pub mod metrics;
+mod protocol;
+mod proxy;
+pub mod server;
+pub mod shutdown;
+#[cfg(unix)]
+pub mod signal;
+mod stream;
+mod text;
+mod timestamp;
+#[cfg(feature = "tls")]
+mod tls;
@@ -0,0 +1,1334 @@
+//! The application log, writing the entries to the sinks configured by the log namespace.
+//!
+//! The logger is installed before the configuration is loaded and writes to the standard output
+//! until it is configured.
+//!
+//! When the log namespace defines a buffer, the entries are formatted as they're logged, but
+//! they're only written to the sinks by the `log-flush` thread every flush interval, or once the
+//! buffer is full. The errors are written right away, as the application may exit right after
+//! them, and [`log::Log::flush`] writes the entries left in the buffer before it stops.
+
+use crate::{
+    config::{
+        self, LogFormat, Rotation, SyslogProtocol, SyslogTransport, SYSLOG_DEFAULT_PORT,
+        SYSLOG_DEFAULT_RECONNECT_INTERVAL,
+    },
+    timestamp,
+};
+use log::{Level, LevelFilter, Metadata, Record};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    process,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use syslog::{Facility, Severity};
+
+/// The name of the process in the syslog entries, unless the configuration defines another one.
+const SYSLOG_DEFAULT_PROCESS: &str = "another-mq";
+
+/// The identifier of the structured data element of the RFC 5424 entries. The project has no
+/// private enterprise number, so the one reserved for documentation by RFC 5612 is used.
+const SYSLOG_SD_ID: &str = "another-mq@32473";
+
+/// The abbreviated names of the months in the timestamps of the RFC 3164 entries.
+const RFC3164_MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The timeout of the connection to a syslog server over TCP, and of the writes on it.
+const SYSLOG_TCP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The maximum size of the messages buffered while the connection to a syslog server over TCP is
+/// down.
+const SYSLOG_TCP_BUFFER_MAX_BYTES: usize = 1024 * 1024;
+
+static LOGGER: Logger = Logger {
+    state: Mutex::new(State {
+        level: LevelFilter::Info,
+        targets: Vec::new(),
+        format: LogFormat::Text,
+        timestamp_format: None,
+        utc: true,
+        sinks: Vec::new(),
+        buffer: None,
+        flush_interval: Duration::from_secs(1),
+        buffered: Vec::new(),
+    }),
+};
+
+/// Installs the logger of the application, writing text entries of level `Info` and above to
+/// the standard output.
+pub fn init() {
+    LOGGER.state.lock().unwrap().sinks.push(Sink::Stdout);
+    log::set_logger(&LOGGER).expect("The logger is installed once");
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Applies the log namespace of the configuration to the logger.
+///
+/// The entries are written to the standard output unless the namespace disables it, and they are
+/// also appended to the logfile and sent to the syslog server if they're configured and enabled.
+/// The logfile is rotated according to the `rotation` of the namespace.
+///
+/// The sinks already configured the same way are kept as they are, so reloading the namespace
+/// only opens the sinks it enables and closes the ones it disables. The other sinks keep writing
+/// the entries meanwhile. If a sink can't be opened, an error is returned and the logger is left
+/// untouched.
+///
+/// The entries of the targets of the namespace are filtered by their own level, the others by
+/// the level of the namespace. A target applies to its module and to the modules nested in it,
+/// while a target ending in `::*` only applies to the nested modules. An entry is filtered by the
+/// target naming its module exactly, or else by the most specific target its module is nested
+/// in, a glob winning over the target naming the same module without it.
+///
+/// The entries buffered so far are written before the sinks are replaced.
+pub fn configure(config: &config::Log) -> io::Result<()> {
+    // The new sinks are opened without holding the logger, so the entries logged meanwhile are
+    // still written to the current ones.
+    let kept: Vec<SinkKind> = {
+        let state = LOGGER.state.lock().unwrap_or_else(|err| err.into_inner());
+        state
+            .sinks
+            .iter()
+            .filter(|sink| sink.is_configured(config))
+            .map(Sink::kind)
+            .collect()
+    };
+    let opened = Sink::open_all(config, &kept)?;
+
+    let targets = target_levels(config);
+    let level = config.level.to_level_filter();
+    let max_level = targets
+        .iter()
+        .map(|target| target.level)
+        .fold(level, Ord::max);
+
+    let mut state = LOGGER.state.lock().unwrap_or_else(|err| err.into_inner());
+    // The buffered entries are formatted for the current sinks.
+    state.write_buffered();
+    state.buffer = config.async_buffer;
+    state.flush_interval = config.effective_flush_interval();
+    state.level = level;
+    state.targets = targets;
+    state.format = config.format;
+    state.timestamp_format = config.timestamp_format.clone();
+    state.utc = config.utc;
+    state.sinks.retain(|sink| sink.is_configured(config));
+    state.sinks.extend(opened);
+    for sink in &mut state.sinks {
+        if let Sink::File(file) = sink {
+            file.rotation = config.rotation.clone();
+        }
+    }
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+struct Logger {
+    state: Mutex<State>,
+}
+
+/// Returns the levels of the targets of the log namespace, the most specific ones first, so
+/// they're matched before the modules holding them.
+fn target_levels(config: &config::Log) -> Vec<TargetLevel> {
+    let mut targets: Vec<_> = config
+        .targets
+        .iter()
+        .map(|(target, level)| {
+            let (module, glob) = match target.strip_suffix("::*") {
+                Some(module) => (module, true),
+                None => (target.as_str(), false),
+            };
+
+            TargetLevel {
+                module: module.into(),
+                glob,
+                level: level.to_level_filter(),
+            }
+        })
+        .collect();
+    targets.sort_by(|a, b| {
+        b.module
+            .len()
+            .cmp(&a.module.len())
+            .then_with(|| b.glob.cmp(&a.glob))
+            .then_with(|| a.module.cmp(&b.module))
+    });
+
+    targets
+}
+
+/// The level of the entries of a target of the log namespace.
+struct TargetLevel {
+    /// The module named by the target, without its glob.
+    module: String,
+    /// Whether the target ends in `::*`, so it only applies to the modules nested in `module`.
+    glob: bool,
+    level: LevelFilter,
+}
+
+struct State {
+    level: LevelFilter,
+    /// The levels of the targets overriding `level`, the most specific ones first.
+    targets: Vec<TargetLevel>,
+    format: LogFormat,
+    /// The format of the timestamps, or `None` for the RFC 3339 one.
+    timestamp_format: Option<String>,
+    utc: bool,
+    sinks: Vec<Sink>,
+    /// The number of entries buffered before they're written, or `None` if they're written as
+    /// they're logged.
+    buffer: Option<usize>,
+    flush_interval: Duration,
+    /// The entries which are not written yet, along with their level, formatted for each sink
+    /// in turn.
+    buffered: Vec<(Level, Vec<String>)>,
+}
+
+impl State {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+        let level = self
+            .targets
+            .iter()
+            .find(|level| {
+                target
+                    .strip_prefix(level.module.as_str())
+                    .is_some_and(|rest| (rest.is_empty() && !level.glob) || rest.starts_with("::"))
+            })
+            .map_or(self.level, |level| level.level);
+
+        metadata.level() <= level
+    }
+
+    /// Returns the timestamp of an entry logged now.
+    fn timestamp(&self) -> String {
+        let format = match &self.timestamp_format {
+            Some(format) => format,
+            None if self.utc => timestamp::RFC3339_UTC,
+            None => timestamp::RFC3339_LOCAL,
+        };
+
+        // The format is checked when the configuration is validated.
+        timestamp::format(format, SystemTime::now(), self.utc).unwrap_or_default()
+    }
+
+    /// Writes the buffered entries to the sinks, then flushes them.
+    fn write_buffered(&mut self) {
+        for (level, entries) in self.buffered.drain(..) {
+            for (sink, entry) in self.sinks.iter_mut().zip(&entries) {
+                // There is nowhere to report a failure of the log itself.
+                let _ = sink.write(level, entry);
+            }
+        }
+
+        for sink in &mut self.sinks {
+            let _ = sink.flush();
+        }
+    }
+}
+
+/// Starts the `log-flush` thread, writing the buffered entries every flush interval. It has
+/// nothing to write while the log namespace defines no buffer.
+pub fn start_flusher() -> io::Result<()> {
+    thread::Builder::new()
+        .name("log-flush".into())
+        .spawn(|| loop {
+            let interval = LOGGER
+                .state
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .flush_interval;
+
+            thread::sleep(interval);
+            LOGGER
+                .state
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .write_buffered();
+        })?;
+
+    Ok(())
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        if !state.enabled(record.metadata()) {
+            return;
+        }
+
+        let format = state.format;
+        let timestamp = state.timestamp();
+        let buffer = match state.buffer {
+            Some(buffer) => buffer,
+            None => {
+                for sink in &mut state.sinks {
+                    let entry = format_entry(format, &timestamp, sink, record);
+
+                    // There is nowhere to report a failure of the log itself.
+                    let _ = sink.write(record.level(), &entry);
+                }
+                return;
+            }
+        };
+
+        let entries = state
+            .sinks
+            .iter()
+            .map(|sink| format_entry(format, &timestamp, sink, record))
+            .collect();
+        state.buffered.push((record.level(), entries));
+        if state.buffered.len() >= buffer || record.level() == Level::Error {
+            state.write_buffered();
+        }
+    }
+
+    fn flush(&self) {
+        self.state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .write_buffered();
+    }
+}
+
+/// The destination of the log entries.
+enum Sink {
+    Stdout,
+    File(Logfile),
+    Syslog(Box<Syslog>),
+}
+
+/// The kinds of sinks, of which the log namespace configures one at most.
+#[derive(Copy, Clone, PartialEq)]
+enum SinkKind {
+    Stdout,
+    File,
+    Syslog,
+}
+
+impl Sink {
+    /// Opens every sink enabled by the log namespace, except the ones of the `kept` kinds.
+    fn open_all(config: &config::Log, kept: &[SinkKind]) -> io::Result<Vec<Self>> {
+        let mut sinks = Vec::new();
+
+        if config.stdout && !kept.contains(&SinkKind::Stdout) {
+            sinks.push(Self::Stdout);
+        }
+        match &config.file {
+            Some(path) if config.file_enabled && !kept.contains(&SinkKind::File) => {
+                sinks.push(Logfile::open(path, config.rotation.clone()).map(Self::File)?)
+            }
+            _ => {}
+        }
+        match &config.syslog {
+            Some(syslog) if syslog.enabled && !kept.contains(&SinkKind::Syslog) => {
+                sinks.push(Self::Syslog(Box::new(Syslog::connect(syslog))))
+            }
+            _ => {}
+        }
+
+        Ok(sinks)
+    }
+
+    fn kind(&self) -> SinkKind {
+        match self {
+            Self::Stdout => SinkKind::Stdout,
+            Self::File(_) => SinkKind::File,
+            Self::Syslog(_) => SinkKind::Syslog,
+        }
+    }
+
+    /// Returns `true` if the sink is enabled by the log namespace with the same configuration,
+    /// so it can be kept open. The rotation of the logfile is applied to an open logfile as well.
+    fn is_configured(&self, config: &config::Log) -> bool {
+        match self {
+            Self::Stdout => config.stdout,
+            Self::File(file) => config.file_enabled && config.file.as_ref() == Some(&file.path),
+            Self::Syslog(syslog) => config
+                .syslog
+                .as_ref()
+                .is_some_and(|config| config.enabled && *config == syslog.config),
+        }
+    }
+
+    fn write(&mut self, level: Level, entry: &str) -> io::Result<()> {
+        match self {
+            Self::Stdout => writeln!(io::stdout().lock(), "{}", entry),
+            Self::File(file) => file.write(entry),
+            Self::Syslog(syslog) => syslog.write(level, entry),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout => io::stdout().flush(),
+            Self::File(file) => file.file.flush(),
+            Self::Syslog(_) => Ok(()),
+        }
+    }
+}
+
+/// The logfile of the application, rotated once it exceeds the configured size.
+struct Logfile {
+    path: String,
+    file: File,
+    size: u64,
+    rotation: Option<Rotation>,
+}
+
+impl Logfile {
+    fn open(path: &str, rotation: Option<Rotation>) -> io::Result<Self> {
+        let file = open_logfile(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_string(),
+            file,
+            size,
+            rotation,
+        })
+    }
+
+    fn write(&mut self, entry: &str) -> io::Result<()> {
+        writeln!(self.file, "{}", entry)?;
+        self.size += entry.len() as u64 + 1;
+
+        match &self.rotation {
+            Some(rotation) if self.size > rotation.max_size_bytes => self.rotate(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Shifts the rotated files, deleting the ones beyond the number of files to keep, then
+    /// renames the logfile and reopens an empty one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let max_files = self
+            .rotation
+            .as_ref()
+            .map_or(0, |rotation| rotation.max_files);
+
+        // The files beyond the number of files to keep may come from a previous configuration.
+        let mut n = max_files.max(1);
+        while remove_file(&self.rotated_path(n))? {
+            n += 1;
+        }
+
+        for n in (1..max_files).rev() {
+            rename(&self.rotated_path(n), &self.rotated_path(n + 1))?;
+        }
+
+        if max_files > 0 {
+            rename(&self.path, &self.rotated_path(1))?;
+        } else {
+            remove_file(&self.path)?;
+        }
+
+        self.file = open_logfile(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> String {
+        format!("{}.{}", self.path, n)
+    }
+}
+
+fn open_logfile(path: &str) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Could not open the logfile {}: {}", path, err),
+            )
+        })
+}
+
+/// Removes a file, returning whether it existed.
+fn remove_file(path: &str) -> io::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Renames a file, doing nothing if it does not exist.
+fn rename(from: &str, to: &str) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// A connection to a syslog server, or to the local syslog daemon when no host is configured.
+struct Syslog {
+    /// The configuration the connection was made from, so it's kept open while it's unchanged.
+    config: config::Syslog,
+    formatter: SyslogFormatter,
+    /// The transport of the messages, or `None` while the syslog server or the local daemon
+    /// can't be reached over UDP or a Unix socket.
+    transport: Option<Transport>,
+    /// The attempts to connect made since the transport couldn't be opened.
+    attempts: u32,
+    last_attempt: Instant,
+}
+
+/// The formatter of the syslog entries. The entries are laid out as the formatters of the syslog
+/// crate do, which always write the PID.
+struct SyslogFormatter {
+    protocol: SyslogProtocol,
+    facility: Facility,
+    /// The TAG of the RFC 3164 entries, or the APP-NAME of the RFC 5424 entries.
+    app_name: String,
+    pid: Option<u32>,
+    /// The structured data attached to every RFC 5424 entry, already formatted.
+    structured_data: String,
+}
+
+impl Syslog {
+    /// Connects to the syslog server, or to the local daemon. The broker must start even when
+    /// neither can be reached, so a failure is written to the standard error and the connection
+    /// is attempted again every `reconnect_interval`, as when it drops: over TCP the messages are
+    /// buffered meanwhile, otherwise they're written to the standard error.
+    fn connect(config: &config::Syslog) -> Self {
+        let process = if config.process.is_empty() {
+            SYSLOG_DEFAULT_PROCESS.to_string()
+        } else {
+            config.process.clone()
+        };
+        let app_name = match (config.protocol, &config.app_name) {
+            (SyslogProtocol::Rfc5424, Some(app_name)) => app_name.clone(),
+            _ => process,
+        };
+
+        let formatter = SyslogFormatter {
+            protocol: config.protocol,
+            facility: config.facility.into(),
+            app_name,
+            pid: if config.include_pid {
+                Some(process::id())
+            } else {
+                None
+            },
+            structured_data: structured_data(config),
+        };
+        let transport = match Transport::connect(config) {
+            Ok(transport) => Some(transport),
+            Err(err) => {
+                // The entries can't be logged, as the logger is being configured.
+                let _ = writeln!(
+                    io::stderr().lock(),
+                    "Could not connect to the syslog server, retrying every {}: {}",
+                    humantime::format_duration(reconnect_interval(config)),
+                    err
+                );
+
+                match &config.host {
+                    Some(host) if config.transport == SyslogTransport::Tcp => {
+                        let port = config.port.unwrap_or(SYSLOG_DEFAULT_PORT);
+                        Some(Transport::Tcp(TcpTransport::disconnected(
+                            host, port, config,
+                        )))
+                    }
+                    _ => None,
+                }
+            }
+        };
+
+        Self {
+            config: config.clone(),
+            formatter,
+            transport,
+            attempts: 0,
+            last_attempt: Instant::now(),
+        }
+    }
+
+    fn write(&mut self, level: Level, entry: &str) -> io::Result<()> {
+        let severity = match level {
+            Level::Error => Severity::LOG_ERR,
+            Level::Warn => Severity::LOG_WARNING,
+            Level::Info => Severity::LOG_INFO,
+            Level::Debug | Level::Trace => Severity::LOG_DEBUG,
+        };
+        let mut message = self.formatter.format(severity, entry).into_bytes();
+
+        if self.transport.is_none()
+            && self.last_attempt.elapsed() >= reconnect_interval(&self.config)
+        {
+            self.reconnect();
+        }
+        match &mut self.transport {
+            Some(transport) => transport.send(&mut message),
+            None => {
+                message.push(b'\n');
+                io::stderr().lock().write_all(&message)
+            }
+        }
+    }
+
+    /// Attempts to open the transport over UDP or a Unix socket, unless the attempts are
+    /// exhausted.
+    fn reconnect(&mut self) {
+        if self
+            .config
+            .max_reconnect_attempts
+            .is_some_and(|max| self.attempts >= max)
+        {
+            return;
+        }
+        self.attempts += 1;
+        self.last_attempt = Instant::now();
+
+        match Transport::connect(&self.config) {
+            Ok(transport) => self.transport = Some(transport),
+            Err(err) if Some(self.attempts) == self.config.max_reconnect_attempts => {
+                let _ = writeln!(
+                    io::stderr().lock(),
+                    "Could not connect to the syslog server after {} attempts, writing its \
+                     messages to the standard error: {}",
+                    self.attempts,
+                    err
+                );
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl SyslogFormatter {
+    fn format(&self, severity: Severity, entry: &str) -> String {
+        let priority = self.facility as u8 | severity as u8;
+
+        match (self.protocol, self.pid) {
+            (SyslogProtocol::Rfc3164, Some(pid)) => format!(
+                "<{}>{} {}[{}]: {}",
+                priority,
+                rfc3164_timestamp(),
+                self.app_name,
+                pid,
+                entry
+            ),
+            (SyslogProtocol::Rfc3164, None) => format!(
+                "<{}>{} {}: {}",
+                priority,
+                rfc3164_timestamp(),
+                self.app_name,
+                entry
+            ),
+            // The PROCID is the NILVALUE without a PID, and there is never a MSGID.
+            (SyslogProtocol::Rfc5424, pid) => format!(
+                "<{}> 1 {} localhost {} {} 0 {} {}",
+                priority,
+                humantime::format_rfc3339_seconds(SystemTime::now()),
+                self.app_name,
+                pid.map_or_else(|| "-".to_string(), |pid| pid.to_string()),
+                self.structured_data,
+                entry
+            ),
+        }
+    }
+}
+
+/// Returns the current local time as written in the RFC 3164 entries, such as `Oct 14 13:22:35`.
+#[cfg(unix)]
+fn rfc3164_timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs()) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        return utc_rfc3164_timestamp();
+    }
+
+    format!(
+        "{} {:02} {:02}:{:02}:{:02}",
+        RFC3164_MONTHS[tm.tm_mon as usize % 12],
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// The local time zone is not looked up on these platforms, so the timestamps are in UTC.
+#[cfg(not(unix))]
+fn rfc3164_timestamp() -> String {
+    utc_rfc3164_timestamp()
+}
+
+/// Returns the current UTC time as written in the RFC 3164 entries, taken from its RFC 3339
+/// form `YYYY-MM-DDTHH:MM:SSZ`.
+fn utc_rfc3164_timestamp() -> String {
+    let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let month: usize = now[5..7].parse().unwrap_or(1);
+
+    format!(
+        "{} {} {}",
+        RFC3164_MONTHS[(month + 11) % 12],
+        &now[8..10],
+        &now[11..19]
+    )
+}
+
+/// Formats the structured data of the RFC 5424 entries, made of a single element holding the
+/// configured parameters, or the NILVALUE without parameters. The values are escaped as required
+/// by the RFC.
+fn structured_data(config: &config::Syslog) -> String {
+    let params = match &config.structured_data {
+        Some(params) => params,
+        None => return "-".into(),
+    };
+    let mut data = format!("[{}", SYSLOG_SD_ID);
+
+    for (name, value) in params {
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace(']', "\\]");
+
+        let _ = write!(data, " {}=\"{}\"", name, value);
+    }
+    data.push(']');
+
+    data
+}
+
+/// The transport of the syslog messages.
+enum Transport {
+    #[cfg(unix)]
+    Local(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpTransport),
+}
+
+impl Transport {
+    fn connect(config: &config::Syslog) -> io::Result<Self> {
+        let host = match &config.host {
+            Some(host) => host,
+            None => return Self::connect_local(),
+        };
+        let port = config.port.unwrap_or(SYSLOG_DEFAULT_PORT);
+
+        match config.transport {
+            SyslogTransport::Udp => {
+                let address = resolve(host, port)?;
+                let local = match address {
+                    SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+                    SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(address)?;
+
+                Ok(Self::Udp(socket))
+            }
+            SyslogTransport::Tcp => TcpTransport::connect(host, port, config).map(Self::Tcp),
+        }
+    }
+
+    #[cfg(unix)]
+    fn connect_local() -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect("/dev/log")
+            .or_else(|_| socket.connect("/var/run/syslog"))?;
+
+        Ok(Self::Local(socket))
+    }
+
+    #[cfg(not(unix))]
+    fn connect_local() -> io::Result<Self> {
+        Err(io::Error::other(
+            "the local syslog daemon is only supported on Unix, a host must be configured",
+        ))
+    }
+
+    fn send(&mut self, message: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Self::Local(socket) => socket.send(message).map(|_| ()),
+            Self::Udp(socket) => socket.send(message).map(|_| ()),
+            Self::Tcp(transport) => {
+                // The messages are delimited by a line feed over TCP (RFC 6587).
+                message.push(b'\n');
+                transport.send(message)
+            }
+        }
+    }
+}
+
+/// The delay between two attempts to connect to the syslog server.
+fn reconnect_interval(config: &config::Syslog) -> Duration {
+    config
+        .reconnect_interval
+        .unwrap_or(SYSLOG_DEFAULT_RECONNECT_INTERVAL)
+}
+
+/// Resolves the address of the syslog server at `host`, which is either an IP address or a name.
+fn resolve(host: &str, port: u16) -> io::Result<SocketAddr> {
+    let not_found = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("the host {} has no address", host),
+        )
+    };
+
+    (host, port)
+        .to_socket_addrs()
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("could not resolve the host {}: {}", host, err),
+            )
+        })?
+        .next()
+        .ok_or_else(not_found)
+}
+
+/// A TCP connection to a syslog server, reopened when it fails. The host of the server is
+/// resolved again by each attempt to reconnect.
+struct TcpTransport {
+    host: String,
+    port: u16,
+    stream: Option<TcpStream>,
+    reconnect_interval: Duration,
+    max_reconnect_attempts: Option<u32>,
+    /// The attempts to reconnect made since the connection dropped.
+    attempts: u32,
+    last_attempt: Instant,
+    /// The messages logged while the connection is down, sent once it's reopened.
+    pending: VecDeque<Vec<u8>>,
+    pending_bytes: usize,
+}
+
+impl TcpTransport {
+    fn connect(host: &str, port: u16, config: &config::Syslog) -> io::Result<Self> {
+        let stream = Self::open(host, port)?;
+
+        Ok(Self {
+            stream: Some(stream),
+            ..Self::disconnected(host, port, config)
+        })
+    }
+
+    /// Creates a transport whose connection is down, reconnecting once `reconnect_interval` has
+    /// elapsed.
+    fn disconnected(host: &str, port: u16, config: &config::Syslog) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            stream: None,
+            reconnect_interval: reconnect_interval(config),
+            max_reconnect_attempts: config.max_reconnect_attempts,
+            attempts: 0,
+            last_attempt: Instant::now(),
+            pending: VecDeque::new(),
+            pending_bytes: 0,
+        }
+    }
+
+    fn open(host: &str, port: u16) -> io::Result<TcpStream> {
+        let address = resolve(host, port)?;
+        let stream = TcpStream::connect_timeout(&address, SYSLOG_TCP_TIMEOUT)?;
+        stream.set_write_timeout(Some(SYSLOG_TCP_TIMEOUT))?;
+
+        Ok(stream)
+    }
+
+    /// Sends a message, reconnecting right away if the connection failed. While the server is
+    /// unreachable, a reconnection is only attempted every `reconnect_interval`, so the log does
+    /// not block the application, and the messages are buffered until they're sent on the new
+    /// connection. They're written to the standard error instead once the buffer is full, or
+    /// once the attempts to reconnect are exhausted.
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        if let Some(stream) = &mut self.stream {
+            if stream.write_all(message).is_ok() {
+                return Ok(());
+            }
+
+            self.stream = None;
+            self.attempts = 0;
+            self.reconnect();
+        } else if !self.gave_up() && self.last_attempt.elapsed() >= self.reconnect_interval {
+            self.reconnect();
+        }
+
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return self.buffer(message),
+        };
+        while let Some(pending) = self.pending.front() {
+            if stream.write_all(pending).is_err() {
+                self.stream = None;
+                self.attempts = 0;
+                self.last_attempt = Instant::now();
+                return self.buffer(message);
+            }
+
+            self.pending_bytes -= pending.len();
+            self.pending.pop_front();
+        }
+
+        stream.write_all(message)
+    }
+
+    fn reconnect(&mut self) {
+        self.attempts += 1;
+        self.last_attempt = Instant::now();
+
+        match Self::open(&self.host, self.port) {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.attempts = 0;
+            }
+            Err(err) if self.gave_up() => {
+                // The entries can't be logged, as the logger is the one writing them.
+                let _ = writeln!(
+                    io::stderr().lock(),
+                    "Could not reconnect to the syslog server at {}:{} after {} attempts, writing \
+                     its messages to the standard error: {}",
+                    self.host,
+                    self.port,
+                    self.attempts,
+                    err
+                );
+
+                let mut stderr = io::stderr().lock();
+                for pending in self.pending.drain(..) {
+                    let _ = stderr.write_all(&pending);
+                }
+                self.pending_bytes = 0;
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn gave_up(&self) -> bool {
+        self.stream.is_none()
+            && self
+                .max_reconnect_attempts
+                .is_some_and(|max| self.attempts >= max)
+    }
+
+    /// Buffers a message until the connection is reopened, or writes it to the standard error
+    /// if it can't be buffered.
+    fn buffer(&mut self, message: &[u8]) -> io::Result<()> {
+        if self.gave_up() || self.pending_bytes + message.len() > SYSLOG_TCP_BUFFER_MAX_BYTES {
+            return io::stderr().lock().write_all(message);
+        }
+
+        self.pending_bytes += message.len();
+        self.pending.push_back(message.to_vec());
+
+        Ok(())
+    }
+}
+
+/// Formats a record in the configured format, with its timestamp. The syslog entries do not hold a
+/// timestamp, as the syslog protocols already timestamp them.
+fn format_entry(format: LogFormat, timestamp: &str, sink: &Sink, record: &Record) -> String {
+    match (format, sink) {
+        (LogFormat::Text, Sink::Syslog(_)) => format!("[{}] {}", record.target(), record.args()),
+        (LogFormat::Text, _) => format!(
+            "[{} {:<5} {}] {}",
+            timestamp,
+            record.level(),
+            record.target(),
+            record.args()
+        ),
+        (LogFormat::Json, _) => {
+            let mut entry = String::from("{\"timestamp\":");
+            write_json_str(&mut entry, timestamp);
+            entry.push_str(",\"level\":");
+            write_json_str(&mut entry, &record.level().to_string());
+            entry.push_str(",\"target\":");
+            write_json_str(&mut entry, record.target());
+            entry.push_str(",\"message\":");
+            write_json_str(&mut entry, &record.args().to_string());
+            entry.push('}');
+            entry
+        }
+    }
+}
+
+/// Appends a string to a JSON document, quoted and escaped.
+fn write_json_str(output: &mut String, value: &str) {
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Read,
+        net::TcpListener,
+        path::{Path, PathBuf},
+    };
+
+    /// Returns an empty directory of the temporary directory, dedicated to the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("another-mq-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns a logger writing the text entries of level `Info` and above to `sinks`.
+    fn logger(sinks: Vec<Sink>) -> Logger {
+        Logger {
+            state: Mutex::new(State {
+                level: LevelFilter::Info,
+                targets: Vec::new(),
+                format: LogFormat::Text,
+                timestamp_format: None,
+                utc: true,
+                sinks,
+                buffer: None,
+                flush_interval: Duration::from_secs(1),
+                buffered: Vec::new(),
+            }),
+        }
+    }
+
+    fn log(logger: &Logger, level: Level, message: &str) {
+        log::Log::log(
+            logger,
+            &Record::builder()
+                .level(level)
+                .target("another_mq::broker")
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    /// Returns a syslog server over UDP, along with the configuration sending it the entries.
+    fn syslog_server() -> (UdpSocket, config::Syslog) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let config = config::Syslog {
+            host: Some("127.0.0.1".into()),
+            port: Some(server.local_addr().unwrap().port()),
+            ..config::Syslog::default()
+        };
+
+        (server, config)
+    }
+
+    /// Returns the configuration sending the entries over TCP to a syslog server which is not
+    /// listening yet, along with its address.
+    fn unreachable_syslog_server() -> (SocketAddr, config::Syslog) {
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = config::Syslog {
+            host: Some("127.0.0.1".into()),
+            port: Some(address.port()),
+            transport: SyslogTransport::Tcp,
+            reconnect_interval: Some(Duration::from_millis(50)),
+            ..config::Syslog::default()
+        };
+
+        (address, config)
+    }
+
+    #[test]
+    fn entry_reaches_every_sink() {
+        let dir = temp_dir("sinks");
+        let path = dir.join("broker.log").display().to_string();
+        let (server, syslog) = syslog_server();
+        let logger = logger(vec![
+            Sink::File(Logfile::open(&path, None).unwrap()),
+            Sink::Syslog(Box::new(Syslog::connect(&syslog))),
+        ]);
+
+        log(&logger, Level::Info, "Declared queue tasks");
+        log(&logger, Level::Debug, "Filtered out");
+
+        let logged = fs::read_to_string(&path).unwrap();
+        assert_eq!(logged.lines().count(), 1);
+        assert!(logged.ends_with("INFO  another_mq::broker] Declared queue tasks\n"));
+        let mut datagram = [0; 1024];
+        let size = server.recv(&mut datagram).unwrap();
+        let sent = String::from_utf8_lossy(&datagram[..size]);
+        assert!(sent.ends_with("[another_mq::broker] Declared queue tasks"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn logfile_keeps_the_standard_output() {
+        let dir = temp_dir("stdout");
+        let path = dir.join("broker.log").display().to_string();
+        let (_server, syslog) = syslog_server();
+        let mut config = config::Log {
+            file: Some(path),
+            syslog: Some(syslog),
+            ..config::Log::default()
+        };
+
+        let kinds: Vec<_> = Sink::open_all(&config, &[])
+            .unwrap()
+            .iter()
+            .map(Sink::kind)
+            .collect();
+        assert!(kinds == [SinkKind::Stdout, SinkKind::File, SinkKind::Syslog]);
+
+        config.stdout = false;
+        let kinds: Vec<_> = Sink::open_all(&config, &[SinkKind::Syslog])
+            .unwrap()
+            .iter()
+            .map(Sink::kind)
+            .collect();
+        assert!(kinds == [SinkKind::File]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn logfile_is_rotated_past_its_maximum_size() {
+        let dir = temp_dir("rotation");
+        let path = dir.join("broker.log").display().to_string();
+        let rotation = Rotation {
+            max_size_bytes: 100,
+            max_files: 2,
+        };
+        let mut logfile = Logfile::open(&path, Some(rotation)).unwrap();
+
+        // Each entry takes 50 bytes with its newline, so every third one rotates the logfile.
+        for _ in 0..9 {
+            logfile.write(&"x".repeat(49)).unwrap();
+        }
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        assert_eq!(fs::metadata(format!("{}.1", path)).unwrap().len(), 150);
+        assert_eq!(fs::metadata(format!("{}.2", path)).unwrap().len(), 150);
+        assert!(!Path::new(&format!("{}.3", path)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn logfile_below_its_maximum_size_is_not_rotated() {
+        let dir = temp_dir("no-rotation");
+        let path = dir.join("broker.log").display().to_string();
+        let rotation = Rotation {
+            max_size_bytes: 100,
+            max_files: 2,
+        };
+        let mut logfile = Logfile::open(&path, Some(rotation)).unwrap();
+
+        logfile.write(&"x".repeat(99)).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), 100);
+        assert!(!Path::new(&format!("{}.1", path)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Returns a logger of level `Info` filtering the entries of `targets` by their own level.
+    fn filtering(targets: &[(&str, Level)]) -> Logger {
+        let config = config::Log {
+            targets: targets
+                .iter()
+                .map(|(target, level)| (target.to_string(), *level))
+                .collect(),
+            ..config::Log::default()
+        };
+        let logger = logger(Vec::new());
+        logger.state.lock().unwrap().targets = target_levels(&config);
+        logger
+    }
+
+    /// Returns the most verbose level of the entries of `target` enabled by `logger`, if any.
+    fn enabled_level(logger: &Logger, target: &str) -> Option<Level> {
+        let state = logger.state.lock().unwrap();
+
+        [
+            Level::Trace,
+            Level::Debug,
+            Level::Info,
+            Level::Warn,
+            Level::Error,
+        ]
+        .iter()
+        .copied()
+        .find(|&level| state.enabled(&Metadata::builder().level(level).target(target).build()))
+    }
+
+    #[test]
+    fn target_applies_to_its_module_and_the_modules_nested_in_it() {
+        let logger = filtering(&[("another_mq::broker", Level::Debug)]);
+
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker"),
+            Some(Level::Debug)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker::queue"),
+            Some(Level::Debug)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::brokers"),
+            Some(Level::Info)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::server"),
+            Some(Level::Info)
+        );
+    }
+
+    #[test]
+    fn glob_target_only_applies_to_the_nested_modules() {
+        let logger = filtering(&[("another_mq::broker::*", Level::Trace)]);
+
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker"),
+            Some(Level::Info)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker::queue"),
+            Some(Level::Trace)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker::queue::dispatch"),
+            Some(Level::Trace)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::brokers"),
+            Some(Level::Info)
+        );
+    }
+
+    #[test]
+    fn exact_target_wins_over_an_overlapping_glob() {
+        let logger = filtering(&[
+            ("another_mq::*", Level::Trace),
+            ("another_mq::broker::queue", Level::Warn),
+        ]);
+
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker::queue"),
+            Some(Level::Warn)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker"),
+            Some(Level::Trace)
+        );
+        assert_eq!(enabled_level(&logger, "another_mq"), Some(Level::Info));
+    }
+
+    #[test]
+    fn most_specific_glob_wins() {
+        let logger = filtering(&[
+            ("another_mq::*", Level::Debug),
+            ("another_mq::broker::*", Level::Error),
+        ]);
+
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker::queue"),
+            Some(Level::Error)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::server"),
+            Some(Level::Debug)
+        );
+    }
+
+    #[test]
+    fn glob_wins_over_the_target_naming_the_same_module() {
+        let logger = filtering(&[
+            ("another_mq::broker", Level::Warn),
+            ("another_mq::broker::*", Level::Trace),
+        ]);
+
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker"),
+            Some(Level::Warn)
+        );
+        assert_eq!(
+            enabled_level(&logger, "another_mq::broker::queue"),
+            Some(Level::Trace)
+        );
+    }
+
+    #[test]
+    fn unreachable_syslog_server_keeps_the_other_sinks() {
+        let dir = temp_dir("unreachable");
+        let path = dir.join("broker.log").display().to_string();
+        let (_, syslog) = unreachable_syslog_server();
+        let config = config::Log {
+            file: Some(path.clone()),
+            syslog: Some(syslog),
+            ..config::Log::default()
+        };
+
+        let sinks = Sink::open_all(&config, &[]).unwrap();
+        let kinds: Vec<_> = sinks.iter().map(Sink::kind).collect();
+        assert!(kinds == [SinkKind::Stdout, SinkKind::File, SinkKind::Syslog]);
+
+        let logger = logger(sinks.into_iter().skip(1).collect());
+        log(&logger, Level::Info, "Declared queue tasks");
+        let logged = fs::read_to_string(&path).unwrap();
+        assert!(logged.ends_with("another_mq::broker] Declared queue tasks\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn entries_are_sent_once_the_syslog_server_is_reachable() {
+        let (address, syslog) = unreachable_syslog_server();
+        let logger = logger(vec![Sink::Syslog(Box::new(Syslog::connect(&syslog)))]);
+        log(&logger, Level::Info, "Declared queue tasks");
+
+        let server = TcpListener::bind(address).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        log(&logger, Level::Info, "Declared queue jobs");
+
+        let (mut stream, _) = server.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut sent = String::new();
+        let mut buf = [0; 1024];
+        while !sent.ends_with("Declared queue jobs\n") {
+            let size = stream.read(&mut buf).unwrap();
+            assert!(size > 0);
+            sent.push_str(&String::from_utf8_lossy(&buf[..size]));
+        }
+        let entries: Vec<_> = sent.lines().collect();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].ends_with("[another_mq::broker] Declared queue tasks"));
+    }
+}
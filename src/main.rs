@@ -1,9 +1,255 @@
-mod config;
-
-use config::Config;
+#[cfg(unix)]
+use another_mq::signal;
+use another_mq::{
+    admin,
+    broker::Broker,
+    config::{self, Config, ConfigError, ConfigFormat},
+    logging, metrics,
+    server::Server,
+    shutdown::Shutdown,
+};
+use std::{
+    env, io, process,
+    sync::{Arc, RwLock},
+};
 
 fn main() {
-    let config = Config::from_config_file();
+    // The logger is installed before loading the configuration, so the entries emitted while
+    // validating it are not lost. It is configured once the configuration is loaded.
+    logging::init();
+
+    let mut args = env::args().skip(1);
+    if args.next().as_deref() == Some("check-config") {
+        check_config(args.next());
+    }
+
+    // The configuration file can be chosen with `--config <path>`, `-` reading the configuration
+    // from the standard input instead, in the format given by `--config-format`.
+    let path = option("--config");
+    let format = match option("--config-format").map(|format| format.parse()) {
+        Some(Ok(format)) => format,
+        Some(Err(err)) => {
+            log::error!("invalid value for --config-format: {}", err);
+            process::exit(1);
+        }
+        None => ConfigFormat::Toml,
+    };
+
+    // The unknown keys of the configuration file are only rejected when asked for, so a file
+    // written for a newer version can still be loaded.
+    let strict = env::args().skip(1).any(|arg| arg == "--strict-config");
+    let mut config = match load(path.as_deref(), format, strict) {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    if env::args().skip(1).any(|arg| arg == "--dump-config") {
+        print!("{}", config.effective_toml());
+        return;
+    }
+
+    if let Err(err) = logging::configure(&config.log) {
+        log::error!("{}", err);
+        process::exit(1);
+    }
+
+    if let Err(err) = config.check_bindable() {
+        log::error!("{}", err);
+        process::exit(1);
+    }
+
+    let server = match Server::bind(&config.network) {
+        Ok(server) => server,
+        Err(err) => {
+            log::error!("{}", err);
+            process::exit(1);
+        }
+    };
+    if config.network.listeners.is_empty() {
+        // The port may have been picked from the range, which the live configuration reports.
+        config.network.port = server.local_addrs()[0].port();
+    }
+
+    // The line operators look for to check that the broker is up, and how it's configured.
+    log::info!("Starting the broker: {}", config.summary());
+
+    log::info!(
+        "Sized for {} worker threads, as a hint: each connection is served by threads of its own",
+        config.network.effective_worker_threads()
+    );
+
+    if config.auth.allows_anonymous() {
+        log::warn!("No user is defined by the auth namespace, any client can connect anonymously");
+    }
+
+    let broker = match Broker::new(&config.queue, &config.vhosts, &config.metrics) {
+        Ok(broker) => Arc::new(broker),
+        Err(err) => {
+            let path = config
+                .queue
+                .storage
+                .as_ref()
+                .map_or("", |storage| &storage.path);
+
+            log::error!("Could not open the storage at {}: {}", path, err);
+            process::exit(1);
+        }
+    };
+    let metrics_address = if config.metrics.enabled {
+        Some(config.metrics.address())
+    } else {
+        None
+    };
+    let admin_address = if config.admin.enabled {
+        Some(config.admin.address())
+    } else {
+        None
+    };
+
+    let shutdown = Arc::new(Shutdown::default());
+    let config = Arc::new(RwLock::new(config));
+
+    #[cfg(unix)]
+    {
+        let config = config.clone();
+        let broker = broker.clone();
+        let shutdown = shutdown.clone();
+        let path = path.clone();
+        let immediate_signals = config
+            .read()
+            .unwrap()
+            .network
+            .immediate_shutdown_signals
+            .clone();
+        let handler = move |signal| {
+            let requested = match signal {
+                signal::Signal::Hangup => return reload(&config, &broker, path.as_deref()),
+                signal::Signal::Interrupt => config::ShutdownSignal::Interrupt,
+                signal::Signal::Terminate => config::ShutdownSignal::Terminate,
+            };
+
+            // A second signal stops the application without waiting for the connections, and so
+            // does a signal configured to stop it right away.
+            if shutdown.is_requested() || immediate_signals.contains(&requested) {
+                log::warn!("Stopping immediately");
+                log::logger().flush();
+                process::exit(1);
+            }
+
+            log::info!("Shutting down, waiting for the connections to close");
+            shutdown.request();
+        };
+
+        if let Err(err) = signal::install(handler) {
+            log::error!("Could not handle the signals: {}", err);
+            process::exit(1);
+        }
+    }
+
+    // The other threads are started once the signals are handled, so they don't receive them.
+    if let Err(err) = logging::start_flusher() {
+        log::error!("Could not start the flush of the log: {}", err);
+        process::exit(1);
+    }
+    if let Err(err) = broker.start_expiry() {
+        log::error!("Could not start the expiry of the messages: {}", err);
+        process::exit(1);
+    }
+    if let Err(err) = broker.start_alerts() {
+        log::error!("Could not start the alerts of the queues: {}", err);
+        process::exit(1);
+    }
+
+    if let Some(address) = metrics_address {
+        if let Err(err) = metrics::serve(address, broker.clone()) {
+            log::error!("Could not serve the metrics on {}: {}", address, err);
+            process::exit(1);
+        }
+    }
+
+    if let Some(address) = admin_address {
+        if let Err(err) = admin::serve(address, broker.clone(), shutdown.clone(), config.clone()) {
+            log::error!("Could not serve the admin API on {}: {}", address, err);
+            process::exit(1);
+        }
+    }
+
+    server.run(broker, config, shutdown);
+    log::info!("Stopped");
+    // The entries left in the log buffer are written before exiting.
+    log::logger().flush();
+}
+
+/// Returns the value following the command line option `name`, if it's given.
+fn option(name: &str) -> Option<String> {
+    let mut args = env::args().skip(1);
+
+    args.find(|arg| arg == name)?;
+    args.next()
+}
+
+/// Loads the configuration from the file at `path`, from the standard input if `path` is `-`, or
+/// from the default configuration file. `format` is only used for the standard input, the format
+/// of a file being chosen from its extension.
+fn load(path: Option<&str>, format: ConfigFormat, strict: bool) -> Result<Config, ConfigError> {
+    match path {
+        Some("-") if strict => Config::from_reader_strict(io::stdin().lock(), format),
+        Some("-") => Config::from_reader(io::stdin().lock(), format),
+        Some(path) if strict => Config::from_file_strict(path),
+        Some(path) => Config::try_from_file(path),
+        None if strict => Config::from_config_file_strict(),
+        None => Config::try_from_config_file(),
+    }
+}
+
+/// Checks the configuration file at `path`, or the default one, without starting the broker. Every
+/// error it holds is logged, the application exiting with `1` if there is any.
+fn check_config(path: Option<String>) -> ! {
+    let checked = match &path {
+        Some(path) => Config::validate_file(path),
+        None => Config::validate_config_file(),
+    };
+
+    match checked {
+        Ok(()) => {
+            log::info!("The configuration is valid");
+            process::exit(0);
+        }
+        Err(errors) => {
+            for err in &errors {
+                log::error!("{}", err);
+            }
+            process::exit(1);
+        }
+    }
+}
+
+/// Reloads the live configuration from the configuration file at `path`, or from the default one,
+/// then applies its hot-reloadable namespaces. The configuration read from the standard input
+/// can't be reloaded.
+#[cfg(unix)]
+fn reload(config: &RwLock<Config>, broker: &Broker, path: Option<&str>) {
+    if path == Some("-") {
+        log::warn!("The configuration read from the standard input can't be reloaded");
+        return;
+    }
+    log::info!("Reloading the configuration");
+
+    let mut config = config.write().unwrap_or_else(|err| err.into_inner());
+    let reloaded = match path {
+        Some(path) => config.reload(path),
+        None => config.reload_config_file(),
+    };
+    if let Err(err) = reloaded {
+        log::error!("Could not reload the configuration: {}", err);
+        return;
+    }
 
-    println!("Hello, world!");
+    if let Err(err) = logging::configure(&config.log) {
+        log::error!("Could not reload the log configuration: {}", err);
+    }
+    broker.configure(&config.queue, &config.vhosts);
 }
@@ -0,0 +1,327 @@
+//! The metrics of the broker, served over HTTP in the text format of Prometheus.
+//!
+//! The counters and the gauges are held by a [`Registry`] which the broker updates as the
+//! messages flow. The counters of each queue are held by the queue itself, along with the
+//! histogram of the sizes of its messages, and its depth is read from it when the metrics are
+//! scraped, so there are only series for the declared queues.
+
+use crate::{
+    broker::{Broker, Queue},
+    http::{self, Response},
+};
+use std::{
+    fmt::Write as _,
+    io,
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// A value which only goes up, such as a number of messages published.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value which goes up and down, such as a number of open connections.
+#[derive(Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The distribution of values, such as the sizes of the messages, counted in buckets by their
+/// upper bound.
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// The values counted in each bucket, above the bound of the previous bucket and up to the
+    /// bound of the bucket. The last one counts the values above every bound.
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    /// Creates a histogram whose buckets have the upper bounds `bounds`, in increasing order.
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value as f64 <= *bound)
+            .unwrap_or(self.bounds.len());
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Writes the series of the histogram `name`, with `labels`: the cumulative count of each
+    /// bucket, then the sum and the count of the values.
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut count = 0;
+
+        for (bucket, counted) in self.buckets.iter().enumerate() {
+            count += counted.load(Ordering::Relaxed);
+
+            let _ = match self.bounds.get(bucket) {
+                Some(bound) => writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name, labels, bound, count
+                ),
+                None => writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, count),
+            };
+        }
+        let _ = writeln!(
+            out,
+            "{}_sum{{{}}} {}",
+            name,
+            labels,
+            self.sum.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, count);
+    }
+}
+
+/// The counters and the gauges of the broker.
+pub struct Registry {
+    /// The connections currently open by the clients.
+    pub connections: Gauge,
+    /// The messages published to a queue.
+    pub published: Counter,
+    /// The messages delivered to a consumer or fetched by a client.
+    pub delivered: Counter,
+    /// The messages acknowledged by the clients.
+    pub acknowledged: Counter,
+    /// The messages rejected or expired in their queue, which are dead-lettered.
+    pub dead_lettered: Counter,
+    /// The upper bounds of the buckets of the histograms of the message sizes, in bytes.
+    size_buckets: Vec<f64>,
+}
+
+impl Registry {
+    /// Creates the registry, the histograms of the message sizes of the queues having buckets
+    /// bounded by `size_buckets`.
+    pub fn new(size_buckets: Vec<f64>) -> Self {
+        Self {
+            connections: Gauge::default(),
+            published: Counter::default(),
+            delivered: Counter::default(),
+            acknowledged: Counter::default(),
+            dead_lettered: Counter::default(),
+            size_buckets,
+        }
+    }
+
+    /// Creates the counters of a queue declared by the broker.
+    pub fn queue_counters(&self) -> QueueCounters {
+        QueueCounters {
+            published: Counter::default(),
+            consumed: Counter::default(),
+            message_sizes: Histogram::new(&self.size_buckets),
+        }
+    }
+}
+
+/// The counters of a queue, exported with the labels of the queue.
+pub struct QueueCounters {
+    /// The messages published to the queue.
+    pub published: Counter,
+    /// The messages of the queue delivered to a consumer or fetched by a client.
+    pub consumed: Counter,
+    /// The sizes of the bodies of the messages published to the queue, in bytes.
+    pub message_sizes: Histogram,
+}
+
+/// Renders the metrics of `broker` in the text format of Prometheus.
+pub fn render(broker: &Broker) -> String {
+    let registry = broker.metrics();
+    let mut out = String::new();
+
+    metric(
+        &mut out,
+        "anothermq_connections",
+        "gauge",
+        "The number of connections open by the clients.",
+    );
+    let _ = writeln!(out, "anothermq_connections {}", registry.connections.get());
+
+    for (name, help, counter) in &[
+        (
+            "anothermq_messages_published_total",
+            "The number of messages published to a queue.",
+            &registry.published,
+        ),
+        (
+            "anothermq_messages_delivered_total",
+            "The number of messages delivered to a consumer or fetched by a client.",
+            &registry.delivered,
+        ),
+        (
+            "anothermq_messages_acknowledged_total",
+            "The number of messages acknowledged by the clients.",
+            &registry.acknowledged,
+        ),
+        (
+            "anothermq_messages_dead_lettered_total",
+            "The number of messages rejected or expired in their queue.",
+            &registry.dead_lettered,
+        ),
+    ] {
+        metric(&mut out, name, "counter", help);
+        let _ = writeln!(out, "{} {}", name, counter.get());
+    }
+
+    // There is one series of each queue metric for each declared queue.
+    let queues: Vec<_> = broker
+        .queues()
+        .into_iter()
+        .map(|queue| (labels(&queue), queue))
+        .collect();
+
+    queue_metric(
+        &mut out,
+        "anothermq_queue_messages",
+        "gauge",
+        "The number of messages waiting in each queue.",
+        &queues,
+        |queue| queue.message_count() as u64,
+    );
+    queue_metric(
+        &mut out,
+        "anothermq_queue_consumers",
+        "gauge",
+        "The number of consumers of each queue.",
+        &queues,
+        |queue| queue.consumer_count() as u64,
+    );
+    queue_metric(
+        &mut out,
+        "anothermq_queue_messages_published_total",
+        "counter",
+        "The number of messages published to each queue.",
+        &queues,
+        |queue| queue.counters().published.get(),
+    );
+    queue_metric(
+        &mut out,
+        "anothermq_queue_messages_consumed_total",
+        "counter",
+        "The number of messages of each queue delivered to a consumer or fetched by a client.",
+        &queues,
+        |queue| queue.counters().consumed.get(),
+    );
+
+    let name = "anothermq_queue_message_size_bytes";
+    metric(
+        &mut out,
+        name,
+        "histogram",
+        "The size of the bodies of the messages published to each queue, in bytes.",
+    );
+    for (labels, queue) in &queues {
+        queue.counters().message_sizes.write(&mut out, name, labels);
+    }
+
+    out
+}
+
+/// Writes a metric with one series for each queue of `queues`, along with its labels, whose
+/// value is read by `value`.
+fn queue_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    queues: &[(String, Arc<Queue>)],
+    value: fn(&Queue) -> u64,
+) {
+    metric(out, name, kind, help);
+    for (labels, queue) in queues {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value(queue));
+    }
+}
+
+/// Writes the labels of the series of a queue: its virtual host, its name, then the labels of its
+/// definition in the order of their names.
+fn labels(queue: &Queue) -> String {
+    let mut labels = format!(
+        "vhost=\"{}\",queue=\"{}\"",
+        escape_label(queue.vhost()),
+        escape_label(queue.name())
+    );
+
+    let mut custom: Vec<_> = queue.definition().metrics_labels.iter().flatten().collect();
+    custom.sort();
+    for (name, value) in custom {
+        let _ = write!(labels, ",{}=\"{}\"", name, escape_label(value));
+    }
+
+    labels
+}
+
+/// Writes the help and the type of a metric.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes the value of a label, as the text format of Prometheus expects it.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Binds the HTTP listener serving the metrics of `broker` on `address`, then serves the
+/// requests from a thread of its own. Only `/metrics` is served.
+pub fn serve(address: SocketAddr, broker: Arc<Broker>) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    log::info!("Serving the metrics on http://{}/metrics", address);
+
+    http::spawn("metrics", listener, move |request| {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => Some(Response {
+                status: "200 OK",
+                content_type: "text/plain; version=0.0.4",
+                headers: Vec::new(),
+                body: render(&broker),
+            }),
+            (_, "/metrics") => Some(http::method_not_allowed()),
+            _ => None,
+        }
+    })
+}
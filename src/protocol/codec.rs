@@ -0,0 +1,252 @@
+use super::{Error, FieldTable, FieldValue};
+use std::convert::TryInto;
+
+/// Reads the AMQP data types out of the payload of a frame.
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// Returns `true` if the whole payload has been read.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Returns the number of bytes of the payload left to read.
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() < len {
+            return Err(Error::Syntax("unexpected end of payload"));
+        }
+
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a short string, prefixed by its length on one octet.
+    pub fn read_short_str(&mut self) -> Result<String, Error> {
+        let len = self.read_u8()? as usize;
+        let bytes = self.read_bytes(len)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::Syntax("short string is not UTF-8"))
+    }
+
+    /// Reads a long string, prefixed by its length on four octets.
+    pub fn read_long_str(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.read_u32()? as usize;
+
+        Ok(self.read_bytes(len)?.to_vec())
+    }
+
+    pub fn read_table(&mut self) -> Result<FieldTable, Error> {
+        let len = self.read_u32()? as usize;
+        let mut decoder = Decoder::new(self.read_bytes(len)?);
+        let mut table = FieldTable::new();
+
+        while !decoder.is_empty() {
+            let name = decoder.read_short_str()?;
+            let value = decoder.read_field_value()?;
+
+            table.insert(name, value);
+        }
+
+        Ok(table)
+    }
+
+    fn read_field_value(&mut self) -> Result<FieldValue, Error> {
+        let value = match self.read_u8()? {
+            b't' => FieldValue::Bool(self.read_u8()? != 0),
+            b'b' => FieldValue::I8(self.read_u8()? as i8),
+            b'B' => FieldValue::U8(self.read_u8()?),
+            b's' => FieldValue::I16(self.read_u16()? as i16),
+            b'u' => FieldValue::U16(self.read_u16()?),
+            b'I' => FieldValue::I32(self.read_u32()? as i32),
+            b'i' => FieldValue::U32(self.read_u32()?),
+            b'l' => FieldValue::I64(self.read_u64()? as i64),
+            b'f' => FieldValue::F32(f32::from_bits(self.read_u32()?)),
+            b'd' => FieldValue::F64(f64::from_bits(self.read_u64()?)),
+            b'D' => FieldValue::Decimal(self.read_u8()?, self.read_u32()?),
+            b'S' => FieldValue::LongStr(self.read_long_str()?),
+            b'A' => {
+                let len = self.read_u32()? as usize;
+                let mut decoder = Decoder::new(self.read_bytes(len)?);
+                let mut values = Vec::new();
+
+                while !decoder.is_empty() {
+                    values.push(decoder.read_field_value()?);
+                }
+
+                FieldValue::Array(values)
+            }
+            b'T' => FieldValue::Timestamp(self.read_u64()?),
+            b'F' => FieldValue::Table(self.read_table()?),
+            b'V' => FieldValue::Void,
+            b'x' => FieldValue::Bytes(self.read_long_str()?),
+            _ => return Err(Error::Syntax("unknown field value type")),
+        };
+
+        Ok(value)
+    }
+}
+
+/// Writes the AMQP data types into the payload of a frame.
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    /// Writes a short string, truncated to 255 bytes if it's longer.
+    pub fn write_short_str(&mut self, value: &str) {
+        let len = value.len().min(u8::MAX as usize);
+
+        self.write_u8(len as u8);
+        self.write_bytes(&value.as_bytes()[..len]);
+    }
+
+    pub fn write_long_str(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.write_bytes(value);
+    }
+
+    pub fn write_table(&mut self, table: &FieldTable) {
+        let mut encoder = Encoder::new();
+
+        for (name, value) in table {
+            encoder.write_short_str(name);
+            encoder.write_field_value(value);
+        }
+
+        self.write_long_str(&encoder.into_inner());
+    }
+
+    fn write_field_value(&mut self, value: &FieldValue) {
+        match value {
+            FieldValue::Bool(value) => {
+                self.write_u8(b't');
+                self.write_u8(*value as u8);
+            }
+            FieldValue::I8(value) => {
+                self.write_u8(b'b');
+                self.write_u8(*value as u8);
+            }
+            FieldValue::U8(value) => {
+                self.write_u8(b'B');
+                self.write_u8(*value);
+            }
+            FieldValue::I16(value) => {
+                self.write_u8(b's');
+                self.write_u16(*value as u16);
+            }
+            FieldValue::U16(value) => {
+                self.write_u8(b'u');
+                self.write_u16(*value);
+            }
+            FieldValue::I32(value) => {
+                self.write_u8(b'I');
+                self.write_u32(*value as u32);
+            }
+            FieldValue::U32(value) => {
+                self.write_u8(b'i');
+                self.write_u32(*value);
+            }
+            FieldValue::I64(value) => {
+                self.write_u8(b'l');
+                self.write_u64(*value as u64);
+            }
+            FieldValue::F32(value) => {
+                self.write_u8(b'f');
+                self.write_u32(value.to_bits());
+            }
+            FieldValue::F64(value) => {
+                self.write_u8(b'd');
+                self.write_u64(value.to_bits());
+            }
+            FieldValue::Decimal(scale, value) => {
+                self.write_u8(b'D');
+                self.write_u8(*scale);
+                self.write_u32(*value);
+            }
+            FieldValue::LongStr(value) => {
+                self.write_u8(b'S');
+                self.write_long_str(value);
+            }
+            FieldValue::Array(values) => {
+                let mut encoder = Encoder::new();
+
+                for value in values {
+                    encoder.write_field_value(value);
+                }
+
+                self.write_u8(b'A');
+                self.write_long_str(&encoder.into_inner());
+            }
+            FieldValue::Timestamp(value) => {
+                self.write_u8(b'T');
+                self.write_u64(*value);
+            }
+            FieldValue::Table(table) => {
+                self.write_u8(b'F');
+                self.write_table(table);
+            }
+            FieldValue::Void => self.write_u8(b'V'),
+            FieldValue::Bytes(value) => {
+                self.write_u8(b'x');
+                self.write_long_str(value);
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use super::{Decoder, Encoder, Error, FieldTable};
+
+/// The identifier of the basic class, the only class whose methods carry a content.
+pub const CLASS_BASIC: u16 = 60;
+
+/// The header of a content, carried by a content header frame after the method publishing or
+/// delivering it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentHeader {
+    pub class_id: u16,
+    /// The size of the body, split into the content body frames following the header.
+    pub body_size: u64,
+    pub properties: Properties,
+}
+
+impl ContentHeader {
+    /// Decodes a content header out of the payload of a content header frame.
+    pub fn decode(payload: &[u8]) -> Result<Self, Error> {
+        let mut decoder = Decoder::new(payload);
+        let class_id = decoder.read_u16()?;
+        let _weight = decoder.read_u16()?;
+        let body_size = decoder.read_u64()?;
+        let properties = Properties::decode(&mut decoder)?;
+
+        Ok(Self {
+            class_id,
+            body_size,
+            properties,
+        })
+    }
+
+    /// Encodes the content header into the payload of a content header frame.
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_u16(self.class_id);
+        encoder.write_u16(0);
+        encoder.write_u64(self.body_size);
+        self.properties.encode(encoder);
+    }
+}
+
+/// The properties of a message of the basic class.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Properties {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub headers: Option<FieldTable>,
+    /// `1` for a transient message, `2` for a persistent one.
+    pub delivery_mode: Option<u8>,
+    pub priority: Option<u8>,
+    pub correlation_id: Option<String>,
+    pub reply_to: Option<String>,
+    pub expiration: Option<String>,
+    pub message_id: Option<String>,
+    pub timestamp: Option<u64>,
+    pub kind: Option<String>,
+    pub user_id: Option<String>,
+    pub app_id: Option<String>,
+    pub cluster_id: Option<String>,
+}
+
+/// The flags of the properties, the first property being the most significant bit.
+const CONTENT_TYPE: u16 = 1 << 15;
+const CONTENT_ENCODING: u16 = 1 << 14;
+const HEADERS: u16 = 1 << 13;
+const DELIVERY_MODE: u16 = 1 << 12;
+const PRIORITY: u16 = 1 << 11;
+const CORRELATION_ID: u16 = 1 << 10;
+const REPLY_TO: u16 = 1 << 9;
+const EXPIRATION: u16 = 1 << 8;
+const MESSAGE_ID: u16 = 1 << 7;
+const TIMESTAMP: u16 = 1 << 6;
+const KIND: u16 = 1 << 5;
+const USER_ID: u16 = 1 << 4;
+const APP_ID: u16 = 1 << 3;
+const CLUSTER_ID: u16 = 1 << 2;
+
+/// The flag announcing another set of property flags, never set by the basic class.
+const CONTINUATION: u16 = 1;
+
+impl Properties {
+    fn decode(decoder: &mut Decoder) -> Result<Self, Error> {
+        let flags = decoder.read_u16()?;
+
+        if flags & CONTINUATION != 0 {
+            return Err(Error::Syntax("unexpected property flags"));
+        }
+
+        let short_str = |decoder: &mut Decoder, flag| -> Result<Option<String>, Error> {
+            if flags & flag != 0 {
+                decoder.read_short_str().map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+
+        Ok(Self {
+            content_type: short_str(decoder, CONTENT_TYPE)?,
+            content_encoding: short_str(decoder, CONTENT_ENCODING)?,
+            headers: if flags & HEADERS != 0 {
+                Some(decoder.read_table()?)
+            } else {
+                None
+            },
+            delivery_mode: if flags & DELIVERY_MODE != 0 {
+                Some(decoder.read_u8()?)
+            } else {
+                None
+            },
+            priority: if flags & PRIORITY != 0 {
+                Some(decoder.read_u8()?)
+            } else {
+                None
+            },
+            correlation_id: short_str(decoder, CORRELATION_ID)?,
+            reply_to: short_str(decoder, REPLY_TO)?,
+            expiration: short_str(decoder, EXPIRATION)?,
+            message_id: short_str(decoder, MESSAGE_ID)?,
+            timestamp: if flags & TIMESTAMP != 0 {
+                Some(decoder.read_u64()?)
+            } else {
+                None
+            },
+            kind: short_str(decoder, KIND)?,
+            user_id: short_str(decoder, USER_ID)?,
+            app_id: short_str(decoder, APP_ID)?,
+            cluster_id: short_str(decoder, CLUSTER_ID)?,
+        })
+    }
+
+    fn encode(&self, encoder: &mut Encoder) {
+        let mut flags = 0;
+        let mut properties = Encoder::new();
+
+        write_short_str(
+            &mut properties,
+            &mut flags,
+            CONTENT_TYPE,
+            &self.content_type,
+        );
+        write_short_str(
+            &mut properties,
+            &mut flags,
+            CONTENT_ENCODING,
+            &self.content_encoding,
+        );
+        if let Some(headers) = &self.headers {
+            flags |= HEADERS;
+            properties.write_table(headers);
+        }
+        if let Some(delivery_mode) = self.delivery_mode {
+            flags |= DELIVERY_MODE;
+            properties.write_u8(delivery_mode);
+        }
+        if let Some(priority) = self.priority {
+            flags |= PRIORITY;
+            properties.write_u8(priority);
+        }
+        write_short_str(
+            &mut properties,
+            &mut flags,
+            CORRELATION_ID,
+            &self.correlation_id,
+        );
+        write_short_str(&mut properties, &mut flags, REPLY_TO, &self.reply_to);
+        write_short_str(&mut properties, &mut flags, EXPIRATION, &self.expiration);
+        write_short_str(&mut properties, &mut flags, MESSAGE_ID, &self.message_id);
+        if let Some(timestamp) = self.timestamp {
+            flags |= TIMESTAMP;
+            properties.write_u64(timestamp);
+        }
+        write_short_str(&mut properties, &mut flags, KIND, &self.kind);
+        write_short_str(&mut properties, &mut flags, USER_ID, &self.user_id);
+        write_short_str(&mut properties, &mut flags, APP_ID, &self.app_id);
+        write_short_str(&mut properties, &mut flags, CLUSTER_ID, &self.cluster_id);
+
+        encoder.write_u16(flags);
+        encoder.write_bytes(&properties.into_inner());
+    }
+}
+
+/// Writes an optional short string property, setting its flag when it's defined.
+fn write_short_str(properties: &mut Encoder, flags: &mut u16, flag: u16, value: &Option<String>) {
+    if let Some(value) = value {
+        *flags |= flag;
+        properties.write_short_str(value);
+    }
+}
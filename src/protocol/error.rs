@@ -0,0 +1,68 @@
+use super::reply_code;
+use std::{error, fmt, io};
+
+/// The errors which can occur while reading or writing the frames of a connection.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying stream of the connection failed.
+    Io(io::Error),
+
+    /// The frame has an unknown type.
+    UnknownFrameType(u8),
+
+    /// The frame is larger than the maximum frame size negotiated with the peer.
+    FrameTooLarge(u32),
+
+    /// The frame is not terminated by the frame-end octet.
+    MissingFrameEnd,
+
+    /// The payload of the frame could not be decoded.
+    Syntax(&'static str),
+
+    /// The method is not implemented by the broker.
+    UnknownMethod(u16, u16),
+}
+
+impl Error {
+    /// Returns the reply code used to close the connection because of this error.
+    pub fn reply_code(&self) -> u16 {
+        match self {
+            Self::Io(_) => reply_code::INTERNAL_ERROR,
+            Self::UnknownFrameType(_) | Self::FrameTooLarge(_) | Self::MissingFrameEnd => {
+                reply_code::FRAME_ERROR
+            }
+            Self::Syntax(_) => reply_code::SYNTAX_ERROR,
+            Self::UnknownMethod(_, _) => reply_code::NOT_IMPLEMENTED,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::UnknownFrameType(frame_type) => write!(f, "unknown frame type {}", frame_type),
+            Self::FrameTooLarge(size) => write!(f, "frame of {} bytes is too large", size),
+            Self::MissingFrameEnd => write!(f, "frame is not terminated by a frame-end octet"),
+            Self::Syntax(reason) => write!(f, "malformed frame: {}", reason),
+            Self::UnknownMethod(class_id, method_id) => {
+                write!(f, "unknown method {}.{}", class_id, method_id)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
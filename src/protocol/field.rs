@@ -0,0 +1,32 @@
+use std::collections::BTreeMap;
+
+/// A table of named values, used for the properties of a connection or the headers of a message.
+pub type FieldTable = BTreeMap<String, FieldValue>;
+
+/// A value of a field table.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Bool(bool),
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Decimal(u8, u32),
+    LongStr(Vec<u8>),
+    Array(Vec<FieldValue>),
+    Timestamp(u64),
+    Table(FieldTable),
+    Void,
+    Bytes(Vec<u8>),
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        Self::LongStr(value.as_bytes().to_vec())
+    }
+}
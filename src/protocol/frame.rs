@@ -0,0 +1,189 @@
+use super::{ContentHeader, Encoder, Error, Method, Properties, CLASS_BASIC};
+use std::io::{Read, Write};
+
+const FRAME_METHOD: u8 = 1;
+const FRAME_HEADER: u8 = 2;
+const FRAME_BODY: u8 = 3;
+const FRAME_HEARTBEAT: u8 = 8;
+
+/// The octet terminating every frame.
+const FRAME_END: u8 = 0xCE;
+
+/// The size of the header of a frame and of its frame-end octet.
+const FRAME_OVERHEAD: u32 = 8;
+
+/// A frame exchanged between the broker and a client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    /// A method frame, sent on the given channel.
+    Method(u16, Method),
+
+    /// A content header frame, following a method carrying a content.
+    Header(u16, Box<ContentHeader>),
+
+    /// A content body frame, holding a part of the body announced by the content header.
+    Body(u16, Vec<u8>),
+
+    /// A heartbeat frame, always sent on the channel 0.
+    Heartbeat,
+}
+
+impl Frame {
+    /// Reads the next frame out of `reader`.
+    ///
+    /// The frame is rejected if its size, header and frame-end octet included, exceeds
+    /// `frame_max` bytes.
+    pub fn read<R: Read>(reader: &mut R, frame_max: u32) -> Result<Self, Error> {
+        let mut header = [0; 7];
+        reader.read_exact(&mut header)?;
+
+        let frame_type = header[0];
+        let channel = u16::from_be_bytes([header[1], header[2]]);
+        let size = u32::from_be_bytes([header[3], header[4], header[5], header[6]]);
+
+        if size.saturating_add(FRAME_OVERHEAD) > frame_max {
+            return Err(Error::FrameTooLarge(size));
+        }
+
+        let mut payload = vec![0; size as usize + 1];
+        reader.read_exact(&mut payload)?;
+
+        if payload.pop() != Some(FRAME_END) {
+            return Err(Error::MissingFrameEnd);
+        }
+
+        match frame_type {
+            FRAME_METHOD => Ok(Self::Method(channel, Method::decode(&payload)?)),
+            FRAME_HEADER => Ok(Self::Header(
+                channel,
+                Box::new(ContentHeader::decode(&payload)?),
+            )),
+            FRAME_BODY => Ok(Self::Body(channel, payload)),
+            FRAME_HEARTBEAT => Ok(Self::Heartbeat),
+            _ => Err(Error::UnknownFrameType(frame_type)),
+        }
+    }
+
+    /// Builds the frames sending a method which carries a content: the method frame, the content
+    /// header frame and the content body frames, each of them holding at most `frame_max` bytes.
+    pub fn content(
+        channel: u16,
+        method: Method,
+        properties: Properties,
+        body: &[u8],
+        frame_max: u32,
+    ) -> Vec<Self> {
+        let chunk_size = (frame_max - FRAME_OVERHEAD) as usize;
+        let mut frames = Vec::with_capacity(2 + body.len() / chunk_size + 1);
+
+        frames.push(Self::Method(channel, method));
+        frames.push(Self::Header(
+            channel,
+            Box::new(ContentHeader {
+                class_id: CLASS_BASIC,
+                body_size: body.len() as u64,
+                properties,
+            }),
+        ));
+        frames.extend(
+            body.chunks(chunk_size)
+                .map(|chunk| Self::Body(channel, chunk.to_vec())),
+        );
+
+        frames
+    }
+
+    /// Writes the frame into `writer`.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let mut encoder = Encoder::new();
+
+        let (frame_type, channel) = match self {
+            Self::Method(channel, method) => {
+                method.encode(&mut encoder);
+                (FRAME_METHOD, *channel)
+            }
+            Self::Header(channel, header) => {
+                header.encode(&mut encoder);
+                (FRAME_HEADER, *channel)
+            }
+            Self::Body(channel, body) => {
+                encoder.write_bytes(body);
+                (FRAME_BODY, *channel)
+            }
+            Self::Heartbeat => (FRAME_HEARTBEAT, 0),
+        };
+
+        let payload = encoder.into_inner();
+        let mut frame = Vec::with_capacity(payload.len() + FRAME_OVERHEAD as usize);
+
+        frame.push(frame_type);
+        frame.extend_from_slice(&channel.to_be_bytes());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame.push(FRAME_END);
+
+        writer.write_all(&frame)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::reply_code;
+    use std::io::Cursor;
+
+    /// Returns the bytes of a body frame on the channel 1 holding `size` bytes.
+    fn body_frame(size: usize) -> Vec<u8> {
+        let mut frame = Vec::new();
+
+        Frame::Body(1, vec![0; size]).write(&mut frame).unwrap();
+        frame
+    }
+
+    #[test]
+    fn frame_up_to_the_maximum_is_read() {
+        let frame = body_frame(4096 - FRAME_OVERHEAD as usize);
+
+        match Frame::read(&mut Cursor::new(frame), 4096) {
+            Ok(Frame::Body(1, body)) => assert_eq!(body.len(), 4088),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversize_frame_is_rejected() {
+        let frame = body_frame(4096 - FRAME_OVERHEAD as usize + 1);
+
+        match Frame::read(&mut Cursor::new(frame), 4096) {
+            Err(err @ Error::FrameTooLarge(4089)) => {
+                assert_eq!(err.reply_code(), reply_code::FRAME_ERROR)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversize_frame_is_rejected_before_its_payload_is_read() {
+        // The header announces a frame of 4 GiB, of which nothing is sent.
+        let mut reader = Cursor::new(vec![FRAME_BODY, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        assert!(matches!(
+            Frame::read(&mut reader, 131_072),
+            Err(Error::FrameTooLarge(u32::MAX))
+        ));
+        assert_eq!(reader.position(), 7);
+    }
+
+    #[test]
+    fn frame_without_frame_end_is_rejected() {
+        let mut frame = body_frame(16);
+        *frame.last_mut().unwrap() = 0;
+
+        assert!(matches!(
+            Frame::read(&mut Cursor::new(frame), 4096),
+            Err(Error::MissingFrameEnd)
+        ));
+    }
+}
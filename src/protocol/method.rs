@@ -0,0 +1,580 @@
+use super::{Decoder, Encoder, Error, FieldTable};
+
+/// A method of the protocol, carried by a method frame.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Method {
+    ConnectionStart {
+        version_major: u8,
+        version_minor: u8,
+        server_properties: FieldTable,
+        mechanisms: Vec<u8>,
+        locales: Vec<u8>,
+    },
+    ConnectionStartOk {
+        client_properties: FieldTable,
+        mechanism: String,
+        response: Vec<u8>,
+        locale: String,
+    },
+    ConnectionTune {
+        channel_max: u16,
+        frame_max: u32,
+        heartbeat: u16,
+    },
+    ConnectionTuneOk {
+        channel_max: u16,
+        frame_max: u32,
+        heartbeat: u16,
+    },
+    ConnectionOpen {
+        virtual_host: String,
+    },
+    ConnectionOpenOk,
+    ConnectionClose {
+        reply_code: u16,
+        reply_text: String,
+        class_id: u16,
+        method_id: u16,
+    },
+    ConnectionCloseOk,
+    ChannelOpen,
+    ChannelOpenOk,
+    ChannelClose {
+        reply_code: u16,
+        reply_text: String,
+        class_id: u16,
+        method_id: u16,
+    },
+    ChannelCloseOk,
+    QueueDeclare {
+        queue: String,
+        passive: bool,
+        durable: bool,
+        exclusive: bool,
+        auto_delete: bool,
+        no_wait: bool,
+        arguments: FieldTable,
+    },
+    QueueDeclareOk {
+        queue: String,
+        message_count: u32,
+        consumer_count: u32,
+    },
+    BasicQos {
+        prefetch_size: u32,
+        prefetch_count: u16,
+        global: bool,
+    },
+    BasicQosOk,
+    BasicConsume {
+        queue: String,
+        consumer_tag: String,
+        no_local: bool,
+        no_ack: bool,
+        exclusive: bool,
+        no_wait: bool,
+        arguments: FieldTable,
+    },
+    BasicConsumeOk {
+        consumer_tag: String,
+    },
+    BasicCancel {
+        consumer_tag: String,
+        no_wait: bool,
+    },
+    BasicCancelOk {
+        consumer_tag: String,
+    },
+    BasicPublish {
+        exchange: String,
+        routing_key: String,
+        mandatory: bool,
+        immediate: bool,
+    },
+    BasicReturn {
+        reply_code: u16,
+        reply_text: String,
+        exchange: String,
+        routing_key: String,
+    },
+    BasicDeliver {
+        consumer_tag: String,
+        delivery_tag: u64,
+        redelivered: bool,
+        exchange: String,
+        routing_key: String,
+    },
+    BasicGet {
+        queue: String,
+        no_ack: bool,
+    },
+    BasicGetOk {
+        delivery_tag: u64,
+        redelivered: bool,
+        exchange: String,
+        routing_key: String,
+        message_count: u32,
+    },
+    BasicGetEmpty,
+    BasicAck {
+        delivery_tag: u64,
+        multiple: bool,
+    },
+    BasicReject {
+        delivery_tag: u64,
+        requeue: bool,
+    },
+    BasicNack {
+        delivery_tag: u64,
+        multiple: bool,
+        requeue: bool,
+    },
+}
+
+impl Method {
+    /// Returns the class and the method identifiers of the method.
+    pub fn id(&self) -> (u16, u16) {
+        match self {
+            Self::ConnectionStart { .. } => (10, 10),
+            Self::ConnectionStartOk { .. } => (10, 11),
+            Self::ConnectionTune { .. } => (10, 30),
+            Self::ConnectionTuneOk { .. } => (10, 31),
+            Self::ConnectionOpen { .. } => (10, 40),
+            Self::ConnectionOpenOk => (10, 41),
+            Self::ConnectionClose { .. } => (10, 50),
+            Self::ConnectionCloseOk => (10, 51),
+            Self::ChannelOpen => (20, 10),
+            Self::ChannelOpenOk => (20, 11),
+            Self::ChannelClose { .. } => (20, 40),
+            Self::ChannelCloseOk => (20, 41),
+            Self::QueueDeclare { .. } => (50, 10),
+            Self::QueueDeclareOk { .. } => (50, 11),
+            Self::BasicQos { .. } => (60, 10),
+            Self::BasicQosOk => (60, 11),
+            Self::BasicConsume { .. } => (60, 20),
+            Self::BasicConsumeOk { .. } => (60, 21),
+            Self::BasicCancel { .. } => (60, 30),
+            Self::BasicCancelOk { .. } => (60, 31),
+            Self::BasicPublish { .. } => (60, 40),
+            Self::BasicReturn { .. } => (60, 50),
+            Self::BasicDeliver { .. } => (60, 60),
+            Self::BasicGet { .. } => (60, 70),
+            Self::BasicGetOk { .. } => (60, 71),
+            Self::BasicGetEmpty => (60, 72),
+            Self::BasicAck { .. } => (60, 80),
+            Self::BasicReject { .. } => (60, 90),
+            Self::BasicNack { .. } => (60, 120),
+        }
+    }
+
+    /// Decodes a method out of the payload of a method frame.
+    pub fn decode(payload: &[u8]) -> Result<Self, Error> {
+        let mut decoder = Decoder::new(payload);
+        let class_id = decoder.read_u16()?;
+        let method_id = decoder.read_u16()?;
+
+        let method = match (class_id, method_id) {
+            (10, 10) => Self::ConnectionStart {
+                version_major: decoder.read_u8()?,
+                version_minor: decoder.read_u8()?,
+                server_properties: decoder.read_table()?,
+                mechanisms: decoder.read_long_str()?,
+                locales: decoder.read_long_str()?,
+            },
+            (10, 11) => Self::ConnectionStartOk {
+                client_properties: decoder.read_table()?,
+                mechanism: decoder.read_short_str()?,
+                response: decoder.read_long_str()?,
+                locale: decoder.read_short_str()?,
+            },
+            (10, 30) => Self::ConnectionTune {
+                channel_max: decoder.read_u16()?,
+                frame_max: decoder.read_u32()?,
+                heartbeat: decoder.read_u16()?,
+            },
+            (10, 31) => Self::ConnectionTuneOk {
+                channel_max: decoder.read_u16()?,
+                frame_max: decoder.read_u32()?,
+                heartbeat: decoder.read_u16()?,
+            },
+            (10, 40) => {
+                let virtual_host = decoder.read_short_str()?;
+                let _reserved = decoder.read_short_str()?;
+                let _reserved = decoder.read_u8()?;
+
+                Self::ConnectionOpen { virtual_host }
+            }
+            (10, 41) => {
+                let _reserved = decoder.read_short_str()?;
+
+                Self::ConnectionOpenOk
+            }
+            (10, 50) => Self::ConnectionClose {
+                reply_code: decoder.read_u16()?,
+                reply_text: decoder.read_short_str()?,
+                class_id: decoder.read_u16()?,
+                method_id: decoder.read_u16()?,
+            },
+            (10, 51) => Self::ConnectionCloseOk,
+            (20, 10) => {
+                let _reserved = decoder.read_short_str()?;
+
+                Self::ChannelOpen
+            }
+            (20, 11) => {
+                let _reserved = decoder.read_long_str()?;
+
+                Self::ChannelOpenOk
+            }
+            (20, 40) => Self::ChannelClose {
+                reply_code: decoder.read_u16()?,
+                reply_text: decoder.read_short_str()?,
+                class_id: decoder.read_u16()?,
+                method_id: decoder.read_u16()?,
+            },
+            (20, 41) => Self::ChannelCloseOk,
+            (50, 10) => {
+                let _reserved = decoder.read_u16()?;
+                let queue = decoder.read_short_str()?;
+                let bits = decoder.read_u8()?;
+
+                Self::QueueDeclare {
+                    queue,
+                    passive: bits & 0x01 != 0,
+                    durable: bits & 0x02 != 0,
+                    exclusive: bits & 0x04 != 0,
+                    auto_delete: bits & 0x08 != 0,
+                    no_wait: bits & 0x10 != 0,
+                    arguments: decoder.read_table()?,
+                }
+            }
+            (50, 11) => Self::QueueDeclareOk {
+                queue: decoder.read_short_str()?,
+                message_count: decoder.read_u32()?,
+                consumer_count: decoder.read_u32()?,
+            },
+            (60, 10) => Self::BasicQos {
+                prefetch_size: decoder.read_u32()?,
+                prefetch_count: decoder.read_u16()?,
+                global: decoder.read_u8()? & 0x01 != 0,
+            },
+            (60, 11) => Self::BasicQosOk,
+            (60, 20) => {
+                let _reserved = decoder.read_u16()?;
+                let queue = decoder.read_short_str()?;
+                let consumer_tag = decoder.read_short_str()?;
+                let bits = decoder.read_u8()?;
+
+                Self::BasicConsume {
+                    queue,
+                    consumer_tag,
+                    no_local: bits & 0x01 != 0,
+                    no_ack: bits & 0x02 != 0,
+                    exclusive: bits & 0x04 != 0,
+                    no_wait: bits & 0x08 != 0,
+                    arguments: decoder.read_table()?,
+                }
+            }
+            (60, 21) => Self::BasicConsumeOk {
+                consumer_tag: decoder.read_short_str()?,
+            },
+            (60, 30) => Self::BasicCancel {
+                consumer_tag: decoder.read_short_str()?,
+                no_wait: decoder.read_u8()? & 0x01 != 0,
+            },
+            (60, 31) => Self::BasicCancelOk {
+                consumer_tag: decoder.read_short_str()?,
+            },
+            (60, 40) => {
+                let _reserved = decoder.read_u16()?;
+                let exchange = decoder.read_short_str()?;
+                let routing_key = decoder.read_short_str()?;
+                let bits = decoder.read_u8()?;
+
+                Self::BasicPublish {
+                    exchange,
+                    routing_key,
+                    mandatory: bits & 0x01 != 0,
+                    immediate: bits & 0x02 != 0,
+                }
+            }
+            (60, 50) => Self::BasicReturn {
+                reply_code: decoder.read_u16()?,
+                reply_text: decoder.read_short_str()?,
+                exchange: decoder.read_short_str()?,
+                routing_key: decoder.read_short_str()?,
+            },
+            (60, 60) => Self::BasicDeliver {
+                consumer_tag: decoder.read_short_str()?,
+                delivery_tag: decoder.read_u64()?,
+                redelivered: decoder.read_u8()? & 0x01 != 0,
+                exchange: decoder.read_short_str()?,
+                routing_key: decoder.read_short_str()?,
+            },
+            (60, 70) => {
+                let _reserved = decoder.read_u16()?;
+
+                Self::BasicGet {
+                    queue: decoder.read_short_str()?,
+                    no_ack: decoder.read_u8()? & 0x01 != 0,
+                }
+            }
+            (60, 71) => Self::BasicGetOk {
+                delivery_tag: decoder.read_u64()?,
+                redelivered: decoder.read_u8()? & 0x01 != 0,
+                exchange: decoder.read_short_str()?,
+                routing_key: decoder.read_short_str()?,
+                message_count: decoder.read_u32()?,
+            },
+            (60, 72) => {
+                let _reserved = decoder.read_short_str()?;
+
+                Self::BasicGetEmpty
+            }
+            (60, 80) => Self::BasicAck {
+                delivery_tag: decoder.read_u64()?,
+                multiple: decoder.read_u8()? & 0x01 != 0,
+            },
+            (60, 90) => Self::BasicReject {
+                delivery_tag: decoder.read_u64()?,
+                requeue: decoder.read_u8()? & 0x01 != 0,
+            },
+            (60, 120) => {
+                let delivery_tag = decoder.read_u64()?;
+                let bits = decoder.read_u8()?;
+
+                Self::BasicNack {
+                    delivery_tag,
+                    multiple: bits & 0x01 != 0,
+                    requeue: bits & 0x02 != 0,
+                }
+            }
+            _ => return Err(Error::UnknownMethod(class_id, method_id)),
+        };
+
+        Ok(method)
+    }
+
+    /// Encodes the method into the payload of a method frame.
+    pub fn encode(&self, encoder: &mut Encoder) {
+        let (class_id, method_id) = self.id();
+
+        encoder.write_u16(class_id);
+        encoder.write_u16(method_id);
+
+        match self {
+            Self::ConnectionStart {
+                version_major,
+                version_minor,
+                server_properties,
+                mechanisms,
+                locales,
+            } => {
+                encoder.write_u8(*version_major);
+                encoder.write_u8(*version_minor);
+                encoder.write_table(server_properties);
+                encoder.write_long_str(mechanisms);
+                encoder.write_long_str(locales);
+            }
+            Self::ConnectionStartOk {
+                client_properties,
+                mechanism,
+                response,
+                locale,
+            } => {
+                encoder.write_table(client_properties);
+                encoder.write_short_str(mechanism);
+                encoder.write_long_str(response);
+                encoder.write_short_str(locale);
+            }
+            Self::ConnectionTune {
+                channel_max,
+                frame_max,
+                heartbeat,
+            }
+            | Self::ConnectionTuneOk {
+                channel_max,
+                frame_max,
+                heartbeat,
+            } => {
+                encoder.write_u16(*channel_max);
+                encoder.write_u32(*frame_max);
+                encoder.write_u16(*heartbeat);
+            }
+            Self::ConnectionOpen { virtual_host } => {
+                encoder.write_short_str(virtual_host);
+                encoder.write_short_str("");
+                encoder.write_u8(0);
+            }
+            Self::ConnectionOpenOk => encoder.write_short_str(""),
+            Self::ConnectionClose {
+                reply_code,
+                reply_text,
+                class_id,
+                method_id,
+            }
+            | Self::ChannelClose {
+                reply_code,
+                reply_text,
+                class_id,
+                method_id,
+            } => {
+                encoder.write_u16(*reply_code);
+                encoder.write_short_str(reply_text);
+                encoder.write_u16(*class_id);
+                encoder.write_u16(*method_id);
+            }
+            Self::ConnectionCloseOk | Self::ChannelCloseOk => {}
+            Self::ChannelOpen => encoder.write_short_str(""),
+            Self::ChannelOpenOk => encoder.write_long_str(b""),
+            Self::QueueDeclare {
+                queue,
+                passive,
+                durable,
+                exclusive,
+                auto_delete,
+                no_wait,
+                arguments,
+            } => {
+                encoder.write_u16(0);
+                encoder.write_short_str(queue);
+                encoder.write_u8(bits(&[
+                    *passive,
+                    *durable,
+                    *exclusive,
+                    *auto_delete,
+                    *no_wait,
+                ]));
+                encoder.write_table(arguments);
+            }
+            Self::QueueDeclareOk {
+                queue,
+                message_count,
+                consumer_count,
+            } => {
+                encoder.write_short_str(queue);
+                encoder.write_u32(*message_count);
+                encoder.write_u32(*consumer_count);
+            }
+            Self::BasicQos {
+                prefetch_size,
+                prefetch_count,
+                global,
+            } => {
+                encoder.write_u32(*prefetch_size);
+                encoder.write_u16(*prefetch_count);
+                encoder.write_u8(bits(&[*global]));
+            }
+            Self::BasicQosOk => {}
+            Self::BasicConsume {
+                queue,
+                consumer_tag,
+                no_local,
+                no_ack,
+                exclusive,
+                no_wait,
+                arguments,
+            } => {
+                encoder.write_u16(0);
+                encoder.write_short_str(queue);
+                encoder.write_short_str(consumer_tag);
+                encoder.write_u8(bits(&[*no_local, *no_ack, *exclusive, *no_wait]));
+                encoder.write_table(arguments);
+            }
+            Self::BasicConsumeOk { consumer_tag } | Self::BasicCancelOk { consumer_tag } => {
+                encoder.write_short_str(consumer_tag)
+            }
+            Self::BasicCancel {
+                consumer_tag,
+                no_wait,
+            } => {
+                encoder.write_short_str(consumer_tag);
+                encoder.write_u8(bits(&[*no_wait]));
+            }
+            Self::BasicPublish {
+                exchange,
+                routing_key,
+                mandatory,
+                immediate,
+            } => {
+                encoder.write_u16(0);
+                encoder.write_short_str(exchange);
+                encoder.write_short_str(routing_key);
+                encoder.write_u8(bits(&[*mandatory, *immediate]));
+            }
+            Self::BasicReturn {
+                reply_code,
+                reply_text,
+                exchange,
+                routing_key,
+            } => {
+                encoder.write_u16(*reply_code);
+                encoder.write_short_str(reply_text);
+                encoder.write_short_str(exchange);
+                encoder.write_short_str(routing_key);
+            }
+            Self::BasicDeliver {
+                consumer_tag,
+                delivery_tag,
+                redelivered,
+                exchange,
+                routing_key,
+            } => {
+                encoder.write_short_str(consumer_tag);
+                encoder.write_u64(*delivery_tag);
+                encoder.write_u8(bits(&[*redelivered]));
+                encoder.write_short_str(exchange);
+                encoder.write_short_str(routing_key);
+            }
+            Self::BasicGet { queue, no_ack } => {
+                encoder.write_u16(0);
+                encoder.write_short_str(queue);
+                encoder.write_u8(bits(&[*no_ack]));
+            }
+            Self::BasicGetOk {
+                delivery_tag,
+                redelivered,
+                exchange,
+                routing_key,
+                message_count,
+            } => {
+                encoder.write_u64(*delivery_tag);
+                encoder.write_u8(bits(&[*redelivered]));
+                encoder.write_short_str(exchange);
+                encoder.write_short_str(routing_key);
+                encoder.write_u32(*message_count);
+            }
+            Self::BasicGetEmpty => encoder.write_short_str(""),
+            Self::BasicAck {
+                delivery_tag,
+                multiple,
+            } => {
+                encoder.write_u64(*delivery_tag);
+                encoder.write_u8(bits(&[*multiple]));
+            }
+            Self::BasicReject {
+                delivery_tag,
+                requeue,
+            } => {
+                encoder.write_u64(*delivery_tag);
+                encoder.write_u8(bits(&[*requeue]));
+            }
+            Self::BasicNack {
+                delivery_tag,
+                multiple,
+                requeue,
+            } => {
+                encoder.write_u64(*delivery_tag);
+                encoder.write_u8(bits(&[*multiple, *requeue]));
+            }
+        }
+    }
+}
+
+/// Packs consecutive bit fields into an octet, the first field being the least significant bit.
+fn bits(fields: &[bool]) -> u8 {
+    fields
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &field)| bits | ((field as u8) << i))
+}
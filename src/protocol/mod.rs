@@ -0,0 +1,48 @@
+//! The implementation of the wire format of the [AMQP 0.9.1](https://www.rabbitmq.com/resources/specs/amqp0-9-1.pdf)
+//! protocol spoken by the clients of `another-mq`.
+//!
+//! Only the classes and the methods used by the broker are implemented.
+
+mod codec;
+mod content;
+mod error;
+mod field;
+mod frame;
+mod method;
+
+pub use codec::{Decoder, Encoder};
+pub use content::{ContentHeader, Properties, CLASS_BASIC};
+pub use error::Error;
+pub use field::{FieldTable, FieldValue};
+pub use frame::Frame;
+pub use method::Method;
+
+/// The header sent by a client when it opens a connection.
+pub const PROTOCOL_HEADER: [u8; 8] = *b"AMQP\x00\x00\x09\x01";
+
+/// The minimum size of a frame that any peer must accept, used until the connection is tuned.
+pub const FRAME_MIN_SIZE: u32 = 4096;
+
+/// The reply codes used to close a channel or a connection, as defined by the specification.
+#[allow(dead_code)]
+pub mod reply_code {
+    pub const REPLY_SUCCESS: u16 = 200;
+    pub const CONTENT_TOO_LARGE: u16 = 311;
+    pub const NO_ROUTE: u16 = 312;
+    pub const NO_CONSUMERS: u16 = 313;
+    pub const CONNECTION_FORCED: u16 = 320;
+    pub const INVALID_PATH: u16 = 402;
+    pub const ACCESS_REFUSED: u16 = 403;
+    pub const NOT_FOUND: u16 = 404;
+    pub const RESOURCE_LOCKED: u16 = 405;
+    pub const PRECONDITION_FAILED: u16 = 406;
+    pub const FRAME_ERROR: u16 = 501;
+    pub const SYNTAX_ERROR: u16 = 502;
+    pub const COMMAND_INVALID: u16 = 503;
+    pub const CHANNEL_ERROR: u16 = 504;
+    pub const UNEXPECTED_FRAME: u16 = 505;
+    pub const RESOURCE_ERROR: u16 = 506;
+    pub const NOT_ALLOWED: u16 = 530;
+    pub const NOT_IMPLEMENTED: u16 = 540;
+    pub const INTERNAL_ERROR: u16 = 541;
+}
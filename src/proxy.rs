@@ -0,0 +1,131 @@
+//! The header of the PROXY protocol, sent by a load balancer at the start of each connection it
+//! forwards, so the broker knows the address of the client rather than the one of the load
+//! balancer.
+//!
+//! Both versions of the header are read:
+//!
+//! | Version | Header                                                                        |
+//! | ------- | ----------------------------------------------------------------------------- |
+//! | 1       | A line such as `PROXY TCP4 192.0.2.1 198.51.100.1 56324 5672\r\n`             |
+//! | 2       | A binary header, starting with the signature `\r\n\r\n\0\r\nQUIT\n`           |
+//!
+//! The headers which don't hold the address of a client, such as the `UNKNOWN` ones of version 1
+//! or the `LOCAL` ones of version 2 sent by the health checks, leave the connection with the
+//! address it was accepted from.
+
+use std::{
+    io::{self, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+/// The signature starting the headers of version 2.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The maximum size of a header of version 1, line break included.
+const V1_MAX_BYTES: usize = 107;
+
+/// Reads the header at the start of a connection, returning the address of the client it holds,
+/// or `None` if it holds none. Nothing past the header is read. An error of the `InvalidData`
+/// kind is returned if the header is malformed.
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0; 6];
+    reader.read_exact(&mut start)?;
+
+    if &start == b"PROXY " {
+        read_v1(reader)
+    } else if start == V2_SIGNATURE[..6] {
+        read_v2(reader)
+    } else {
+        Err(invalid(
+            "the connection does not start with a PROXY protocol header",
+        ))
+    }
+}
+
+/// Reads the rest of a header of version 1, once its `PROXY ` prefix is read.
+fn read_v1<R: Read>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    // The line is read byte by byte, so the bytes following it are left to the connection.
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        if line.len() + 6 >= V1_MAX_BYTES {
+            return Err(invalid("the header is too long"));
+        }
+
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("the header is not valid ASCII"))?;
+    let fields: Vec<_> = line.split(' ').collect();
+
+    match fields[..] {
+        ["UNKNOWN", ..] => Ok(None),
+        [protocol @ ("TCP4" | "TCP6"), source, _, source_port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("the source address is invalid"))?;
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| invalid("the source port is invalid"))?;
+
+            if ip.is_ipv4() != (protocol == "TCP4") {
+                return Err(invalid("the source address does not match the protocol"));
+            }
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("the header is malformed")),
+    }
+}
+
+/// Reads the rest of a header of version 2, once the start of its signature is read.
+fn read_v2<R: Read>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut rest = [0; 10];
+    reader.read_exact(&mut rest)?;
+
+    if rest[..6] != V2_SIGNATURE[6..] {
+        return Err(invalid("the signature of the header is invalid"));
+    }
+    let (version, command, family) = (rest[6] >> 4, rest[6] & 0x0f, rest[7]);
+    let len = u16::from_be_bytes([rest[8], rest[9]]) as usize;
+
+    // The addresses are followed by TLVs, which are skipped.
+    let mut addresses = vec![0; len];
+    reader.read_exact(&mut addresses)?;
+
+    if version != 2 {
+        return Err(invalid("the version of the header is not supported"));
+    }
+    match command {
+        // A connection of the proxy itself, such as a health check.
+        0x0 => return Ok(None),
+        0x1 => {}
+        _ => return Err(invalid("the command of the header is not supported")),
+    }
+
+    // The address family is in the high nibble, the transport protocol in the low one.
+    match family >> 4 {
+        0x1 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        0x2 if addresses.len() >= 36 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        0x1 | 0x2 => Err(invalid("the addresses of the header are truncated")),
+        // The unspecified family and the Unix sockets have no address to recover.
+        0x0 | 0x3 => Ok(None),
+        _ => Err(invalid("the address family of the header is not supported")),
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
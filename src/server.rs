@@ -0,0 +1,1200 @@
+//! The server accepting the connections of the clients on each listener of the application.
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsAcceptor;
+use crate::{
+    broker::Broker,
+    config::{Cidr, Config, Listener, ListenerProtocol, Network},
+    connection::{Connection, ListenerRateLimiter},
+    proxy,
+    shutdown::Shutdown,
+    stream::{Peer, Stream},
+    text::Session,
+};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{
+        fs::FileTypeExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The longest a listener waits after failing to accept connections repeatedly, unless the
+/// backoff of the network namespace is longer.
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+/// How long a listener expecting the PROXY protocol waits for the header of a connection.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The listeners of the application, bound to their addresses.
+pub struct Server {
+    listeners: Vec<BoundListener>,
+    transport: Transport,
+    /// The ranges of addresses the clients can connect from, if they're restricted.
+    allowed_ranges: Option<Arc<Vec<Cidr>>>,
+}
+
+impl Server {
+    /// Binds every listener of the network namespace, then its Unix socket if it defines one.
+    pub fn bind(network: &Network) -> io::Result<Self> {
+        let transport = Transport::new(network)?;
+        let mut listeners: Vec<_> = if network.listeners.is_empty() {
+            let listener = &network.effective_listeners()[0];
+            let bound = bind(listener, network.ports())?;
+            if let (Some((first, last)), Some(address)) = (network.port_range, bound.local_addr()) {
+                if address.port() != network.port {
+                    log::info!(
+                        "The port {} is in use, picked the port {} of the range {}-{}",
+                        network.port,
+                        address.port(),
+                        first,
+                        last
+                    );
+                }
+            }
+            vec![bound]
+        } else {
+            network
+                .effective_listeners()
+                .iter()
+                .map(|listener| bind(listener, Some(listener.port)))
+                .collect::<io::Result<_>>()?
+        };
+        listeners.extend(bind_unix(network)?);
+
+        let backlog = effective_backlog(network.effective_backlog());
+        for listener in &listeners {
+            set_backlog(&listener.socket, backlog)?;
+        }
+
+        Ok(Self {
+            listeners,
+            transport,
+            allowed_ranges: network.allowed_ranges().map(Arc::new),
+        })
+    }
+
+    /// Returns the addresses the TCP listeners are bound to, with the ports they ended up with.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(BoundListener::local_addr)
+            .collect()
+    }
+
+    /// Accepts the connections of the clients on every listener, until the shutdown is requested.
+    /// The clients are then given up to the shutdown timeout of the network namespace to close
+    /// their connections.
+    pub fn run(self, broker: Arc<Broker>, config: Arc<RwLock<Config>>, shutdown: Arc<Shutdown>) {
+        let shutdown_timeout = config.read().unwrap().network.shutdown_timeout;
+        let transport = self.transport;
+        let allowed_ranges = self.allowed_ranges;
+        let handles: Vec<_> = self
+            .listeners
+            .into_iter()
+            .map(|listener| {
+                let transport = transport.clone();
+                let allowed_ranges = allowed_ranges.clone();
+                let broker = broker.clone();
+                let config = config.clone();
+                let shutdown = shutdown.clone();
+
+                thread::Builder::new()
+                    .name(format!("listener-{}", listener.connections.address))
+                    .spawn(move || {
+                        accept(
+                            listener,
+                            transport,
+                            allowed_ranges,
+                            broker,
+                            config,
+                            shutdown,
+                        )
+                    })
+            })
+            .filter_map(|handle| match handle {
+                Ok(handle) => Some(handle),
+                Err(err) => {
+                    log::error!("Could not start a listener: {}", err);
+                    None
+                }
+            })
+            .collect();
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+
+        match shutdown.wait_connections(shutdown_timeout) {
+            0 => log::info!("Every connection is closed"),
+            open => log::warn!(
+                "Stopping with {} connections still open after {}",
+                open,
+                humantime::format_duration(shutdown_timeout)
+            ),
+        }
+    }
+}
+
+/// Turns the sockets accepted by the listeners into the streams of the connections.
+#[derive(Clone)]
+struct Transport {
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<TlsAcceptor>>,
+}
+
+impl Transport {
+    #[cfg(feature = "tls")]
+    fn new(network: &Network) -> io::Result<Self> {
+        let tls = match &network.tls {
+            Some(tls) => {
+                let acceptor = tls
+                    .load()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+
+                Some(Arc::new(acceptor))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            nodelay: network.tcp_nodelay,
+            keepalive: network.tcp_keepalive,
+            tls,
+        })
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn new(network: &Network) -> io::Result<Self> {
+        Ok(Self {
+            nodelay: network.tcp_nodelay,
+            keepalive: network.tcp_keepalive,
+        })
+    }
+
+    /// Opens the stream of a connection, once the options of its socket are set, along with the
+    /// `buffers` of its listener, performing the TLS handshake if needed, each of its reads
+    /// waiting for up to `handshake_timeout`. The connections of the Unix socket are opened as
+    /// they are.
+    fn open(
+        &self,
+        socket: Socket,
+        buffers: Option<&SocketBuffers>,
+        handshake_timeout: Duration,
+    ) -> io::Result<Stream> {
+        let socket = match socket {
+            Socket::Tcp(socket) => socket,
+            #[cfg(unix)]
+            Socket::Unix(socket) => return Ok(Stream::Unix(socket)),
+        };
+
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = self.keepalive {
+            set_keepalive(&socket, keepalive)?;
+        }
+        if let Some(buffers) = buffers {
+            buffers.apply(&socket)?;
+        }
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            socket.set_read_timeout(Some(handshake_timeout))?;
+            return tls.accept(socket).map(Stream::Tls);
+        }
+        #[cfg(not(feature = "tls"))]
+        let _ = handshake_timeout;
+
+        Ok(Stream::Tcp(socket))
+    }
+}
+
+/// A listener bound to its address.
+struct BoundListener {
+    socket: Arc<ListenerSocket>,
+    protocol: ListenerProtocol,
+    /// Whether the connections start with the header of the PROXY protocol.
+    proxy_protocol: bool,
+    connections: Arc<ConnectionCounter>,
+    /// The rate limit of the messages published by the connections, all together.
+    rate_limiter: Option<Arc<ListenerRateLimiter>>,
+    /// The sizes of the buffers of the sockets of the connections, if they're not left to the OS.
+    buffers: Option<Arc<SocketBuffers>>,
+}
+
+impl BoundListener {
+    /// Returns the address the listener is bound to, if it's a TCP listener.
+    fn local_addr(&self) -> Option<SocketAddr> {
+        match &*self.socket {
+            ListenerSocket::Tcp(socket) => socket.local_addr().ok(),
+            #[cfg(unix)]
+            ListenerSocket::Unix(..) => None,
+        }
+    }
+}
+
+/// The sizes of the buffers set on the sockets of the connections of a TCP listener.
+struct SocketBuffers {
+    /// The address the listener is bound to.
+    address: String,
+    recv_bytes: Option<usize>,
+    send_bytes: Option<usize>,
+    /// Whether the sizes the OS ended up with were logged, which is done for the first connection
+    /// only, as the OS treats the sockets of a listener alike.
+    logged: AtomicBool,
+}
+
+impl SocketBuffers {
+    /// Sets the sizes of the buffers of `socket`, logging the sizes the OS ends up with, which
+    /// may be clamped by its limits.
+    #[cfg(unix)]
+    fn apply(&self, socket: &TcpStream) -> io::Result<()> {
+        let fd = socket.as_raw_fd();
+
+        // The sizes are checked against `c_int::MAX` by the validation of the network namespace.
+        if let Some(bytes) = self.recv_bytes {
+            set_socket_option(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, bytes as libc::c_int)?;
+        }
+        if let Some(bytes) = self.send_bytes {
+            set_socket_option(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, bytes as libc::c_int)?;
+        }
+
+        if self.logged.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let recv_bytes = buffer_size(fd, libc::SO_RCVBUF, self.recv_bytes.is_some())?;
+        let send_bytes = buffer_size(fd, libc::SO_SNDBUF, self.send_bytes.is_some())?;
+
+        log::info!(
+            "The sockets of the connections on {} have buffers of {} bytes to receive and {} \
+             bytes to send",
+            self.address,
+            recv_bytes,
+            send_bytes
+        );
+        for (name, requested, effective) in [
+            ("receive", self.recv_bytes, recv_bytes),
+            ("send", self.send_bytes, send_bytes),
+        ] {
+            match requested {
+                Some(requested) if effective < requested => log::warn!(
+                    "The OS clamped the {} buffers of the sockets on {} to {} bytes, below the {} \
+                     bytes configured",
+                    name,
+                    self.address,
+                    effective,
+                    requested
+                ),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The sizes of the buffers are rejected by the validation of the network namespace on these
+    /// platforms.
+    #[cfg(not(unix))]
+    fn apply(&self, _: &TcpStream) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The socket on which a listener accepts the connections.
+enum ListenerSocket {
+    Tcp(TcpListener),
+    /// A Unix socket, along with the path of its file which is removed once the listener stops.
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl ListenerSocket {
+    /// Accepts a connection, `number` being the number of the connections accepted before it.
+    fn accept(&self, number: u64) -> io::Result<(Socket, Peer)> {
+        match self {
+            Self::Tcp(listener) => listener
+                .accept()
+                .map(|(socket, peer)| (Socket::Tcp(socket), Peer::Tcp(peer))),
+            #[cfg(unix)]
+            Self::Unix(listener, path) => listener.accept().map(|(socket, _)| {
+                let peer = Peer::Unix(path.display().to_string(), number);
+                (Socket::Unix(socket), peer)
+            }),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for ListenerSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Tcp(listener) => listener.as_raw_fd(),
+            Self::Unix(listener, _) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// A socket accepted by a listener, whose stream is not opened yet.
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// The number of connections opened on a listener, bounded by the limit of the listener.
+pub struct ConnectionCounter {
+    /// The address or the path the listener is bound to.
+    address: String,
+    limit: Option<usize>,
+    current: AtomicUsize,
+
+    /// Whether the limit was reached, so it's only reported once until a connection drops.
+    limit_reached: AtomicBool,
+}
+
+impl ConnectionCounter {
+    fn new(address: String, limit: Option<usize>) -> Self {
+        Self {
+            address,
+            limit,
+            current: AtomicUsize::new(0),
+            limit_reached: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the number of connections currently opened on the listener.
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// Counts a new connection, unless the limit of the listener is reached.
+    fn acquire(self: &Arc<Self>) -> Option<ConnectionSlot> {
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let acquired = self
+            .current
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                if current < limit {
+                    Some(current + 1)
+                } else {
+                    None
+                }
+            });
+
+        match acquired {
+            Ok(_) => Some(ConnectionSlot(self.clone())),
+            Err(current) => {
+                if !self.limit_reached.swap(true, Ordering::SeqCst) {
+                    log::warn!(
+                        "The listener {} reached its limit of {} connections",
+                        self.address,
+                        current
+                    );
+                }
+
+                None
+            }
+        }
+    }
+}
+
+/// A connection counted by a listener, until it's dropped.
+struct ConnectionSlot(Arc<ConnectionCounter>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::SeqCst);
+        self.0.limit_reached.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Binds `listener` to the first free port of `ports`.
+fn bind(listener: &Listener, ports: impl IntoIterator<Item = u16>) -> io::Result<BoundListener> {
+    match bind_first_free(listener, ports) {
+        Ok(socket) => {
+            let address = socket.local_addr()?;
+            match listener.protocol {
+                ListenerProtocol::Native => log::info!("Listening on {}", address),
+                ListenerProtocol::Text => {
+                    log::info!("Listening on {} with the text protocol", address)
+                }
+            }
+            Ok(BoundListener {
+                socket: Arc::new(ListenerSocket::Tcp(socket)),
+                protocol: listener.protocol,
+                proxy_protocol: listener.proxy_protocol == Some(true),
+                connections: Arc::new(ConnectionCounter::new(
+                    address.to_string(),
+                    listener.max_connections,
+                )),
+                rate_limiter: listener
+                    .max_messages_per_second
+                    .map(|rate| Arc::new(ListenerRateLimiter::new(rate))),
+                buffers: match (listener.recv_buffer_bytes, listener.send_buffer_bytes) {
+                    (None, None) => None,
+                    (recv_bytes, send_bytes) => Some(Arc::new(SocketBuffers {
+                        address: address.to_string(),
+                        recv_bytes,
+                        send_bytes,
+                        logged: AtomicBool::new(false),
+                    })),
+                },
+            })
+        }
+        Err(err) => Err(io::Error::new(
+            err.kind(),
+            format!("could not bind {}: {}", listener.address(), err),
+        )),
+    }
+}
+
+/// Opens a TCP socket listening on the hostname of `listener` and the first of `ports` which can
+/// be bound, with the socket options of the listener. The error of the last port is returned if
+/// none can.
+pub fn bind_first_free(
+    listener: &Listener,
+    ports: impl IntoIterator<Item = u16>,
+) -> io::Result<TcpListener> {
+    let reuse_address = listener.reuse_address == Some(true);
+    let reuse_port = listener.reuse_port == Some(true);
+    let mut last_err = None;
+
+    for port in ports {
+        let address = SocketAddr::new(listener.hostname, port);
+        match bind_tcp(address, reuse_address, reuse_port) {
+            Ok(socket) => return Ok(socket),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no port to bind")))
+}
+
+/// Opens a TCP socket listening on `address`, `SO_REUSEADDR` and `SO_REUSEPORT` being set as
+/// asked before it's bound.
+#[cfg(unix)]
+pub fn bind_tcp(
+    address: SocketAddr,
+    reuse_address: bool,
+    reuse_port: bool,
+) -> io::Result<TcpListener> {
+    let domain = match address {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The listener owns the socket right away, so it's closed if it can't be bound.
+    let socket = unsafe { TcpListener::from_raw_fd(fd) };
+
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    set_socket_option(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_REUSEADDR,
+        reuse_address as libc::c_int,
+    )?;
+    if reuse_port {
+        set_socket_option(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)?;
+    }
+
+    let bound = match address {
+        SocketAddr::V4(address) => {
+            let mut raw: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+            raw.sin_family = libc::AF_INET as libc::sa_family_t;
+            raw.sin_port = address.port().to_be();
+            raw.sin_addr.s_addr = u32::from_ne_bytes(address.ip().octets());
+
+            unsafe {
+                libc::bind(
+                    fd,
+                    &raw as *const libc::sockaddr_in as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(address) => {
+            let mut raw: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+            raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            raw.sin6_port = address.port().to_be();
+            raw.sin6_addr.s6_addr = address.ip().octets();
+            raw.sin6_flowinfo = address.flowinfo();
+            raw.sin6_scope_id = address.scope_id();
+
+            unsafe {
+                libc::bind(
+                    fd,
+                    &raw as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if bound < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The backlog is set once every listener is bound.
+    if unsafe { libc::listen(fd, 128) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+/// The socket options are left to the standard library on these platforms, `SO_REUSEPORT` being
+/// rejected by the validation of the network namespace.
+#[cfg(not(unix))]
+pub fn bind_tcp(address: SocketAddr, _: bool, _: bool) -> io::Result<TcpListener> {
+    TcpListener::bind(address)
+}
+
+/// Binds the Unix socket of the network namespace, if it defines one. Its clients speak the
+/// native protocol, and they're bounded by the limit of connections of the namespace.
+#[cfg(unix)]
+fn bind_unix(network: &Network) -> io::Result<Option<BoundListener>> {
+    let path = match &network.unix_socket {
+        Some(path) => PathBuf::from(path),
+        None => return Ok(None),
+    };
+
+    remove_stale_socket(&path)?;
+
+    match UnixListener::bind(&path) {
+        Ok(socket) => {
+            log::info!("Listening on {}", path.display());
+
+            let address = path.display().to_string();
+            Ok(Some(BoundListener {
+                socket: Arc::new(ListenerSocket::Unix(socket, path)),
+                protocol: ListenerProtocol::Native,
+                proxy_protocol: false,
+                connections: Arc::new(ConnectionCounter::new(address, network.max_connections)),
+                rate_limiter: None,
+                buffers: None,
+            }))
+        }
+        Err(err) => Err(io::Error::new(
+            err.kind(),
+            format!("could not bind {}: {}", path.display(), err),
+        )),
+    }
+}
+
+/// The Unix socket is rejected by the validation of the network namespace on these platforms.
+#[cfg(not(unix))]
+fn bind_unix(_: &Network) -> io::Result<Option<BoundListener>> {
+    Ok(None)
+}
+
+/// Removes the file of a Unix socket left behind by a broker which didn't stop gracefully. The
+/// file is kept if another process still listens on it, so binding it fails.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let is_socket =
+        fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket());
+
+    if is_socket && UnixStream::connect(path).is_err() {
+        log::info!("Removing the stale Unix socket {}", path.display());
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the backlog which the listeners actually get, as the OS silently lowers the backlogs
+/// above its maximum.
+#[cfg(target_os = "linux")]
+fn effective_backlog(backlog: u32) -> u32 {
+    let max = fs::read_to_string("/proc/sys/net/core/somaxconn")
+        .ok()
+        .and_then(|max| max.trim().parse().ok());
+
+    match max {
+        Some(max) if backlog > max => {
+            log::warn!(
+                "The backlog of {} exceeds the maximum of the system, {} is used instead (see net.core.somaxconn)",
+                backlog,
+                max
+            );
+            max
+        }
+        _ => backlog,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn effective_backlog(backlog: u32) -> u32 {
+    backlog
+}
+
+/// Sets the backlog of a listener which is already listening, by listening again.
+#[cfg(unix)]
+fn set_backlog(socket: &ListenerSocket, backlog: u32) -> io::Result<()> {
+    let backlog = backlog.min(libc::c_int::MAX as u32) as libc::c_int;
+
+    if unsafe { libc::listen(socket.as_raw_fd(), backlog) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// The backlog of a listener can't be changed on these platforms, it's the default of the
+/// standard library.
+#[cfg(not(unix))]
+fn set_backlog(_: &ListenerSocket, backlog: u32) -> io::Result<()> {
+    log::warn!(
+        "The backlog of {} can't be set on this platform, the default of the OS is used instead",
+        backlog
+    );
+    Ok(())
+}
+
+/// Accepts the connections of the clients on `listener`, each one being served on its own thread,
+/// until the shutdown is requested.
+///
+/// Once the listener reached its limit of connections, the new connections are closed as soon as
+/// they're accepted. After failing to accept a connection, the listener backs off before trying
+/// again, so it doesn't spin while the failure lasts.
+fn accept(
+    listener: BoundListener,
+    transport: Transport,
+    allowed_ranges: Option<Arc<Vec<Cidr>>>,
+    broker: Arc<Broker>,
+    config: Arc<RwLock<Config>>,
+    shutdown: Arc<Shutdown>,
+) {
+    let address = listener.connections.address.clone();
+    let mut accepted_count = 0;
+    let mut backoff = AcceptBackoff::new(config.read().unwrap().network.accept_backoff);
+
+    // Shutting the reading side of the socket down unblocks the pending accept.
+    #[cfg(unix)]
+    {
+        let socket = listener.socket.clone();
+        shutdown.watch_listener(move || unsafe {
+            libc::shutdown(socket.as_raw_fd(), libc::SHUT_RD);
+        });
+    }
+
+    loop {
+        let accepted = listener.socket.accept(accepted_count);
+
+        if shutdown.is_requested() {
+            log::info!("Stopped listening on {}", address);
+
+            #[cfg(unix)]
+            if let ListenerSocket::Unix(_, path) = &*listener.socket {
+                if let Err(err) = fs::remove_file(path) {
+                    log::warn!(
+                        "Could not remove the Unix socket {}: {}",
+                        path.display(),
+                        err
+                    );
+                }
+            }
+            return;
+        }
+
+        let (socket, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                let delay = backoff.failed();
+
+                log::error!(
+                    "Could not accept a connection on {}, retrying in {}: {}",
+                    address,
+                    humantime::format_duration(delay),
+                    err
+                );
+                thread::sleep(delay);
+                continue;
+            }
+        };
+        accepted_count += 1;
+        backoff.reset();
+
+        // The socket is closed by being dropped. The address of a client behind a proxy is only
+        // known once the thread of its connection reads the header of the PROXY protocol.
+        if !listener.proxy_protocol && !is_allowed(allowed_ranges.as_deref(), &peer) {
+            log::debug!(
+                "Rejected connection from {} on {}, as its address is not allowed",
+                peer,
+                address
+            );
+            continue;
+        }
+
+        let slot = match listener.connections.acquire() {
+            Some(slot) => slot,
+            None => {
+                log::debug!("Rejected connection from {} on {}", peer, address);
+                continue;
+            }
+        };
+
+        if !listener.proxy_protocol {
+            log::info!(
+                "Accepted connection from {} on {} ({} connections)",
+                peer,
+                address,
+                listener.connections.current()
+            );
+        }
+
+        let transport = transport.clone();
+        let protocol = listener.protocol;
+        let proxy_protocol = listener.proxy_protocol;
+        let connections = listener.connections.clone();
+        let rate_limiter = listener.rate_limiter.clone();
+        let buffers = listener.buffers.clone();
+        let allowed_ranges = allowed_ranges.clone();
+        let broker = broker.clone();
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        let mut client = peer.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("connection-{}", peer))
+            .spawn(move || {
+                if proxy_protocol {
+                    client = match read_proxy_header(&socket, &client) {
+                        Ok(client) => client,
+                        Err(err) => {
+                            log::warn!(
+                                "Rejected connection from {} on {}, as its PROXY protocol header \
+                                 could not be read: {}",
+                                client,
+                                connections.address,
+                                err
+                            );
+                            return;
+                        }
+                    };
+                    if !is_allowed(allowed_ranges.as_deref(), &client) {
+                        log::debug!(
+                            "Rejected connection from {} on {}, as its address is not allowed",
+                            client,
+                            connections.address
+                        );
+                        return;
+                    }
+
+                    log::info!(
+                        "Accepted connection from {} on {} ({} connections)",
+                        client,
+                        connections.address,
+                        connections.current()
+                    );
+                }
+
+                let handshake_timeout = config.read().unwrap().network.handshake_timeout;
+                let served = transport.open(socket, buffers.as_deref(), handshake_timeout);
+                let served = served.and_then(|stream| match protocol {
+                    ListenerProtocol::Native => Connection::new(
+                        stream,
+                        client.clone(),
+                        broker,
+                        config,
+                        shutdown,
+                        rate_limiter,
+                    )
+                    .map(Connection::run),
+                    ListenerProtocol::Text => {
+                        Session::new(stream, client.clone(), broker, config, shutdown)
+                            .map(Session::run)
+                    }
+                });
+
+                if let Err(err) = served {
+                    log::warn!("Could not open the connection from {}: {}", client, err);
+                }
+
+                drop(slot);
+            });
+
+        if let Err(err) = spawned {
+            log::error!("Could not serve the connection from {}: {}", peer, err);
+        }
+    }
+}
+
+/// How long a listener waits after failing to accept a connection, which doubles with each
+/// failure in a row up to [`ACCEPT_BACKOFF_MAX`], and goes back to the backoff of the network
+/// namespace once a connection is accepted.
+struct AcceptBackoff {
+    initial: Duration,
+    next: Duration,
+}
+
+impl AcceptBackoff {
+    fn new(initial: Duration) -> Self {
+        Self {
+            initial,
+            next: initial,
+        }
+    }
+
+    /// Returns how long to wait after a failure, doubling the wait of the next one.
+    fn failed(&mut self) -> Duration {
+        let delay = self.next;
+
+        self.next = delay
+            .saturating_mul(2)
+            .min(ACCEPT_BACKOFF_MAX.max(self.initial));
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+/// Returns `true` if the ranges of addresses the clients can connect from, if they're restricted,
+/// hold the address of `peer`. The clients of the Unix socket are always allowed.
+fn is_allowed(allowed_ranges: Option<&Vec<Cidr>>, peer: &Peer) -> bool {
+    match (allowed_ranges, peer) {
+        (Some(ranges), Peer::Tcp(client)) => ranges.iter().any(|range| range.contains(client.ip())),
+        _ => true,
+    }
+}
+
+/// Reads the header of the PROXY protocol starting a connection, returning the client it was
+/// forwarded for, or `peer` if the header holds no address.
+fn read_proxy_header(socket: &Socket, peer: &Peer) -> io::Result<Peer> {
+    let mut socket = match socket {
+        Socket::Tcp(socket) => socket,
+        #[cfg(unix)]
+        Socket::Unix(_) => return Ok(peer.clone()),
+    };
+
+    socket.set_read_timeout(Some(PROXY_HEADER_TIMEOUT))?;
+    let client = proxy::read_header(&mut socket)?;
+    socket.set_read_timeout(None)?;
+
+    Ok(client.map_or_else(|| peer.clone(), Peer::Tcp))
+}
+
+/// Enables the OS keepalive on `socket`: the client is probed once the connection stays silent
+/// for `keepalive`, then every `keepalive` until it answers or the OS gives up.
+///
+/// The platforms which can't tune the probes only enable them, using the delays of the OS.
+#[cfg(unix)]
+fn set_keepalive(socket: &TcpStream, keepalive: Duration) -> io::Result<()> {
+    let fd = socket.as_raw_fd();
+    let secs = keepalive.as_secs().min(libc::c_int::MAX as u64) as libc::c_int;
+
+    set_socket_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    ))]
+    set_socket_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, secs)?;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    set_socket_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPALIVE, secs)?;
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    set_socket_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, secs)?;
+
+    Ok(())
+}
+
+/// The keepalive is rejected by the validation of the network namespace on these platforms.
+#[cfg(not(unix))]
+fn set_keepalive(_: &TcpStream, _: Duration) -> io::Result<()> {
+    Ok(())
+}
+
+/// Returns the size of the buffer `name` of the socket `fd`, as reported by the OS. Once the size
+/// is `set`, Linux reports twice the size it was set to, as it accounts for its bookkeeping, so
+/// the size set is returned instead.
+#[cfg(unix)]
+fn buffer_size(fd: libc::c_int, name: libc::c_int, set: bool) -> io::Result<usize> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            name,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let size = value.max(0) as usize;
+    if set && cfg!(any(target_os = "linux", target_os = "android")) {
+        Ok(size / 2)
+    } else {
+        Ok(size)
+    }
+}
+
+#[cfg(unix)]
+fn set_socket_option(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    /// Returns the socket the client connects from, along with the one the listener accepts.
+    fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+
+        (client, accepted)
+    }
+
+    fn open(network: &Network, buffers: Option<&SocketBuffers>) -> TcpStream {
+        let (_, accepted) = connected();
+        let transport = Transport::new(network).unwrap();
+
+        match transport.open(Socket::Tcp(accepted), buffers, Duration::from_secs(1)) {
+            Ok(Stream::Tcp(socket)) => socket,
+            _ => panic!("expected a TCP stream"),
+        }
+    }
+
+    #[cfg(unix)]
+    fn socket_option(socket: &TcpStream, level: libc::c_int, name: libc::c_int) -> libc::c_int {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+
+        assert_eq!(result, 0, "{}", io::Error::last_os_error());
+        value
+    }
+
+    #[test]
+    fn nodelay_is_applied() {
+        for nodelay in [true, false] {
+            let network = Network {
+                tcp_nodelay: nodelay,
+                ..Network::default()
+            };
+
+            assert_eq!(open(&network, None).nodelay().unwrap(), nodelay);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keepalive_is_left_to_the_os_by_default() {
+        let socket = open(&Network::default(), None);
+
+        assert_eq!(
+            socket_option(&socket, libc::SOL_SOCKET, libc::SO_KEEPALIVE),
+            0
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn keepalive_is_applied() {
+        let network = Network {
+            tcp_keepalive: Some(Duration::from_secs(42)),
+            ..Network::default()
+        };
+        let socket = open(&network, None);
+
+        assert_ne!(
+            socket_option(&socket, libc::SOL_SOCKET, libc::SO_KEEPALIVE),
+            0
+        );
+        assert_eq!(
+            socket_option(&socket, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE),
+            42
+        );
+        assert_eq!(
+            socket_option(&socket, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL),
+            42
+        );
+    }
+
+    #[cfg(unix)]
+    fn buffers(recv_bytes: Option<usize>, send_bytes: Option<usize>) -> SocketBuffers {
+        SocketBuffers {
+            address: "127.0.0.1:5672".into(),
+            recv_bytes,
+            send_bytes,
+            logged: AtomicBool::new(false),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn socket_buffers_are_applied() {
+        let buffers = buffers(Some(12288), Some(20480));
+        let socket = open(&Network::default(), Some(&buffers));
+        let fd = socket.as_raw_fd();
+
+        assert_eq!(buffer_size(fd, libc::SO_RCVBUF, true).unwrap(), 12288);
+        assert_eq!(buffer_size(fd, libc::SO_SNDBUF, true).unwrap(), 20480);
+        assert!(buffers.logged.load(Ordering::SeqCst));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_buffer_left_unset_keeps_the_os_default() {
+        let (_, default) = connected();
+        let socket = open(&Network::default(), Some(&buffers(Some(12288), None)));
+
+        assert_eq!(
+            socket_option(&socket, libc::SOL_SOCKET, libc::SO_SNDBUF),
+            socket_option(&default, libc::SOL_SOCKET, libc::SO_SNDBUF)
+        );
+    }
+
+    /// Returns a port which is free, as far as it's known.
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// Returns a network namespace listening on the loopback interface on `port`, falling back to
+    /// `port_range`.
+    fn local_network(port: u16, port_range: Option<(u16, u16)>) -> Network {
+        Network {
+            hostname: Ipv4Addr::LOCALHOST.into(),
+            port,
+            port_range,
+            ..Network::default()
+        }
+    }
+
+    #[test]
+    fn first_free_port_is_bound() {
+        let used = TcpListener::bind("127.0.0.1:0").unwrap();
+        let used_port = used.local_addr().unwrap().port();
+        let free = free_port();
+        let network = local_network(used_port, None);
+        let listener = &network.effective_listeners()[0];
+
+        let socket = bind_first_free(listener, [used_port, free, used_port]).unwrap();
+
+        assert_eq!(socket.local_addr().unwrap().port(), free);
+    }
+
+    #[test]
+    fn no_free_port_gives_the_error_of_the_last_one() {
+        let used = TcpListener::bind("127.0.0.1:0").unwrap();
+        let used_port = used.local_addr().unwrap().port();
+        let network = local_network(used_port, None);
+        let listener = &network.effective_listeners()[0];
+
+        let err = bind_first_free(listener, [used_port]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        let err = bind_first_free(listener, []).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn port_is_preferred_to_its_range() {
+        let port = free_port();
+        let range = free_port();
+        let server = Server::bind(&local_network(port, Some((range, range)))).unwrap();
+
+        assert_eq!(server.local_addrs()[0].port(), port);
+    }
+
+    #[test]
+    fn port_in_use_falls_back_to_its_range() {
+        let used = TcpListener::bind("127.0.0.1:0").unwrap();
+        let used_port = used.local_addr().unwrap().port();
+        let range = free_port();
+        let server = Server::bind(&local_network(used_port, Some((range, range)))).unwrap();
+
+        assert_eq!(server.local_addrs()[0].port(), range);
+    }
+
+    #[test]
+    fn backoff_doubles_with_repeated_accept_errors() {
+        let mut backoff = AcceptBackoff::new(Duration::from_millis(100));
+        let delays: Vec<_> = (0..9).map(|_| backoff.failed().as_millis()).collect();
+
+        assert_eq!(delays, [100, 200, 400, 800, 1600, 3200, 5000, 5000, 5000]);
+    }
+
+    #[test]
+    fn backoff_is_reset_once_a_connection_is_accepted() {
+        let mut backoff = AcceptBackoff::new(Duration::from_millis(100));
+
+        for _ in 0..3 {
+            backoff.failed();
+        }
+        backoff.reset();
+
+        assert_eq!(backoff.failed(), Duration::from_millis(100));
+        assert_eq!(backoff.failed(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn backoff_longer_than_the_maximum_is_kept() {
+        let mut backoff = AcceptBackoff::new(Duration::from_secs(10));
+
+        assert_eq!(backoff.failed(), Duration::from_secs(10));
+        assert_eq!(backoff.failed(), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,120 @@
+//! The graceful shutdown of the application.
+//!
+//! Once the shutdown is requested, the listeners stop accepting connections and the clients are
+//! asked to close theirs. The application stops once every connection is closed, or once the
+//! shutdown timeout elapsed.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The signal asking the application to stop, observed by the listeners and the connections.
+#[derive(Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    state: Mutex<State>,
+    closed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    /// Called to wake the listeners up when the shutdown is requested.
+    listeners: Vec<Box<dyn Fn() + Send>>,
+
+    /// Called to ask the clients to close their connections when the shutdown is requested.
+    connections: HashMap<u64, Box<dyn Fn() + Send>>,
+    last_connection: u64,
+}
+
+impl Shutdown {
+    /// Returns `true` once the shutdown is requested.
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Registers a listener, `wake` being called to unblock it when the shutdown is requested.
+    pub fn watch_listener<F>(&self, wake: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        self.state.lock().unwrap().listeners.push(Box::new(wake));
+    }
+
+    /// Registers an open connection, `close` being called to ask the client to close it when the
+    /// shutdown is requested, right away if it's already requested. The connection is awaited by
+    /// the shutdown until the returned guard is dropped.
+    pub fn watch_connection<F>(self: &Arc<Self>, close: F) -> ConnectionGuard
+    where
+        F: Fn() + Send + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+
+        if self.is_requested() {
+            close();
+        }
+
+        state.last_connection += 1;
+        let id = state.last_connection;
+        state.connections.insert(id, Box::new(close));
+
+        ConnectionGuard {
+            shutdown: self.clone(),
+            id,
+        }
+    }
+
+    /// Requests the shutdown, waking the listeners up and asking every client to close its
+    /// connection.
+    pub fn request(&self) {
+        let state = self.state.lock().unwrap();
+
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        for wake in &state.listeners {
+            wake();
+        }
+        for close in state.connections.values() {
+            close();
+        }
+    }
+
+    /// Waits up to `timeout` for every connection to be closed. The number of connections still
+    /// open is returned.
+    pub fn wait_connections(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+
+        while !state.connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            state = self.closed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        state.connections.len()
+    }
+}
+
+/// A connection awaited by the shutdown, until the guard is dropped.
+pub struct ConnectionGuard {
+    shutdown: Arc<Shutdown>,
+    id: u64,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut state = self.shutdown.state.lock().unwrap();
+
+        state.connections.remove(&self.id);
+        self.shutdown.closed.notify_all();
+    }
+}
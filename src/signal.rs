@@ -0,0 +1,83 @@
+//! The handling of the signals sent to the application, on Unix.
+//!
+//! The signals are blocked in every thread of the application and waited for by a dedicated
+//! thread, so their handler is free to run any code.
+
+use std::{io, mem, ptr, thread};
+
+/// The signals handled by the application.
+#[derive(Copy, Clone, Debug)]
+pub enum Signal {
+    /// `SIGHUP`, requesting the configuration to be reloaded.
+    Hangup,
+
+    /// `SIGINT`, requesting the application to stop, usually sent from a terminal.
+    Interrupt,
+
+    /// `SIGTERM`, requesting the application to stop.
+    Terminate,
+}
+
+impl Signal {
+    const ALL: [Self; 3] = [Self::Hangup, Self::Interrupt, Self::Terminate];
+
+    fn number(self) -> libc::c_int {
+        match self {
+            Self::Hangup => libc::SIGHUP,
+            Self::Interrupt => libc::SIGINT,
+            Self::Terminate => libc::SIGTERM,
+        }
+    }
+
+    fn from_number(number: libc::c_int) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|signal| signal.number() == number)
+    }
+}
+
+/// Installs `handler`, called with each signal received by the application.
+///
+/// This must be called before spawning any other thread, as the threads inherit the blocked
+/// signals of the thread spawning them.
+pub fn install<F>(mut handler: F) -> io::Result<()>
+where
+    F: FnMut(Signal) + Send + 'static,
+{
+    let set = unsafe {
+        let mut set = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in &Signal::ALL {
+            libc::sigaddset(&mut set, signal.number());
+        }
+        set
+    };
+
+    match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) } {
+        0 => {}
+        err => return Err(io::Error::from_raw_os_error(err)),
+    }
+
+    thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || loop {
+            let mut number = 0;
+
+            match unsafe { libc::sigwait(&set, &mut number) } {
+                0 => {
+                    if let Some(signal) = Signal::from_number(number) {
+                        handler(signal);
+                    }
+                }
+                err => {
+                    log::error!(
+                        "Could not wait for a signal: {}",
+                        io::Error::from_raw_os_error(err)
+                    );
+                    return;
+                }
+            }
+        })
+        .map(|_| ())
+}
@@ -0,0 +1,331 @@
+//! The line-based protocol spoken on the listeners using the `text` protocol, meant to inspect the
+//! broker by hand with tools like `nc`.
+//!
+//! Each line sent by the client is a command, answered by a line, or by several lines for
+//! `QUEUES`. The words of a command are separated by spaces, the command itself being
+//! case-insensitive:
+//!
+//! | Command                   | Answer                                                      |
+//! | ------------------------- | ----------------------------------------------------------- |
+//! | `AUTH <user> <password>`  | `OK`                                                        |
+//! | `PING`                    | `PONG`                                                      |
+//! | `QUEUES`                  | `QUEUE <name> <messages> <consumers>` lines, then `END`     |
+//! | `DECLARE <queue>`         | `OK`                                                        |
+//! | `PUBLISH <queue> <body>`  | `OK`, the body being the rest of the line                   |
+//! | `GET <queue>`             | `MESSAGE <body>`, or `EMPTY` if the queue has no message    |
+//! | `QUIT`                    | `BYE`, then the connection is closed                        |
+//!
+//! The errors are answered by `ERR <reason>`. When the auth namespace defines users, the client
+//! must authenticate before any command other than `AUTH`, `PING` and `QUIT`, and it's refused
+//! the queues its user is not granted by the access control rules. The connection counts in the
+//! connections of its user once authenticated. The messages taken by `GET`
+//! are settled right away, and their body is escaped so it fits on a line.
+//!
+//! The client always uses the queues of the default virtual host.
+
+use crate::{
+    broker::{Broker, Message},
+    config::{Config, QueueDefinition, DEFAULT_VHOST},
+    protocol::Properties,
+    shutdown::Shutdown,
+    stream::{Peer, Stream},
+};
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    sync::{Arc, Mutex, RwLock},
+};
+
+/// The maximum size of a line sent by the client.
+const LINE_MAX_BYTES: u64 = 64 * 1024;
+
+/// A connection of a client speaking the text protocol.
+pub struct Session {
+    reader: BufReader<Stream>,
+    writer: Stream,
+    peer: Peer,
+    broker: Arc<Broker>,
+    config: Arc<RwLock<Config>>,
+    shutdown: Arc<Shutdown>,
+    authenticated: bool,
+    /// The user the client authenticated as, which is empty for an anonymous client.
+    username: String,
+}
+
+impl Session {
+    pub fn new(
+        stream: Stream,
+        peer: Peer,
+        broker: Arc<Broker>,
+        config: Arc<RwLock<Config>>,
+        shutdown: Arc<Shutdown>,
+    ) -> io::Result<Self> {
+        let (idle_timeout, authenticated) = {
+            let config = config.read().unwrap();
+            (config.network.idle_timeout, config.auth.allows_anonymous())
+        };
+        stream.set_read_timeout(idle_timeout)?;
+
+        Ok(Self {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            peer,
+            broker,
+            config,
+            shutdown,
+            authenticated,
+            username: String::new(),
+        })
+    }
+
+    /// Serves the client until its connection is closed.
+    pub fn run(mut self) {
+        self.broker.metrics().connections.inc();
+
+        // The pending read is unblocked by shutting the stream down.
+        let stream = match self.writer.try_clone() {
+            Ok(stream) => Mutex::new(stream),
+            Err(err) => {
+                log::warn!("Could not serve the connection from {}: {}", self.peer, err);
+                return;
+            }
+        };
+        let id = self.broker.connections().add(self.peer.clone());
+        let guard = self.shutdown.watch_connection(move || {
+            let mut stream = stream.lock().unwrap();
+            let _ = stream.write_all(b"BYE the broker is shutting down\n");
+            let _ = stream.shutdown();
+        });
+
+        match self.serve() {
+            Ok(()) => log::info!("Connection from {} closed", self.peer),
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                let _ = self.writer.write_all(b"BYE the connection is idle\n");
+                log::info!("Connection from {} closed after being idle", self.peer)
+            }
+            Err(err) => log::info!("Connection from {} lost: {}", self.peer, err),
+        }
+
+        drop(guard);
+        self.broker.usage().disconnect(&self.username);
+        self.broker.connections().remove(id);
+        self.broker.metrics().connections.dec();
+    }
+
+    fn serve(&mut self) -> io::Result<()> {
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            let read = (&mut self.reader)
+                .take(LINE_MAX_BYTES)
+                .read_until(b'\n', &mut line)?;
+
+            if read == 0 || self.shutdown.is_requested() {
+                return Ok(());
+            }
+            if line.last() != Some(&b'\n') && read as u64 == LINE_MAX_BYTES {
+                return self.answer("ERR the line is too long");
+            }
+
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            let (command, arguments) = match line.split_once(' ') {
+                Some((command, arguments)) => (command, arguments),
+                None => (line, ""),
+            };
+
+            match command.to_ascii_uppercase().as_str() {
+                "" => continue,
+                "QUIT" => return self.answer("BYE"),
+                "PING" => self.answer("PONG")?,
+                "AUTH" => {
+                    let answer = self.authenticate(arguments);
+                    self.answer(&answer)?
+                }
+                _ if !self.authenticated => self.answer("ERR authentication required")?,
+                "QUEUES" => {
+                    let answer = self.queues();
+                    self.answer(&answer)?
+                }
+                "DECLARE" => {
+                    let answer = self.declare(arguments);
+                    self.answer(&answer)?
+                }
+                "PUBLISH" => {
+                    let answer = self.publish(arguments);
+                    self.answer(&answer)?
+                }
+                "GET" => {
+                    let answer = self.get(arguments);
+                    self.answer(&answer)?
+                }
+                _ => self.answer(&format!("ERR unknown command {}", command))?,
+            }
+        }
+    }
+
+    fn answer(&mut self, answer: &str) -> io::Result<()> {
+        self.writer.write_all(answer.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    fn authenticate(&mut self, arguments: &str) -> String {
+        let (username, password) = match arguments.split_once(' ') {
+            Some(credentials) => credentials,
+            None => return "ERR expected AUTH <user> <password>".into(),
+        };
+
+        let config = self.config.read().unwrap();
+        if config.auth.verify(username, password) {
+            let max_connections = config
+                .auth
+                .user(username)
+                .and_then(|user| user.max_connections);
+
+            // The connection stops counting for the user it was authenticated as, if any.
+            if username != self.username {
+                if !self.broker.usage().connect(username, max_connections) {
+                    return format!(
+                        "ERR user {} already has its maximum of {} connections",
+                        username,
+                        max_connections.unwrap_or_default()
+                    );
+                }
+                self.broker.usage().disconnect(&self.username);
+            }
+
+            self.authenticated = true;
+            self.username = username.into();
+            "OK".into()
+        } else {
+            log::warn!(
+                "Connection from {} refused: login refused for user {}",
+                self.peer,
+                username
+            );
+            "ERR login refused".into()
+        }
+    }
+
+    fn queues(&self) -> String {
+        let mut answer = String::new();
+
+        for queue in self.broker.queues() {
+            if queue.vhost() != DEFAULT_VHOST {
+                continue;
+            }
+
+            let _ = writeln!(
+                answer,
+                "QUEUE {} {} {}",
+                queue.name(),
+                queue.message_count(),
+                queue.consumer_count()
+            );
+        }
+        answer.push_str("END");
+
+        answer
+    }
+
+    fn declare(&self, name: &str) -> String {
+        if name.is_empty() || name.contains(' ') {
+            return "ERR expected DECLARE <queue>".into();
+        }
+
+        self.broker
+            .declare(DEFAULT_VHOST, QueueDefinition::new(name));
+        "OK".into()
+    }
+
+    fn publish(&self, arguments: &str) -> String {
+        let (name, body) = match arguments.split_once(' ') {
+            Some((name, body)) => (name, body),
+            None => (arguments, ""),
+        };
+        if name.is_empty() {
+            return "ERR expected PUBLISH <queue> <body>".into();
+        }
+
+        let queue = match self.broker.queue(DEFAULT_VHOST, name) {
+            Some(queue) => queue,
+            None => return format!("ERR no queue named {}", name),
+        };
+        if !self
+            .config
+            .read()
+            .unwrap()
+            .auth
+            .allows_publish(&self.username, DEFAULT_VHOST, name)
+        {
+            return format!("ERR not allowed to publish to queue {}", name);
+        }
+        if let Some(max_message_bytes) = self.broker.max_message_bytes() {
+            if body.len() as u64 > max_message_bytes {
+                return format!(
+                    "ERR the body exceeds the maximum size of {} bytes",
+                    max_message_bytes
+                );
+            }
+        }
+
+        let message = Message::new(
+            String::new(),
+            name.into(),
+            Properties::default(),
+            body.as_bytes().to_vec(),
+        );
+        if !self.broker.publish(&queue, message) {
+            return format!("ERR the queue {} is full", name);
+        }
+
+        "OK".into()
+    }
+
+    fn get(&self, name: &str) -> String {
+        let queue = match self.broker.queue(DEFAULT_VHOST, name) {
+            Some(queue) => queue,
+            None => return format!("ERR no queue named {}", name),
+        };
+        if !self
+            .config
+            .read()
+            .unwrap()
+            .auth
+            .allows_consume(&self.username, DEFAULT_VHOST, name)
+        {
+            return format!("ERR not allowed to consume queue {}", name);
+        }
+
+        match self.broker.get(&queue, true) {
+            Some(message) => format!("MESSAGE {}", escape(&message.body)),
+            None => "EMPTY".into(),
+        }
+    }
+}
+
+/// Escapes a body so it fits on a line: the backslashes, the line breaks and the other control
+/// characters are escaped. The bytes which are not valid UTF-8 are replaced by `U+FFFD`.
+fn escape(body: &[u8]) -> String {
+    let mut escaped = String::new();
+
+    for c in String::from_utf8_lossy(body).chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
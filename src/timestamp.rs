@@ -0,0 +1,261 @@
+//! The timestamps of the log entries, written with the conversions of `strftime`.
+//!
+//! The conversions supported are the following ones, the names of the months and of the days
+//! being written in English:
+//!
+//! | Conversion | Written as                                                    |
+//! | ---------- | ------------------------------------------------------------- |
+//! | `%Y`       | The year, such as `2024`                                      |
+//! | `%y`       | The last two digits of the year, from `00` to `99`            |
+//! | `%m`       | The month, from `01` to `12`                                  |
+//! | `%b`, `%h` | The abbreviated name of the month, such as `Jan`              |
+//! | `%B`       | The full name of the month, such as `January`                 |
+//! | `%d`       | The day of the month, from `01` to `31`                       |
+//! | `%e`       | The day of the month, from ` 1` to `31`                       |
+//! | `%j`       | The day of the year, from `001` to `366`                      |
+//! | `%a`       | The abbreviated name of the day of the week, such as `Mon`    |
+//! | `%A`       | The full name of the day of the week, such as `Monday`        |
+//! | `%u`       | The day of the week, from `1` for Monday to `7`               |
+//! | `%w`       | The day of the week, from `0` for Sunday to `6`               |
+//! | `%H`       | The hour, from `00` to `23`                                   |
+//! | `%I`       | The hour, from `01` to `12`                                   |
+//! | `%p`       | `AM` or `PM`                                                  |
+//! | `%M`       | The minute, from `00` to `59`                                 |
+//! | `%S`       | The second, from `00` to `60`                                 |
+//! | `%s`       | The number of seconds since the Unix epoch                    |
+//! | `%z`       | The offset from UTC, such as `+0200`                          |
+//! | `%:z`      | The offset from UTC, such as `+02:00`                         |
+//! | `%F`       | The date, as `%Y-%m-%d`                                       |
+//! | `%T`       | The time, as `%H:%M:%S`                                       |
+//! | `%%`       | `%`                                                           |
+
+use std::{
+    fmt::Write as _,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The format of the timestamps in UTC when none is configured, as defined by RFC 3339.
+pub const RFC3339_UTC: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// The format of the timestamps in local time when none is configured, as defined by RFC 3339.
+pub const RFC3339_LOCAL: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// Writes `time` with the conversions of `format`, in UTC or in local time. An error is returned
+/// if `format` holds a conversion which is not supported.
+pub fn format(format: &str, time: SystemTime, utc: bool) -> Result<String, String> {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    };
+    let civil = if utc {
+        Civil::utc(seconds)
+    } else {
+        Civil::local(seconds)
+    };
+
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+
+        let conversion = match chars.next() {
+            Some(':') => match chars.next() {
+                Some('z') => {
+                    civil.write_offset(&mut output, ":");
+                    continue;
+                }
+                Some(conversion) => return Err(format!("unknown conversion %:{}", conversion)),
+                None => return Err("the format ends with a lone %:".into()),
+            },
+            Some(conversion) => conversion,
+            None => return Err("the format ends with a lone %".into()),
+        };
+
+        let _ = match conversion {
+            'Y' => write!(output, "{}", civil.year),
+            'y' => write!(output, "{:02}", civil.year.rem_euclid(100)),
+            'm' => write!(output, "{:02}", civil.month),
+            'b' | 'h' => write!(output, "{}", &MONTHS[civil.month as usize - 1][..3]),
+            'B' => write!(output, "{}", MONTHS[civil.month as usize - 1]),
+            'd' => write!(output, "{:02}", civil.day),
+            'e' => write!(output, "{:2}", civil.day),
+            'j' => write!(output, "{:03}", civil.yday + 1),
+            'a' => write!(output, "{}", &WEEKDAYS[civil.weekday as usize][..3]),
+            'A' => write!(output, "{}", WEEKDAYS[civil.weekday as usize]),
+            'u' => write!(output, "{}", (civil.weekday + 6) % 7 + 1),
+            'w' => write!(output, "{}", civil.weekday),
+            'H' => write!(output, "{:02}", civil.hour),
+            'I' => write!(output, "{:02}", (civil.hour + 11) % 12 + 1),
+            'p' => write!(output, "{}", if civil.hour < 12 { "AM" } else { "PM" }),
+            'M' => write!(output, "{:02}", civil.minute),
+            'S' => write!(output, "{:02}", civil.second),
+            's' => write!(output, "{}", seconds),
+            'z' => {
+                civil.write_offset(&mut output, "");
+                Ok(())
+            }
+            'F' => write!(output, "{}-{:02}-{:02}", civil.year, civil.month, civil.day),
+            'T' => write!(
+                output,
+                "{:02}:{:02}:{:02}",
+                civil.hour, civil.minute, civil.second
+            ),
+            '%' => write!(output, "%"),
+            conversion => return Err(format!("unknown conversion %{}", conversion)),
+        };
+    }
+
+    Ok(output)
+}
+
+/// A point in time broken down into the fields of the calendar.
+struct Civil {
+    year: i64,
+    /// The month, from 1 to 12.
+    month: u32,
+    /// The day of the month, from 1 to 31.
+    day: u32,
+    /// The day of the year, from 0 to 365.
+    yday: u32,
+    /// The day of the week, from 0 for Sunday to 6.
+    weekday: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    /// The offset from UTC, in seconds.
+    offset: i64,
+}
+
+impl Civil {
+    fn utc(seconds: i64) -> Self {
+        let days = seconds.div_euclid(86_400);
+        let time = seconds.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            year,
+            month,
+            day,
+            yday: (days - days_from_civil(year, 1, 1)) as u32,
+            // The Unix epoch was a Thursday.
+            weekday: (days + 4).rem_euclid(7) as u32,
+            hour: (time / 3600) as u32,
+            minute: (time / 60 % 60) as u32,
+            second: (time % 60) as u32,
+            offset: 0,
+        }
+    }
+
+    /// Breaks `seconds` down in the local time zone, or in UTC if it can't be looked up.
+    #[cfg(unix)]
+    fn local(seconds: i64) -> Self {
+        let time = seconds as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+        if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+            return Self::utc(seconds);
+        }
+
+        let year = tm.tm_year as i64 + 1900;
+        let (month, day) = (tm.tm_mon as u32 + 1, tm.tm_mday as u32);
+        // The offset is the difference between the local time read as UTC and the time itself.
+        let local = days_from_civil(year, month, day) * 86_400
+            + tm.tm_hour as i64 * 3600
+            + tm.tm_min as i64 * 60
+            + tm.tm_sec as i64;
+
+        Self {
+            year,
+            month,
+            day,
+            yday: tm.tm_yday as u32,
+            weekday: tm.tm_wday as u32,
+            hour: tm.tm_hour as u32,
+            minute: tm.tm_min as u32,
+            second: tm.tm_sec as u32,
+            offset: local - seconds,
+        }
+    }
+
+    /// The local time zone is not looked up on these platforms, so the time is in UTC.
+    #[cfg(not(unix))]
+    fn local(seconds: i64) -> Self {
+        Self::utc(seconds)
+    }
+
+    /// Writes the offset from UTC, such as `+0200`, with `separator` between the hours and the
+    /// minutes.
+    fn write_offset(&self, output: &mut String, separator: &str) {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let minutes = self.offset.abs() / 60;
+
+        let _ = write!(
+            output,
+            "{}{:02}{}{:02}",
+            sign,
+            minutes / 60,
+            separator,
+            minutes % 60
+        );
+    }
+}
+
+/// Returns the year, the month and the day of the month of the day numbered `days` since the
+/// Unix epoch, in the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // The days are counted from 0000-03-01, so the leap day ends the year.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Returns the number of the day `year`-`month`-`day` since the Unix epoch, in the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year =
+        (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
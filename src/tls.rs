@@ -0,0 +1,491 @@
+//! The termination of the TLS connections of the clients, built on top of the OpenSSL library of
+//! the system.
+//!
+//! The sockets of the TLS connections are non-blocking: the session is shared between the thread
+//! reading the frames and the thread writing them, and it's locked only while OpenSSL is called.
+//!
+//! Each certificate is loaded in a context of its own. The handshakes start with the context of
+//! the default certificate, which switches to the context of another certificate when the server
+//! name requested by the client matches it.
+
+use crate::config::{ConfigError, TlsConfig};
+use std::{
+    ffi::{CStr, CString},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    os::{
+        raw::{c_char, c_int, c_ulong, c_void},
+        unix::io::AsRawFd,
+    },
+    ptr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+mod ffi {
+    use std::os::raw::{c_char, c_int, c_long, c_ulong, c_void};
+
+    pub enum SSL_METHOD {}
+    pub enum SSL_CTX {}
+    pub enum SSL {}
+    pub enum X509_NAME_STACK {}
+
+    pub const SSL_FILETYPE_PEM: c_int = 1;
+    pub const SSL_VERIFY_PEER: c_int = 0x01;
+    pub const SSL_VERIFY_FAIL_IF_NO_PEER_CERT: c_int = 0x02;
+
+    pub const SSL_ERROR_WANT_READ: c_int = 2;
+    pub const SSL_ERROR_WANT_WRITE: c_int = 3;
+    pub const SSL_ERROR_SYSCALL: c_int = 5;
+    pub const SSL_ERROR_ZERO_RETURN: c_int = 6;
+
+    pub const SSL_CTRL_SET_TLSEXT_SERVERNAME_CB: c_int = 53;
+    pub const SSL_CTRL_SET_TLSEXT_SERVERNAME_ARG: c_int = 54;
+    pub const TLSEXT_NAMETYPE_HOST_NAME: c_int = 0;
+    pub const SSL_TLSEXT_ERR_OK: c_int = 0;
+
+    #[link(name = "ssl")]
+    #[link(name = "crypto")]
+    extern "C" {
+        pub fn TLS_server_method() -> *const SSL_METHOD;
+        pub fn SSL_CTX_new(method: *const SSL_METHOD) -> *mut SSL_CTX;
+        pub fn SSL_CTX_free(ctx: *mut SSL_CTX);
+        pub fn SSL_CTX_use_certificate_chain_file(ctx: *mut SSL_CTX, file: *const c_char) -> c_int;
+        pub fn SSL_CTX_use_PrivateKey_file(
+            ctx: *mut SSL_CTX,
+            file: *const c_char,
+            file_type: c_int,
+        ) -> c_int;
+        pub fn SSL_CTX_check_private_key(ctx: *const SSL_CTX) -> c_int;
+        pub fn SSL_CTX_load_verify_locations(
+            ctx: *mut SSL_CTX,
+            ca_file: *const c_char,
+            ca_path: *const c_char,
+        ) -> c_int;
+        pub fn SSL_load_client_CA_file(file: *const c_char) -> *mut X509_NAME_STACK;
+        pub fn SSL_CTX_set_client_CA_list(ctx: *mut SSL_CTX, list: *mut X509_NAME_STACK);
+        pub fn SSL_CTX_set_verify(ctx: *mut SSL_CTX, mode: c_int, callback: *const c_void);
+        pub fn SSL_CTX_ctrl(
+            ctx: *mut SSL_CTX,
+            cmd: c_int,
+            larg: c_long,
+            parg: *mut c_void,
+        ) -> c_long;
+        pub fn SSL_CTX_callback_ctrl(
+            ctx: *mut SSL_CTX,
+            cmd: c_int,
+            callback: Option<unsafe extern "C" fn()>,
+        ) -> c_long;
+
+        pub fn SSL_new(ctx: *mut SSL_CTX) -> *mut SSL;
+        pub fn SSL_free(ssl: *mut SSL);
+        pub fn SSL_set_fd(ssl: *mut SSL, fd: c_int) -> c_int;
+        pub fn SSL_accept(ssl: *mut SSL) -> c_int;
+        pub fn SSL_read(ssl: *mut SSL, buf: *mut c_void, num: c_int) -> c_int;
+        pub fn SSL_write(ssl: *mut SSL, buf: *const c_void, num: c_int) -> c_int;
+        pub fn SSL_shutdown(ssl: *mut SSL) -> c_int;
+        pub fn SSL_get_error(ssl: *const SSL, ret: c_int) -> c_int;
+        pub fn SSL_get_servername(ssl: *const SSL, name_type: c_int) -> *const c_char;
+        pub fn SSL_set_SSL_CTX(ssl: *mut SSL, ctx: *mut SSL_CTX) -> *mut SSL_CTX;
+
+        pub fn ERR_get_error() -> c_ulong;
+        pub fn ERR_error_string_n(err: c_ulong, buf: *mut c_char, len: usize);
+    }
+}
+
+/// The TLS contexts shared by the listeners, used to accept the TLS connections.
+pub struct TlsAcceptor {
+    /// The context of the default certificate, which starts every handshake.
+    context: Context,
+    /// The contexts of the certificates selected by SNI, only kept alive for the callback of the
+    /// default context, and boxed so it keeps pointing at them when the acceptor is moved.
+    _servers: Box<Servers>,
+}
+
+// The contexts are never mutated once built, OpenSSL allows sharing them between threads.
+unsafe impl Send for TlsAcceptor {}
+unsafe impl Sync for TlsAcceptor {}
+
+impl TlsAcceptor {
+    /// Builds the TLS contexts out of the certificates and the private keys of `config`, checking
+    /// that each certificate matches its key.
+    pub fn new(config: &TlsConfig) -> Result<Self, ConfigError> {
+        let ca_file = match &config.ca_file {
+            Some(ca_file) => Some(readable_path("network.tls.ca_file", ca_file)?),
+            None => None,
+        };
+
+        let context = Context::new(
+            "network.tls",
+            &config.cert_file,
+            &config.key_file,
+            ca_file.as_ref(),
+        )?;
+
+        let mut servers = Vec::new();
+        for (i, cert) in config.certs.iter().enumerate() {
+            servers.push(Server {
+                name: cert.server_name.to_ascii_lowercase(),
+                context: Context::new(
+                    &format!("network.tls.cert[{}]", i),
+                    &cert.cert_file,
+                    &cert.key_file,
+                    ca_file.as_ref(),
+                )?,
+            });
+        }
+        let servers = Box::new(Servers(servers));
+
+        if !servers.0.is_empty() {
+            unsafe {
+                let callback: unsafe extern "C" fn() = std::mem::transmute(
+                    select_certificate
+                        as extern "C" fn(*mut ffi::SSL, *mut c_int, *mut c_void) -> c_int,
+                );
+
+                ffi::SSL_CTX_callback_ctrl(
+                    context.0,
+                    ffi::SSL_CTRL_SET_TLSEXT_SERVERNAME_CB,
+                    Some(callback),
+                );
+                ffi::SSL_CTX_ctrl(
+                    context.0,
+                    ffi::SSL_CTRL_SET_TLSEXT_SERVERNAME_ARG,
+                    0,
+                    &*servers as *const Servers as *mut c_void,
+                );
+            }
+        }
+
+        Ok(Self {
+            context,
+            _servers: servers,
+        })
+    }
+
+    /// Performs the TLS handshake of a client which just connected.
+    pub fn accept(&self, socket: TcpStream) -> io::Result<TlsStream> {
+        let ssl = unsafe { ffi::SSL_new(self.context.0) };
+        if ssl.is_null() {
+            return Err(io::Error::other(last_error()));
+        }
+
+        let session = Session(ssl);
+
+        if unsafe { ffi::SSL_set_fd(ssl, socket.as_raw_fd()) } != 1 {
+            return Err(io::Error::other(last_error()));
+        }
+
+        let ret = unsafe { ffi::SSL_accept(ssl) };
+        if ret != 1 {
+            return Err(session.error(ret));
+        }
+
+        socket.set_nonblocking(true)?;
+
+        Ok(TlsStream {
+            inner: Arc::new(Inner {
+                session: Mutex::new(session),
+                read_timeout: Mutex::new(None),
+                socket,
+            }),
+        })
+    }
+}
+
+/// An OpenSSL context, holding a certificate and its private key.
+struct Context(*mut ffi::SSL_CTX);
+
+impl Context {
+    /// Builds a context out of the certificate chain at `cert_file` and of the private key at
+    /// `key_file`, the errors being reported on the fields of the `table` of the configuration.
+    fn new(
+        table: &str,
+        cert_file: &str,
+        key_file: &str,
+        ca_file: Option<&CString>,
+    ) -> Result<Self, ConfigError> {
+        let cert_field = format!("{}.cert_file", table);
+        let key_field = format!("{}.key_file", table);
+        let cert_file = readable_path(&cert_field, cert_file)?;
+        let key_file = readable_path(&key_field, key_file)?;
+
+        let ctx = unsafe { ffi::SSL_CTX_new(ffi::TLS_server_method()) };
+        if ctx.is_null() {
+            return Err(invalid(table, last_error()));
+        }
+
+        // The context is freed on the error paths when it's dropped.
+        let context = Self(ctx);
+
+        unsafe {
+            if ffi::SSL_CTX_use_certificate_chain_file(ctx, cert_file.as_ptr()) != 1 {
+                return Err(invalid(&cert_field, last_error()));
+            }
+            if ffi::SSL_CTX_use_PrivateKey_file(ctx, key_file.as_ptr(), ffi::SSL_FILETYPE_PEM) != 1
+            {
+                return Err(invalid(&key_field, last_error()));
+            }
+            if ffi::SSL_CTX_check_private_key(ctx) != 1 {
+                return Err(invalid(&key_field, last_error()));
+            }
+
+            // The client certificates are verified with the trusted authorities of the context
+            // the handshake ends up with, so every context loads them.
+            if let Some(ca_file) = ca_file {
+                if ffi::SSL_CTX_load_verify_locations(ctx, ca_file.as_ptr(), ptr::null()) != 1 {
+                    return Err(invalid("network.tls.ca_file", last_error()));
+                }
+
+                let names = ffi::SSL_load_client_CA_file(ca_file.as_ptr());
+                if names.is_null() {
+                    return Err(invalid("network.tls.ca_file", last_error()));
+                }
+
+                ffi::SSL_CTX_set_client_CA_list(ctx, names);
+                ffi::SSL_CTX_set_verify(
+                    ctx,
+                    ffi::SSL_VERIFY_PEER | ffi::SSL_VERIFY_FAIL_IF_NO_PEER_CERT,
+                    ptr::null(),
+                );
+            }
+        }
+
+        Ok(context)
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe { ffi::SSL_CTX_free(self.0) }
+    }
+}
+
+/// The certificates of the `[[network.tls.cert]]` entries, with their server names in lowercase.
+struct Servers(Vec<Server>);
+
+struct Server {
+    name: String,
+    context: Context,
+}
+
+impl Servers {
+    /// Returns the context of the certificate of the server name `name`, in lowercase, preferring
+    /// an exact match over a wildcard.
+    fn select(&self, name: &str) -> Option<&Context> {
+        let exact = self.0.iter().find(|server| server.name == name);
+
+        exact
+            .or_else(|| {
+                // The wildcard stands for the first label, which must not be empty.
+                let parent = &name[name.find('.').filter(|&i| i > 0)?..];
+
+                self.0
+                    .iter()
+                    .find(|server| server.name.strip_prefix('*') == Some(parent))
+            })
+            .map(|server| &server.context)
+    }
+}
+
+/// Switches the handshake of `ssl` to the certificate of the server name requested by the client,
+/// if any. The handshake goes on with the default certificate otherwise.
+extern "C" fn select_certificate(
+    ssl: *mut ffi::SSL,
+    _alert: *mut c_int,
+    arg: *mut c_void,
+) -> c_int {
+    // The argument is the `Servers` of the acceptor, which outlives its handshakes.
+    let servers = unsafe { &*(arg as *const Servers) };
+    let name = unsafe { ffi::SSL_get_servername(ssl, ffi::TLSEXT_NAMETYPE_HOST_NAME) };
+
+    if !name.is_null() {
+        let name = unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .to_ascii_lowercase();
+
+        if let Some(context) = servers.select(&name) {
+            unsafe { ffi::SSL_set_SSL_CTX(ssl, context.0) };
+        }
+    }
+
+    ffi::SSL_TLSEXT_ERR_OK
+}
+
+/// A TLS connection, whose handles can be shared between the reading and the writing threads.
+#[derive(Clone)]
+pub struct TlsStream {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    session: Mutex<Session>,
+    read_timeout: Mutex<Option<Duration>>,
+    socket: TcpStream,
+}
+
+impl TlsStream {
+    /// Shuts both directions of the underlying socket down, without closing the TLS session.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.inner.socket.shutdown(Shutdown::Both)
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.inner.read_timeout.lock().unwrap() = timeout;
+
+        Ok(())
+    }
+
+    /// Calls OpenSSL with the session locked, until the operation is not blocked anymore.
+    fn io<F>(&self, mut op: F, timeout: Option<Duration>) -> io::Result<usize>
+    where
+        F: FnMut(*mut ffi::SSL) -> c_int,
+    {
+        loop {
+            let events = {
+                let session = self.inner.session.lock().unwrap();
+                let ret = op(session.0);
+
+                if ret > 0 {
+                    return Ok(ret as usize);
+                }
+
+                match unsafe { ffi::SSL_get_error(session.0, ret) } {
+                    ffi::SSL_ERROR_WANT_READ => libc::POLLIN,
+                    ffi::SSL_ERROR_WANT_WRITE => libc::POLLOUT,
+                    ffi::SSL_ERROR_ZERO_RETURN => return Ok(0),
+                    ffi::SSL_ERROR_SYSCALL if ret == 0 => return Ok(0),
+                    _ => return Err(session.error(ret)),
+                }
+            };
+
+            self.wait(events, timeout)?;
+        }
+    }
+
+    /// Waits for the socket to be ready, without holding the lock of the session.
+    fn wait(&self, events: libc::c_short, timeout: Option<Duration>) -> io::Result<()> {
+        let mut fd = libc::pollfd {
+            fd: self.inner.socket.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(i32::MAX as u128) as i32
+        });
+
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+
+                if err.kind() == io::ErrorKind::Interrupted {
+                    Ok(())
+                } else {
+                    Err(err)
+                }
+            }
+            0 => Err(io::Error::new(io::ErrorKind::WouldBlock, "read timed out")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = *self.inner.read_timeout.lock().unwrap();
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+
+        self.io(
+            |ssl| unsafe { ffi::SSL_read(ssl, buf.as_mut_ptr() as *mut c_void, len) },
+            timeout,
+        )
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+
+        self.io(
+            |ssl| unsafe { ffi::SSL_write(ssl, buf.as_ptr() as *const c_void, len) },
+            None,
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An OpenSSL session, freed once the last handle of its stream is dropped.
+struct Session(*mut ffi::SSL);
+
+// The session is only used while its lock is held.
+unsafe impl Send for Session {}
+
+impl Session {
+    fn error(&self, ret: c_int) -> io::Error {
+        match unsafe { ffi::SSL_get_error(self.0, ret) } {
+            ffi::SSL_ERROR_SYSCALL => {
+                let err = io::Error::last_os_error();
+
+                if err.raw_os_error() == Some(0) {
+                    io::Error::new(io::ErrorKind::UnexpectedEof, "TLS connection lost")
+                } else {
+                    err
+                }
+            }
+            _ => io::Error::other(last_error()),
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::SSL_shutdown(self.0);
+            ffi::SSL_free(self.0);
+        }
+    }
+}
+
+/// Checks that the file at `path` can be read, so a missing file is reported with its path.
+fn readable_path(field: &str, path: &str) -> Result<CString, ConfigError> {
+    if let Err(err) = std::fs::File::open(path) {
+        return Err(invalid(field, format!("could not read {}: {}", path, err)));
+    }
+
+    CString::new(path).map_err(|_| invalid(field, "the path contains a NUL byte".into()))
+}
+
+fn invalid(field: &str, reason: String) -> ConfigError {
+    ConfigError::Invalid {
+        field: field.into(),
+        reason,
+    }
+}
+
+/// Pops the errors queued by OpenSSL, and returns the first one.
+fn last_error() -> String {
+    let mut reason = None;
+
+    loop {
+        let err: c_ulong = unsafe { ffi::ERR_get_error() };
+        if err == 0 {
+            break;
+        }
+
+        let mut buf = [0 as c_char; 256];
+        unsafe { ffi::ERR_error_string_n(err, buf.as_mut_ptr(), buf.len()) };
+
+        let bytes: Vec<u8> = buf
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        reason.get_or_insert_with(|| String::from_utf8_lossy(&bytes).into_owned());
+    }
+
+    reason.unwrap_or_else(|| "unknown TLS error".into())
+}